@@ -0,0 +1,17 @@
+//! Seeds shared by the program and its clients.
+//!
+//! PDAs are derived from the program id along with their seeds, so deployments at different
+//! addresses never share an escrow PDA. Clients derive the same addresses from these constants.
+
+/// First seed of every escrow PDA, followed by the initializer and the little endian nonce
+pub const ESCROW_SEED_PREFIX: &[u8] = b"escrow";
+
+/// Only seed of the config PDA holding the program wide settings
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// Only seed of the PDA that owns the fee collector token accounts
+pub const FEE_COLLECTOR_SEED: &[u8] = b"fee_collector";
+
+/// First seed of the contribution accounts of crowd fills, followed by the escrow and the
+/// contributor
+pub const CONTRIBUTION_SEED: &[u8] = b"contribution";
@@ -0,0 +1,59 @@
+//! Helpers for other on-chain programs settling escrows through cross-program invocations.
+
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::instruction::EscrowInstruction;
+
+/// Number of accounts every `Exchange` takes, see
+/// [EscrowInstruction::Exchange](../instruction/enum.EscrowInstruction.html#variant.Exchange)
+const EXCHANGE_ACCOUNTS: usize = 10;
+
+/// Takes the whole remaining deposit of an escrow with `Exchange`, paying at most
+/// `max_payment`, 0 meaning there is no limit. `accounts` are the accounts `Exchange` expects
+/// in the same order, the extra ones of oracle, gated, receipt, basket or other escrows
+/// included. When the taker is a PDA of the calling program, `signer_seeds` are its seeds
+pub fn exchange_cpi<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    max_payment: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if accounts.len() < EXCHANGE_ACCOUNTS {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let data = EscrowInstruction::Exchange {
+        max_payment,
+        preimage: None,
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+
+    // the taker signs, the token program and the PDA are only read, extra accounts keep the
+    // access the caller was given
+    let accounts_metas = accounts
+        .iter()
+        .enumerate()
+        .map(|(index, account)| match index {
+            0 => AccountMeta::new_readonly(*account.key, true),
+            7 | 8 => AccountMeta::new_readonly(*account.key, false),
+            1..=9 => AccountMeta::new(*account.key, false),
+            _ if account.is_writable => AccountMeta::new(*account.key, false),
+            _ => AccountMeta::new_readonly(*account.key, false),
+        })
+        .collect();
+
+    let exchange_ix = Instruction {
+        program_id: *program_id,
+        accounts: accounts_metas,
+        data,
+    };
+    invoke_signed(&exchange_ix, accounts, signer_seeds)
+}
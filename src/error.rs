@@ -3,20 +3,281 @@ use thiserror::Error;
 
 use solana_program::program_error::ProgramError;
 
-#[derive(Error, Debug, Copy, Clone)]
+/// Errors are returned to clients as `ProgramError::Custom` with the discriminant as the code.
+/// The codes are part of the interface, a variant keeps its code and new ones are appended
+#[derive(Error, Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
 pub enum EscrowError {
     /// Invalid instruction
     #[error("Invalid Instruction")]
-    InvalidInstruction,
+    InvalidInstruction = 0,
 
     #[error("Account Not Rent Exempt")]
-    NotRentExempt,
+    NotRentExempt = 1,
 
     #[error("Expected Amount Missmacth")]
-    ExpectedAmountMissmatch,
+    ExpectedAmountMissmatch = 2,
 
     #[error("Amount Over Flow")]
-    AmountOverFlow,
+    AmountOverFlow = 3,
+
+    #[error("Signer Is Not The Initializer")]
+    UnauthorizedInitializer = 4,
+
+    #[error("Escrow Expired")]
+    EscrowExpired = 5,
+
+    #[error("Escrow Not Expired")]
+    EscrowNotExpired = 6,
+
+    #[error("Invalid Fill Amount")]
+    InvalidFillAmount = 7,
+
+    #[error("Fill Too Small")]
+    FillTooSmall = 8,
+
+    #[error("Temp Account Not Owned By The PDA")]
+    InvalidTempAccountOwner = 9,
+
+    #[error("Unauthorized Taker")]
+    UnauthorizedTaker = 10,
+
+    #[error("No Pending Counter Offer")]
+    NoCounterOffer = 11,
+
+    #[error("Counter Offer Not Funded")]
+    CounterOfferUnfunded = 12,
+
+    #[error("Escrow Requires A Two Phase Exchange")]
+    TwoPhaseRequired = 13,
+
+    #[error("Escrow Is Not Two Phase")]
+    NotTwoPhase = 14,
+
+    #[error("Deposit Pending")]
+    DepositPending = 15,
+
+    #[error("No Pending Deposit")]
+    NoPendingDeposit = 16,
+
+    #[error("Signer Is Not A Party To The Exchange")]
+    UnauthorizedSigner = 17,
+
+    #[error("Escrow Closed")]
+    EscrowClosed = 18,
+
+    #[error("Invalid Amount")]
+    InvalidAmount = 19,
+
+    #[error("Escrow Partially Filled")]
+    EscrowPartiallyFilled = 20,
+
+    #[error("Mint Mismatch")]
+    MintMismatch = 21,
+
+    #[error("Escrow Has No Designated Taker")]
+    NoDesignatedTaker = 22,
+
+    #[error("New Expiry Doesn't Extend The Current One")]
+    ExpiryNotExtended = 23,
+
+    #[error("Remaining Amounts Don't Match The Temp Account")]
+    InconsistentRemainingAmounts = 24,
+
+    #[error("Escrows Have Different Initializers")]
+    InitializerMismatch = 25,
+
+    #[error("Escrows Have Different Receiving Accounts")]
+    ReceiveAccountMismatch = 26,
+
+    #[error("Amount Below The Minimum")]
+    BelowMinimum = 27,
+
+    #[error("Payment Exceeds The Taker's Maximum")]
+    SlippageExceeded = 28,
+
+    #[error("Invalid Auction Parameters")]
+    InvalidAuctionParameters = 29,
+
+    #[error("Escrow Only Accepts Bids")]
+    BidAuctionOnly = 30,
+
+    #[error("Escrow Isn't A Bid Auction")]
+    NotBidAuction = 31,
+
+    #[error("Bid Too Low")]
+    BidTooLow = 32,
+
+    #[error("Best Bid Can't Be Withdrawn While The Auction Is Open")]
+    BestBidLocked = 33,
+
+    #[error("Too Many Assets In The Basket")]
+    BasketTooLarge = 34,
+
+    #[error("Escrow Can Only Be Settled By Exchange")]
+    ExchangeOnly = 35,
+
+    #[error("Preimage Doesn't Match The Hash Lock")]
+    PreimageMismatch = 36,
+
+    #[error("Invalid Oracle Account")]
+    InvalidOracleAccount = 37,
+
+    #[error("Oracle Price Unavailable")]
+    OraclePriceUnavailable = 38,
+
+    #[error("Oracle Price Is Stale")]
+    StaleOraclePrice = 39,
+
+    #[error("Price Outside The Oracle Band")]
+    PriceOutOfBand = 40,
+
+    #[error("Escrow Is Paid In Native SOL")]
+    PaidInSol = 41,
+
+    #[error("Escrow Isn't Paid In Native SOL")]
+    NotPaidInSol = 42,
+
+    #[error("Escrow Has No Arbiter")]
+    NoArbiter = 43,
+
+    #[error("Signer Is Not The Arbiter")]
+    UnauthorizedArbiter = 44,
+
+    #[error("Escrow Is Disputed")]
+    EscrowDisputed = 45,
+
+    #[error("Escrow Is Not Disputed")]
+    NotDisputed = 46,
+
+    #[error("Invalid Vesting Schedule")]
+    InvalidVestingSchedule = 47,
+
+    #[error("Escrow Is Vesting")]
+    EscrowVesting = 48,
+
+    #[error("Escrow Is Not Vesting")]
+    NotVesting = 49,
+
+    #[error("Escrow Only Accepts Stream Payments")]
+    StreamOnly = 50,
+
+    #[error("Escrow Isn't Streamed")]
+    NotStreaming = 51,
+
+    #[error("Invalid NFT Mint")]
+    InvalidNftMint = 52,
+
+    #[error("Taker Doesn't Own The Receive Account")]
+    InvalidTakerReceiveAccount = 53,
+
+    #[error("Account Doesn't Hold The Position Token")]
+    InvalidPositionAccount = 54,
+
+    #[error("Nothing To Claim")]
+    NothingToClaim = 55,
+
+    #[error("Escrow Isn't A Crowd Fill")]
+    NotCrowdFill = 56,
+
+    #[error("Escrow Only Accepts Contributions")]
+    CrowdFillOnly = 57,
+
+    #[error("Crowd Fill Isn't Filled")]
+    CrowdNotFilled = 58,
+
+    #[error("Crowd Fill Is Already Filled")]
+    CrowdFilled = 59,
+
+    #[error("Escrow Is Locked By A Trade In Progress")]
+    EscrowLocked = 60,
+
+    #[error("Malformed Merkle Proof")]
+    MalformedMerkleProof = 61,
+
+    #[error("Escrow Belongs To Its Position Token Holder")]
+    PositionOwned = 62,
+
+    #[error("Taker Doesn't Hold Enough Of The Gate Mint")]
+    GateRequirementNotMet = 63,
+
+    #[error("Gateway Token Missing")]
+    GatewayTokenMissing = 64,
+
+    #[error("Gateway Token Isn't Active")]
+    GatewayTokenInactive = 65,
+
+    #[error("Gateway Token Expired")]
+    GatewayTokenExpired = 66,
+
+    #[error("Gateway Token Isn't The Taker's Or Of The Gatekeeper Network")]
+    GatewayTokenMismatch = 67,
+
+    #[error("Escrow Not Initialized")]
+    EscrowNotInitialized = 68,
+
+    #[error("Trade Requires A Memo")]
+    MemoRequired = 69,
+
+    #[error("Payment Split Basis Points Don't Add Up To 10000")]
+    InvalidPaymentSplit = 70,
+
+    #[error("Metadata Account Isn't The Deposit Mint's")]
+    InvalidMetadata = 71,
+
+    #[error("Deposit Mint Isn't An NFT")]
+    NotAnNft = 72,
+
+    #[error("PDA Doesn't Match The Escrow's Seeds")]
+    InvalidPda = 73,
+
+    #[error("Receive Mint Isn't Wrapped SOL")]
+    NotWrappedSol = 74,
+
+    #[error("Taker Doesn't Have Enough Lamports")]
+    InsufficientLamports = 75,
+
+    #[error("Invalid Fee Schedule")]
+    InvalidFeeSchedule = 76,
+
+    #[error("Taker's Token Account Holds Too Little")]
+    InsufficientTakerBalance = 77,
+
+    #[error("Fee Exemption List Is Full")]
+    FeeExemptionsFull = 78,
+
+    #[error("Pubkey Isn't Fee Exempt")]
+    NotFeeExempt = 79,
+
+    #[error("Initializer Can't Pay The Creation Fee")]
+    CreationFeeUnaffordable = 80,
+
+    #[error("Invalid Cancel Penalty")]
+    InvalidCancelPenalty = 81,
+
+    #[error("New Escrows Are Paused")]
+    ProgramPaused = 82,
+
+    #[error("Deposit And Payment Have The Same Mint")]
+    SameMint = 83,
+
+    #[error("Escrow Is Inactive")]
+    EscrowInactive = 84,
+
+    #[error("Account Not Writable")]
+    AccountNotWritable = 85,
+
+    #[error("Mint Isn't Allowed")]
+    MintNotAllowed = 86,
+
+    #[error("Allowed Mint List Is Full")]
+    AllowedMintsFull = 87,
+
+    #[error("Mint Isn't Listed As Allowed")]
+    MintNotListed = 88,
+
+    #[error("Deposit Shrank Since The Bid")]
+    BidDepositChanged = 89,
 }
 
 impl From<EscrowError> for ProgramError {
@@ -24,3 +285,121 @@ impl From<EscrowError> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The custom error code of every variant as clients see it, a variant must never change code
+    const ERROR_CODES: &[(EscrowError, u32)] = &[
+        (EscrowError::InvalidInstruction, 0),
+        (EscrowError::NotRentExempt, 1),
+        (EscrowError::ExpectedAmountMissmatch, 2),
+        (EscrowError::AmountOverFlow, 3),
+        (EscrowError::UnauthorizedInitializer, 4),
+        (EscrowError::EscrowExpired, 5),
+        (EscrowError::EscrowNotExpired, 6),
+        (EscrowError::InvalidFillAmount, 7),
+        (EscrowError::FillTooSmall, 8),
+        (EscrowError::InvalidTempAccountOwner, 9),
+        (EscrowError::UnauthorizedTaker, 10),
+        (EscrowError::NoCounterOffer, 11),
+        (EscrowError::CounterOfferUnfunded, 12),
+        (EscrowError::TwoPhaseRequired, 13),
+        (EscrowError::NotTwoPhase, 14),
+        (EscrowError::DepositPending, 15),
+        (EscrowError::NoPendingDeposit, 16),
+        (EscrowError::UnauthorizedSigner, 17),
+        (EscrowError::EscrowClosed, 18),
+        (EscrowError::InvalidAmount, 19),
+        (EscrowError::EscrowPartiallyFilled, 20),
+        (EscrowError::MintMismatch, 21),
+        (EscrowError::NoDesignatedTaker, 22),
+        (EscrowError::ExpiryNotExtended, 23),
+        (EscrowError::InconsistentRemainingAmounts, 24),
+        (EscrowError::InitializerMismatch, 25),
+        (EscrowError::ReceiveAccountMismatch, 26),
+        (EscrowError::BelowMinimum, 27),
+        (EscrowError::SlippageExceeded, 28),
+        (EscrowError::InvalidAuctionParameters, 29),
+        (EscrowError::BidAuctionOnly, 30),
+        (EscrowError::NotBidAuction, 31),
+        (EscrowError::BidTooLow, 32),
+        (EscrowError::BestBidLocked, 33),
+        (EscrowError::BasketTooLarge, 34),
+        (EscrowError::ExchangeOnly, 35),
+        (EscrowError::PreimageMismatch, 36),
+        (EscrowError::InvalidOracleAccount, 37),
+        (EscrowError::OraclePriceUnavailable, 38),
+        (EscrowError::StaleOraclePrice, 39),
+        (EscrowError::PriceOutOfBand, 40),
+        (EscrowError::PaidInSol, 41),
+        (EscrowError::NotPaidInSol, 42),
+        (EscrowError::NoArbiter, 43),
+        (EscrowError::UnauthorizedArbiter, 44),
+        (EscrowError::EscrowDisputed, 45),
+        (EscrowError::NotDisputed, 46),
+        (EscrowError::InvalidVestingSchedule, 47),
+        (EscrowError::EscrowVesting, 48),
+        (EscrowError::NotVesting, 49),
+        (EscrowError::StreamOnly, 50),
+        (EscrowError::NotStreaming, 51),
+        (EscrowError::InvalidNftMint, 52),
+        (EscrowError::InvalidTakerReceiveAccount, 53),
+        (EscrowError::InvalidPositionAccount, 54),
+        (EscrowError::NothingToClaim, 55),
+        (EscrowError::NotCrowdFill, 56),
+        (EscrowError::CrowdFillOnly, 57),
+        (EscrowError::CrowdNotFilled, 58),
+        (EscrowError::CrowdFilled, 59),
+        (EscrowError::EscrowLocked, 60),
+        (EscrowError::MalformedMerkleProof, 61),
+        (EscrowError::PositionOwned, 62),
+        (EscrowError::GateRequirementNotMet, 63),
+        (EscrowError::GatewayTokenMissing, 64),
+        (EscrowError::GatewayTokenInactive, 65),
+        (EscrowError::GatewayTokenExpired, 66),
+        (EscrowError::GatewayTokenMismatch, 67),
+        (EscrowError::EscrowNotInitialized, 68),
+        (EscrowError::MemoRequired, 69),
+        (EscrowError::InvalidPaymentSplit, 70),
+        (EscrowError::InvalidMetadata, 71),
+        (EscrowError::NotAnNft, 72),
+        (EscrowError::InvalidPda, 73),
+        (EscrowError::NotWrappedSol, 74),
+        (EscrowError::InsufficientLamports, 75),
+        (EscrowError::InvalidFeeSchedule, 76),
+        (EscrowError::InsufficientTakerBalance, 77),
+        (EscrowError::FeeExemptionsFull, 78),
+        (EscrowError::NotFeeExempt, 79),
+        (EscrowError::CreationFeeUnaffordable, 80),
+        (EscrowError::InvalidCancelPenalty, 81),
+        (EscrowError::ProgramPaused, 82),
+        (EscrowError::SameMint, 83),
+        (EscrowError::EscrowInactive, 84),
+        (EscrowError::AccountNotWritable, 85),
+        (EscrowError::MintNotAllowed, 86),
+        (EscrowError::AllowedMintsFull, 87),
+        (EscrowError::MintNotListed, 88),
+        (EscrowError::BidDepositChanged, 89),
+    ];
+
+    #[test]
+    fn test_every_error_converts_to_its_code() {
+        for &(error, code) in ERROR_CODES {
+            assert_eq!(
+                ProgramError::from(error),
+                ProgramError::Custom(code),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_codes_are_unique_and_contiguous() {
+        for (index, &(_, code)) in ERROR_CODES.iter().enumerate() {
+            assert_eq!(code, index as u32);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Structured log lines for off-chain indexers.
+//!
+//! Every event is a single `msg!` line starting with one of the prefixes below followed by
+//! space separated `key=value` pairs, pubkeys in base58 and amounts in base units:
+//!
+//! `ESCROW_INIT escrow=<pubkey> initializer=<pubkey> amount=<u64> sequence=<u64>`
+//!
+//! `ESCROW_EXCHANGE escrow=<pubkey> taker=<pubkey> amount=<u64> payment=<u64> sequence=<u64>`
+//!
+//! `ESCROW_CANCEL escrow=<pubkey> initializer=<pubkey> sequence=<u64>`
+//!
+//! `ESCROW_RECEIPT escrow=<pubkey> taker=<pubkey> mint=<pubkey> amount=<u64> payment=<u64>`
+//!
+//! `ESCROW_MEMO escrow=<pubkey> memo=<text>`, the memo being the rest of the line
+//!
+//! `sequence` is the escrow's sequence once the event happened, it grows by one with every change
+//! of the escrow so events seen twice or out of order can be told apart.
+//!
+//! The prefixes and the order of the pairs are stable, new pairs are only ever appended.
+
+use solana_program::{msg, pubkey::Pubkey};
+
+pub const ESCROW_INIT: &str = "ESCROW_INIT";
+pub const ESCROW_EXCHANGE: &str = "ESCROW_EXCHANGE";
+pub const ESCROW_CANCEL: &str = "ESCROW_CANCEL";
+pub const ESCROW_RECEIPT: &str = "ESCROW_RECEIPT";
+pub const ESCROW_MEMO: &str = "ESCROW_MEMO";
+
+pub fn emit_init(escrow: &Pubkey, initializer: &Pubkey, amount: u64, sequence: u64) {
+    msg!(
+        "{} escrow={} initializer={} amount={} sequence={}",
+        ESCROW_INIT,
+        escrow,
+        initializer,
+        amount,
+        sequence
+    );
+}
+
+/// `amount` is the part of the deposit the taker received and `payment` what they paid for it
+pub fn emit_exchange(escrow: &Pubkey, taker: &Pubkey, amount: u64, payment: u64, sequence: u64) {
+    msg!(
+        "{} escrow={} taker={} amount={} payment={} sequence={}",
+        ESCROW_EXCHANGE,
+        escrow,
+        taker,
+        amount,
+        payment,
+        sequence
+    );
+}
+
+pub fn emit_cancel(escrow: &Pubkey, initializer: &Pubkey, sequence: u64) {
+    msg!(
+        "{} escrow={} initializer={} sequence={}",
+        ESCROW_CANCEL,
+        escrow,
+        initializer,
+        sequence
+    );
+}
+
+/// `mint` is the receipt mint the taker got one token of, `amount` and `payment` are the same as
+/// for the exchange it proves
+pub fn emit_receipt(escrow: &Pubkey, taker: &Pubkey, mint: &Pubkey, amount: u64, payment: u64) {
+    msg!(
+        "{} escrow={} taker={} mint={} amount={} payment={}",
+        ESCROW_RECEIPT,
+        escrow,
+        taker,
+        mint,
+        amount,
+        payment
+    );
+}
+
+/// `memo` is the data of the spl-memo instruction that came with a trade, invalid UTF-8 is
+/// replaced
+pub fn emit_memo(escrow: &Pubkey, memo: &[u8]) {
+    msg!(
+        "{} escrow={} memo={}",
+        ESCROW_MEMO,
+        escrow,
+        String::from_utf8_lossy(memo)
+    );
+}
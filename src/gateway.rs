@@ -0,0 +1,113 @@
+//! Minimal reader for Civic gateway tokens, only the fields the taker check needs.
+//!
+//! Gateway tokens are Borsh encoded: the feature flags, the optional parent token, the owner
+//! wallet, the optional owner identity, the gatekeeper network, the issuing gatekeeper, the
+//! state and the optional expiry time. Options are a 0/1 flag byte followed by the value, so
+//! offsets past the first one depend on which are set.
+
+use solana_program::{clock::UnixTimestamp, pubkey::Pubkey};
+
+use crate::error::EscrowError;
+
+/// Id of the Civic gateway program, which owns the gateway token accounts
+pub mod gateway_program {
+    solana_program::declare_id!("gatem74V238djXdzWnJf94Wo1DcnuGkfijbf3AuBhfs");
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GatewayTokenState {
+    Active,
+    /// Temporarily suspended by the gatekeeper
+    Frozen,
+    /// Permanently withdrawn by the gatekeeper
+    Revoked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GatewayToken {
+    pub owner_wallet: Pubkey,
+    pub gatekeeper_network: Pubkey,
+    pub state: GatewayTokenState,
+    /// Unix timestamp after which the token is no longer valid, `None` means it never expires
+    pub expire_time: Option<UnixTimestamp>,
+}
+
+/// Reads a gateway token account, rejecting data that isn't one
+pub fn load_gateway_token(data: &[u8]) -> Result<GatewayToken, EscrowError> {
+    let mut reader = Reader(data);
+    let _features = reader.read_u8()?;
+    reader.read_option::<32>()?;
+    let owner_wallet = Pubkey::new_from_array(reader.read_array()?);
+    reader.read_option::<32>()?;
+    let gatekeeper_network = Pubkey::new_from_array(reader.read_array()?);
+    let _issuing_gatekeeper: [u8; 32] = reader.read_array()?;
+    let state = match reader.read_u8()? {
+        0 => GatewayTokenState::Active,
+        1 => GatewayTokenState::Frozen,
+        2 => GatewayTokenState::Revoked,
+        _ => return Err(EscrowError::GatewayTokenMissing),
+    };
+    let expire_time = reader.read_option()?.map(UnixTimestamp::from_le_bytes);
+
+    Ok(GatewayToken {
+        owner_wallet,
+        gatekeeper_network,
+        state,
+        expire_time,
+    })
+}
+
+/// Checks that the gateway token was issued to `taker` by `gatekeeper_network` and is still
+/// valid at `now`
+pub fn check_gateway_token(
+    gateway_token: &GatewayToken,
+    taker: &Pubkey,
+    gatekeeper_network: &Pubkey,
+    now: UnixTimestamp,
+) -> Result<(), EscrowError> {
+    if gateway_token.owner_wallet != *taker
+        || gateway_token.gatekeeper_network != *gatekeeper_network
+    {
+        return Err(EscrowError::GatewayTokenMismatch);
+    }
+
+    if gateway_token.state != GatewayTokenState::Active {
+        return Err(EscrowError::GatewayTokenInactive);
+    }
+
+    match gateway_token.expire_time {
+        Some(expire_time) if now > expire_time => Err(EscrowError::GatewayTokenExpired),
+        _ => Ok(()),
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], EscrowError> {
+        if self.0.len() < len {
+            return Err(EscrowError::GatewayTokenMissing);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, EscrowError> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], EscrowError> {
+        self.read(N)?
+            .try_into()
+            .map_err(|_| EscrowError::GatewayTokenMissing)
+    }
+
+    fn read_option<const N: usize>(&mut self) -> Result<Option<[u8; N]>, EscrowError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.read_array()?)),
+            _ => Err(EscrowError::GatewayTokenMissing),
+        }
+    }
+}
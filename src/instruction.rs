@@ -1,31 +1,798 @@
-use solana_program::program_error::ProgramError;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_associated_token_account::get_associated_token_address;
 use std::convert::TryInto;
+use std::mem::size_of;
 
-use crate::error::EscrowError::InvalidInstruction;
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
 
+use crate::{
+    error::EscrowError::InvalidInstruction,
+    metadata::RoyaltyPolicy,
+    oracle::OracleKind,
+    processor::{config_pda, escrow_pda, fee_collector_pda},
+    state::FeeTier,
+};
+
+/// Instructions are a tag byte followed by the little endian fields of the variant, see
+/// [EscrowInstruction::unpack](enum.EscrowInstruction.html#method.unpack).
+///
+/// With the `borsh` feature the instruction can also be read with
+/// [EscrowInstruction::unpack_borsh](enum.EscrowInstruction.html#method.unpack_borsh). The Borsh
+/// layout is the same tag byte and fields, `Option` being a 0/1 flag byte followed by the value,
+/// except for `Vec` fields: [EscrowInstruction::pack](enum.EscrowInstruction.html#method.pack)
+/// writes their length as a single byte where Borsh writes a little endian u32. Instructions with
+/// a `Vec` field, such as `InitEscrowBatch` or the merkle proof of `Exchange`, can only be read in
+/// the format they were written in. Unlike the tag byte format Borsh doesn't accept instructions
+/// with trailing optional fields left out.
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Debug, PartialEq)]
 pub enum EscrowInstruction {
     /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
     ///
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 0. `[signer, writable]` The account of the person initializing the escrow, paying the creation fee
     /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer
-    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through.
+    ///    `[writable]` when `unwrap_sol` is set, its close authority is moved from the initializer to the PDA
     /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
-    /// 4. `[]` The rent sysvar
-    /// 5. `[]` The token program
+    /// 4. `[]` The token program, the classic one or Token-2022. It must own the accounts above
+    /// 5. `[]` The config account, see `InitializeConfig`
+    /// 6. `[writable]` The fee collector PDA, receiving the config's creation fee from the initializer
+    /// 7. `[]` The system program
+    /// 8. `[writable]` The receipt mint, only when `receipt` is set. It should be created prior to this instruction
+    ///    with 0 decimals and no supply, its mint authority is moved from the initializer to the PDA
+    /// 9. `[writable]` The position mint, only when `position` is set. It is created like the receipt mint
+    /// 10. `[writable]` The initializer's token account for the position mint, only when `position` is set
+    /// 11. `[]` The PDA account, only when `position` is set, to mint the one position token
+    /// 12. `[]` The deposit mint, only when `nft` is set. It must have 0 decimals and a supply of 1
+    /// 13. `[writable]` Optionally, up to three more temporary token accounts owned by the initializer, making
+    ///     the deposit a basket that is taken all at once
     InitEscrow {
-        /// The amount party A expects to receive of token Y
+        /// The amount party A expects to receive of token Y, neither it nor the deposit can be 0
+        amount: u64,
+        /// The last slot at which the trade can be taken, 0 means the escrow never expires
+        expiry_slot: u64,
+        /// The last unix timestamp at which the trade can be taken, 0 means the escrow never expires
+        expiry_unix_timestamp: i64,
+        /// The only account allowed to take the trade, anyone can take it when it's not set.
+        /// Packed as a flag byte followed by the pubkey when the flag is 1
+        allowed_taker: Option<Pubkey>,
+        /// Whether takers have to use `DepositForExchange` and wait for the initializer to confirm
+        two_phase: bool,
+        /// The smallest amount of the deposited token a taker can take, 0 means there is no minimum
+        min_taker_amount: u64,
+        /// Picks the escrow's PDA among the initializer's, see [escrow_pda](../processor/fn.escrow_pda.html)
+        nonce: u64,
+        /// Neutral party that settles disputes, the escrow has to be two-phase when it's set.
+        /// Packed like `allowed_taker`
+        arbiter: Option<Pubkey>,
+        /// Whether `Exchange` mints the taker a receipt, the escrow can't be two-phase when it's set
+        receipt: bool,
+        /// Whether the initializer gets a position token. Whoever holds it is paid on exchange
+        /// and can cancel or update the escrow instead of the initializer. The escrow can't be
+        /// two-phase when it's set
+        position: bool,
+        /// Merkle root of the takers allowed to take the trade, see the
+        /// [merkle](../merkle/index.html) module. Takers prove they are on the allowlist in
+        /// `Exchange`, the only way these escrows are taken, so the escrow can't be two-phase
+        /// when it's set. Packed like `allowed_taker`
+        taker_merkle_root: Option<[u8; 32]>,
+        /// Mint takers have to hold `gate_min_amount` of, they show their balance in `Exchange`
+        /// or `PartialExchange`, so the escrow can't be two-phase when it's set. Packed like
+        /// `allowed_taker`
+        gate_mint: Option<Pubkey>,
+        /// Least balance of `gate_mint` a taker has to hold, it can't be 0 when `gate_mint` is set
+        gate_min_amount: u64,
+        /// Civic gatekeeper network takers need a valid gateway token of, they show it in
+        /// `Exchange` or `PartialExchange`, so the escrow can't be two-phase when it's set.
+        /// Packed like `allowed_taker`
+        gatekeeper_network: Option<Pubkey>,
+        /// Whether trades have to come with an spl-memo instruction, such as an invoice
+        /// reference, in the same transaction. Only `Exchange` and `PartialExchange` check it,
+        /// so the escrow can't be two-phase when it's set
+        memo_required: bool,
+        /// Whether `Exchange` and `PartialExchange` pay the verified creators of the deposit
+        /// mint their royalties out of the payment, from its Metaplex metadata. The escrow can't
+        /// be two-phase unless it's `RoyaltyPolicy::None`. Packed as a u8
+        royalty_policy: RoyaltyPolicy,
+        /// Whether the deposit is a single NFT. The deposit mint is checked to have 0 decimals
+        /// and a supply of 1, the deposit has to be that one token, and `Exchange` moves it with
+        /// `transfer_checked`. The payment stays a fungible amount
+        nft: bool,
+        /// Whether the trade pays the initializer in lamports. The receiving account has to be a
+        /// wrapped SOL account of the spl-token program, which the trade closes to the
+        /// initializer once paid, so the escrow is taken whole. It can't be two-phase or have a
+        /// position token when it's set, and cancelling hands the close authority back
+        unwrap_sol: bool,
+    },
+
+    /// Accepts a trade, the taker takes what is left of the deposit paying what is left of the
+    /// expected amount. Tokens sent to the PDA's temp token account on top of the deposit go back
+    /// to the initializer with the account. Use `PartialExchange` to take only a part of it
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The taker's token account for the token they send. A wrapped SOL account is
+    ///    synced first, so lamports sent to it count towards the payment
+    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through, owned by the taker
+    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 4. `[writable]` The initializer's main account to send their rent fees to
+    /// 5. `[writable]` The initializer's token account that will receive tokens, closed to account 4
+    ///    for escrows started with `unwrap_sol`
+    /// 6. `[writable]` The escrow account holding the escrow info
+    /// 7. `[]` The token program the escrow was started with, owning all the token accounts above
+    /// 8. `[]` The PDA account
+    /// 9. `[writable]` The fee collector's token account for the token the taker sends, owned by the fee collector PDA
+    /// 10. `[]` The config account, which may not exist yet
+    /// 11. `[]` The Pyth price account, only for escrows started with `InitOracleBand`
+    /// 12. `[]` The taker's token account for the gate mint, only for token gated escrows. It is
+    ///     only read
+    /// 13. `[]` The taker's Civic gateway token, only for escrows started with a gatekeeper network
+    /// 14. `[]` The instructions sysvar, only for escrows requiring a memo. The transaction has to
+    ///     include an spl-memo instruction, which is logged with the escrow
+    /// 15. `[]` The Metaplex metadata account of the deposit mint, only for escrows started with a
+    ///     royalty policy
+    /// 16. `[]` The deposit mint, only for NFT escrows
+    ///
+    /// Then for escrows started with a receipt mint, the taker gets its only token:
+    ///
+    /// 0. `[writable]` The receipt mint
+    /// 1. `[writable]` The taker's token account for the receipt mint
+    ///
+    /// Then for escrows started with a position token, the initializer's accounts above have to be
+    /// the position holder's instead:
+    ///
+    /// 0. `[]` The holder's token account holding the position token
+    ///
+    /// Then for each verified creator listed in the metadata, in order, unless royalties are
+    /// skipped because the metadata account isn't the deposit mint's:
+    ///
+    /// 0. `[writable]` The creator's token account for the token the taker sends
+    ///
+    /// Then for each share of a split payment after the first, in the order they were given to
+    /// InitSplitPayment:
+    ///
+    /// 0. `[writable]` The token account receiving the share
+    ///
+    /// Then for each payment leg of a basket payment, in the order they were given to InitBasketPayment:
+    ///
+    /// 0. `[writable]` The taker's token account for the token of the leg
+    /// 1. `[writable]` The initializer's token account receiving the leg
+    ///
+    /// Then for each basket temp token account, in the order they were given to InitEscrow:
+    ///
+    /// 0. `[writable]` The basket temp token account
+    /// 1. `[writable]` The taker's token account for its mint
+    Exchange {
+        /// The most the taker is willing to pay for it, taker fee included, 0 means there is no
+        /// limit
+        max_payment: u64,
+        /// The secret whose SHA-256 hash locks the escrow, only needed for hash locked escrows.
+        /// Packed as a flag byte followed by the 32 bytes when the flag is 1
+        preimage: Option<[u8; 32]>,
+        /// The siblings on the path from the taker's leaf to the allowlist root, only needed for
+        /// escrows started with `taker_merkle_root`. Packed as a u8 count followed by the hashes
+        merkle_proof: Vec<[u8; 32]>,
+    },
+
+    /// Cancels the trade, returning the deposited tokens to the initializer and closing the escrow
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees back
+    /// 1. `[writable]` The PDA's temp token account to return tokens from and eventually close
+    /// 2. `[writable]` The initializer's token account that will receive the returned tokens
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account
+    /// 6. `[writable]` The initializer's token account that would have received tokens, only for
+    ///    escrows started with `unwrap_sol`. Its close authority is handed back to the initializer
+    /// 7. `[writable]` The position holder's token account holding the position token, only for escrows
+    ///    started with one. The holder signs instead of the initializer and the token is burned
+    /// 8. `[writable]` The position mint, only for escrows started with a position token
+    /// 9. `[writable]` The crowd vault, only for crowd fills. Crowd fills can't be cancelled once
+    ///    contributed to, the vault is closed along with the escrow
+    /// 10. `[writable]` The fee collector's token account for the deposited mint, only for escrows
+    ///     started with a `penalty_bps` cancel penalty
+    /// 11. `[writable]` The fee collector PDA, only for escrows started with a `penalty_lamports`
+    ///     cancel penalty
+    /// 12. `[]` The system program, only for escrows started with a `penalty_lamports` cancel penalty
+    /// 13. For each basket temp token account, in the order they were given to InitEscrow:
+    ///     `[writable]` the basket temp token account, then `[writable]` the initializer's token account for its mint
+    CancelEscrow,
+
+    /// Returns the deposited tokens of an expired escrow to the initializer and closes it.
+    /// Anyone can submit this, it fails if the escrow hasn't expired yet
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The PDA's temp token account to return tokens from and eventually close
+    /// 1. `[writable]` The initializer's token account for the deposited mint that will receive the tokens
+    /// 2. `[writable]` The initializer's main account to send their rent fees to
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account
+    /// 6. `[writable]` The initializer's token account that would have received tokens, only for
+    ///    escrows started with `unwrap_sol`. Its close authority is handed back to the initializer
+    /// 7. `[]` The position holder's token account holding the position token, only for escrows started
+    ///    with one. Accounts 1 and 2 are then the holder's
+    /// 8. `[writable]` The crowd vault, only for crowd fills. It is closed when no contribution is left
+    ///    in it, otherwise the contributors get theirs back with `RefundContribution`
+    /// 9. For each basket temp token account, in the order they were given to InitEscrow:
+    ///    `[writable]` the basket temp token account, then `[writable]` the initializer's token account for its mint
+    RefundExpired,
+
+    /// Proposes to take the whole remaining deposit for a different amount than the one expected.
+    /// The PDA is approved as delegate of the taker's token account for the proposed amount so the
+    /// initializer can settle it on their own, a newer counter-offer replaces the pending one
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person proposing the counter-offer
+    /// 1. `[writable]` The proposer's token account for the token they send
+    /// 2. `[]` The proposer's token account for the token they will receive should the counter-offer be accepted
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account
+    ProposeCounterOffer {
+        /// The amount of the expected token the proposer is willing to pay
         amount: u64,
     },
 
-    /// Accepts a trade
+    /// Accepts the pending counter-offer, settling the trade at the proposed amount
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees back
+    /// 1. `[writable]` The initializer's token account that will receive tokens
+    /// 2. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 3. `[writable]` The proposer's token account the counter-offer is paid from
+    /// 4. `[writable]` The proposer's token account that will receive the deposited tokens
+    /// 5. `[writable]` The escrow account holding the escrow info
+    /// 6. `[]` The token program
+    /// 7. `[]` The PDA account
+    /// 8. `[writable]` The fee collector's token account for the token the proposer sends, owned by the fee collector PDA.
+    ///    The maker fee comes out of the counter-offer, there is no taker fee on top of it
+    /// 9. `[]` The config account, which may not exist yet
+    AcceptCounterOffer,
+
+    /// Takes part of the remaining deposit for a payment chosen by the taker, which has to give the
+    /// initializer at least the pro rata share of the remaining expected amount
+    ///
+    ///
+    /// Accounts expected are the same as for `Exchange`
+    PartialExchange {
+        /// The amount of the deposited token the taker wants to receive
+        amount_to_take: u64,
+        /// The amount of the expected token the taker pays for it
+        amount_to_give: u64,
+    },
+
+    /// Deposits the taker's payment for a two-phase escrow, transferring ownership of the given temp
+    /// token account to the PDA until the initializer confirms or rejects the exchange
     ///
     ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` Temporary token account holding at least the remaining expected amount, owned by the taker
+    /// 2. `[]` The taker's token account for the token they will receive should the trade go through
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    DepositForExchange,
+
+    /// Confirms the pending deposit of a two-phase escrow, paying the initializer from it and sending
+    /// the remaining deposit to the taker. The taker's temp token account goes back to them
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees back
+    /// 1. `[writable]` The initializer's token account that will receive tokens
+    /// 2. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 3. `[writable]` The taker's temp token account holding their deposit
+    /// 4. `[writable]` The taker's token account that will receive the deposited tokens
+    /// 5. `[writable]` The escrow account holding the escrow info
+    /// 6. `[]` The token program
+    /// 7. `[]` The PDA account
+    /// 8. `[writable]` The fee collector's token account for the token the taker deposited, owned by the fee collector PDA.
+    ///    The maker fee comes out of the deposit, there is no taker fee on top of it
+    /// 9. `[]` The config account, which may not exist yet
+    ConfirmExchange,
+
+    /// Rejects the pending deposit of a two-phase escrow, giving the taker's temp token account back
+    /// to them. Either the initializer or the taker can reject
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The initializer or the taker
+    /// 1. `[writable]` The taker's temp token account holding their deposit
+    /// 2. `[writable]` The escrow account holding the escrow info
+    /// 3. `[]` The token program
+    /// 4. `[]` The PDA account
+    RejectExchange,
+
+    /// Changes the amount the initializer expects to receive, only allowed before any part of the
+    /// trade was taken
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[]` The signer's token account holding the position token, only for escrows started with
+    ///    one. The position holder signs instead of the initializer
+    UpdateExpectedAmount {
+        /// The new amount party A expects to receive of token Y
+        new_amount: u64,
+    },
+
+    /// Adds more tokens to the PDA's temp token account, the expected amount stays the same
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The initializer's token account to take the additional tokens from
+    /// 2. `[writable]` The PDA's temp token account holding the deposit
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    TopUp {
+        /// The amount of the deposited token to add
+        additional_amount: u64,
+    },
+
+    /// Cancels an escrow restricted to a designated taker with the consent of both parties,
+    /// returning the deposited tokens to the initializer and closing the escrow
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees back
+    /// 1. `[signer]` The designated taker
+    /// 2. `[writable]` The PDA's temp token account to return tokens from and eventually close
+    /// 3. `[writable]` The initializer's token account that will receive the returned tokens
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    MutualCancel,
+
+    /// Moves the expiry slot of the escrow further out, it can never be brought forward
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[]` The signer's token account holding the position token, only for escrows started with
+    ///    one. The position holder signs instead of the initializer
+    ExtendExpiry {
+        /// The new last slot at which the trade can be taken
+        new_expiry_slot: u64,
+    },
+
+    /// Cancels the escrow and lists what is left of the deposit again with new terms in one step,
+    /// keeping the same escrow and temp token accounts. A pending counter-offer and the designated
+    /// taker are cleared, the unix timestamp expiry is kept
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[]` The PDA's temp token account holding the deposit
+    /// 2. `[writable]` The escrow account holding the escrow info
+    Replace {
+        /// The amount party A expects to receive of token Y for what is left of the deposit
+        new_expected_amount: u64,
+        /// The last slot at which the trade can be taken, 0 means the escrow never expires
+        new_expiry: u64,
+    },
+
+    /// Moves part of the remaining deposit into a new escrow with the same terms, the expected
+    /// amount is split pro rata with the new escrow rounded down
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The PDA's temp token account to move tokens from
+    /// 3. `[writable]` The new escrow account, it will hold all necessary info about the split trade
+    /// 4. `[writable]` Empty temporary token account for the deposited mint, created prior to this instruction and owned by the initializer
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    SplitEscrow {
+        /// The amount of the remaining deposit to move into the new escrow
+        split_deposit_amount: u64,
+    },
+
+    /// Moves the deposit of the second escrow into the first one and adds up their expected
+    /// amounts, closing the second escrow. Both must belong to the same initializer, hold the same
+    /// mint and pay into the same receiving account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized both escrows, receives the rent fees back
+    /// 1. `[writable]` The escrow account to merge into
+    /// 2. `[writable]` The PDA's temp token account of the escrow to merge into
+    /// 3. `[writable]` The escrow account to merge and close
+    /// 4. `[writable]` The PDA's temp token account of the escrow to merge and close
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account of the escrow to merge into
+    /// 7. `[]` The PDA account of the escrow to merge and close
+    MergeEscrows,
+
+    /// Settles two opposing escrows against each other, each initializer receives the whole
+    /// remaining deposit of the other escrow less the fees of a trade of their escrow, and both
+    /// escrows are closed. It only matches when each remaining deposit covers what is left of the
+    /// other side's expected amount and is within the price band of the other side's oracle,
+    /// partial matches aren't supported. Anyone can submit this
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The first escrow account
+    /// 1. `[writable]` The PDA's temp token account of the first escrow
+    /// 2. `[writable]` The first initializer's main account to send their rent fees to
+    /// 3. `[writable]` The first initializer's token account that will receive tokens
+    /// 4. `[writable]` The second escrow account
+    /// 5. `[writable]` The PDA's temp token account of the second escrow
+    /// 6. `[writable]` The second initializer's main account to send their rent fees to
+    /// 7. `[writable]` The second initializer's token account that will receive tokens
+    /// 8. `[]` The token program
+    /// 9. `[]` The PDA account of the first escrow
+    /// 10. `[]` The PDA account of the second escrow
+    /// 11. `[writable]` The fee collector's token account for the token the first initializer receives
+    /// 12. `[writable]` The fee collector's token account for the token the second initializer receives
+    /// 13. `[]` The config account
+    ///
+    /// Then for each escrow with an oracle, first escrow first:
+    ///
+    /// 0. `[]` The oracle price account of the escrow
+    MatchEscrows,
+
+    /// Takes the whole remaining deposit of several escrows for the same pair in one instruction.
+    /// Every escrow is checked and settled like a full `Exchange`, if any of them fails the whole
+    /// instruction fails. Each escrow adds 5 accounts, with the 1232 byte transaction size limit
+    /// that leaves room for 5 escrows in a transaction the taker pays for. 5 escrows also fit in
+    /// the default budget of 200_000 compute units
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trades
+    /// 1. `[writable]` The taker's token account for the token they send
+    /// 2. `[writable]` The taker's token account for the token they will receive
+    /// 3. `[]` The token program
+    /// 4. `[writable]` The fee collector's token account for the token the taker sends, owned by the fee collector PDA
+    /// 5. `[]` The config account, which may not exist yet
+    ///
+    /// Then for each of the `count` escrows:
+    ///
+    /// 0. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The initializer's main account to send their rent fees to
+    /// 3. `[writable]` The initializer's token account that will receive tokens
+    /// 4. `[]` The PDA account of the escrow
+    ExchangeBatch {
+        /// The number of escrows to take
+        count: u8,
+    },
+
+    /// Starts several trades at once, each entry works like `InitEscrow` with its amount and nonce,
+    /// no expiry and no designated taker. If any entry fails the whole instruction fails and the
+    /// index of the failing entry is logged
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person initializing the escrows, paying a creation fee per entry
+    /// 1. `[]` The initializer's token account for the token they will receive should the trades go through
+    /// 2. `[]` The token program
+    /// 3. `[]` The config account
+    /// 4. `[writable]` The fee collector PDA
+    /// 5. `[]` The system program
+    ///
+    /// Then for each entry of `entries`:
+    ///
+    /// 0. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer
+    /// 1. `[writable]` The escrow account, it will hold all necessary info about the trade.
+    InitEscrowBatch {
+        /// The terms of each escrow. Packed as a u8 count followed by the amount and nonce of
+        /// each entry
+        entries: Vec<BatchEntry>,
+    },
+
+    /// Starts a Dutch auction, the price of the whole deposit decays linearly from `start_amount`
+    /// to `end_amount` between `start_slot` and `end_slot` and stays at `end_amount` afterwards.
+    /// Takers always take the whole deposit at the current price
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitDutchAuction {
+        /// The price of the whole deposit before and at the start slot
+        start_amount: u64,
+        /// The price of the whole deposit at and after the end slot
+        end_amount: u64,
+        /// The slot the price starts decaying at
+        start_slot: u64,
+        /// The slot the price stops decaying at
+        end_slot: u64,
+    },
+
+    /// Starts a bid auction, takers bid for the whole deposit with `PlaceBid` and the initializer
+    /// accepts one of the bids with `AcceptBid` until the end slot. After it the best bid can be
+    /// withdrawn like any other
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitBidAuction {
+        /// The smallest bid of token Y that can be placed
+        reserve_amount: u64,
+        /// The last slot at which bids can be placed and accepted, it has to be in the future
+        end_slot: u64,
+    },
+
+    /// Places a bid on a bid auction, transferring ownership of the given temp token account to the
+    /// PDA until the bid is accepted or withdrawn. The bid has to beat the best bid so far
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the bidder
+    /// 1. `[writable]` Temporary token account holding at least the bid amount, owned by the bidder
+    /// 2. `[]` The bidder's token account for the token they will receive should the bid be accepted
+    /// 3. `[writable]` The bid account, created prior to this instruction and owned by the program
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    PlaceBid {
+        /// The amount of the expected token the bidder pays for the whole deposit
+        amount: u64,
+    },
+
+    /// Accepts a bid, paying the initializer from the bid and sending the deposit to the bidder.
+    /// The bidder gets their temp token account and the bid account rent back and the escrow is closed
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees back
+    /// 1. `[writable]` The initializer's token account that will receive tokens
+    /// 2. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 3. `[writable]` The bid account
+    /// 4. `[writable]` The bidder's temp token account holding the bid
+    /// 5. `[writable]` The bidder's token account that will receive the deposited tokens
+    /// 6. `[writable]` The bidder's main account to send the bid account rent to
+    /// 7. `[writable]` The escrow account holding the escrow info
+    /// 8. `[]` The token program
+    /// 9. `[]` The PDA account
+    /// 10. `[writable]` The fee collector's token account for the token of the bid, owned by the fee collector PDA.
+    ///     The maker fee comes out of the bid, there is no taker fee on top of it
+    /// 11. `[]` The config account, which may not exist yet
+    AcceptBid,
+
+    /// Withdraws a bid, giving the bidder's temp token account back and closing the bid account.
+    /// The best bid can only be withdrawn once the auction is over
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the bidder, receives the bid account rent back
+    /// 1. `[writable]` The bid account
+    /// 2. `[writable]` The bidder's temp token account holding the bid
+    /// 3. `[]` The escrow account the bid was placed on, it may already be closed
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account
+    WithdrawBid,
+
+    /// Starts a trade priced per unit of the deposit instead of for the whole deposit, a taker
+    /// taking `fill_amount` pays `fill_amount * price_numerator / price_denominator` rounded up
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitRatePriced {
+        /// Units of token Y paid per `price_denominator` units of the deposit
+        price_numerator: u64,
+        /// Units of the deposit priced at `price_numerator`
+        price_denominator: u64,
+    },
+
+    /// Starts a trade where the taker pays in up to four tokens, all of them in one exchange.
+    /// The first amount is paid like the expected amount of `InitEscrow`, fee included, and each
+    /// further amount is paid whole to the matching token account of the initializer
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0-7. The same accounts as for `InitEscrow`
+    ///
+    /// Then for each amount after the first:
+    ///
+    /// 0. `[]` The initializer's token account that will receive that amount
+    InitBasketPayment {
+        /// The amount of each token the initializer expects to receive.
+        /// Packed as a u8 count followed by the amounts
+        amounts: Vec<u64>,
+    },
+
+    /// Starts a hash time locked trade for cross-chain swaps. The taker has to reveal the secret
+    /// matching `hash` in `Exchange` to take the whole deposit. Until `timeout_slot` the initializer
+    /// can't cancel, afterwards anyone can return the deposit with `RefundExpired`
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitHashLock {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+        /// SHA-256 hash of the secret
+        hash: [u8; 32],
+        /// The last slot at which the trade can be taken
+        timeout_slot: u64,
+    },
+
+    /// Starts a trade that can only be taken at a price close to a Pyth or Switchboard oracle
+    /// price. The oracle feed has to price one whole deposited token in whole tokens of the
+    /// expected mint
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0-7. The same accounts as for `InitEscrow`
+    /// 8. `[]` The Pyth price account or the Switchboard aggregator account
+    /// 9. `[]` The mint of the deposited token
+    /// 10. `[]` The mint of the token the initializer will receive
+    InitOracleBand {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+        /// The largest deviation of a trade from the oracle price, in basis points
+        max_deviation_bps: u16,
+        /// The oldest oracle price a trade can be checked against, in seconds for Pyth and in
+        /// slots for Switchboard
+        max_staleness: i64,
+        /// The program the oracle account belongs to, packed as 0 for Pyth and 1 for Switchboard.
+        /// Pyth when omitted
+        oracle_kind: OracleKind,
+    },
+
+    /// Starts a trade paid in native SOL instead of a token, taken whole with `ExchangeForSol`
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person initializing the escrow, paying the creation fee. It
+    ///    receives the SOL unless account 7 is given
+    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer
+    /// 2. `[writable]` The escrow account, it will hold all necessary info about the trade.
+    /// 3. `[]` The token program
+    /// 4. `[]` The config account, see `InitializeConfig`
+    /// 5. `[writable]` The fee collector PDA, receiving the config's creation fee from the initializer
+    /// 6. `[]` The system program
+    /// 7. `[]` Optionally, the system account receiving the SOL instead of the initializer
+    InitEscrowForSol {
+        /// The lamports party A expects to receive
+        sol_amount: u64,
+        /// Picks the escrow's PDA among the initializer's, like `InitEscrow`'s. 0 when omitted
+        nonce: u64,
+    },
+
+    /// Accepts a trade paid in native SOL, the taker pays the expected lamports to the initializer
+    /// and takes the whole deposit. The fee is paid in lamports too
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The system account of the person taking the trade, it pays the SOL
+    /// 1. `[writable]` The taker's token account for the token they will receive should the trade go through
+    /// 2. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 3. `[writable]` The initializer's main account receiving the SOL and the rent fees
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    /// 6. `[]` The system program
+    /// 7. `[]` The PDA account
+    /// 8. `[writable]` The fee collector's wrapped SOL account, owned by the fee collector PDA
+    /// 9. `[]` The config account, which may not exist yet
+    /// 10. `[writable]` The account receiving the SOL, only when the initializer picked one other
+    ///     than their main account. It has to be rent exempt once paid
+    ExchangeForSol,
+
+    /// Flags the pending deposit of an escrow with an arbiter as disputed. Until the arbiter
+    /// resolves it, the escrow can't be exchanged, confirmed, rejected or cancelled
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The initializer or the taker who made the pending deposit
+    /// 1. `[writable]` The escrow account holding the escrow info
+    RaiseDispute,
+
+    /// Settles a disputed escrow, either completing the swap like `ConfirmExchange` or giving the
+    /// taker's deposit back and refunding the initializer. Only the arbiter can sign it
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The arbiter of the escrow
+    /// 1. `[writable]` The initializer's main account to send their rent fees to
+    /// 2. `[writable]` The initializer's token account that will receive tokens
+    /// 3. `[writable]` The initializer's token account for the deposited mint, refunded to when the deposit isn't released
+    /// 4. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 5. `[writable]` The taker's temp token account holding their deposit
+    /// 6. `[writable]` The taker's token account that will receive the deposited tokens
+    /// 7. `[writable]` The escrow account holding the escrow info
+    /// 8. `[]` The token program
+    /// 9. `[]` The PDA account
+    /// 10. `[writable]` The fee collector's token account for the token the taker deposited, owned by the fee collector PDA.
+    ///     Only read when the deposit is released, see `ConfirmExchange`
+    /// 11. `[]` The config account, which may not exist yet, only read when the deposit is released
+    ResolveDispute {
+        /// Whether the swap goes through, otherwise both sides get their deposit back
+        release_to_taker: bool,
+    },
+
+    /// Starts a vesting escrow. It is taken whole with `Exchange`, which pays the initializer in
+    /// full but keeps the deposit in the temp token account, the taker then withdraws it with
+    /// `ClaimVested` as it unlocks
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitVesting {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+        /// The slot before which nothing can be claimed
+        cliff_slot: u64,
+        /// The slot at which the whole deposit can be claimed
+        end_slot: u64,
+    },
+
+    /// Transfers the part of a vesting deposit unlocked so far and not claimed yet to the taker.
+    /// The final claim closes the temp token account and the escrow account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The taker who exchanged the escrow
+    /// 1. `[writable]` The taker's token account that receives the vested tokens
+    /// 2. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 3. `[writable]` The initializer's main account to send their rent fees to
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    ClaimVested,
+
+    /// Starts a streamed escrow. Takers pay for it in installments with `StreamPayment` instead of
+    /// taking it with `Exchange`
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitStream {
+        /// The amount party A expects to receive of token Y over all installments
+        amount: u64,
+    },
+
+    /// Pays an installment of a streamed escrow and releases the matching share of the deposit
+    /// to the taker. The installment is capped at what is left to pay, the installment paying
+    /// the expected amount in full closes the temp token account and the escrow account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person paying the installment
     /// 1. `[writable]` The taker's token account for the token they send
     /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through
     /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
@@ -34,10 +801,412 @@ pub enum EscrowInstruction {
     /// 6. `[writable]` The escrow account holding the escrow info
     /// 7. `[]` The token program
     /// 8. `[]` The PDA account
-    Exchange {
-        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token
+    /// 9. `[writable]` The fee collector's token account for the token the taker sends, owned by the fee collector PDA
+    /// 10. `[]` The config account, which may not exist yet
+    StreamPayment {
+        /// The amount of token Y paid in this installment
+        amount: u64,
+    },
+
+    /// Starts a crowd fill. Any number of contributors pay for it with `Contribute` instead of
+    /// taking it with `Exchange`, and share the deposit pro rata once it is settled
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. - 7. The same as for `InitEscrow`
+    /// 8. `[writable]` The crowd vault, an empty token account for the token the initializer
+    ///    receives, owned by the initializer. Its ownership is transferred to the PDA
+    InitCrowdFill {
+        /// The amount party A expects to receive of token Y from all contributors
+        amount: u64,
+        /// Last slot at which contributions are taken, 0 means the crowd fill never expires.
+        /// Contributions to a crowd fill that expired unfilled are refunded with
+        /// `RefundContribution`
+        expiry_slot: u64,
+    },
+
+    /// Pays part of the expected amount of a crowd fill into the crowd vault. The contribution is
+    /// capped at what is left to contribute and recorded in the contribution account of the
+    /// contributor, which is created on their first contribution
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The contributor, pays the rent of the contribution account
+    /// 1. `[writable]` The contributor's token account for the token they send
+    /// 2. `[writable]` The contribution account, the PDA of the escrow and the contributor
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[writable]` The crowd vault
+    /// 5. `[]` The token program
+    /// 6. `[]` The system program
+    Contribute {
+        /// The amount of token Y contributed
+        amount: u64,
+    },
+
+    /// Pays the contributions of a filled crowd fill to the initializer. Anyone can submit this,
+    /// the contributors then claim their share of the deposit with `ClaimShare`
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The escrow account holding the escrow info
+    /// 1. `[writable]` The crowd vault, closed once emptied
+    /// 2. `[writable]` The initializer's main account to send the rent of the crowd vault to
+    /// 3. `[writable]` The initializer's token account that will receive tokens
+    /// 4. `[writable]` The fee collector's token account for the token contributed, owned by the fee collector PDA
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    /// 7. `[]` The config account, which may not exist yet
+    Settle,
+
+    /// Sends a contributor their share of the deposit of a settled crowd fill and closes their
+    /// contribution account. The last claim closes the temp token account and the escrow account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The contributor, receives the rent of the contribution account
+    /// 1. `[writable]` The contributor's token account for the deposited token, owned by the contributor
+    /// 2. `[writable]` The contribution account
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 5. `[writable]` The initializer's main account to send their rent fees to
+    /// 6. `[]` The token program
+    /// 7. `[]` The PDA account
+    ClaimShare,
+
+    /// Returns the contributions of a contributor and closes their contribution account, once the
+    /// crowd fill expired without being filled or was cancelled or refunded
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The contributor, receives the rent of the contribution account
+    /// 1. `[writable]` The contributor's token account that will receive the contributions back
+    /// 2. `[writable]` The contribution account
+    /// 3. `[writable]` The escrow account the contributions were made to, it may be closed already
+    /// 4. `[writable]` The crowd vault
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    /// 7. `[writable]` The initializer's main account, receives the rent of the crowd vault when the
+    ///    last contribution is refunded after the escrow was closed
+    RefundContribution,
+
+    /// Hands the escrow over to another initializer, who can then cancel or update it and gets
+    /// the rent back when it closes. The PDA keeps owning the temp token account, so only the
+    /// escrow info changes. Payments still go to the receiving account given at init. Escrows
+    /// started with a position token are handed over by transferring the token instead
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current initializer
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[]` The new initializer
+    TransferInitializer,
+
+    /// Starts a trade where the payment, once the fee is taken out, is split between up to four
+    /// token accounts by basis points, such as between the co-owners of the deposit. Each share
+    /// is rounded down and the remainder goes to the first account. The deposit is taken whole
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0-7. The same accounts as for `InitEscrow`, the token account that will receive tokens
+    ///      getting the first share
+    ///
+    /// Then for each share after the first:
+    ///
+    /// 0. `[]` The token account for the same mint that will receive that share
+    InitSplitPayment {
+        /// The amount party A expects to receive of token Y
         amount: u64,
+        /// The share of the payment of each receiving account in basis points, adding up to
+        /// 10000. Packed as a u8 count followed by the shares
+        basis_points: Vec<u16>,
     },
+
+    /// Accepts a trade like `Exchange`, first creating the taker's associated token account for
+    /// the deposit mint when it doesn't exist yet. Only escrows started with the spl-token
+    /// program can be taken this way
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[]` The mint of the deposited token
+    /// 1. `[]` The associated token account program
+    /// 2. `[]` The system program
+    /// 3. `[]` The rent sysvar
+    ///
+    /// Then the same accounts as for `Exchange`, with the taker `[signer, writable]` to pay the
+    /// rent of the new account and the taker's token account for the token they will receive
+    /// being their associated token account for the deposit mint, which may not exist yet
+    ExchangeWithAtaCreation {
+        /// The most the taker is willing to pay for it, 0 means there is no limit
+        max_payment: u64,
+        /// The secret whose SHA-256 hash locks the escrow, only needed for hash locked escrows.
+        /// Packed as a flag byte followed by the 32 bytes when the flag is 1
+        preimage: Option<[u8; 32]>,
+        /// The siblings on the path from the taker's leaf to the allowlist root, only needed for
+        /// escrows started with `taker_merkle_root`. Packed as a u8 count followed by the hashes
+        merkle_proof: Vec<[u8; 32]>,
+    },
+
+    /// Accepts a trade like `Exchange`, the taker paying with lamports instead of a token account.
+    /// The lamports are wrapped into a new wrapped SOL account of the taker, which pays the trade
+    /// and is closed back to the taker afterwards with whatever it didn't spend. Only escrows
+    /// started with the spl-token program and expecting wrapped SOL can be taken this way
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[]` The native mint
+    /// 1. `[]` The system program
+    /// 2. `[]` The rent sysvar
+    ///
+    /// Then the same accounts as for `Exchange`, with the taker `[signer, writable]` to fund the
+    /// new account and the taker's token account for the token they send being a new account
+    /// `[signer, writable]` that doesn't exist yet
+    ExchangeWithSol {
+        /// The lamports wrapped to pay for the trade, the most the taker is willing to pay. The
+        /// taker needs them on top of the rent of the wrapped SOL account
+        max_payment: u64,
+        /// The secret whose SHA-256 hash locks the escrow, only needed for hash locked escrows.
+        /// Packed as a flag byte followed by the 32 bytes when the flag is 1
+        preimage: Option<[u8; 32]>,
+        /// The siblings on the path from the taker's leaf to the allowlist root, only needed for
+        /// escrows started with `taker_merkle_root`. Packed as a u8 count followed by the hashes
+        merkle_proof: Vec<[u8; 32]>,
+    },
+
+    /// Runs the checks of `Exchange` without moving any token or changing the escrow, so a wallet
+    /// can tell whether the trade would go through, and why not, before the taker signs it. It
+    /// fails with the error the trade would fail with
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// The same accounts as for `Exchange`
+    SimulateExchange {
+        /// The amount of the deposit the taker would take, paying the pro rata share of the
+        /// expected amount. 0 means all of it
+        amount: u64,
+        /// The secret the taker would unlock a hash locked escrow with, packed like `Exchange`'s
+        preimage: Option<[u8; 32]>,
+        /// The allowlist proof the taker would trade with, packed like `Exchange`'s
+        merkle_proof: Vec<[u8; 32]>,
+    },
+
+    /// Creates the config PDA holding the fee rates, the signer becoming its admin. Until it is
+    /// created trades pay the default rates, it is meant to be created right after deployment
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin, pays the rent of the config account
+    /// 1. `[writable]` The config account, the PDA of the `config` seed
+    /// 2. `[]` The system program
+    InitializeConfig {
+        /// Share of the payment taken out of what the initializer receives below the first fee
+        /// tier, in basis points
+        maker_fee_bps: u16,
+        /// Share of the payment the taker pays on top of it, in basis points
+        taker_fee_bps: u16,
+        /// Up to four maker fee rates by strictly ascending threshold, a trade pays the rate of
+        /// the tier with the largest threshold not above its payment. Packed as a u8 count
+        /// followed by the u64 threshold and the u16 rate of each tier
+        fee_tiers: Vec<FeeTier>,
+    },
+
+    /// Replaces the fee rates of the config, taking effect from the next trade
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    SetFeeSchedule {
+        /// Same as for `InitializeConfig`
+        maker_fee_bps: u16,
+        /// Same as for `InitializeConfig`
+        taker_fee_bps: u16,
+        /// Same as for `InitializeConfig`
+        fee_tiers: Vec<FeeTier>,
+    },
+
+    /// Lets a pubkey trade without fees, such as a market maker. A trade pays no fee at all when
+    /// its initializer is exempt as maker or its taker is exempt as taker. Adding a pubkey that
+    /// is exempt already replaces its flags
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The pubkey to exempt
+    AddFeeExemption {
+        /// Trades of escrows it initializes are fee free
+        as_maker: bool,
+        /// Trades it takes are fee free
+        as_taker: bool,
+    },
+
+    /// Makes a pubkey exempted with `AddFeeExemption` pay fees again
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The exempt pubkey
+    RemoveFeeExemption,
+
+    /// Sends fees collected in one of the fee collector's token accounts to a token account of
+    /// the admin's choice
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[]` The config account
+    /// 2. `[writable]` The fee collector's token account to withdraw from, owned by the fee collector PDA
+    /// 3. `[writable]` The token account receiving the fees, for the same mint
+    /// 4. `[]` The token program owning both token accounts
+    /// 5. `[]` The fee collector PDA
+    WithdrawFees {
+        /// The amount withdrawn, `u64::MAX` withdraws the whole balance
+        amount: u64,
+    },
+
+    /// Sets the flat fee `InitEscrow` charges, making spam escrows costly. The fee is paid in
+    /// lamports to the fee collector PDA
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    SetCreationFee {
+        /// The fee in lamports, 0 lets escrows be created for free
+        lamports: u64,
+    },
+
+    /// Sends creation fees collected by the fee collector PDA to an account of the admin's choice
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[]` The config account
+    /// 2. `[writable]` The fee collector PDA
+    /// 3. `[writable]` The account receiving the lamports
+    /// 4. `[]` The system program
+    WithdrawCreationFees {
+        /// The lamports withdrawn, `u64::MAX` withdraws the whole balance
+        amount: u64,
+    },
+
+    /// Starts an escrow whose initializer pays a penalty to the fee collector when they cancel it
+    /// before `no_penalty_after_slot`, from then on `CancelEscrow` is free
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitWithCancelPenalty {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+        /// The slot from which cancelling is free, it can't be 0
+        no_penalty_after_slot: u64,
+        /// Lamports the initializer pays on an early cancel, on top of the `penalty_bps` share
+        penalty_lamports: u64,
+        /// Share of the remaining deposit kept on an early cancel, in basis points. It rounds down
+        /// and at least one of it and `penalty_lamports` has to be set
+        penalty_bps: u16,
+    },
+
+    /// Updates the settings of the config besides its fees, see `SetFeeSchedule` and
+    /// `SetCreationFee` for those
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    SetConfig {
+        /// Whether `InitEscrow` refuses new escrows, existing escrows can still be taken or cancelled
+        paused: bool,
+        /// The pubkey taking over as admin, the admin stays the same when it's not set. Packed
+        /// as a flag byte followed by the pubkey when the flag is 1
+        new_admin: Option<Pubkey>,
+    },
+
+    /// Starts an escrow whose payment may be of the same mint as the deposit, e.g. to sell a
+    /// token at a discount. `InitEscrow` refuses such escrows, here only the amounts have to differ
+    ///
+    ///
+    /// Accounts expected are the same as for `InitEscrow`
+    InitSameMint {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+    },
+
+    /// Takes an escrow off the market or puts it back, without touching its deposit. While it's
+    /// inactive it can't be traded, but it can still be topped up and cancelled
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The escrow account holding the escrow info
+    SetEscrowActive {
+        /// Whether the escrow can be traded
+        active: bool,
+    },
+
+    /// Lists a mint `InitEscrow` accepts. Once a mint is listed, escrows whose deposit or payment
+    /// is of an unlisted mint are refused, while the list is empty any mint is accepted. Adding a
+    /// mint that is listed already does nothing
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The mint to allow
+    AddAllowedMint,
+
+    /// Unlists a mint added with `AddAllowedMint`, escrows already started with it are kept
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin of the config
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The allowed mint
+    RemoveAllowedMint,
+
+    /// Grows a config packed with an older layout to `Config::LEN`, the admin paying the rent of
+    /// the added bytes. Trades can read a config of any layout, but the admin instructions only
+    /// change a migrated one
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin of the config
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The system program
+    MigrateConfig,
+}
+
+/// One escrow of `InitEscrowBatch`
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchEntry {
+    /// The amount party A expects to receive of token Y
+    pub amount: u64,
+    /// Picks the escrow's PDA among the initializer's, see [escrow_pda](../processor/fn.escrow_pda.html)
+    pub nonce: u64,
 }
 
 impl EscrowInstruction {
@@ -46,23 +1215,921 @@ impl EscrowInstruction {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
 
         Ok(match tag {
-            0 => Self::InitEscrow {
-                amount: Self::unpack_amount(rest)?,
-            },
+            0 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (expiry_slot, rest) = Self::unpack_optional_u64(rest)?;
+                let (expiry_unix_timestamp, rest) = Self::unpack_optional_u64(rest)?;
+                let (allowed_taker, rest) = Self::unpack_optional_pubkey(rest)?;
+                let (two_phase, rest) = Self::unpack_optional_bool(rest)?;
+                let (min_taker_amount, rest) = Self::unpack_optional_u64(rest)?;
+                let (nonce, rest) = Self::unpack_optional_u64(rest)?;
+                let (arbiter, rest) = Self::unpack_optional_pubkey(rest)?;
+                let (receipt, rest) = Self::unpack_optional_bool(rest)?;
+                let (position, rest) = Self::unpack_optional_bool(rest)?;
+                let (taker_merkle_root, rest) = Self::unpack_optional_bytes32(rest)?;
+                let (gate_mint, rest) = Self::unpack_optional_pubkey(rest)?;
+                let (gate_min_amount, rest) = Self::unpack_optional_u64(rest)?;
+                let (gatekeeper_network, rest) = Self::unpack_optional_pubkey(rest)?;
+                let (memo_required, rest) = Self::unpack_optional_bool(rest)?;
+                let (royalty_policy, rest) = match rest.split_first() {
+                    None => (RoyaltyPolicy::None, rest),
+                    Some((&0, rest)) => (RoyaltyPolicy::None, rest),
+                    Some((&1, rest)) => (RoyaltyPolicy::BestEffort, rest),
+                    Some((&2, rest)) => (RoyaltyPolicy::Enforced, rest),
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let (nft, rest) = Self::unpack_optional_bool(rest)?;
+                let (unwrap_sol, _rest) = Self::unpack_optional_bool(rest)?;
+                Self::InitEscrow {
+                    amount,
+                    expiry_slot,
+                    expiry_unix_timestamp: expiry_unix_timestamp as i64,
+                    allowed_taker,
+                    two_phase,
+                    min_taker_amount,
+                    nonce,
+                    arbiter,
+                    receipt,
+                    position,
+                    taker_merkle_root,
+                    gate_mint,
+                    gate_min_amount,
+                    gatekeeper_network,
+                    memo_required,
+                    royalty_policy,
+                    nft,
+                    unwrap_sol,
+                }
+            }
+
+            1 => {
+                let (max_payment, rest) = Self::unpack_optional_u64(rest)?;
+                let (preimage, rest) = Self::unpack_optional_bytes32(rest)?;
+                Self::Exchange {
+                    max_payment,
+                    preimage,
+                    merkle_proof: Self::unpack_hashes(rest)?,
+                }
+            }
 
-            1 => Self::Exchange {
-                amount: Self::unpack_amount(rest)?,
+            2 => Self::CancelEscrow,
+            3 => Self::RefundExpired,
+            4 => Self::ProposeCounterOffer {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            5 => Self::AcceptCounterOffer,
+            6 => {
+                let (amount_to_take, rest) = Self::unpack_u64(rest)?;
+                let (amount_to_give, _rest) = Self::unpack_u64(rest)?;
+                Self::PartialExchange {
+                    amount_to_take,
+                    amount_to_give,
+                }
+            }
+            7 => Self::DepositForExchange,
+            8 => Self::ConfirmExchange,
+            9 => Self::RejectExchange,
+            10 => Self::UpdateExpectedAmount {
+                new_amount: Self::unpack_u64(rest)?.0,
+            },
+            11 => Self::TopUp {
+                additional_amount: Self::unpack_u64(rest)?.0,
+            },
+            12 => Self::MutualCancel,
+            13 => Self::ExtendExpiry {
+                new_expiry_slot: Self::unpack_u64(rest)?.0,
+            },
+            14 => {
+                let (new_expected_amount, rest) = Self::unpack_u64(rest)?;
+                let (new_expiry, _rest) = Self::unpack_u64(rest)?;
+                Self::Replace {
+                    new_expected_amount,
+                    new_expiry,
+                }
+            }
+            15 => Self::SplitEscrow {
+                split_deposit_amount: Self::unpack_u64(rest)?.0,
             },
+            16 => Self::MergeEscrows,
+            17 => Self::MatchEscrows,
+            18 => Self::ExchangeBatch {
+                count: *rest.first().ok_or(InvalidInstruction)?,
+            },
+            19 => Self::InitEscrowBatch {
+                entries: Self::unpack_batch_entries(rest)?,
+            },
+            20 => {
+                let (start_amount, rest) = Self::unpack_u64(rest)?;
+                let (end_amount, rest) = Self::unpack_u64(rest)?;
+                let (start_slot, rest) = Self::unpack_u64(rest)?;
+                let (end_slot, _rest) = Self::unpack_u64(rest)?;
+                Self::InitDutchAuction {
+                    start_amount,
+                    end_amount,
+                    start_slot,
+                    end_slot,
+                }
+            }
+            21 => {
+                let (reserve_amount, rest) = Self::unpack_u64(rest)?;
+                let (end_slot, _rest) = Self::unpack_u64(rest)?;
+                Self::InitBidAuction {
+                    reserve_amount,
+                    end_slot,
+                }
+            }
+            22 => Self::PlaceBid {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            23 => Self::AcceptBid,
+            24 => Self::WithdrawBid,
+            25 => {
+                let (price_numerator, rest) = Self::unpack_u64(rest)?;
+                let (price_denominator, _rest) = Self::unpack_u64(rest)?;
+                Self::InitRatePriced {
+                    price_numerator,
+                    price_denominator,
+                }
+            }
+            26 => Self::InitBasketPayment {
+                amounts: Self::unpack_amounts(rest)?,
+            },
+            27 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let hash = rest
+                    .get(..32)
+                    .and_then(|slice| slice.try_into().ok())
+                    .ok_or(InvalidInstruction)?;
+                let (timeout_slot, _rest) = Self::unpack_u64(&rest[32..])?;
+                Self::InitHashLock {
+                    amount,
+                    hash,
+                    timeout_slot,
+                }
+            }
+            28 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let max_deviation_bps = rest
+                    .get(..2)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(u16::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let (max_staleness, rest) = Self::unpack_u64(&rest[2..])?;
+                let oracle_kind = match rest.first() {
+                    None | Some(0) => OracleKind::Pyth,
+                    Some(1) => OracleKind::Switchboard,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::InitOracleBand {
+                    amount,
+                    max_deviation_bps,
+                    max_staleness: max_staleness as i64,
+                    oracle_kind,
+                }
+            }
+            29 => {
+                let (sol_amount, rest) = Self::unpack_u64(rest)?;
+                let (nonce, _) = Self::unpack_optional_u64(rest)?;
+                Self::InitEscrowForSol { sol_amount, nonce }
+            }
+            30 => Self::ExchangeForSol,
+            31 => Self::RaiseDispute,
+            32 => Self::ResolveDispute {
+                release_to_taker: match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                },
+            },
+            33 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (cliff_slot, rest) = Self::unpack_u64(rest)?;
+                let (end_slot, _rest) = Self::unpack_u64(rest)?;
+                Self::InitVesting {
+                    amount,
+                    cliff_slot,
+                    end_slot,
+                }
+            }
+            34 => Self::ClaimVested,
+            35 => Self::InitStream {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            36 => Self::StreamPayment {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            37 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (expiry_slot, _rest) = Self::unpack_u64(rest)?;
+                Self::InitCrowdFill {
+                    amount,
+                    expiry_slot,
+                }
+            }
+            38 => Self::Contribute {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            39 => Self::Settle,
+            40 => Self::ClaimShare,
+            41 => Self::RefundContribution,
+            42 => Self::TransferInitializer,
+            43 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                Self::InitSplitPayment {
+                    amount,
+                    basis_points: Self::unpack_basis_points(rest)?,
+                }
+            }
+            44 => {
+                let (max_payment, rest) = Self::unpack_optional_u64(rest)?;
+                let (preimage, rest) = Self::unpack_optional_bytes32(rest)?;
+                Self::ExchangeWithAtaCreation {
+                    max_payment,
+                    preimage,
+                    merkle_proof: Self::unpack_hashes(rest)?,
+                }
+            }
+            45 => {
+                let (max_payment, rest) = Self::unpack_u64(rest)?;
+                let (preimage, rest) = Self::unpack_optional_bytes32(rest)?;
+                Self::ExchangeWithSol {
+                    max_payment,
+                    preimage,
+                    merkle_proof: Self::unpack_hashes(rest)?,
+                }
+            }
+            46 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (preimage, rest) = Self::unpack_optional_bytes32(rest)?;
+                Self::SimulateExchange {
+                    amount,
+                    preimage,
+                    merkle_proof: Self::unpack_hashes(rest)?,
+                }
+            }
+            47 => {
+                let (maker_fee_bps, taker_fee_bps, fee_tiers) = Self::unpack_fee_schedule(rest)?;
+                Self::InitializeConfig {
+                    maker_fee_bps,
+                    taker_fee_bps,
+                    fee_tiers,
+                }
+            }
+            48 => {
+                let (maker_fee_bps, taker_fee_bps, fee_tiers) = Self::unpack_fee_schedule(rest)?;
+                Self::SetFeeSchedule {
+                    maker_fee_bps,
+                    taker_fee_bps,
+                    fee_tiers,
+                }
+            }
+            49 => match rest {
+                [as_maker @ (0 | 1), as_taker @ (0 | 1), ..] => Self::AddFeeExemption {
+                    as_maker: *as_maker == 1,
+                    as_taker: *as_taker == 1,
+                },
+                _ => return Err(InvalidInstruction.into()),
+            },
+            50 => Self::RemoveFeeExemption,
+            51 => Self::WithdrawFees {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            52 => Self::SetCreationFee {
+                lamports: Self::unpack_u64(rest)?.0,
+            },
+            53 => Self::WithdrawCreationFees {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            54 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (no_penalty_after_slot, rest) = Self::unpack_u64(rest)?;
+                let (penalty_lamports, rest) = Self::unpack_u64(rest)?;
+                let penalty_bps = rest
+                    .get(..2)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(u16::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::InitWithCancelPenalty {
+                    amount,
+                    no_penalty_after_slot,
+                    penalty_lamports,
+                    penalty_bps,
+                }
+            }
+            55 => {
+                let (paused, rest) = match rest.split_first() {
+                    Some((&0, rest)) => (false, rest),
+                    Some((&1, rest)) => (true, rest),
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let (new_admin, _rest) = Self::unpack_optional_pubkey(rest)?;
+                Self::SetConfig { paused, new_admin }
+            }
+            56 => Self::InitSameMint {
+                amount: Self::unpack_u64(rest)?.0,
+            },
+            57 => Self::SetEscrowActive {
+                active: match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                },
+            },
+            58 => Self::AddAllowedMint,
+            59 => Self::RemoveAllowedMint,
+            60 => Self::MigrateConfig,
             _ => return Err(InvalidInstruction.into()),
         })
     }
 
-    fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
-        let amount = input
+    /// Unpacks a Borsh encoded byte buffer into a [EscrowInstruction](enum.EscrowInstruction.html).
+    #[cfg(feature = "borsh")]
+    pub fn unpack_borsh(input: &[u8]) -> Result<Self, ProgramError> {
+        Self::try_from_slice(input).map_err(|_| InvalidInstruction.into())
+    }
+
+    /// Packs a [EscrowInstruction](enum.EscrowInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>());
+        match self {
+            Self::InitEscrow {
+                amount,
+                expiry_slot,
+                expiry_unix_timestamp,
+                allowed_taker,
+                two_phase,
+                min_taker_amount,
+                nonce,
+                arbiter,
+                receipt,
+                position,
+                taker_merkle_root,
+                gate_mint,
+                gate_min_amount,
+                gatekeeper_network,
+                memo_required,
+                royalty_policy,
+                nft,
+                unwrap_sol,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&expiry_slot.to_le_bytes());
+                buf.extend_from_slice(&expiry_unix_timestamp.to_le_bytes());
+                Self::pack_pubkey_option(allowed_taker, &mut buf);
+                buf.push(*two_phase as u8);
+                buf.extend_from_slice(&min_taker_amount.to_le_bytes());
+                buf.extend_from_slice(&nonce.to_le_bytes());
+                Self::pack_pubkey_option(arbiter, &mut buf);
+                buf.push(*receipt as u8);
+                buf.push(*position as u8);
+                Self::pack_bytes32_option(taker_merkle_root, &mut buf);
+                Self::pack_pubkey_option(gate_mint, &mut buf);
+                buf.extend_from_slice(&gate_min_amount.to_le_bytes());
+                Self::pack_pubkey_option(gatekeeper_network, &mut buf);
+                buf.push(*memo_required as u8);
+                buf.push(*royalty_policy as u8);
+                buf.push(*nft as u8);
+                buf.push(*unwrap_sol as u8);
+            }
+            Self::Exchange {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(&max_payment.to_le_bytes());
+                Self::pack_bytes32_option(preimage, &mut buf);
+                buf.push(merkle_proof.len() as u8);
+                for hash in merkle_proof {
+                    buf.extend_from_slice(hash);
+                }
+            }
+            Self::CancelEscrow => buf.push(2),
+            Self::RefundExpired => buf.push(3),
+            Self::ProposeCounterOffer { amount } => {
+                buf.push(4);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::AcceptCounterOffer => buf.push(5),
+            Self::PartialExchange {
+                amount_to_take,
+                amount_to_give,
+            } => {
+                buf.push(6);
+                buf.extend_from_slice(&amount_to_take.to_le_bytes());
+                buf.extend_from_slice(&amount_to_give.to_le_bytes());
+            }
+            Self::DepositForExchange => buf.push(7),
+            Self::ConfirmExchange => buf.push(8),
+            Self::RejectExchange => buf.push(9),
+            Self::UpdateExpectedAmount { new_amount } => {
+                buf.push(10);
+                buf.extend_from_slice(&new_amount.to_le_bytes());
+            }
+            Self::TopUp { additional_amount } => {
+                buf.push(11);
+                buf.extend_from_slice(&additional_amount.to_le_bytes());
+            }
+            Self::MutualCancel => buf.push(12),
+            Self::ExtendExpiry { new_expiry_slot } => {
+                buf.push(13);
+                buf.extend_from_slice(&new_expiry_slot.to_le_bytes());
+            }
+            Self::Replace {
+                new_expected_amount,
+                new_expiry,
+            } => {
+                buf.push(14);
+                buf.extend_from_slice(&new_expected_amount.to_le_bytes());
+                buf.extend_from_slice(&new_expiry.to_le_bytes());
+            }
+            Self::SplitEscrow {
+                split_deposit_amount,
+            } => {
+                buf.push(15);
+                buf.extend_from_slice(&split_deposit_amount.to_le_bytes());
+            }
+            Self::MergeEscrows => buf.push(16),
+            Self::MatchEscrows => buf.push(17),
+            Self::ExchangeBatch { count } => {
+                buf.push(18);
+                buf.push(*count);
+            }
+            Self::InitEscrowBatch { entries } => {
+                buf.push(19);
+                buf.push(entries.len() as u8);
+                for entry in entries {
+                    buf.extend_from_slice(&entry.amount.to_le_bytes());
+                    buf.extend_from_slice(&entry.nonce.to_le_bytes());
+                }
+            }
+            Self::InitDutchAuction {
+                start_amount,
+                end_amount,
+                start_slot,
+                end_slot,
+            } => {
+                buf.push(20);
+                buf.extend_from_slice(&start_amount.to_le_bytes());
+                buf.extend_from_slice(&end_amount.to_le_bytes());
+                buf.extend_from_slice(&start_slot.to_le_bytes());
+                buf.extend_from_slice(&end_slot.to_le_bytes());
+            }
+            Self::InitBidAuction {
+                reserve_amount,
+                end_slot,
+            } => {
+                buf.push(21);
+                buf.extend_from_slice(&reserve_amount.to_le_bytes());
+                buf.extend_from_slice(&end_slot.to_le_bytes());
+            }
+            Self::PlaceBid { amount } => {
+                buf.push(22);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::AcceptBid => buf.push(23),
+            Self::WithdrawBid => buf.push(24),
+            Self::InitRatePriced {
+                price_numerator,
+                price_denominator,
+            } => {
+                buf.push(25);
+                buf.extend_from_slice(&price_numerator.to_le_bytes());
+                buf.extend_from_slice(&price_denominator.to_le_bytes());
+            }
+            Self::InitBasketPayment { amounts } => {
+                buf.push(26);
+                buf.push(amounts.len() as u8);
+                for amount in amounts {
+                    buf.extend_from_slice(&amount.to_le_bytes());
+                }
+            }
+            Self::InitHashLock {
+                amount,
+                hash,
+                timeout_slot,
+            } => {
+                buf.push(27);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(hash);
+                buf.extend_from_slice(&timeout_slot.to_le_bytes());
+            }
+            Self::InitOracleBand {
+                amount,
+                max_deviation_bps,
+                max_staleness,
+                oracle_kind,
+            } => {
+                buf.push(28);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&max_deviation_bps.to_le_bytes());
+                buf.extend_from_slice(&max_staleness.to_le_bytes());
+                buf.push(*oracle_kind as u8);
+            }
+            Self::InitEscrowForSol { sol_amount, nonce } => {
+                buf.push(29);
+                buf.extend_from_slice(&sol_amount.to_le_bytes());
+                buf.extend_from_slice(&nonce.to_le_bytes());
+            }
+            Self::ExchangeForSol => buf.push(30),
+            Self::RaiseDispute => buf.push(31),
+            Self::ResolveDispute { release_to_taker } => {
+                buf.push(32);
+                buf.push(*release_to_taker as u8);
+            }
+            Self::InitVesting {
+                amount,
+                cliff_slot,
+                end_slot,
+            } => {
+                buf.push(33);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&cliff_slot.to_le_bytes());
+                buf.extend_from_slice(&end_slot.to_le_bytes());
+            }
+            Self::ClaimVested => buf.push(34),
+            Self::InitStream { amount } => {
+                buf.push(35);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::StreamPayment { amount } => {
+                buf.push(36);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::InitCrowdFill {
+                amount,
+                expiry_slot,
+            } => {
+                buf.push(37);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&expiry_slot.to_le_bytes());
+            }
+            Self::Contribute { amount } => {
+                buf.push(38);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Settle => buf.push(39),
+            Self::ClaimShare => buf.push(40),
+            Self::RefundContribution => buf.push(41),
+            Self::TransferInitializer => buf.push(42),
+            Self::InitSplitPayment {
+                amount,
+                basis_points,
+            } => {
+                buf.push(43);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.push(basis_points.len() as u8);
+                for share in basis_points {
+                    buf.extend_from_slice(&share.to_le_bytes());
+                }
+            }
+            Self::ExchangeWithAtaCreation {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                buf.push(44);
+                buf.extend_from_slice(&max_payment.to_le_bytes());
+                Self::pack_bytes32_option(preimage, &mut buf);
+                buf.push(merkle_proof.len() as u8);
+                for hash in merkle_proof {
+                    buf.extend_from_slice(hash);
+                }
+            }
+            Self::ExchangeWithSol {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                buf.push(45);
+                buf.extend_from_slice(&max_payment.to_le_bytes());
+                Self::pack_bytes32_option(preimage, &mut buf);
+                buf.push(merkle_proof.len() as u8);
+                for hash in merkle_proof {
+                    buf.extend_from_slice(hash);
+                }
+            }
+            Self::SimulateExchange {
+                amount,
+                preimage,
+                merkle_proof,
+            } => {
+                buf.push(46);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                Self::pack_bytes32_option(preimage, &mut buf);
+                buf.push(merkle_proof.len() as u8);
+                for hash in merkle_proof {
+                    buf.extend_from_slice(hash);
+                }
+            }
+            Self::InitializeConfig {
+                maker_fee_bps,
+                taker_fee_bps,
+                fee_tiers,
+            } => {
+                buf.push(47);
+                Self::pack_fee_schedule(*maker_fee_bps, *taker_fee_bps, fee_tiers, &mut buf);
+            }
+            Self::SetFeeSchedule {
+                maker_fee_bps,
+                taker_fee_bps,
+                fee_tiers,
+            } => {
+                buf.push(48);
+                Self::pack_fee_schedule(*maker_fee_bps, *taker_fee_bps, fee_tiers, &mut buf);
+            }
+            Self::AddFeeExemption { as_maker, as_taker } => {
+                buf.push(49);
+                buf.push(*as_maker as u8);
+                buf.push(*as_taker as u8);
+            }
+            Self::RemoveFeeExemption => buf.push(50),
+            Self::WithdrawFees { amount } => {
+                buf.push(51);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetCreationFee { lamports } => {
+                buf.push(52);
+                buf.extend_from_slice(&lamports.to_le_bytes());
+            }
+            Self::WithdrawCreationFees { amount } => {
+                buf.push(53);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::InitWithCancelPenalty {
+                amount,
+                no_penalty_after_slot,
+                penalty_lamports,
+                penalty_bps,
+            } => {
+                buf.push(54);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&no_penalty_after_slot.to_le_bytes());
+                buf.extend_from_slice(&penalty_lamports.to_le_bytes());
+                buf.extend_from_slice(&penalty_bps.to_le_bytes());
+            }
+            Self::SetConfig { paused, new_admin } => {
+                buf.push(55);
+                buf.push(*paused as u8);
+                Self::pack_pubkey_option(new_admin, &mut buf);
+            }
+            Self::InitSameMint { amount } => {
+                buf.push(56);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetEscrowActive { active } => {
+                buf.push(57);
+                buf.push(*active as u8);
+            }
+            Self::AddAllowedMint => buf.push(58),
+            Self::RemoveAllowedMint => buf.push(59),
+            Self::MigrateConfig => buf.push(60),
+        };
+        buf
+    }
+
+    fn pack_pubkey_option(value: &Option<Pubkey>, buf: &mut Vec<u8>) {
+        Self::pack_bytes32_option(&value.map(|key| key.to_bytes()), buf);
+    }
+
+    fn pack_bytes32_option(value: &Option<[u8; 32]>, buf: &mut Vec<u8>) {
+        match value {
+            Some(bytes) => {
+                buf.push(1);
+                buf.extend_from_slice(bytes);
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn pack_fee_schedule(
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_tiers: &[FeeTier],
+        buf: &mut Vec<u8>,
+    ) {
+        buf.extend_from_slice(&maker_fee_bps.to_le_bytes());
+        buf.extend_from_slice(&taker_fee_bps.to_le_bytes());
+        buf.push(fee_tiers.len() as u8);
+        for tier in fee_tiers {
+            buf.extend_from_slice(&tier.threshold.to_le_bytes());
+            buf.extend_from_slice(&tier.basis_points.to_le_bytes());
+        }
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        let value = input
             .get(..8)
             .and_then(|slice| slice.try_into().ok())
             .map(u64::from_le_bytes)
             .ok_or(InvalidInstruction)?;
-        Ok(amount)
+        Ok((value, &input[8..]))
+    }
+
+    /// Unpacks a u8 count followed by that many u64 amounts
+    fn unpack_amounts(input: &[u8]) -> Result<Vec<u64>, ProgramError> {
+        let (&count, mut rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let mut amounts = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (amount, next) = Self::unpack_u64(rest)?;
+            amounts.push(amount);
+            rest = next;
+        }
+        Ok(amounts)
+    }
+
+    /// Unpacks a u8 count followed by that many amount and nonce pairs
+    fn unpack_batch_entries(input: &[u8]) -> Result<Vec<BatchEntry>, ProgramError> {
+        let (&count, mut rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (amount, next) = Self::unpack_u64(rest)?;
+            let (nonce, next) = Self::unpack_u64(next)?;
+            entries.push(BatchEntry { amount, nonce });
+            rest = next;
+        }
+        Ok(entries)
+    }
+
+    /// Unpacks a u8 count followed by that many u16 basis points
+    fn unpack_basis_points(input: &[u8]) -> Result<Vec<u16>, ProgramError> {
+        let (&count, rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let shares = rest.get(..count as usize * 2).ok_or(InvalidInstruction)?;
+        Ok(shares
+            .chunks_exact(2)
+            .map(|share| u16::from_le_bytes([share[0], share[1]]))
+            .collect())
+    }
+
+    /// Unpacks the maker and taker rates followed by a u8 count and that many fee tiers
+    fn unpack_fee_schedule(input: &[u8]) -> Result<(u16, u16, Vec<FeeTier>), ProgramError> {
+        let rates = input.get(..5).ok_or(InvalidInstruction)?;
+        let maker_fee_bps = u16::from_le_bytes([rates[0], rates[1]]);
+        let taker_fee_bps = u16::from_le_bytes([rates[2], rates[3]]);
+        let tiers = input
+            .get(5..5 + rates[4] as usize * 10)
+            .ok_or(InvalidInstruction)?;
+        let fee_tiers = tiers
+            .chunks_exact(10)
+            .map(|tier| {
+                let (threshold, basis_points) = Self::unpack_u64(tier)?;
+                Ok(FeeTier {
+                    threshold,
+                    basis_points: u16::from_le_bytes([basis_points[0], basis_points[1]]),
+                })
+            })
+            .collect::<Result<_, ProgramError>>()?;
+        Ok((maker_fee_bps, taker_fee_bps, fee_tiers))
+    }
+
+    /// Unpacks a u8 count followed by that many 32 byte hashes, no hashes when it's missing
+    fn unpack_hashes(input: &[u8]) -> Result<Vec<[u8; 32]>, ProgramError> {
+        let (&count, rest) = match input.split_first() {
+            Some(split) => split,
+            None => return Ok(Vec::new()),
+        };
+        let hashes = rest.get(..count as usize * 32).ok_or(InvalidInstruction)?;
+        hashes
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().map_err(|_| InvalidInstruction.into()))
+            .collect()
+    }
+
+    /// Fields appended after the first release are optional so older clients keep working,
+    /// a missing value unpacks as 0.
+    fn unpack_optional_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.is_empty() {
+            return Ok((0, input));
+        }
+        Self::unpack_u64(input)
+    }
+
+    fn unpack_optional_bool(input: &[u8]) -> Result<(bool, &[u8]), ProgramError> {
+        match input.split_first() {
+            None => Ok((false, input)),
+            Some((&0, rest)) => Ok((false, rest)),
+            Some((&1, rest)) => Ok((true, rest)),
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_optional_pubkey(input: &[u8]) -> Result<(Option<Pubkey>, &[u8]), ProgramError> {
+        let (bytes, rest) = Self::unpack_optional_bytes32(input)?;
+        Ok((bytes.map(Pubkey::new_from_array), rest))
+    }
+
+    fn unpack_optional_bytes32(input: &[u8]) -> Result<(Option<[u8; 32]>, &[u8]), ProgramError> {
+        match input.split_first() {
+            None => Ok((None, input)),
+            Some((&0, rest)) => Ok((None, rest)),
+            Some((&1, rest)) if rest.len() >= 32 => {
+                let (bytes, rest) = rest.split_at(32);
+                Ok((bytes.try_into().ok(), rest))
+            }
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+}
+
+/// Creates an `InitEscrow` instruction for an escrow that never expires and can be taken by anyone
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    temp_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let data = EscrowInstruction::InitEscrow {
+        amount,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new(*temp_token_account, false),
+        AccountMeta::new_readonly(*token_to_receive_account, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+        AccountMeta::new(fee_collector_pda(program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an `Exchange` instruction taking the whole remaining deposit, with its eleven accounts
+/// in the order `Exchange` expects them. The PDA is derived from `initializer` and the escrow's
+/// `nonce`, see [escrow_pda](../processor/fn.escrow_pda.html), the token program is the classic
+/// one and the fee collector token account is the fee collector's associated token account for
+/// `receive_mint`, the mint the initializer receives. Escrows of Token-2022, fee collector token
+/// accounts that aren't associated ones and position escrows, whose PDA stays the one of the
+/// original initializer, need those accounts replaced
+#[allow(clippy::too_many_arguments)]
+pub fn exchange(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    taker_token_to_send_account: &Pubkey,
+    taker_token_to_receive_account: &Pubkey,
+    pdas_temp_token_account: &Pubkey,
+    initializer: &Pubkey,
+    initializer_token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    nonce: u64,
+    receive_mint: &Pubkey,
+) -> Instruction {
+    let data = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: None,
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*taker, true),
+        AccountMeta::new(*taker_token_to_send_account, false),
+        AccountMeta::new(*taker_token_to_receive_account, false),
+        AccountMeta::new(*pdas_temp_token_account, false),
+        AccountMeta::new(*initializer, false),
+        AccountMeta::new(*initializer_token_to_receive_account, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(escrow_pda(program_id, initializer, nonce).0, false),
+        AccountMeta::new(
+            get_associated_token_address(&fee_collector_pda(program_id).0, receive_mint),
+            false,
+        ),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
     }
 }
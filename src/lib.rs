@@ -1,5 +1,47 @@
+pub mod constants;
+pub mod cpi;
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
 pub mod error;
+pub mod events;
+pub mod gateway;
 pub mod instruction;
+pub mod math;
+pub mod memo;
+pub mod merkle;
+pub mod metadata;
+pub mod oracle;
 pub mod processor;
 pub mod state;
+pub mod token;
+
+use solana_program::{program_error::ProgramError, program_pack::Pack};
+
+pub use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+
+// the address the program is deployed at, so clients can build instructions with `id()`
+solana_program::declare_id!("ZrovYsompC1wJjJunvPmtgvEuvvya6RMg3pRVvWn7RE");
+
+/// Decodes the data of an escrow account, as returned by `get_account_data`, without going
+/// through the `state` module. Fails for accounts that aren't initialized escrows
+///
+/// ```
+/// use bpf_program_template::{
+///     state::{Escrow, EscrowState},
+///     unpack_escrow,
+/// };
+/// use solana_program::program_pack::Pack;
+///
+/// let mut escrow = Escrow::unpack_unchecked(&[0; Escrow::LEN]).unwrap();
+/// escrow.state = EscrowState::Active;
+/// escrow.expected_amount = 10_000;
+/// let mut data = vec![0; Escrow::LEN];
+/// Escrow::pack(escrow, &mut data).unwrap();
+///
+/// let escrow = unpack_escrow(&data).unwrap();
+/// assert_eq!(escrow.expected_amount, 10_000);
+/// assert!(unpack_escrow(&[0; Escrow::LEN]).is_err());
+/// ```
+pub fn unpack_escrow(data: &[u8]) -> Result<Escrow, ProgramError> {
+    Escrow::unpack(data)
+}
@@ -0,0 +1,78 @@
+//! Checked arithmetic for token amounts, any overflow is reported as `AmountOverFlow`.
+
+use crate::error::EscrowError;
+
+/// Share of `amount` worth `bps` basis points, rounded down in favor of the payer
+pub fn checked_fee(amount: u64, bps: u16) -> Result<u64, EscrowError> {
+    let fee = amount as u128 * bps as u128 / 10_000;
+    u64::try_from(fee).map_err(|_| EscrowError::AmountOverFlow)
+}
+
+/// What is left of `amount` once `part` of it is taken out
+pub fn checked_remainder(amount: u64, part: u64) -> Result<u64, EscrowError> {
+    amount.checked_sub(part).ok_or(EscrowError::AmountOverFlow)
+}
+
+/// Price of `amount` at `numerator / denominator`, rounded up in favor of the payee
+pub fn checked_rate_payment(
+    amount: u64,
+    numerator: u64,
+    denominator: u64,
+) -> Result<u64, EscrowError> {
+    if denominator == 0 {
+        return Err(EscrowError::InvalidAmount);
+    }
+
+    let numerator = amount as u128 * numerator as u128;
+    let denominator = denominator as u128;
+
+    let mut payment = numerator / denominator;
+    if payment * denominator < numerator {
+        payment += 1;
+    }
+    u64::try_from(payment).map_err(|_| EscrowError::AmountOverFlow)
+}
+
+/// How a trade's payment is shared out between the initializer and the fee collector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TradeFees {
+    /// The headline price the taker pays for what they take
+    pub payment: u64,
+    /// Taken out of what the initializer receives
+    pub maker_fee: u64,
+    /// Paid by the taker on top of the payment
+    pub taker_fee: u64,
+}
+
+impl TradeFees {
+    pub fn checked_new(
+        payment: u64,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+    ) -> Result<Self, EscrowError> {
+        Ok(TradeFees {
+            payment,
+            maker_fee: checked_fee(payment, maker_fee_bps)?,
+            taker_fee: checked_fee(payment, taker_fee_bps)?,
+        })
+    }
+
+    /// What the initializer ends up with
+    pub fn initializer_amount(&self) -> Result<u64, EscrowError> {
+        checked_remainder(self.payment, self.maker_fee)
+    }
+
+    /// What leaves the taker's account
+    pub fn taker_amount(&self) -> Result<u64, EscrowError> {
+        self.payment
+            .checked_add(self.taker_fee)
+            .ok_or(EscrowError::AmountOverFlow)
+    }
+
+    /// What the fee collector receives, both legs in one transfer
+    pub fn collected(&self) -> Result<u64, EscrowError> {
+        self.maker_fee
+            .checked_add(self.taker_fee)
+            .ok_or(EscrowError::AmountOverFlow)
+    }
+}
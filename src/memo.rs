@@ -0,0 +1,44 @@
+//! Finds the spl-memo instruction of a transaction through the instructions sysvar.
+
+use solana_program::{
+    account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey, sysvar::instructions,
+};
+
+/// Id of the current spl-memo program
+pub mod spl_memo {
+    solana_program::declare_id!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+}
+
+/// Id of the first spl-memo program, still accepted by wallets
+pub mod spl_memo_v1 {
+    solana_program::declare_id!("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+}
+
+pub fn is_memo_program(key: &Pubkey) -> bool {
+    *key == spl_memo::id() || *key == spl_memo_v1::id()
+}
+
+/// Returns the memo of the first spl-memo instruction of the transaction, `None` when it has
+/// none. Fails when `instructions_sysvar` isn't the instructions sysvar
+pub fn find_memo(instructions_sysvar: &AccountInfo) -> Result<Option<Vec<u8>>, ProgramError> {
+    if *instructions_sysvar.key != instructions::id() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // the sysvar starts with the number of instructions of the transaction
+    let count = instructions_sysvar
+        .try_borrow_data()?
+        .get(..2)
+        .and_then(|slice| slice.try_into().ok())
+        .map(u16::from_le_bytes)
+        .ok_or(ProgramError::InvalidAccountData)?;
+
+    for index in 0..count {
+        let instruction =
+            instructions::load_instruction_at_checked(index as usize, instructions_sysvar)?;
+        if is_memo_program(&instruction.program_id) {
+            return Ok(Some(instruction.data));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,132 @@
+//! Merkle allowlists of takers, hashed with SHA-256.
+//!
+//! A leaf is the hash of a 0 byte followed by the taker's pubkey and a node is the hash of a 1
+//! byte followed by its two children, the smaller one first. The prefixes keep a node from
+//! passing as a leaf and sorting the children lets a proof be the list of siblings alone.
+
+use solana_program::{hash::hashv, pubkey::Pubkey};
+
+use crate::error::EscrowError;
+
+/// Longest proof accepted, enough for allowlists of 65536 takers
+pub const MAX_PROOF_DEPTH: usize = 16;
+
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+/// Leaf of `key` in the tree
+pub fn leaf(key: &Pubkey) -> [u8; 32] {
+    hashv(&[LEAF_PREFIX, key.as_ref()]).to_bytes()
+}
+
+/// Parent of two nodes of the tree, in either order
+pub fn node(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[NODE_PREFIX, left, right]).to_bytes()
+}
+
+/// Whether `proof`, the siblings on the path from the leaf of `key` up to the root, leads to
+/// `root`. Fails for proofs longer than MAX_PROOF_DEPTH
+pub fn verify(root: &[u8; 32], key: &Pubkey, proof: &[[u8; 32]]) -> Result<bool, EscrowError> {
+    if proof.len() > MAX_PROOF_DEPTH {
+        return Err(EscrowError::MalformedMerkleProof);
+    }
+
+    let computed = proof
+        .iter()
+        .fold(leaf(key), |hash, sibling| node(&hash, sibling));
+    Ok(computed == *root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the tree of `keys`, a node without a sibling moving up a level as is. Returns the
+    /// root and the proof of each key
+    fn tree(keys: &[Pubkey]) -> ([u8; 32], Vec<Vec<[u8; 32]>>) {
+        let mut level: Vec<[u8; 32]> = keys.iter().map(leaf).collect();
+        // index in the current level of the node each key's path goes through
+        let mut positions: Vec<usize> = (0..keys.len()).collect();
+        let mut proofs = vec![Vec::new(); keys.len()];
+
+        while level.len() > 1 {
+            for (proof, position) in proofs.iter_mut().zip(positions.iter_mut()) {
+                if let Some(sibling) = level.get(*position ^ 1) {
+                    proof.push(*sibling);
+                }
+                *position /= 2;
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => node(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        (level[0], proofs)
+    }
+
+    fn keys(count: usize) -> Vec<Pubkey> {
+        (0..count).map(|_| Pubkey::new_unique()).collect()
+    }
+
+    #[test]
+    fn test_members_of_a_small_tree_verify() {
+        for count in [1, 2, 3, 4, 5] {
+            let keys = keys(count);
+            let (root, proofs) = tree(&keys);
+            for (key, proof) in keys.iter().zip(&proofs) {
+                assert_eq!(verify(&root, key, proof), Ok(true));
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_member_is_rejected() {
+        let keys = keys(4);
+        let (root, proofs) = tree(&keys);
+        let outsider = Pubkey::new_unique();
+        for proof in &proofs {
+            assert_eq!(verify(&root, &outsider, proof), Ok(false));
+        }
+        assert_eq!(verify(&root, &outsider, &[]), Ok(false));
+    }
+
+    #[test]
+    fn test_proof_of_another_member_is_rejected() {
+        let keys = keys(4);
+        let (root, proofs) = tree(&keys);
+        assert_eq!(verify(&root, &keys[0], &proofs[1]), Ok(false));
+        assert_eq!(verify(&root, &keys[0], &proofs[0][..1]), Ok(false));
+    }
+
+    #[test]
+    fn test_node_does_not_pass_as_a_leaf() {
+        let keys = keys(4);
+        let (root, _) = tree(&keys);
+        let left = node(&leaf(&keys[0]), &leaf(&keys[1]));
+        let right = node(&leaf(&keys[2]), &leaf(&keys[3]));
+        assert_eq!(node(&left, &right), root);
+
+        // the hash of a node is never the leaf of a key, so a key can't be made up from one
+        let fake_key = Pubkey::new_from_array(left);
+        assert_eq!(verify(&root, &fake_key, &[right]), Ok(false));
+    }
+
+    #[test]
+    fn test_proof_deeper_than_the_limit_is_malformed() {
+        let key = Pubkey::new_unique();
+        let proof = vec![[7; 32]; MAX_PROOF_DEPTH + 1];
+        let root = proof
+            .iter()
+            .fold(leaf(&key), |hash, sibling| node(&hash, sibling));
+        assert_eq!(
+            verify(&root, &key, &proof),
+            Err(EscrowError::MalformedMerkleProof)
+        );
+        assert_eq!(verify(&root, &key, &proof[..MAX_PROOF_DEPTH]), Ok(false));
+    }
+}
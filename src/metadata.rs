@@ -0,0 +1,168 @@
+//! Minimal reader for Metaplex token metadata accounts, only the fields creator royalties need.
+//!
+//! Metadata accounts are Borsh encoded: the account key, the update authority, the mint, the
+//! name, symbol and uri strings, the seller fee in basis points and the optional creators, each
+//! an address, a verified flag and a share in percent. Strings are a u32 length followed by the
+//! bytes, so offsets past the mint depend on their lengths.
+
+use solana_program::pubkey::Pubkey;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{error::EscrowError, math};
+
+/// Id of the Metaplex token metadata program, which owns the metadata accounts
+pub mod token_metadata_program {
+    solana_program::declare_id!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bWh3UsTrn");
+}
+
+const KEY_METADATA_V1: u8 = 4;
+
+/// Most creators a metadata account can list
+pub const MAX_CREATORS: usize = 5;
+
+/// What a trade does with the creator royalties of the deposit mint
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoyaltyPolicy {
+    /// No royalties are paid
+    None,
+    /// Royalties are paid when the metadata account is the deposit mint's, skipped otherwise
+    BestEffort,
+    /// The trade fails unless the metadata account is the deposit mint's
+    Enforced,
+}
+
+impl Default for RoyaltyPolicy {
+    fn default() -> Self {
+        RoyaltyPolicy::None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Creator {
+    pub address: Pubkey,
+    /// Whether the creator signed the metadata, only verified creators are paid
+    pub verified: bool,
+    /// Share of the royalties in percent
+    pub share: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub mint: Pubkey,
+    /// Share of each sale owed to the creators, in basis points
+    pub seller_fee_basis_points: u16,
+    pub creators: Vec<Creator>,
+}
+
+/// Address of the metadata account of `mint`
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    let program_id = token_metadata_program::id();
+    Pubkey::find_program_address(
+        &[b"metadata", program_id.as_ref(), mint.as_ref()],
+        &program_id,
+    )
+    .0
+}
+
+/// Reads a metadata account, rejecting data that isn't one
+pub fn load_metadata(data: &[u8]) -> Result<Metadata, EscrowError> {
+    let mut reader = Reader(data);
+    if reader.read_u8()? != KEY_METADATA_V1 {
+        return Err(EscrowError::InvalidMetadata);
+    }
+    let _update_authority: [u8; 32] = reader.read_array()?;
+    let mint = Pubkey::new_from_array(reader.read_array()?);
+    for _ in 0..3 {
+        reader.read_string()?;
+    }
+    let seller_fee_basis_points = u16::from_le_bytes(reader.read_array()?);
+
+    let mut creators = Vec::new();
+    if reader.read_bool()? {
+        let count = u32::from_le_bytes(reader.read_array()?) as usize;
+        if count > MAX_CREATORS {
+            return Err(EscrowError::InvalidMetadata);
+        }
+        for _ in 0..count {
+            creators.push(Creator {
+                address: Pubkey::new_from_array(reader.read_array()?),
+                verified: reader.read_bool()?,
+                share: reader.read_u8()?,
+            });
+        }
+    }
+
+    Ok(Metadata {
+        mint,
+        seller_fee_basis_points,
+        creators,
+    })
+}
+
+/// Royalties each verified creator is owed out of `payment`, in the order they are listed.
+/// Every amount is rounded down, so the rest of the payment stays with the seller
+pub fn royalty_shares(
+    metadata: &Metadata,
+    payment: u64,
+) -> Result<Vec<(Pubkey, u64)>, EscrowError> {
+    let total_share: u32 = metadata
+        .creators
+        .iter()
+        .map(|creator| creator.share as u32)
+        .sum();
+    if metadata.seller_fee_basis_points > 10_000 || total_share > 100 {
+        return Err(EscrowError::InvalidMetadata);
+    }
+    let royalties = math::checked_fee(payment, metadata.seller_fee_basis_points)?;
+
+    metadata
+        .creators
+        .iter()
+        .filter(|creator| creator.verified)
+        .map(|creator| {
+            let share = royalties as u128 * creator.share as u128 / 100;
+            u64::try_from(share)
+                .map(|share| (creator.address, share))
+                .map_err(|_| EscrowError::AmountOverFlow)
+        })
+        .collect()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], EscrowError> {
+        if self.0.len() < len {
+            return Err(EscrowError::InvalidMetadata);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, EscrowError> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_bool(&mut self) -> Result<bool, EscrowError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(EscrowError::InvalidMetadata),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], EscrowError> {
+        self.read(N)?
+            .try_into()
+            .map_err(|_| EscrowError::InvalidMetadata)
+    }
+
+    fn read_string(&mut self) -> Result<&'a [u8], EscrowError> {
+        let len = u32::from_le_bytes(self.read_array()?) as usize;
+        self.read(len)
+    }
+}
@@ -0,0 +1,159 @@
+//! Minimal readers for Pyth price accounts and Switchboard aggregator accounts, only the fields
+//! the price band check needs.
+//!
+//! Pyth offsets follow the version 2 price account layout: the account header, the price
+//! exponent, the publish timestamp and the aggregate price with its status. Switchboard offsets
+//! follow the version 2 aggregator layout: the account discriminator and the latest confirmed
+//! round with its open slot and result.
+
+use solana_program::clock::UnixTimestamp;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::error::EscrowError;
+
+const MAGIC: u32 = 0xa1b2_c3d4;
+const VERSION: u32 = 2;
+const ACCOUNT_TYPE_PRICE: u32 = 3;
+const STATUS_TRADING: u32 = 1;
+
+const EXPO_OFFSET: usize = 20;
+const TIMESTAMP_OFFSET: usize = 96;
+const AGG_PRICE_OFFSET: usize = 208;
+const AGG_STATUS_OFFSET: usize = 224;
+
+const AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+const ROUND_NUM_SUCCESS_OFFSET: usize = 341;
+const ROUND_OPEN_SLOT_OFFSET: usize = 350;
+const ROUND_MANTISSA_OFFSET: usize = 366;
+const ROUND_SCALE_OFFSET: usize = 382;
+
+/// Program the oracle account of an escrow belongs to
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OracleKind {
+    Pyth,
+    Switchboard,
+}
+
+/// The aggregate price of a feed, worth `price * 10^expo`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub expo: i32,
+    /// When the price was published, a unix timestamp for Pyth and a slot for Switchboard
+    pub publish_time: UnixTimestamp,
+}
+
+/// Reads the aggregate price of a Pyth price account, rejecting other accounts and feeds that
+/// aren't trading
+pub fn load_price(data: &[u8]) -> Result<OraclePrice, EscrowError> {
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset + 4)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(EscrowError::InvalidOracleAccount)
+    };
+    let read_i64 = |offset: usize| {
+        data.get(offset..offset + 8)
+            .and_then(|slice| slice.try_into().ok())
+            .map(i64::from_le_bytes)
+            .ok_or(EscrowError::InvalidOracleAccount)
+    };
+
+    if read_u32(0)? != MAGIC || read_u32(4)? != VERSION || read_u32(8)? != ACCOUNT_TYPE_PRICE {
+        return Err(EscrowError::InvalidOracleAccount);
+    }
+
+    let price = read_i64(AGG_PRICE_OFFSET)?;
+    if read_u32(AGG_STATUS_OFFSET)? != STATUS_TRADING || price <= 0 {
+        return Err(EscrowError::OraclePriceUnavailable);
+    }
+
+    Ok(OraclePrice {
+        price,
+        expo: read_u32(EXPO_OFFSET)? as i32,
+        publish_time: read_i64(TIMESTAMP_OFFSET)?,
+    })
+}
+
+/// Reads the result of the latest confirmed round of a Switchboard aggregator account, rejecting
+/// other accounts and aggregators without a successful round
+pub fn load_switchboard_price(data: &[u8]) -> Result<OraclePrice, EscrowError> {
+    if read_array(data, 0)? != AGGREGATOR_DISCRIMINATOR {
+        return Err(EscrowError::InvalidOracleAccount);
+    }
+
+    let num_success = u32::from_le_bytes(read_array(data, ROUND_NUM_SUCCESS_OFFSET)?);
+    let mantissa = i128::from_le_bytes(read_array(data, ROUND_MANTISSA_OFFSET)?);
+    if num_success == 0 || mantissa <= 0 {
+        return Err(EscrowError::OraclePriceUnavailable);
+    }
+
+    let scale = u32::from_le_bytes(read_array(data, ROUND_SCALE_OFFSET)?);
+    let open_slot = u64::from_le_bytes(read_array(data, ROUND_OPEN_SLOT_OFFSET)?);
+    Ok(OraclePrice {
+        price: i64::try_from(mantissa).map_err(|_| EscrowError::AmountOverFlow)?,
+        expo: -i32::try_from(scale).map_err(|_| EscrowError::InvalidOracleAccount)?,
+        publish_time: i64::try_from(open_slot).map_err(|_| EscrowError::InvalidOracleAccount)?,
+    })
+}
+
+fn read_array<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], EscrowError> {
+    data.get(offset..offset + N)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(EscrowError::InvalidOracleAccount)
+}
+
+/// Checks that paying `payment` for `amount` of the deposit is within `max_deviation_bps` of the
+/// oracle price. Both prices are compared in whole tokens, the oracle price being the price of
+/// one whole deposited token in whole received tokens
+pub fn check_price_band(
+    amount: u64,
+    payment: u64,
+    deposit_decimals: u8,
+    receive_decimals: u8,
+    oracle_price: &OraclePrice,
+    max_deviation_bps: u16,
+) -> Result<(), EscrowError> {
+    let pow10 = |exponent: u32| {
+        10u128
+            .checked_pow(exponent)
+            .ok_or(EscrowError::AmountOverFlow)
+    };
+
+    // the trade price is payment * 10^deposit_decimals / (amount * 10^receive_decimals)
+    let trade_numerator = (payment as u128)
+        .checked_mul(pow10(deposit_decimals as u32)?)
+        .ok_or(EscrowError::AmountOverFlow)?;
+    let trade_denominator = (amount as u128)
+        .checked_mul(pow10(receive_decimals as u32)?)
+        .ok_or(EscrowError::AmountOverFlow)?;
+
+    let oracle_numerator = (oracle_price.price as u128)
+        .checked_mul(pow10(oracle_price.expo.max(0) as u32)?)
+        .ok_or(EscrowError::AmountOverFlow)?;
+    let oracle_denominator = pow10(oracle_price.expo.min(0).unsigned_abs())?;
+
+    let trade = trade_numerator
+        .checked_mul(oracle_denominator)
+        .ok_or(EscrowError::AmountOverFlow)?;
+    let oracle = oracle_numerator
+        .checked_mul(trade_denominator)
+        .ok_or(EscrowError::AmountOverFlow)?;
+
+    let deviation = trade.max(oracle) - trade.min(oracle);
+    let allowed = oracle
+        .checked_mul(max_deviation_bps as u128)
+        .ok_or(EscrowError::AmountOverFlow)?;
+    if deviation
+        .checked_mul(10_000)
+        .ok_or(EscrowError::AmountOverFlow)?
+        > allowed
+    {
+        return Err(EscrowError::PriceOutOfBand);
+    }
+
+    Ok(())
+}
@@ -1,209 +1,7160 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    hash::hash,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction, system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
-use spl_token::state::Account as TokenAccount;
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::{
+    constants::{CONFIG_SEED, CONTRIBUTION_SEED, ESCROW_SEED_PREFIX, FEE_COLLECTOR_SEED},
+    error::EscrowError,
+    events, gateway,
+    instruction::{BatchEntry, EscrowInstruction},
+    math, memo,
+    metadata::{self, RoyaltyPolicy},
+    oracle::{self, OracleKind},
+    state::{
+        Bid, Config, Contribution, DisputeStatus, Escrow, EscrowState, FeeExemption, FeeTier,
+        PaymentLeg, PaymentSplit, MAX_BASKET_ASSETS, MAX_PAYMENT_SPLITS,
+    },
+    token,
+};
+
+/// Share of the payment taken out of what the initializer receives, in basis points, until the
+/// config is created. Fees round down and a fee of 0 isn't transferred, so a rate of 0 leaves
+/// trades paying the initializer in full
+pub const MAKER_FEE_BASIS_POINTS: u16 = 25;
+
+/// Share of the payment the taker pays on top of it, in basis points, until the config is
+/// created. It rounds down like the maker fee and both go to the fee collector in the same
+/// transfer
+pub const TAKER_FEE_BASIS_POINTS: u16 = 0;
+
+/// Number of accounts `InitEscrow` takes. The init variants with accounts of their own expect
+/// them after these
+const INIT_ESCROW_ACCOUNTS: usize = 8;
+
+/// Derives the fee collector PDA, which owns the fee token accounts and holds the creation fees
+pub fn fee_collector_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id)
+}
+
+/// Derives the PDA owning the temp token accounts of the escrow `initializer` opened with `nonce`,
+/// so a maker can keep several escrows open at once
+pub fn escrow_pda(program_id: &Pubkey, initializer: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            ESCROW_SEED_PREFIX,
+            initializer.as_ref(),
+            &nonce.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Derives the config PDA holding the fee rates
+pub fn config_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// Derives the account recording the contributions of `contributor` to the crowd fill `escrow`
+pub fn contribution_pda(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    contributor: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CONTRIBUTION_SEED, escrow.as_ref(), contributor.as_ref()],
+        program_id,
+    )
+}
+
+/// Seeds the escrow PDA signs with
+struct PdaSeeds {
+    initializer: Pubkey,
+    nonce: [u8; 8],
+    bump_seed: [u8; 1],
+}
+
+impl PdaSeeds {
+    fn of_escrow(escrow_info: &Escrow) -> Self {
+        PdaSeeds {
+            initializer: escrow_info.pda_initializer_pubkey,
+            nonce: escrow_info.nonce.to_le_bytes(),
+            bump_seed: [escrow_info.bump_seed],
+        }
+    }
+
+    fn of_bid(bid_info: &Bid) -> Self {
+        PdaSeeds {
+            initializer: bid_info.escrow_initializer_pubkey,
+            nonce: bid_info.escrow_nonce.to_le_bytes(),
+            bump_seed: [bid_info.bump_seed],
+        }
+    }
+
+    fn of_contribution(contribution_info: &Contribution) -> Self {
+        PdaSeeds {
+            initializer: contribution_info.escrow_initializer_pubkey,
+            nonce: contribution_info.escrow_nonce.to_le_bytes(),
+            bump_seed: [contribution_info.bump_seed],
+        }
+    }
+
+    fn seeds(&self) -> [&[u8]; 4] {
+        [
+            ESCROW_SEED_PREFIX,
+            self.initializer.as_ref(),
+            &self.nonce,
+            &self.bump_seed,
+        ]
+    }
+
+    /// Re-derives the PDA from the stored bump, a bump that doesn't give a valid address is
+    /// reported as `InvalidPda`
+    fn pda(&self, program_id: &Pubkey) -> Result<Pubkey, ProgramError> {
+        Pubkey::create_program_address(&self.seeds(), program_id)
+            .map_err(|_| EscrowError::InvalidPda.into())
+    }
+}
+
+/// Terms of a new escrow besides its expected amount. `InitEscrow` sets all of them, the other
+/// init instructions start from the defaults and set their own fields on the escrow afterwards
+#[derive(Default)]
+struct InitOptions {
+    expiry_slot: u64,
+    expiry_unix_timestamp: i64,
+    allowed_taker: Option<Pubkey>,
+    two_phase: bool,
+    min_taker_amount: u64,
+    nonce: u64,
+    arbiter: Option<Pubkey>,
+    receipt: bool,
+    position: bool,
+    taker_merkle_root: Option<[u8; 32]>,
+    gate_mint: Option<Pubkey>,
+    gate_min_amount: u64,
+    gatekeeper_network: Option<Pubkey>,
+    memo_required: bool,
+    royalty_policy: RoyaltyPolicy,
+    nft: bool,
+    unwrap_sol: bool,
+    same_mint_allowed: bool,
+}
+
+pub struct Processor;
+impl Processor {
+    pub fn process(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        instruction_data: &[u8],
+    ) -> ProgramResult {
+        let instruction = EscrowInstruction::unpack(instruction_data)?;
+
+        match instruction {
+            EscrowInstruction::InitEscrow {
+                amount,
+                expiry_slot,
+                expiry_unix_timestamp,
+                allowed_taker,
+                two_phase,
+                min_taker_amount,
+                nonce,
+                arbiter,
+                receipt,
+                position,
+                taker_merkle_root,
+                gate_mint,
+                gate_min_amount,
+                gatekeeper_network,
+                memo_required,
+                royalty_policy,
+                nft,
+                unwrap_sol,
+            } => {
+                msg!("Instruction: InitEscrow");
+                Self::process_init_escrow(
+                    accounts,
+                    amount,
+                    InitOptions {
+                        expiry_slot,
+                        expiry_unix_timestamp,
+                        allowed_taker,
+                        two_phase,
+                        min_taker_amount,
+                        nonce,
+                        arbiter,
+                        receipt,
+                        position,
+                        taker_merkle_root,
+                        gate_mint,
+                        gate_min_amount,
+                        gatekeeper_network,
+                        memo_required,
+                        royalty_policy,
+                        nft,
+                        unwrap_sol,
+                        ..InitOptions::default()
+                    },
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::Exchange {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                msg!("Instruction: Exchange");
+                Self::process_trade(
+                    accounts,
+                    None,
+                    None,
+                    max_payment,
+                    preimage,
+                    &merkle_proof,
+                    false,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::CancelEscrow => {
+                msg!("Instruction: CancelEscrow");
+                Self::process_cancel_escrow(accounts, program_id)
+            }
+
+            EscrowInstruction::RefundExpired => {
+                msg!("Instruction: RefundExpired");
+                Self::process_refund_expired(accounts, program_id)
+            }
+
+            EscrowInstruction::ProposeCounterOffer { amount } => {
+                msg!("Instruction: ProposeCounterOffer");
+                Self::process_propose_counter_offer(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::AcceptCounterOffer => {
+                msg!("Instruction: AcceptCounterOffer");
+                Self::process_accept_counter_offer(accounts, program_id)
+            }
+
+            EscrowInstruction::PartialExchange {
+                amount_to_take,
+                amount_to_give,
+            } => {
+                msg!("Instruction: PartialExchange");
+                Self::process_partial_trade(accounts, amount_to_take, amount_to_give, program_id)
+            }
+
+            EscrowInstruction::DepositForExchange => {
+                msg!("Instruction: DepositForExchange");
+                Self::process_deposit_for_exchange(accounts, program_id)
+            }
+
+            EscrowInstruction::ConfirmExchange => {
+                msg!("Instruction: ConfirmExchange");
+                Self::process_confirm_exchange(accounts, program_id)
+            }
+
+            EscrowInstruction::RejectExchange => {
+                msg!("Instruction: RejectExchange");
+                Self::process_reject_exchange(accounts, program_id)
+            }
+
+            EscrowInstruction::UpdateExpectedAmount { new_amount } => {
+                msg!("Instruction: UpdateExpectedAmount");
+                Self::process_update_amount(accounts, new_amount, program_id)
+            }
+
+            EscrowInstruction::TopUp { additional_amount } => {
+                msg!("Instruction: TopUp");
+                Self::process_top_up(accounts, additional_amount, program_id)
+            }
+
+            EscrowInstruction::MutualCancel => {
+                msg!("Instruction: MutualCancel");
+                Self::process_mutual_cancel(accounts, program_id)
+            }
+
+            EscrowInstruction::ExtendExpiry { new_expiry_slot } => {
+                msg!("Instruction: ExtendExpiry");
+                Self::process_extend_expiry(accounts, new_expiry_slot, program_id)
+            }
+
+            EscrowInstruction::Replace {
+                new_expected_amount,
+                new_expiry,
+            } => {
+                msg!("Instruction: Replace");
+                Self::process_replace(accounts, new_expected_amount, new_expiry, program_id)
+            }
+
+            EscrowInstruction::SplitEscrow {
+                split_deposit_amount,
+            } => {
+                msg!("Instruction: SplitEscrow");
+                Self::process_split_escrow(accounts, split_deposit_amount, program_id)
+            }
+
+            EscrowInstruction::MergeEscrows => {
+                msg!("Instruction: MergeEscrows");
+                Self::process_merge_escrows(accounts, program_id)
+            }
+
+            EscrowInstruction::MatchEscrows => {
+                msg!("Instruction: MatchEscrows");
+                Self::process_match_escrows(accounts, program_id)
+            }
+
+            EscrowInstruction::ExchangeBatch { count } => {
+                msg!("Instruction: ExchangeBatch");
+                Self::process_exchange_batch(accounts, count, program_id)
+            }
+
+            EscrowInstruction::InitEscrowBatch { entries } => {
+                msg!("Instruction: InitEscrowBatch");
+                Self::process_init_escrow_batch(accounts, &entries, program_id)
+            }
+
+            EscrowInstruction::InitDutchAuction {
+                start_amount,
+                end_amount,
+                start_slot,
+                end_slot,
+            } => {
+                msg!("Instruction: InitDutchAuction");
+                Self::process_init_dutch_auction(
+                    accounts,
+                    start_amount,
+                    end_amount,
+                    start_slot,
+                    end_slot,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::InitBidAuction {
+                reserve_amount,
+                end_slot,
+            } => {
+                msg!("Instruction: InitBidAuction");
+                Self::process_init_bid_auction(accounts, reserve_amount, end_slot, program_id)
+            }
+
+            EscrowInstruction::PlaceBid { amount } => {
+                msg!("Instruction: PlaceBid");
+                Self::process_place_bid(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::AcceptBid => {
+                msg!("Instruction: AcceptBid");
+                Self::process_accept_bid(accounts, program_id)
+            }
+
+            EscrowInstruction::WithdrawBid => {
+                msg!("Instruction: WithdrawBid");
+                Self::process_withdraw_bid(accounts, program_id)
+            }
+
+            EscrowInstruction::InitRatePriced {
+                price_numerator,
+                price_denominator,
+            } => {
+                msg!("Instruction: InitRatePriced");
+                Self::process_init_rate_priced(
+                    accounts,
+                    price_numerator,
+                    price_denominator,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::InitBasketPayment { amounts } => {
+                msg!("Instruction: InitBasketPayment");
+                Self::process_init_basket_payment(accounts, &amounts, program_id)
+            }
+
+            EscrowInstruction::InitHashLock {
+                amount,
+                hash,
+                timeout_slot,
+            } => {
+                msg!("Instruction: InitHashLock");
+                Self::process_init_hash_lock(accounts, amount, hash, timeout_slot, program_id)
+            }
+
+            EscrowInstruction::InitOracleBand {
+                amount,
+                max_deviation_bps,
+                max_staleness,
+                oracle_kind,
+            } => {
+                msg!("Instruction: InitOracleBand");
+                Self::process_init_oracle_band(
+                    accounts,
+                    amount,
+                    max_deviation_bps,
+                    max_staleness,
+                    oracle_kind,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::InitEscrowForSol { sol_amount, nonce } => {
+                msg!("Instruction: InitEscrowForSol");
+                Self::process_init_escrow_for_sol(accounts, sol_amount, nonce, program_id)
+            }
+
+            EscrowInstruction::ExchangeForSol => {
+                msg!("Instruction: ExchangeForSol");
+                Self::process_trade_for_sol(accounts, program_id)
+            }
+
+            EscrowInstruction::RaiseDispute => {
+                msg!("Instruction: RaiseDispute");
+                Self::process_raise_dispute(accounts, program_id)
+            }
+
+            EscrowInstruction::ResolveDispute { release_to_taker } => {
+                msg!("Instruction: ResolveDispute");
+                Self::process_resolve_dispute(accounts, release_to_taker, program_id)
+            }
+
+            EscrowInstruction::InitVesting {
+                amount,
+                cliff_slot,
+                end_slot,
+            } => {
+                msg!("Instruction: InitVesting");
+                Self::process_init_vesting(accounts, amount, cliff_slot, end_slot, program_id)
+            }
+
+            EscrowInstruction::ClaimVested => {
+                msg!("Instruction: ClaimVested");
+                Self::process_claim_vested(accounts, program_id)
+            }
+
+            EscrowInstruction::InitStream { amount } => {
+                msg!("Instruction: InitStream");
+                Self::process_init_stream(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::StreamPayment { amount } => {
+                msg!("Instruction: StreamPayment");
+                Self::process_stream_payment(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::InitCrowdFill {
+                amount,
+                expiry_slot,
+            } => {
+                msg!("Instruction: InitCrowdFill");
+                Self::process_init_crowd_fill(accounts, amount, expiry_slot, program_id)
+            }
+
+            EscrowInstruction::Contribute { amount } => {
+                msg!("Instruction: Contribute");
+                Self::process_contribute(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::Settle => {
+                msg!("Instruction: Settle");
+                Self::process_settle(accounts, program_id)
+            }
+
+            EscrowInstruction::ClaimShare => {
+                msg!("Instruction: ClaimShare");
+                Self::process_claim_share(accounts, program_id)
+            }
+
+            EscrowInstruction::RefundContribution => {
+                msg!("Instruction: RefundContribution");
+                Self::process_refund_contribution(accounts, program_id)
+            }
+
+            EscrowInstruction::TransferInitializer => {
+                msg!("Instruction: TransferInitializer");
+                Self::process_transfer_initializer(accounts, program_id)
+            }
+
+            EscrowInstruction::InitSplitPayment {
+                amount,
+                basis_points,
+            } => {
+                msg!("Instruction: InitSplitPayment");
+                Self::process_init_split_payment(accounts, amount, &basis_points, program_id)
+            }
+
+            EscrowInstruction::ExchangeWithAtaCreation {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                msg!("Instruction: ExchangeWithAtaCreation");
+                Self::process_exchange_with_ata_creation(
+                    accounts,
+                    max_payment,
+                    preimage,
+                    &merkle_proof,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::ExchangeWithSol {
+                max_payment,
+                preimage,
+                merkle_proof,
+            } => {
+                msg!("Instruction: ExchangeWithSol");
+                Self::process_exchange_with_sol(
+                    accounts,
+                    max_payment,
+                    preimage,
+                    &merkle_proof,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::SimulateExchange {
+                amount,
+                preimage,
+                merkle_proof,
+            } => {
+                msg!("Instruction: SimulateExchange");
+                Self::process_simulate(accounts, amount, preimage, &merkle_proof, program_id)
+            }
+
+            EscrowInstruction::InitializeConfig {
+                maker_fee_bps,
+                taker_fee_bps,
+                fee_tiers,
+            } => {
+                msg!("Instruction: InitializeConfig");
+                Self::process_initialize_config(
+                    accounts,
+                    maker_fee_bps,
+                    taker_fee_bps,
+                    &fee_tiers,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::SetFeeSchedule {
+                maker_fee_bps,
+                taker_fee_bps,
+                fee_tiers,
+            } => {
+                msg!("Instruction: SetFeeSchedule");
+                Self::process_set_fee_schedule(
+                    accounts,
+                    maker_fee_bps,
+                    taker_fee_bps,
+                    &fee_tiers,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::AddFeeExemption { as_maker, as_taker } => {
+                msg!("Instruction: AddFeeExemption");
+                Self::process_add_fee_exemption(accounts, as_maker, as_taker, program_id)
+            }
+
+            EscrowInstruction::RemoveFeeExemption => {
+                msg!("Instruction: RemoveFeeExemption");
+                Self::process_remove_fee_exemption(accounts, program_id)
+            }
+
+            EscrowInstruction::WithdrawFees { amount } => {
+                msg!("Instruction: WithdrawFees");
+                Self::process_withdraw_fees(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::SetCreationFee { lamports } => {
+                msg!("Instruction: SetCreationFee");
+                Self::process_set_creation_fee(accounts, lamports, program_id)
+            }
+
+            EscrowInstruction::WithdrawCreationFees { amount } => {
+                msg!("Instruction: WithdrawCreationFees");
+                Self::process_withdraw_creation_fees(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::InitWithCancelPenalty {
+                amount,
+                no_penalty_after_slot,
+                penalty_lamports,
+                penalty_bps,
+            } => {
+                msg!("Instruction: InitWithCancelPenalty");
+                Self::process_init_with_cancel_penalty(
+                    accounts,
+                    amount,
+                    no_penalty_after_slot,
+                    penalty_lamports,
+                    penalty_bps,
+                    program_id,
+                )
+            }
+
+            EscrowInstruction::SetConfig { paused, new_admin } => {
+                msg!("Instruction: SetConfig");
+                Self::process_set_config(accounts, paused, new_admin, program_id)
+            }
+
+            EscrowInstruction::InitSameMint { amount } => {
+                msg!("Instruction: InitSameMint");
+                Self::process_init_same_mint(accounts, amount, program_id)
+            }
+
+            EscrowInstruction::SetEscrowActive { active } => {
+                msg!("Instruction: SetEscrowActive");
+                Self::process_set_escrow_active(accounts, active, program_id)
+            }
+
+            EscrowInstruction::AddAllowedMint => {
+                msg!("Instruction: AddAllowedMint");
+                Self::process_add_allowed_mint(accounts, program_id)
+            }
+
+            EscrowInstruction::RemoveAllowedMint => {
+                msg!("Instruction: RemoveAllowedMint");
+                Self::process_remove_allowed_mint(accounts, program_id)
+            }
+
+            EscrowInstruction::MigrateConfig => {
+                msg!("Instruction: MigrateConfig");
+                Self::process_migrate_config(accounts, program_id)
+            }
+        }
+    }
+
+    fn process_init_escrow(
+        accounts: &[AccountInfo],
+        amount: u64,
+        options: InitOptions,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let InitOptions {
+            expiry_slot,
+            expiry_unix_timestamp,
+            allowed_taker,
+            two_phase,
+            min_taker_amount,
+            nonce,
+            arbiter,
+            receipt,
+            position,
+            taker_merkle_root,
+            gate_mint,
+            gate_min_amount,
+            gatekeeper_network,
+            memo_required,
+            royalty_policy,
+            nft,
+            unwrap_sol,
+            same_mint_allowed,
+        } = options;
+
+        if amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // disputes are over a pending deposit, which only two-phase escrows have
+        if arbiter.is_some() && !two_phase {
+            return Err(EscrowError::NotTwoPhase.into());
+        }
+
+        // the receipt is minted by Exchange, which two-phase escrows don't take
+        if receipt && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // the position token is burned on settlement, which two-phase escrows reach by Release
+        if position && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // takers prove they are allowed in Exchange, DepositForExchange carries no proof
+        if taker_merkle_root.is_some() && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // the gate balance is shown in Exchange and PartialExchange too
+        if gate_mint.is_some() && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if gate_mint.is_some() && gate_min_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // so is the gateway token
+        if gatekeeper_network.is_some() && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // and the memo is looked for in the transaction of the trade
+        if memo_required && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // and the royalties are carved out of the payment of the trade
+        if royalty_policy != RoyaltyPolicy::None && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // and the receiving account is closed by the trade
+        if unwrap_sol && two_phase {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // the position holder is paid into their own account, which the PDA can't close
+        if unwrap_sol && position {
+            return Err(EscrowError::PositionOwned.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account(account_info_iter, "initializer")?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let temp_token_account = next_account(account_info_iter, "temp_token_account")?;
+        let temp_token_account_info = TokenAccount::unpack(&temp_token_account.try_borrow_data()?)?;
+        if temp_token_account_info.amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        let token_to_receive_account = next_account(account_info_iter, "token_to_receive_account")?;
+        // the deposit would be paid for into the account it's taken from
+        if token_to_receive_account.key == temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let token_to_receive_account_info =
+            TokenAccount::unpack(&token_to_receive_account.try_borrow_data()?)?;
+
+        if token_to_receive_account_info.mint == temp_token_account_info.mint {
+            if !same_mint_allowed {
+                return Err(EscrowError::SameMint.into());
+            }
+            // swapping an amount for the same amount of the same token trades nothing
+            if amount == temp_token_account_info.amount {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+        }
+
+        let escrow_account = next_account(account_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_writable(temp_token_account, "temp_token_account")?;
+        check_writable(escrow_account, "escrow_account")?;
+
+        let rent = Rent::get()?;
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.state != EscrowState::Uninitialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let (pda, bump_seed) = escrow_pda(program_id, initializer.key, nonce);
+
+        escrow_info.state = EscrowState::Active;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.pda_initializer_pubkey = *initializer.key;
+        escrow_info.nonce = nonce;
+        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
+        escrow_info.expected_amount = amount;
+        escrow_info.expiry_slot = expiry_slot;
+        escrow_info.expiry_unix_timestamp = expiry_unix_timestamp;
+        escrow_info.allowed_taker = allowed_taker.unwrap_or_default();
+        escrow_info.taker_merkle_root = taker_merkle_root.unwrap_or_default();
+        escrow_info.gate_mint = gate_mint.unwrap_or_default();
+        escrow_info.gate_min_amount = gate_min_amount;
+        escrow_info.gatekeeper_network = gatekeeper_network.unwrap_or_default();
+        escrow_info.memo_required = memo_required;
+        escrow_info.royalty_policy = royalty_policy;
+        escrow_info.nft = nft;
+        escrow_info.unwrap_sol = unwrap_sol;
+        escrow_info.same_mint_allowed = same_mint_allowed;
+        escrow_info.two_phase = two_phase;
+        escrow_info.min_taker_amount = min_taker_amount;
+        escrow_info.arbiter = arbiter.unwrap_or_default();
+        escrow_info.bump_seed = bump_seed;
+        escrow_info.remaining_deposit = temp_token_account_info.amount;
+        escrow_info.remaining_expected = amount;
+        escrow_info.temp_token_mint = temp_token_account_info.mint;
+        escrow_info.receive_token_mint = token_to_receive_account_info.mint;
+
+        // every later instruction signs with the stored bump, so it has to be the canonical one
+        if PdaSeeds::of_escrow(&escrow_info).pda(program_id)? != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let token_program = next_account(account_info_iter, "token_program")?;
+        // the deposit, the payment and every CPI go through the same token program
+        check_token_program(
+            token_program,
+            &[temp_token_account, token_to_receive_account],
+        )?;
+
+        let config_account = next_account(account_info_iter, "config_account")?;
+        let fee_collector_pda_account =
+            next_account(account_info_iter, "fee_collector_pda_account")?;
+        let system_program_account = next_account(account_info_iter, "system_program")?;
+        let config_info = Self::load_config(config_account, program_id)?;
+        if config_info.paused {
+            return Err(EscrowError::ProgramPaused.into());
+        }
+        if !config_info.is_mint_allowed(&temp_token_account_info.mint)
+            || !config_info.is_mint_allowed(&token_to_receive_account_info.mint)
+        {
+            return Err(EscrowError::MintNotAllowed.into());
+        }
+        Self::charge_creation_fee(
+            config_info.creation_fee_lamports,
+            initializer,
+            fee_collector_pda_account,
+            system_program_account,
+            &rent,
+            program_id,
+        )?;
+
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            initializer.key,
+            &[&initializer.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                temp_token_account.clone(),
+                initializer.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        // wrapped SOL is only unwrapped by the classic token program's native mint
+        if unwrap_sol {
+            if *token_program.key != spl_token::id()
+                || token_to_receive_account_info.mint != spl_token::native_mint::id()
+            {
+                return Err(EscrowError::NotWrappedSol.into());
+            }
+
+            let close_authority_change_ix = token::set_authority(
+                token_program.key,
+                token_to_receive_account.key,
+                Some(&pda),
+                spl_token::instruction::AuthorityType::CloseAccount,
+                initializer.key,
+                &[initializer.key],
+            )?;
+
+            msg!(
+                "Calling the token program to transfer the receiving account's close authority..."
+            );
+            invoke(
+                &close_authority_change_ix,
+                &[
+                    token_to_receive_account.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        if receipt {
+            let receipt_mint = next_account(account_info_iter, "receipt_mint")?;
+            Self::take_mint_authority(token_program, receipt_mint, initializer, &pda)?;
+            escrow_info.receipt_mint = *receipt_mint.key;
+        }
+
+        if position {
+            let position_mint = next_account(account_info_iter, "position_mint")?;
+            let initializer_position_account =
+                next_account(account_info_iter, "initializer_position_account")?;
+            let pda_account = next_account(account_info_iter, "pda_account")?;
+            if *pda_account.key != pda {
+                return Err(EscrowError::InvalidPda.into());
+            }
+            Self::take_mint_authority(token_program, position_mint, initializer, &pda)?;
+            escrow_info.position_mint = *position_mint.key;
+
+            Self::mint_nft(
+                token_program,
+                position_mint,
+                initializer_position_account,
+                pda_account,
+                &PdaSeeds::of_escrow(&escrow_info),
+            )?;
+        }
+
+        if nft {
+            let deposit_mint = next_account(account_info_iter, "deposit_mint")?;
+            Self::check_nft_mint(deposit_mint, token_program, &escrow_info)?;
+
+            // the deposit is the one token, so it is always taken whole
+            if temp_token_account_info.amount != 1 {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+        }
+
+        // any remaining accounts are more temp token accounts making the deposit a basket
+        for basket_vault in account_info_iter {
+            if escrow_info.basket_len as usize == MAX_BASKET_ASSETS - 1 {
+                return Err(EscrowError::BasketTooLarge.into());
+            }
+            if two_phase {
+                return Err(EscrowError::ExchangeOnly.into());
+            }
+            check_token_program(token_program, &[basket_vault])?;
+
+            let owner_change_ix = token::set_authority(
+                token_program.key,
+                basket_vault.key,
+                Some(&pda),
+                spl_token::instruction::AuthorityType::AccountOwner,
+                initializer.key,
+                &[initializer.key],
+            )?;
+
+            msg!("Calling the token program to transfer basket account ownership...");
+            invoke(
+                &owner_change_ix,
+                &[
+                    basket_vault.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            escrow_info.basket_vaults[escrow_info.basket_len as usize] = *basket_vault.key;
+            escrow_info.basket_len += 1;
+        }
+
+        events::emit_init(
+            escrow_account.key,
+            initializer.key,
+            amount,
+            escrow_info.sequence,
+        );
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_escrow_batch(
+        accounts: &[AccountInfo],
+        entries: &[BatchEntry],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account(account_info_iter, "initializer")?;
+        let token_to_receive_account = next_account(account_info_iter, "token_to_receive_account")?;
+        let token_program = next_account(account_info_iter, "token_program")?;
+        check_token_program(token_program, &[token_to_receive_account])?;
+        let config_account = next_account(account_info_iter, "config_account")?;
+        let fee_collector = next_account(account_info_iter, "fee_collector")?;
+        let system_program = next_account(account_info_iter, "system_program")?;
+
+        if entries.is_empty() {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        for (index, entry) in entries.iter().enumerate() {
+            let temp_token_account = next_account(account_info_iter, "temp_token_account")?;
+            let escrow_account = next_account(account_info_iter, "escrow_account")?;
+
+            // same accounts in the same order as a single InitEscrow
+            let init_accounts = [
+                initializer.clone(),
+                temp_token_account.clone(),
+                token_to_receive_account.clone(),
+                escrow_account.clone(),
+                token_program.clone(),
+                config_account.clone(),
+                fee_collector.clone(),
+                system_program.clone(),
+            ];
+            let options = InitOptions {
+                nonce: entry.nonce,
+                ..InitOptions::default()
+            };
+            if let Err(error) =
+                Self::process_init_escrow(&init_accounts, entry.amount, options, program_id)
+            {
+                msg!("InitEscrowBatch entry {} failed", index);
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_init_dutch_auction(
+        accounts: &[AccountInfo],
+        start_amount: u64,
+        end_amount: u64,
+        start_slot: u64,
+        end_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if end_amount == 0 || end_amount > start_amount || start_slot >= end_slot {
+            return Err(EscrowError::InvalidAuctionParameters.into());
+        }
+
+        Self::process_init_escrow(accounts, start_amount, InitOptions::default(), program_id)?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.auction_start_amount = start_amount;
+        escrow_info.auction_end_amount = end_amount;
+        escrow_info.auction_start_slot = start_slot;
+        escrow_info.auction_end_slot = end_slot;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_vesting(
+        accounts: &[AccountInfo],
+        amount: u64,
+        cliff_slot: u64,
+        end_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if end_slot == 0 || cliff_slot > end_slot {
+            return Err(EscrowError::InvalidVestingSchedule.into());
+        }
+
+        Self::process_init_escrow(accounts, amount, InitOptions::default(), program_id)?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.vesting_cliff_slot = cliff_slot;
+        escrow_info.vesting_end_slot = end_slot;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_stream(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        Self::process_init_escrow(accounts, amount, InitOptions::default(), program_id)?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.streaming = true;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_bid_auction(
+        accounts: &[AccountInfo],
+        reserve_amount: u64,
+        end_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if reserve_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // without an end the best bid would stay locked for good if the initializer never accepts
+        if end_slot <= Clock::get()?.slot {
+            return Err(EscrowError::InvalidAuctionParameters.into());
+        }
+
+        Self::process_init_escrow(
+            accounts,
+            reserve_amount,
+            InitOptions {
+                expiry_slot: end_slot,
+                ..InitOptions::default()
+            },
+            program_id,
+        )?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if escrow_info.has_basket() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+        escrow_info.bid_auction = true;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_rate_priced(
+        accounts: &[AccountInfo],
+        price_numerator: u64,
+        price_denominator: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if price_numerator == 0 || price_denominator == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // the price of the whole deposit, kept up to date like for fixed price escrows
+        let temp_token_account = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let deposit = TokenAccount::unpack(&temp_token_account.try_borrow_data()?)?.amount;
+        let expected_amount =
+            math::checked_rate_payment(deposit, price_numerator, price_denominator)?;
+
+        Self::process_init_escrow(
+            accounts,
+            expected_amount,
+            InitOptions::default(),
+            program_id,
+        )?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.price_numerator = price_numerator;
+        escrow_info.price_denominator = price_denominator;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_basket_payment(
+        accounts: &[AccountInfo],
+        amounts: &[u64],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if amounts.is_empty() || amounts.contains(&0) {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+        if amounts.len() > MAX_BASKET_ASSETS {
+            return Err(EscrowError::BasketTooLarge.into());
+        }
+
+        if accounts.len() < INIT_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (init_accounts, leg_accounts) = accounts.split_at(INIT_ESCROW_ACCOUNTS);
+        Self::process_init_escrow(
+            init_accounts,
+            amounts[0],
+            InitOptions::default(),
+            program_id,
+        )?;
+
+        let escrow_account = &init_accounts[3];
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        let account_info_iter = &mut leg_accounts.iter();
+        for (index, &amount) in amounts.iter().enumerate().skip(1) {
+            let destination = next_account(account_info_iter, "payment_leg_destination")?;
+            if *destination.owner != spl_token::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let destination_info = TokenAccount::unpack(&destination.try_borrow_data()?)?;
+            if destination_info.owner != escrow_info.initializer_pubkey {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            escrow_info.payment_legs[index - 1] = PaymentLeg {
+                mint: destination_info.mint,
+                amount,
+                destination: *destination.key,
+            };
+        }
+        escrow_info.payment_legs_len = (amounts.len() - 1) as u8;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_split_payment(
+        accounts: &[AccountInfo],
+        amount: u64,
+        basis_points: &[u16],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if basis_points.is_empty()
+            || basis_points.len() > MAX_PAYMENT_SPLITS
+            || basis_points.contains(&0)
+            || basis_points.iter().map(|&share| share as u32).sum::<u32>() != 10_000
+        {
+            return Err(EscrowError::InvalidPaymentSplit.into());
+        }
+
+        if accounts.len() < INIT_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (init_accounts, split_accounts) = accounts.split_at(INIT_ESCROW_ACCOUNTS);
+        Self::process_init_escrow(init_accounts, amount, InitOptions::default(), program_id)?;
+
+        let token_to_receive_account = &init_accounts[2];
+        let escrow_account = &init_accounts[3];
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        let account_info_iter = &mut split_accounts.iter();
+        for (index, &share) in basis_points.iter().enumerate().skip(1) {
+            let destination = next_account(account_info_iter, "payment_split_destination")?;
+            if destination.owner != token_to_receive_account.owner {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let destination_info = TokenAccount::unpack(&destination.try_borrow_data()?)?;
+            if destination_info.mint != escrow_info.receive_token_mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            escrow_info.payment_splits[index - 1] = PaymentSplit {
+                destination: *destination.key,
+                basis_points: share,
+            };
+        }
+        escrow_info.payment_splits_len = (basis_points.len() - 1) as u8;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_hash_lock(
+        accounts: &[AccountInfo],
+        amount: u64,
+        hash: [u8; 32],
+        timeout_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        // without a timeout the deposit could never be recovered if the secret is never revealed
+        if hash == [0; 32] || timeout_slot == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // the timeout is the expiry, so RefundExpired returns the deposit once it has passed
+        Self::process_init_escrow(
+            accounts,
+            amount,
+            InitOptions {
+                expiry_slot: timeout_slot,
+                ..InitOptions::default()
+            },
+            program_id,
+        )?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.hash_lock = hash;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_oracle_band(
+        accounts: &[AccountInfo],
+        amount: u64,
+        max_deviation_bps: u16,
+        max_staleness: i64,
+        oracle_kind: OracleKind,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if max_deviation_bps == 0 || max_staleness <= 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if accounts.len() < INIT_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (init_accounts, oracle_accounts) = accounts.split_at(INIT_ESCROW_ACCOUNTS);
+        Self::process_init_escrow(init_accounts, amount, InitOptions::default(), program_id)?;
+
+        let escrow_account = &init_accounts[3];
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        let account_info_iter = &mut oracle_accounts.iter();
+
+        let oracle_account = next_account(account_info_iter, "oracle_account")?;
+        let oracle_data = oracle_account.try_borrow_data()?;
+        match oracle_kind {
+            OracleKind::Pyth => oracle::load_price(&oracle_data)?,
+            OracleKind::Switchboard => oracle::load_switchboard_price(&oracle_data)?,
+        };
+
+        let deposit_mint = next_account(account_info_iter, "deposit_mint")?;
+        let receive_mint = next_account(account_info_iter, "receive_mint")?;
+        if *deposit_mint.key != escrow_info.temp_token_mint
+            || *receive_mint.key != escrow_info.receive_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        escrow_info.oracle = *oracle_account.key;
+        escrow_info.oracle_max_deviation_bps = max_deviation_bps;
+        escrow_info.oracle_max_staleness = max_staleness;
+        escrow_info.oracle_kind = oracle_kind;
+        escrow_info.deposit_decimals = Mint::unpack(&deposit_mint.try_borrow_data()?)?.decimals;
+        escrow_info.receive_decimals = Mint::unpack(&receive_mint.try_borrow_data()?)?.decimals;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_escrow_for_sol(
+        accounts: &[AccountInfo],
+        sol_amount: u64,
+        nonce: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if sol_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account(account_info_iter, "initializer")?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let temp_token_account = next_account(account_info_iter, "temp_token_account")?;
+        let temp_token_account_info = TokenAccount::unpack(&temp_token_account.try_borrow_data()?)?;
+        if temp_token_account_info.amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        let escrow_account = next_account(account_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_writable(temp_token_account, "temp_token_account")?;
+        check_writable(escrow_account, "escrow_account")?;
+
+        let rent = Rent::get()?;
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.state != EscrowState::Uninitialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let (pda, bump_seed) = escrow_pda(program_id, initializer.key, nonce);
+
+        escrow_info.state = EscrowState::Active;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.pda_initializer_pubkey = *initializer.key;
+        escrow_info.nonce = nonce;
+        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.expected_amount = sol_amount;
+        escrow_info.bump_seed = bump_seed;
+        escrow_info.remaining_deposit = temp_token_account_info.amount;
+        escrow_info.remaining_expected = sol_amount;
+        escrow_info.temp_token_mint = temp_token_account_info.mint;
+        escrow_info.receive_native_sol = true;
+
+        let token_program = next_account(account_info_iter, "token_program")?;
+        check_token_program(token_program, &[temp_token_account])?;
+
+        let config_account = next_account(account_info_iter, "config_account")?;
+        let fee_collector_pda_account =
+            next_account(account_info_iter, "fee_collector_pda_account")?;
+        let system_program_account = next_account(account_info_iter, "system_program")?;
+        let config_info = Self::load_config(config_account, program_id)?;
+        if config_info.paused {
+            return Err(EscrowError::ProgramPaused.into());
+        }
+        // the payment in SOL is listed as the native mint
+        if !config_info.is_mint_allowed(&temp_token_account_info.mint)
+            || !config_info.is_mint_allowed(&spl_token::native_mint::id())
+        {
+            return Err(EscrowError::MintNotAllowed.into());
+        }
+        Self::charge_creation_fee(
+            config_info.creation_fee_lamports,
+            initializer,
+            fee_collector_pda_account,
+            system_program_account,
+            &rent,
+            program_id,
+        )?;
+
+        // the SOL goes straight to a system account, the initializer's main account by default
+        let destination = account_info_iter.next().unwrap_or(initializer);
+        if *destination.owner != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        escrow_info.initializer_token_to_receive_account_pubkey = *destination.key;
+
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            initializer.key,
+            &[initializer.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                temp_token_account.clone(),
+                initializer.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        events::emit_init(
+            escrow_account.key,
+            initializer.key,
+            sol_amount,
+            escrow_info.sequence,
+        );
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_place_bid(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let bidder_account = next_account(accounts_info_iter, "bidder_account")?;
+
+        if !bidder_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let bidder_temp_token_account =
+            next_account(accounts_info_iter, "bidder_temp_token_account")?;
+        let bidder_token_to_receive_account =
+            next_account(accounts_info_iter, "bidder_token_to_receive_account")?;
+        let bid_account = next_account(accounts_info_iter, "bid_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if bid_account.owner != program_id || escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.bid_auction {
+            return Err(EscrowError::NotBidAuction.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.is_taker_allowed(bidder_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        // the reserve is the expected amount set at init
+        if amount < escrow_info.expected_amount || amount <= escrow_info.best_bid_amount {
+            return Err(EscrowError::BidTooLow.into());
+        }
+
+        let bidder_temp_token_account_info =
+            TokenAccount::unpack(&bidder_temp_token_account.try_borrow_data()?)?;
+        let bidder_token_to_receive_info =
+            TokenAccount::unpack(&bidder_token_to_receive_account.try_borrow_data()?)?;
+
+        if bidder_temp_token_account_info.mint != escrow_info.receive_token_mint
+            || bidder_token_to_receive_info.mint != escrow_info.temp_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        if bidder_temp_token_account_info.amount < amount {
+            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        }
+
+        let rent = Rent::get()?;
+
+        if !rent.is_exempt(bid_account.lamports(), bid_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        let bid_info = Bid::unpack_unchecked(&bid_account.try_borrow_data()?)?;
+        if bid_info.is_initialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        // the balance read above is only real in an account of the token program
+        check_token_program(
+            token_program,
+            &[bidder_temp_token_account, bidder_token_to_receive_account],
+        )?;
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            bidder_temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            bidder_account.key,
+            &[bidder_account.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                bidder_temp_token_account.clone(),
+                bidder_account.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        let bid_info = Bid {
+            is_initialized: true,
+            escrow_pubkey: *escrow_account.key,
+            bidder_pubkey: *bidder_account.key,
+            vault_pubkey: *bidder_temp_token_account.key,
+            bidder_token_to_receive_account_pubkey: *bidder_token_to_receive_account.key,
+            amount,
+            bump_seed: escrow_info.bump_seed,
+            escrow_initializer_pubkey: escrow_info.pda_initializer_pubkey,
+            escrow_nonce: escrow_info.nonce,
+            deposit_amount: escrow_info.remaining_deposit,
+        };
+        Bid::pack(bid_info, &mut bid_account.try_borrow_mut_data()?)?;
+
+        escrow_info.best_bid_amount = amount;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_accept_bid(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let initializer_token_to_receive_account =
+            next_account(accounts_info_iter, "initializer_token_to_receive_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let bid_account = next_account(accounts_info_iter, "bid_account")?;
+        let bidder_temp_token_account =
+            next_account(accounts_info_iter, "bidder_temp_token_account")?;
+        let bidder_token_to_receive_account =
+            next_account(accounts_info_iter, "bidder_token_to_receive_account")?;
+        let bidder_account = next_account(accounts_info_iter, "bidder_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if bid_account.owner != program_id || escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        let bid_info = Bid::unpack(&bid_account.try_borrow_data()?)?;
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if !escrow_info.bid_auction {
+            return Err(EscrowError::NotBidAuction.into());
+        }
+
+        // once expired the best bidder can withdraw, so the bids can no longer be accepted
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.initializer_token_to_receive_account_pubkey
+                != *initializer_token_to_receive_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if bid_info.escrow_pubkey != *escrow_account.key
+            || bid_info.vault_pubkey != *bidder_temp_token_account.key
+            || bid_info.bidder_token_to_receive_account_pubkey
+                != *bidder_token_to_receive_account.key
+            || bid_info.bidder_pubkey != *bidder_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the bidder is paid at least the deposit they bid on, a top up since only adds to it
+        if escrow_info.remaining_deposit < bid_info.deposit_amount {
+            return Err(EscrowError::BidDepositChanged.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                initializer_token_to_receive_account,
+                pdas_temp_token_account,
+                bidder_temp_token_account,
+                bidder_token_to_receive_account,
+            ],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let (fee_collector_account, fee) = Self::next_maker_fee(
+            accounts_info_iter,
+            &escrow_info,
+            token_program,
+            bid_info.amount,
+            program_id,
+        )?;
+
+        let transfer_bid_ix = token::transfer(
+            token_program.key,
+            bidder_temp_token_account.key,
+            initializer_token_to_receive_account.key,
+            &pda,
+            &[&pda],
+            math::checked_remainder(bid_info.amount, fee)?,
+        )?;
+
+        msg!("Calling the token program to pay the initializer from the bid...");
+        invoke_signed(
+            &transfer_bid_ix,
+            &[
+                token_program.clone(),
+                bidder_temp_token_account.clone(),
+                initializer_token_to_receive_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::collect_fee(
+            token_program,
+            bidder_temp_token_account,
+            fee_collector_account,
+            pda_account,
+            &seeds,
+            fee,
+        )?;
+
+        let transfer_deposit_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            bidder_token_to_receive_account.key,
+            &pda,
+            &[&pda],
+            escrow_info.remaining_deposit,
+        )?;
+
+        msg!("Calling the token program to transfer tokens to the bidder...");
+        invoke_signed(
+            &transfer_deposit_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                bidder_token_to_receive_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        escrow_info.advance_sequence();
+        events::emit_exchange(
+            escrow_account.key,
+            bidder_account.key,
+            escrow_info.remaining_deposit,
+            bid_info.amount,
+            escrow_info.sequence,
+        );
+
+        Self::return_taker_deposit(
+            token_program,
+            bidder_temp_token_account,
+            bidder_account.key,
+            pda_account,
+            &seeds,
+        )?;
+        Self::close_program_account(bid_account, bidder_account)?;
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_withdraw_bid(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let bidder_account = next_account(accounts_info_iter, "bidder_account")?;
+
+        if !bidder_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let bid_account = next_account(accounts_info_iter, "bid_account")?;
+        if bid_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let bid_info = Bid::unpack(&bid_account.try_borrow_data()?)?;
+
+        if bid_info.bidder_pubkey != *bidder_account.key {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+
+        let bidder_temp_token_account =
+            next_account(accounts_info_iter, "bidder_temp_token_account")?;
+        if bid_info.vault_pubkey != *bidder_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if bid_info.escrow_pubkey != *escrow_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the best bid has to stay while the auction can still be settled, every other bid and
+        // every bid on an accepted, cancelled or expired auction can be withdrawn
+        if escrow_account.owner == program_id {
+            let escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+            let clock = Clock::get()?;
+            if escrow_info.state == EscrowState::Active
+                && !escrow_info.is_expired(&clock)
+                && escrow_info.best_bid_amount == bid_info.amount
+            {
+                return Err(EscrowError::BestBidLocked.into());
+            }
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(token_program, &[bidder_temp_token_account])?;
+        let pda = PdaSeeds::of_bid(&bid_info).pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        Self::return_taker_deposit(
+            token_program,
+            bidder_temp_token_account,
+            bidder_account.key,
+            pda_account,
+            &PdaSeeds::of_bid(&bid_info),
+        )?;
+        Self::close_program_account(bid_account, bidder_account)
+    }
+
+    /// Takes `amount` of the deposit, or what is left of it when it's not set,
+    /// paying either the pro rata share of the expected amount or the `offered_payment` chosen by
+    /// the taker. With `simulate` it only runs the checks, moving nothing and leaving the escrow
+    /// as it was
+    #[allow(clippy::too_many_arguments)]
+    fn process_trade(
+        accounts: &[AccountInfo],
+        amount: Option<u64>,
+        offered_payment: Option<u64>,
+        max_payment: u64,
+        preimage: Option<[u8; 32]>,
+        merkle_proof: &[[u8; 32]],
+        simulate: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let trade_taker_account = next_account(accounts_info_iter, "trade_taker_account")?;
+
+        // checking if this account is the signer
+        if !trade_taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // getting the amount of Y tokens in takers account
+        let taker_token_to_send_account =
+            next_account(accounts_info_iter, "taker_token_to_send_account")?;
+
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        if taker_token_to_recieve_account.key == taker_token_to_send_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_writable(taker_token_to_send_account, "taker_token_to_send_account")?;
+        check_writable(
+            taker_token_to_recieve_account,
+            "taker_token_to_recieve_account",
+        )?;
+        check_writable(pdas_temp_token_account, "pdas_temp_token_account")?;
+        check_writable(
+            initializer_token_to_recieve_account,
+            "initializer_token_to_recieve_account",
+        )?;
+        check_writable(escrow_account, "escrow_account")?;
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        // tokens sent to the temp token account on top of the deposit aren't for sale, so the
+        // whole trade takes only what is left of the deposit
+        let amount = amount.unwrap_or(escrow_info.remaining_deposit);
+        if pdas_temp_token_account_info.amount < amount {
+            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        }
+
+        // locked until the trade is done, a trade nested in one of its CPIs finds it locked. A
+        // simulation makes no CPI that could nest one and leaves the escrow untouched
+        if !simulate {
+            escrow_info.state = EscrowState::Locked;
+            Escrow::pack(
+                escrow_info.clone(),
+                &mut escrow_account.try_borrow_mut_data()?,
+            )?;
+            escrow_info.state = EscrowState::Active;
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        // the deposit, the payment and every CPI go through the same token program
+        check_token_program(
+            token_program,
+            &[
+                taker_token_to_send_account,
+                taker_token_to_recieve_account,
+                pdas_temp_token_account,
+                initializer_token_to_recieve_account,
+            ],
+        )?;
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        if pdas_temp_token_account_info.owner != pda {
+            return Err(EscrowError::InvalidTempAccountOwner.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if escrow_info.two_phase {
+            return Err(EscrowError::TwoPhaseRequired.into());
+        }
+
+        if escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        if escrow_info.streaming {
+            return Err(EscrowError::StreamOnly.into());
+        }
+
+        if escrow_info.crowd_fill {
+            return Err(EscrowError::CrowdFillOnly.into());
+        }
+
+        if escrow_info.receive_native_sol {
+            return Err(EscrowError::PaidInSol.into());
+        }
+
+        if !escrow_info.is_taker_allowed_with_proof(trade_taker_account.key, merkle_proof)? {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        // what is left can always be taken, even when it's below the minimum
+        if amount < escrow_info.min_taker_amount && amount != escrow_info.remaining_deposit {
+            return Err(EscrowError::BelowMinimum.into());
+        }
+
+        // the basket assets can't be split and a revealed secret would let anyone take the rest,
+        // so the deposit is taken in one go
+        if escrow_info.is_exchange_only() && amount != escrow_info.remaining_deposit {
+            return Err(EscrowError::InvalidFillAmount.into());
+        }
+
+        if escrow_info.has_hash_lock() {
+            let preimage = preimage.ok_or(EscrowError::PreimageMismatch)?;
+            if hash(&preimage).to_bytes() != escrow_info.hash_lock {
+                return Err(EscrowError::PreimageMismatch.into());
+            }
+        }
+
+        if escrow_info.is_dutch_auction() {
+            if amount != escrow_info.remaining_deposit {
+                return Err(EscrowError::InvalidFillAmount.into());
+            }
+            escrow_info.remaining_expected = escrow_info.dutch_auction_price(clock.slot)?;
+        }
+
+        // the taker pays the share of the expected amount matching the part of the deposit they take
+        let payment = match offered_payment {
+            Some(offered_payment) => {
+                escrow_info.check_offered_payment(amount, offered_payment)?;
+                offered_payment
+            }
+            None => escrow_info.payment_for_fill(amount)?,
+        };
+
+        let mut taker_token_to_send_info =
+            TokenAccount::unpack(&taker_token_to_send_account.try_borrow_data()?)?;
+
+        // lamports sent straight to a wrapped SOL account only count once it's synced
+        if let COption::Some(rent_exempt_reserve) = taker_token_to_send_info.is_native {
+            if simulate {
+                // a simulation makes no CPI, the balance a sync would give is worked out instead
+                taker_token_to_send_info.amount = taker_token_to_send_account
+                    .lamports()
+                    .checked_sub(rent_exempt_reserve)
+                    .ok_or(EscrowError::AmountOverFlow)?;
+            } else {
+                let sync_native_ix =
+                    token::sync_native(token_program.key, taker_token_to_send_account.key)?;
+
+                msg!("Calling the token program to sync the taker's wrapped SOL account...");
+                invoke(
+                    &sync_native_ix,
+                    &[token_program.clone(), taker_token_to_send_account.clone()],
+                )?;
+
+                taker_token_to_send_info =
+                    TokenAccount::unpack(&taker_token_to_send_account.try_borrow_data()?)?;
+            }
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let taker_token_to_recieve_info =
+            TokenAccount::unpack(&taker_token_to_recieve_account.try_borrow_data()?)?;
+        if taker_token_to_recieve_info.mint != escrow_info.temp_token_mint
+            || pdas_temp_token_account_info.mint != escrow_info.temp_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        if taker_token_to_send_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // tokens sent to an account the taker doesn't own would be out of their reach
+        if taker_token_to_recieve_info.owner != *trade_taker_account.key {
+            return Err(EscrowError::InvalidTakerReceiveAccount.into());
+        }
+
+        // invoke(&transfer_x_to_trade_taker_ix, &[token_program, pdas_temp_token_account, taker_token_to_recieve_account, ])
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if fee_collector_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let fees = Self::load_config(config_account, program_id)?.trade_fees(
+            payment,
+            &escrow_info.initializer_pubkey,
+            trade_taker_account.key,
+        )?;
+        let taker_amount = fees.taker_amount()?;
+
+        if max_payment != 0 && taker_amount > max_payment {
+            return Err(EscrowError::SlippageExceeded.into());
+        }
+
+        Self::check_taker_balance(taker_token_to_send_info.amount, taker_amount)?;
+
+        let fee = fees.collected()?;
+        let initializer_payment = fees.initializer_amount()?;
+
+        if escrow_info.has_oracle() {
+            let oracle_account = next_account(accounts_info_iter, "oracle_account")?;
+            Self::check_oracle_price(oracle_account, &escrow_info, amount, payment, &clock)?;
+        }
+
+        if escrow_info.is_token_gated() {
+            let taker_gate_account = next_account(accounts_info_iter, "taker_gate_account")?;
+            Self::check_gate(taker_gate_account, trade_taker_account.key, &escrow_info)?;
+        }
+
+        if escrow_info.has_gatekeeper_network() {
+            let gateway_token_account = next_account(accounts_info_iter, "gateway_token_account")?;
+            if *gateway_token_account.owner != gateway::gateway_program::id() {
+                return Err(EscrowError::GatewayTokenMissing.into());
+            }
+
+            let gateway_token =
+                gateway::load_gateway_token(&gateway_token_account.try_borrow_data()?)?;
+            gateway::check_gateway_token(
+                &gateway_token,
+                trade_taker_account.key,
+                &escrow_info.gatekeeper_network,
+                clock.unix_timestamp,
+            )?;
+        }
+
+        if escrow_info.memo_required {
+            let instructions_sysvar = next_account(accounts_info_iter, "instructions_sysvar")?;
+            let memo = memo::find_memo(instructions_sysvar)?.ok_or(EscrowError::MemoRequired)?;
+            events::emit_memo(escrow_account.key, &memo);
+        }
+
+        // a metadata account that isn't the deposit mint's only fails the trade when enforced
+        let royalties = if escrow_info.has_royalties() {
+            let metadata_account = next_account(accounts_info_iter, "metadata_account")?;
+            match Self::royalty_shares(metadata_account, &escrow_info, payment) {
+                Ok(royalties) => royalties,
+                Err(error) if escrow_info.royalty_policy == RoyaltyPolicy::Enforced => {
+                    return Err(error.into())
+                }
+                Err(_) => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        let deposit_mint = if escrow_info.nft {
+            let deposit_mint = next_account(accounts_info_iter, "deposit_mint")?;
+            Self::check_nft_mint(deposit_mint, token_program, &escrow_info)?;
+            Some(deposit_mint)
+        } else {
+            None
+        };
+
+        let receipt_accounts = if escrow_info.has_receipt() {
+            let receipt_mint = next_account(accounts_info_iter, "receipt_mint")?;
+            if *receipt_mint.key != escrow_info.receipt_mint {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let taker_receipt_account = next_account(accounts_info_iter, "taker_receipt_account")?;
+            Some((receipt_mint, taker_receipt_account))
+        } else {
+            None
+        };
+
+        // whoever holds the position token is paid in place of the initializer
+        if escrow_info.has_position() {
+            let holder_position_account =
+                next_account(accounts_info_iter, "holder_position_account")?;
+            let holder = Self::position_holder(holder_position_account, &escrow_info)?;
+            if *initializer_account.key != holder {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let holder_token_to_receive_info =
+                TokenAccount::unpack(&initializer_token_to_recieve_account.try_borrow_data()?)?;
+            if holder_token_to_receive_info.owner != holder {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if holder_token_to_receive_info.mint != escrow_info.receive_token_mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+        } else {
+            if escrow_info.initializer_pubkey != *initializer_account.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            if escrow_info.initializer_token_to_receive_account_pubkey
+                != *initializer_token_to_recieve_account.key
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+
+        let royalty_total = royalties
+            .iter()
+            .try_fold(0u64, |total, (_, share)| total.checked_add(*share))
+            .ok_or(EscrowError::AmountOverFlow)?;
+        let initializer_payment = math::checked_remainder(initializer_payment, royalty_total)?;
+        let (initializer_payment, split_shares) = escrow_info.split_payment(initializer_payment)?;
+
+        let transfer_y_to_initializer_ix = token::transfer(
+            token_program.key,
+            taker_token_to_send_account.key,
+            initializer_token_to_recieve_account.key,
+            trade_taker_account.key,
+            &[&trade_taker_account.key],
+            initializer_payment,
+        )?;
+
+        // transfers y from taker to initializer
+        if !simulate {
+            invoke(
+                &transfer_y_to_initializer_ix,
+                &[
+                    token_program.clone(),
+                    taker_token_to_send_account.clone(),
+                    initializer_token_to_recieve_account.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        for (creator, share) in royalties {
+            let creator_token_account = next_account(accounts_info_iter, "creator_token_account")?;
+            let creator_token_info =
+                TokenAccount::unpack(&creator_token_account.try_borrow_data()?)?;
+            if creator_token_info.owner != creator {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if creator_token_info.mint != escrow_info.receive_token_mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            // a small payment can leave a creator nothing once rounded down
+            if share == 0 || simulate {
+                continue;
+            }
+
+            let transfer_royalty_ix = token::transfer(
+                token_program.key,
+                taker_token_to_send_account.key,
+                creator_token_account.key,
+                trade_taker_account.key,
+                &[trade_taker_account.key],
+                share,
+            )?;
+
+            msg!("Calling the token program to transfer a creator royalty...");
+            invoke(
+                &transfer_royalty_ix,
+                &[
+                    token_program.clone(),
+                    taker_token_to_send_account.clone(),
+                    creator_token_account.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        // only set for split payments, which are always taken whole
+        for (payment_split, share) in escrow_info.payment_splits().iter().zip(split_shares) {
+            let destination = next_account(accounts_info_iter, "payment_split_destination")?;
+            if *destination.key != payment_split.destination {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if simulate {
+                continue;
+            }
+
+            let transfer_share_ix = token::transfer(
+                token_program.key,
+                taker_token_to_send_account.key,
+                destination.key,
+                trade_taker_account.key,
+                &[trade_taker_account.key],
+                share,
+            )?;
+
+            msg!("Calling the token program to transfer a share of the payment...");
+            invoke(
+                &transfer_share_ix,
+                &[
+                    token_program.clone(),
+                    taker_token_to_send_account.clone(),
+                    destination.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        if fee > 0 && !simulate {
+            let transfer_fee_ix = token::transfer(
+                token_program.key,
+                taker_token_to_send_account.key,
+                fee_collector_account.key,
+                trade_taker_account.key,
+                &[trade_taker_account.key],
+                fee,
+            )?;
+
+            msg!("Calling the token program to transfer the fee...");
+            invoke(
+                &transfer_fee_ix,
+                &[
+                    token_program.clone(),
+                    taker_token_to_send_account.clone(),
+                    fee_collector_account.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        // only set for basket payments, which are always taken whole
+        for payment_leg in escrow_info.payment_legs() {
+            let taker_leg_account = next_account(accounts_info_iter, "taker_payment_leg_account")?;
+            let destination = next_account(accounts_info_iter, "payment_leg_destination")?;
+
+            if *destination.key != payment_leg.destination {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let taker_leg_info = TokenAccount::unpack(&taker_leg_account.try_borrow_data()?)?;
+            if taker_leg_info.mint != payment_leg.mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+            Self::check_taker_balance(taker_leg_info.amount, payment_leg.amount)?;
+            if simulate {
+                continue;
+            }
+
+            let transfer_leg_ix = token::transfer(
+                token_program.key,
+                taker_leg_account.key,
+                destination.key,
+                trade_taker_account.key,
+                &[trade_taker_account.key],
+                payment_leg.amount,
+            )?;
+
+            msg!("Calling the token program to transfer a payment leg...");
+            invoke(
+                &transfer_leg_ix,
+                &[
+                    token_program.clone(),
+                    taker_leg_account.clone(),
+                    destination.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        // every check passed, nothing has been moved
+        if simulate {
+            return Ok(());
+        }
+
+        if let Some((receipt_mint, taker_receipt_account)) = receipt_accounts {
+            Self::mint_nft(
+                token_program,
+                receipt_mint,
+                taker_receipt_account,
+                pda_account,
+                &seeds,
+            )?;
+            events::emit_receipt(
+                escrow_account.key,
+                trade_taker_account.key,
+                receipt_mint.key,
+                amount,
+                payment,
+            );
+        }
+
+        // the deposit stays in the temp token account and is claimed as it vests
+        if escrow_info.has_vesting() {
+            escrow_info.state = EscrowState::Vesting;
+            escrow_info.vesting_beneficiary = *trade_taker_account.key;
+            escrow_info.vesting_receive_account = *taker_token_to_recieve_account.key;
+            escrow_info.vesting_start_slot = clock.slot;
+            escrow_info.vesting_amount = amount;
+            escrow_info.remaining_deposit = 0;
+            escrow_info.remaining_expected = escrow_info.remaining_expected.saturating_sub(payment);
+            escrow_info.advance_sequence();
+            events::emit_exchange(
+                escrow_account.key,
+                trade_taker_account.key,
+                amount,
+                payment,
+                escrow_info.sequence,
+            );
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        if let Some(deposit_mint) = deposit_mint {
+            // the NFT is moved with its mint so the token program checks it is the one token
+            let transfer_nft_to_trade_taker_ix = token::transfer_checked(
+                token_program.key,
+                pdas_temp_token_account.key,
+                deposit_mint.key,
+                taker_token_to_recieve_account.key,
+                &pda,
+                &[&pda],
+                amount,
+                0,
+            )?;
+
+            msg!("Calling the token program to transfer the NFT to the taker...");
+            invoke_signed(
+                &transfer_nft_to_trade_taker_ix,
+                &[
+                    token_program.clone(),
+                    pdas_temp_token_account.clone(),
+                    deposit_mint.clone(),
+                    taker_token_to_recieve_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        } else {
+            let transfer_x_to_trade_taker_ix = token::transfer(
+                token_program.key,
+                pdas_temp_token_account.key,
+                taker_token_to_recieve_account.key,
+                &pda,
+                &[&pda],
+                amount,
+            )?;
+
+            msg!("Calling the token program to transfer tokens to the taker...");
+            invoke_signed(
+                &transfer_x_to_trade_taker_ix,
+                &[
+                    token_program.clone(),
+                    pdas_temp_token_account.clone(),
+                    taker_token_to_recieve_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        }
+
+        escrow_info.remaining_deposit =
+            math::checked_remainder(escrow_info.remaining_deposit, amount)?;
+        // an offered payment can be more than what is left of the expected amount
+        escrow_info.remaining_expected = escrow_info.remaining_expected.saturating_sub(payment);
+        escrow_info.advance_sequence();
+        events::emit_exchange(
+            escrow_account.key,
+            trade_taker_account.key,
+            amount,
+            payment,
+            escrow_info.sequence,
+        );
+
+        if escrow_info.remaining_deposit > 0 {
+            // what is left has changed so a pending counter-offer no longer applies
+            escrow_info.clear_counter_offer();
+            msg!(
+                "Escrow partially filled, {} left to fill",
+                escrow_info.remaining_deposit
+            );
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        // the initializer is paid in full, so their wrapped SOL goes back to being lamports
+        if escrow_info.unwrap_sol {
+            let close_receive_account_ix = token::close_account(
+                token_program.key,
+                initializer_token_to_recieve_account.key,
+                initializer_account.key,
+                &pda,
+                &[&pda],
+            )?;
+
+            msg!("Calling the token program to unwrap the initializer's SOL...");
+            invoke_signed(
+                &close_receive_account_ix,
+                &[
+                    token_program.clone(),
+                    initializer_token_to_recieve_account.clone(),
+                    initializer_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        }
+
+        Self::empty_basket(
+            accounts_info_iter,
+            &escrow_info,
+            None,
+            token_program,
+            initializer_account,
+            pda_account,
+        )?;
+
+        // the temp token account can only be closed empty, with tokens left over the initializer
+        // gets it back instead
+        let surplus = math::checked_remainder(pdas_temp_token_account_info.amount, amount)?;
+        if surplus > 0 {
+            let owner_change_ix = token::set_authority(
+                token_program.key,
+                pdas_temp_token_account.key,
+                Some(initializer_account.key),
+                spl_token::instruction::AuthorityType::AccountOwner,
+                &pda,
+                &[&pda],
+            )?;
+
+            msg!(
+                "Calling the token program to give the initializer the temp account back with {} left over...",
+                surplus
+            );
+            invoke_signed(
+                &owner_change_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+            return Self::close_program_account(escrow_account, initializer_account);
+        }
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_simulate(
+        accounts: &[AccountInfo],
+        amount: u64,
+        preimage: Option<[u8; 32]>,
+        merkle_proof: &[[u8; 32]],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        // 0 stands for the whole deposit, like Exchange takes it
+        let amount = if amount == 0 { None } else { Some(amount) };
+        Self::process_trade(
+            accounts,
+            amount,
+            None,
+            0,
+            preimage,
+            merkle_proof,
+            true,
+            program_id,
+        )
+    }
+
+    fn process_exchange_batch(
+        accounts: &[AccountInfo],
+        count: u8,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let trade_taker_account = next_account(accounts_info_iter, "trade_taker_account")?;
+        let taker_token_to_send_account =
+            next_account(accounts_info_iter, "taker_token_to_send_account")?;
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[taker_token_to_send_account, taker_token_to_recieve_account],
+        )?;
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+
+        if count == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        for _ in 0..count {
+            let pdas_temp_token_account =
+                next_account(accounts_info_iter, "pdas_temp_token_account")?;
+            let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+            let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+            let initializer_token_to_recieve_account =
+                next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+            let pda_account = next_account(accounts_info_iter, "pda_account")?;
+
+            if escrow_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            // same accounts in the same order as a single Exchange
+            let trade_accounts = [
+                trade_taker_account.clone(),
+                taker_token_to_send_account.clone(),
+                taker_token_to_recieve_account.clone(),
+                pdas_temp_token_account.clone(),
+                initializer_account.clone(),
+                initializer_token_to_recieve_account.clone(),
+                escrow_account.clone(),
+                token_program.clone(),
+                pda_account.clone(),
+                fee_collector_account.clone(),
+                config_account.clone(),
+            ];
+            Self::process_trade(&trade_accounts, None, None, 0, None, &[], false, program_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_exchange_with_ata_creation(
+        accounts: &[AccountInfo],
+        max_payment: u64,
+        preimage: Option<[u8; 32]>,
+        merkle_proof: &[[u8; 32]],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if accounts.len() < 4 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (ata_accounts, trade_accounts) = accounts.split_at(4);
+        let account_info_iter = &mut ata_accounts.iter();
+        let mint = next_account(account_info_iter, "mint")?;
+        let ata_program = next_account(account_info_iter, "ata_program")?;
+        let system_program = next_account(account_info_iter, "system_program")?;
+        let rent_sysvar = next_account(account_info_iter, "rent_sysvar")?;
+
+        let trade_taker_account = trade_accounts
+            .first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let taker_token_to_recieve_account = trade_accounts
+            .get(2)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let token_program = trade_accounts
+            .get(7)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        // associated token accounts are only derived for the spl-token program
+        if *ata_program.key != spl_associated_token_account::id()
+            || *token_program.key != spl_token::id()
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if *taker_token_to_recieve_account.key
+            != get_associated_token_address(trade_taker_account.key, mint.key)
+        {
+            return Err(EscrowError::InvalidTakerReceiveAccount.into());
+        }
+
+        // Exchange checks the mint is the deposit's once the account exists
+        if taker_token_to_recieve_account.data_is_empty() {
+            let create_ata_ix = create_associated_token_account(
+                trade_taker_account.key,
+                trade_taker_account.key,
+                mint.key,
+            );
+
+            msg!("Calling the associated token account program to create the taker's account...");
+            invoke(
+                &create_ata_ix,
+                &[
+                    trade_taker_account.clone(),
+                    taker_token_to_recieve_account.clone(),
+                    trade_taker_account.clone(),
+                    mint.clone(),
+                    system_program.clone(),
+                    token_program.clone(),
+                    rent_sysvar.clone(),
+                    ata_program.clone(),
+                ],
+            )?;
+        }
+
+        Self::process_trade(
+            trade_accounts,
+            None,
+            None,
+            max_payment,
+            preimage,
+            merkle_proof,
+            false,
+            program_id,
+        )
+    }
+
+    fn process_exchange_with_sol(
+        accounts: &[AccountInfo],
+        max_payment: u64,
+        preimage: Option<[u8; 32]>,
+        merkle_proof: &[[u8; 32]],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if accounts.len() < 3 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (wrap_accounts, trade_accounts) = accounts.split_at(3);
+        let account_info_iter = &mut wrap_accounts.iter();
+        let native_mint = next_account(account_info_iter, "native_mint")?;
+        let system_program_account = next_account(account_info_iter, "system_program")?;
+        let rent_sysvar = next_account(account_info_iter, "rent_sysvar")?;
+
+        let trade_taker_account = trade_accounts
+            .first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let taker_wrapped_sol_account = trade_accounts
+            .get(1)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let token_program = trade_accounts
+            .get(7)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        // the native mint is the classic token program's
+        if *token_program.key != spl_token::id()
+            || *system_program_account.key != system_program::id()
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if *native_mint.key != spl_token::native_mint::id() {
+            return Err(EscrowError::NotWrappedSol.into());
+        }
+
+        if max_payment == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if !trade_taker_account.is_signer || !taker_wrapped_sol_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if !taker_wrapped_sol_account.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        // the wrapped SOL account holds the payment on top of its rent
+        let lamports = Rent::get()?
+            .minimum_balance(TokenAccount::LEN)
+            .checked_add(max_payment)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        if trade_taker_account.lamports() < lamports {
+            return Err(EscrowError::InsufficientLamports.into());
+        }
+
+        msg!("Calling the system program to create the taker's wrapped SOL account...");
+        invoke(
+            &system_instruction::create_account(
+                trade_taker_account.key,
+                taker_wrapped_sol_account.key,
+                lamports,
+                TokenAccount::LEN as u64,
+                token_program.key,
+            ),
+            &[
+                trade_taker_account.clone(),
+                taker_wrapped_sol_account.clone(),
+                system_program_account.clone(),
+            ],
+        )?;
+
+        let initialize_account_ix = token::initialize_account(
+            token_program.key,
+            taker_wrapped_sol_account.key,
+            native_mint.key,
+            trade_taker_account.key,
+        )?;
+
+        msg!("Calling the token program to wrap the taker's SOL...");
+        invoke(
+            &initialize_account_ix,
+            &[
+                token_program.clone(),
+                taker_wrapped_sol_account.clone(),
+                native_mint.clone(),
+                trade_taker_account.clone(),
+                rent_sysvar.clone(),
+            ],
+        )?;
+
+        Self::process_trade(
+            trade_accounts,
+            None,
+            None,
+            max_payment,
+            preimage,
+            merkle_proof,
+            false,
+            program_id,
+        )?;
+
+        let close_wrapped_sol_account_ix = token::close_account(
+            token_program.key,
+            taker_wrapped_sol_account.key,
+            trade_taker_account.key,
+            trade_taker_account.key,
+            &[trade_taker_account.key],
+        )?;
+
+        msg!("Calling the token program to unwrap what the taker didn't spend...");
+        invoke(
+            &close_wrapped_sol_account_ix,
+            &[
+                token_program.clone(),
+                taker_wrapped_sol_account.clone(),
+                trade_taker_account.clone(),
+                trade_taker_account.clone(),
+            ],
+        )
+    }
+
+    fn process_trade_for_sol(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let trade_taker_account = next_account(accounts_info_iter, "trade_taker_account")?;
+
+        if !trade_taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_writable(
+            taker_token_to_recieve_account,
+            "taker_token_to_recieve_account",
+        )?;
+        check_writable(pdas_temp_token_account, "pdas_temp_token_account")?;
+        check_writable(escrow_account, "escrow_account")?;
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if !escrow_info.receive_native_sol {
+            return Err(EscrowError::NotPaidInSol.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[taker_token_to_recieve_account, pdas_temp_token_account],
+        )?;
+
+        let system_program_account = next_account(accounts_info_iter, "system_program")?;
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.is_taker_allowed(trade_taker_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let taker_token_to_recieve_info =
+            TokenAccount::unpack(&taker_token_to_recieve_account.try_borrow_data()?)?;
+        if taker_token_to_recieve_info.mint != escrow_info.temp_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // tokens sent to an account the taker doesn't own would be out of their reach
+        if taker_token_to_recieve_info.owner != *trade_taker_account.key {
+            return Err(EscrowError::InvalidTakerReceiveAccount.into());
+        }
+
+        // the fee is paid in SOL too, into the fee collector's wrapped SOL account
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        check_token_program(token_program, &[fee_collector_account])?;
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if !fee_collector_info.is_native() {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let sol_amount = escrow_info.remaining_expected;
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let fees = Self::load_config(config_account, program_id)?.trade_fees(
+            sol_amount,
+            &escrow_info.initializer_pubkey,
+            trade_taker_account.key,
+        )?;
+        let fee = fees.collected()?;
+        let initializer_payment = fees.initializer_amount()?;
+
+        // the SOL goes to the initializer unless they picked another account at init
+        let destination = if escrow_info.initializer_token_to_receive_account_pubkey
+            == *initializer_account.key
+        {
+            initializer_account
+        } else {
+            next_account(accounts_info_iter, "sol_destination")?
+        };
+        if escrow_info.initializer_token_to_receive_account_pubkey != *destination.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // only system accounts can pay with a system transfer
+        if *trade_taker_account.owner != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if trade_taker_account.lamports() < fees.taker_amount()? {
+            return Err(EscrowError::InsufficientLamports.into());
+        }
+
+        // a destination left below the rent exempt minimum would be rejected by the runtime
+        let destination_lamports = destination
+            .lamports()
+            .checked_add(initializer_payment)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        if !Rent::get()?.is_exempt(destination_lamports, destination.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        msg!("Calling the system program to transfer SOL to the initializer...");
+        invoke(
+            &system_instruction::transfer(
+                trade_taker_account.key,
+                destination.key,
+                initializer_payment,
+            ),
+            &[
+                trade_taker_account.clone(),
+                destination.clone(),
+                system_program_account.clone(),
+            ],
+        )?;
+
+        if fee > 0 {
+            msg!("Calling the system program to transfer the fee...");
+            invoke(
+                &system_instruction::transfer(
+                    trade_taker_account.key,
+                    fee_collector_account.key,
+                    fee,
+                ),
+                &[
+                    trade_taker_account.clone(),
+                    fee_collector_account.clone(),
+                    system_program_account.clone(),
+                ],
+            )?;
+
+            // the lamports only count as wrapped SOL once the account is synced
+            let sync_native_ix = token::sync_native(token_program.key, fee_collector_account.key)?;
+
+            msg!("Calling the token program to sync the fee collector's wrapped SOL account...");
+            invoke(
+                &sync_native_ix,
+                &[token_program.clone(), fee_collector_account.clone()],
+            )?;
+        }
+
+        let transfer_x_to_trade_taker_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            taker_token_to_recieve_account.key,
+            &pda,
+            &[&pda],
+            escrow_info.remaining_deposit,
+        )?;
+
+        msg!("Calling the token program to transfer tokens to the taker...");
+        invoke_signed(
+            &transfer_x_to_trade_taker_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                taker_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        escrow_info.advance_sequence();
+        events::emit_exchange(
+            escrow_account.key,
+            trade_taker_account.key,
+            escrow_info.remaining_deposit,
+            sol_amount,
+            escrow_info.sequence,
+        );
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_cancel_escrow(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_token_to_refund_account =
+            next_account(accounts_info_iter, "initializer_token_to_refund_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        // closing now would leave the taker's deposit owned by the PDA, contributions to a crowd
+        // fill are only refunded once it expired
+        if escrow_info.has_pending_deposit() || escrow_info.crowd_total > 0 {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        // the taker may already have locked their side of the swap elsewhere against the same hash
+        if escrow_info.has_hash_lock() && !escrow_info.is_expired(&Clock::get()?) {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, initializer_token_to_refund_account],
+        )?;
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        Self::return_close_authority(accounts_info_iter, &escrow_info, token_program, pda_account)?;
+
+        let owner = if escrow_info.has_position() {
+            let holder_position_account =
+                next_account(accounts_info_iter, "holder_position_account")?;
+            let position_mint = next_account(accounts_info_iter, "position_mint")?;
+            if *position_mint.key != escrow_info.position_mint {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let holder = Self::position_holder(holder_position_account, &escrow_info)?;
+            if holder != *initializer_account.key {
+                return Err(EscrowError::UnauthorizedInitializer.into());
+            }
+
+            // the position is settled along with the escrow
+            let burn_position_ix = token::burn(
+                token_program.key,
+                holder_position_account.key,
+                position_mint.key,
+                initializer_account.key,
+                &[initializer_account.key],
+                1,
+            )?;
+
+            msg!("Calling the token program to burn the position token...");
+            invoke(
+                &burn_position_ix,
+                &[
+                    token_program.clone(),
+                    holder_position_account.clone(),
+                    position_mint.clone(),
+                    initializer_account.clone(),
+                ],
+            )?;
+
+            holder
+        } else {
+            escrow_info.initializer_pubkey
+        };
+
+        if owner != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if escrow_info.crowd_fill {
+            let crowd_vault = next_account(accounts_info_iter, "crowd_vault")?;
+            Self::close_empty_crowd_vault(
+                &escrow_info,
+                token_program,
+                crowd_vault,
+                initializer_account,
+                pda_account,
+            )?;
+        }
+
+        if escrow_info.has_cancel_penalty() {
+            Self::charge_cancel_penalty(
+                accounts_info_iter,
+                &escrow_info,
+                token_program,
+                pdas_temp_token_account,
+                initializer_account,
+                pda_account,
+                program_id,
+            )?;
+        }
+
+        Self::empty_basket(
+            accounts_info_iter,
+            &escrow_info,
+            Some(&owner),
+            token_program,
+            initializer_account,
+            pda_account,
+        )?;
+
+        Self::refund_and_close(
+            token_program,
+            pdas_temp_token_account,
+            initializer_token_to_refund_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &PdaSeeds::of_escrow(&escrow_info),
+        )
+    }
+
+    fn process_refund_expired(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_token_to_refund_account =
+            next_account(accounts_info_iter, "initializer_token_to_refund_account")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        // anyone can crank this, so it must never touch an escrow that can still be taken
+        let clock = Clock::get()?;
+        if !escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        // a filled crowd fill is settled even after it expired
+        if escrow_info.crowd_fill && escrow_info.remaining_expected == 0 {
+            return Err(EscrowError::CrowdFilled.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+        let initializer_token_to_refund_info =
+            TokenAccount::unpack(&initializer_token_to_refund_account.try_borrow_data()?)?;
+
+        if initializer_token_to_refund_info.mint != pdas_temp_token_account_info.mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, initializer_token_to_refund_account],
+        )?;
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        Self::return_close_authority(accounts_info_iter, &escrow_info, token_program, pda_account)?;
+
+        // the refund goes to whoever holds the position, the initializer when there is none
+        let owner = Self::escrow_owner(accounts_info_iter, &escrow_info)?;
+        if owner != *initializer_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if initializer_token_to_refund_info.owner != owner {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.crowd_fill {
+            let crowd_vault = next_account(accounts_info_iter, "crowd_vault")?;
+            Self::close_empty_crowd_vault(
+                &escrow_info,
+                token_program,
+                crowd_vault,
+                initializer_account,
+                pda_account,
+            )?;
+        }
+
+        Self::empty_basket(
+            accounts_info_iter,
+            &escrow_info,
+            Some(&owner),
+            token_program,
+            initializer_account,
+            pda_account,
+        )?;
+
+        Self::refund_and_close(
+            token_program,
+            pdas_temp_token_account,
+            initializer_token_to_refund_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &PdaSeeds::of_escrow(&escrow_info),
+        )
+    }
+
+    fn process_propose_counter_offer(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let proposer_account = next_account(accounts_info_iter, "proposer_account")?;
+
+        if !proposer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let proposer_token_to_send_account =
+            next_account(accounts_info_iter, "proposer_token_to_send_account")?;
+        let proposer_token_to_receive_account =
+            next_account(accounts_info_iter, "proposer_token_to_receive_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        if escrow_info.streaming {
+            return Err(EscrowError::StreamOnly.into());
+        }
+
+        if !escrow_info.is_taker_allowed(proposer_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                proposer_token_to_send_account,
+                proposer_token_to_receive_account,
+            ],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let approve_pda_ix = token::approve(
+            token_program.key,
+            proposer_token_to_send_account.key,
+            &pda,
+            proposer_account.key,
+            &[proposer_account.key],
+            amount,
+        )?;
+
+        msg!("Calling the token program to approve the pda as delegate...");
+        invoke(
+            &approve_pda_ix,
+            &[
+                token_program.clone(),
+                proposer_token_to_send_account.clone(),
+                pda_account.clone(),
+                proposer_account.clone(),
+            ],
+        )?;
+
+        escrow_info.counter_offer_taker = *proposer_account.key;
+        escrow_info.counter_offer_amount = amount;
+        escrow_info.counter_offer_payment_account = *proposer_token_to_send_account.key;
+        escrow_info.counter_offer_receive_account = *proposer_token_to_receive_account.key;
+        escrow_info.advance_sequence();
+
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_accept_counter_offer(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let proposer_token_to_send_account =
+            next_account(accounts_info_iter, "proposer_token_to_send_account")?;
+        let proposer_token_to_receive_account =
+            next_account(accounts_info_iter, "proposer_token_to_receive_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.has_counter_offer() {
+            return Err(EscrowError::NoCounterOffer.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey
+            != *initializer_token_to_recieve_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.counter_offer_payment_account != *proposer_token_to_send_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.counter_offer_receive_account != *proposer_token_to_receive_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                initializer_token_to_recieve_account,
+                pdas_temp_token_account,
+                proposer_token_to_send_account,
+                proposer_token_to_receive_account,
+            ],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        // the proposer may have spent their tokens or revoked the approval since proposing
+        let proposer_token_to_send_info =
+            TokenAccount::unpack(&proposer_token_to_send_account.try_borrow_data()?)?;
+        if proposer_token_to_send_info.owner != escrow_info.counter_offer_taker
+            || proposer_token_to_send_info.delegate != COption::Some(pda)
+            || proposer_token_to_send_info.delegated_amount < escrow_info.counter_offer_amount
+            || proposer_token_to_send_info.amount < escrow_info.counter_offer_amount
+        {
+            return Err(EscrowError::CounterOfferUnfunded.into());
+        }
+
+        // the proposer only approved the counter-offer, so the fee comes out of it
+        let (fee_collector_account, fee) = Self::next_maker_fee(
+            accounts_info_iter,
+            &escrow_info,
+            token_program,
+            escrow_info.counter_offer_amount,
+            program_id,
+        )?;
+        let initializer_payment = math::checked_remainder(escrow_info.counter_offer_amount, fee)?;
+
+        let transfer_y_to_initializer_ix = token::transfer(
+            token_program.key,
+            proposer_token_to_send_account.key,
+            initializer_token_to_recieve_account.key,
+            &pda,
+            &[&pda],
+            initializer_payment,
+        )?;
+
+        msg!("Calling the token program to transfer the counter-offer to the initializer...");
+        invoke_signed(
+            &transfer_y_to_initializer_ix,
+            &[
+                token_program.clone(),
+                proposer_token_to_send_account.clone(),
+                initializer_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::collect_fee(
+            token_program,
+            proposer_token_to_send_account,
+            fee_collector_account,
+            pda_account,
+            &seeds,
+            fee,
+        )?;
+
+        let transfer_x_to_proposer_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            proposer_token_to_receive_account.key,
+            &pda,
+            &[&pda],
+            escrow_info.remaining_deposit,
+        )?;
+
+        msg!("Calling the token program to transfer tokens to the proposer...");
+        invoke_signed(
+            &transfer_x_to_proposer_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                proposer_token_to_receive_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_partial_trade(
+        accounts: &[AccountInfo],
+        amount_to_take: u64,
+        amount_to_give: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        Self::process_trade(
+            accounts,
+            Some(amount_to_take),
+            Some(amount_to_give),
+            0,
+            None,
+            &[],
+            false,
+            program_id,
+        )
+    }
+
+    fn process_deposit_for_exchange(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let trade_taker_account = next_account(accounts_info_iter, "trade_taker_account")?;
+
+        if !trade_taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_temp_token_account =
+            next_account(accounts_info_iter, "taker_temp_token_account")?;
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.two_phase {
+            return Err(EscrowError::NotTwoPhase.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(&clock) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.is_taker_allowed(trade_taker_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        let taker_temp_token_account_info =
+            TokenAccount::unpack(&taker_temp_token_account.try_borrow_data()?)?;
+        if taker_temp_token_account_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        if taker_temp_token_account_info.amount < escrow_info.remaining_expected {
+            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        // the balance read above is only real in an account of the token program
+        check_token_program(
+            token_program,
+            &[taker_temp_token_account, taker_token_to_recieve_account],
+        )?;
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            taker_temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            trade_taker_account.key,
+            &[trade_taker_account.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                taker_temp_token_account.clone(),
+                trade_taker_account.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        escrow_info.pending_taker = *trade_taker_account.key;
+        escrow_info.pending_taker_vault = *taker_temp_token_account.key;
+        escrow_info.pending_taker_receive_account = *taker_token_to_recieve_account.key;
+        escrow_info.advance_sequence();
+
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_confirm_exchange(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let taker_temp_token_account =
+            next_account(accounts_info_iter, "taker_temp_token_account")?;
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if !escrow_info.has_pending_deposit() {
+            return Err(EscrowError::NoPendingDeposit.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey
+            != *initializer_token_to_recieve_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.pending_taker_vault != *taker_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.pending_taker_receive_account != *taker_token_to_recieve_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                initializer_token_to_recieve_account,
+                pdas_temp_token_account,
+                taker_temp_token_account,
+                taker_token_to_recieve_account,
+            ],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let (fee_collector_account, fee) = Self::next_maker_fee(
+            accounts_info_iter,
+            &escrow_info,
+            token_program,
+            escrow_info.remaining_expected,
+            program_id,
+        )?;
+
+        Self::complete_pending_exchange(
+            &escrow_info,
+            token_program,
+            initializer_account,
+            initializer_token_to_recieve_account,
+            pdas_temp_token_account,
+            taker_temp_token_account,
+            taker_token_to_recieve_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+            fee_collector_account,
+            fee,
+        )
+    }
+
+    fn process_reject_exchange(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let signer_account = next_account(accounts_info_iter, "signer_account")?;
+
+        if !signer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_temp_token_account =
+            next_account(accounts_info_iter, "taker_temp_token_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if !escrow_info.has_pending_deposit() {
+            return Err(EscrowError::NoPendingDeposit.into());
+        }
+
+        if *signer_account.key != escrow_info.initializer_pubkey
+            && *signer_account.key != escrow_info.pending_taker
+        {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+
+        if escrow_info.pending_taker_vault != *taker_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(token_program, &[taker_temp_token_account])?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        Self::return_taker_deposit(
+            token_program,
+            taker_temp_token_account,
+            &escrow_info.pending_taker,
+            pda_account,
+            &seeds,
+        )?;
+
+        escrow_info.clear_pending_deposit();
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_raise_dispute(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let signer_account = next_account(accounts_info_iter, "signer_account")?;
+
+        if !signer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.has_arbiter() {
+            return Err(EscrowError::NoArbiter.into());
+        }
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if !escrow_info.has_pending_deposit() {
+            return Err(EscrowError::NoPendingDeposit.into());
+        }
+
+        if *signer_account.key != escrow_info.initializer_pubkey
+            && *signer_account.key != escrow_info.pending_taker
+        {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+
+        escrow_info.dispute_status = DisputeStatus::Raised;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_resolve_dispute(
+        accounts: &[AccountInfo],
+        release_to_taker: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let arbiter_account = next_account(accounts_info_iter, "arbiter_account")?;
+
+        if !arbiter_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        let initializer_token_to_refund_account =
+            next_account(accounts_info_iter, "initializer_token_to_refund_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let taker_temp_token_account =
+            next_account(accounts_info_iter, "taker_temp_token_account")?;
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.has_arbiter() {
+            return Err(EscrowError::NoArbiter.into());
+        }
+
+        if escrow_info.arbiter != *arbiter_account.key {
+            return Err(EscrowError::UnauthorizedArbiter.into());
+        }
+
+        if !escrow_info.is_disputed() {
+            return Err(EscrowError::NotDisputed.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key
+            || escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.pending_taker_vault != *taker_temp_token_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, taker_temp_token_account],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        if release_to_taker {
+            if escrow_info.initializer_token_to_receive_account_pubkey
+                != *initializer_token_to_recieve_account.key
+                || escrow_info.pending_taker_receive_account != *taker_token_to_recieve_account.key
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let (fee_collector_account, fee) = Self::next_maker_fee(
+                accounts_info_iter,
+                &escrow_info,
+                token_program,
+                escrow_info.remaining_expected,
+                program_id,
+            )?;
+
+            escrow_info.advance_sequence();
+            events::emit_exchange(
+                escrow_account.key,
+                &escrow_info.pending_taker,
+                escrow_info.remaining_deposit,
+                escrow_info.remaining_expected,
+                escrow_info.sequence,
+            );
+
+            return Self::complete_pending_exchange(
+                &escrow_info,
+                token_program,
+                initializer_account,
+                initializer_token_to_recieve_account,
+                pdas_temp_token_account,
+                taker_temp_token_account,
+                taker_token_to_recieve_account,
+                escrow_account,
+                pda_account,
+                &seeds,
+                fee_collector_account,
+                fee,
+            );
+        }
+
+        let initializer_token_to_refund_info =
+            TokenAccount::unpack(&initializer_token_to_refund_account.try_borrow_data()?)?;
+        if initializer_token_to_refund_info.owner != escrow_info.initializer_pubkey
+            || initializer_token_to_refund_info.mint != escrow_info.temp_token_mint
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Self::return_taker_deposit(
+            token_program,
+            taker_temp_token_account,
+            &escrow_info.pending_taker,
+            pda_account,
+            &seeds,
+        )?;
+
+        escrow_info.advance_sequence();
+        events::emit_cancel(
+            escrow_account.key,
+            initializer_account.key,
+            escrow_info.sequence,
+        );
+
+        Self::refund_and_close(
+            token_program,
+            pdas_temp_token_account,
+            initializer_token_to_refund_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_claim_vested(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let beneficiary_account = next_account(accounts_info_iter, "beneficiary_account")?;
+
+        if !beneficiary_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.state != EscrowState::Vesting {
+            return Err(EscrowError::NotVesting.into());
+        }
+
+        if escrow_info.vesting_beneficiary != *beneficiary_account.key {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        if escrow_info.vesting_receive_account != *taker_token_to_recieve_account.key
+            || escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.initializer_pubkey != *initializer_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[taker_token_to_recieve_account, pdas_temp_token_account],
+        )?;
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let clock = Clock::get()?;
+        let claimable = math::checked_remainder(
+            escrow_info.vested_amount(clock.slot)?,
+            escrow_info.vesting_claimed,
+        )?;
+        if claimable == 0 {
+            return Err(EscrowError::NothingToClaim.into());
+        }
+
+        let transfer_vested_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            taker_token_to_recieve_account.key,
+            &pda,
+            &[&pda],
+            claimable,
+        )?;
+
+        msg!("Calling the token program to transfer vested tokens to the taker...");
+        invoke_signed(
+            &transfer_vested_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                taker_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        escrow_info.vesting_claimed = escrow_info
+            .vesting_claimed
+            .checked_add(claimable)
+            .ok_or(EscrowError::AmountOverFlow)?;
+
+        if escrow_info.vesting_claimed < escrow_info.vesting_amount {
+            escrow_info.advance_sequence();
+            msg!(
+                "Claimed {}, {} left to vest",
+                claimable,
+                escrow_info.vesting_amount - escrow_info.vesting_claimed
+            );
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_stream_payment(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let trade_taker_account = next_account(accounts_info_iter, "trade_taker_account")?;
+
+        if !trade_taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_token_to_send_account =
+            next_account(accounts_info_iter, "taker_token_to_send_account")?;
+        let taker_token_to_recieve_account =
+            next_account(accounts_info_iter, "taker_token_to_recieve_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.streaming {
+            return Err(EscrowError::NotStreaming.into());
+        }
+
+        if escrow_info.is_expired(&Clock::get()?) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.is_taker_allowed(trade_taker_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        if amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key
+            || escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.initializer_token_to_receive_account_pubkey
+                != *initializer_token_to_recieve_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                taker_token_to_send_account,
+                taker_token_to_recieve_account,
+                pdas_temp_token_account,
+                initializer_token_to_recieve_account,
+            ],
+        )?;
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let taker_token_to_send_info =
+            TokenAccount::unpack(&taker_token_to_send_account.try_borrow_data()?)?;
+        let taker_token_to_recieve_info =
+            TokenAccount::unpack(&taker_token_to_recieve_account.try_borrow_data()?)?;
+        if taker_token_to_send_info.mint != escrow_info.receive_token_mint
+            || taker_token_to_recieve_info.mint != escrow_info.temp_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if fee_collector_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // the last installment only pays what is left
+        let payment = amount.min(escrow_info.remaining_expected);
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let fees = Self::load_config(config_account, program_id)?.trade_fees(
+            payment,
+            &escrow_info.initializer_pubkey,
+            trade_taker_account.key,
+        )?;
+        Self::check_taker_balance(taker_token_to_send_info.amount, fees.taker_amount()?)?;
+
+        let paid = escrow_info
+            .stream_paid
+            .checked_add(payment)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        let release = math::checked_remainder(
+            escrow_info.stream_release_for(paid)?,
+            escrow_info.stream_released,
+        )?;
+
+        let fee = fees.collected()?;
+        let initializer_payment = fees.initializer_amount()?;
+
+        let transfer_y_to_initializer_ix = token::transfer(
+            token_program.key,
+            taker_token_to_send_account.key,
+            initializer_token_to_recieve_account.key,
+            trade_taker_account.key,
+            &[trade_taker_account.key],
+            initializer_payment,
+        )?;
+
+        msg!("Calling the token program to transfer the installment to the initializer...");
+        invoke(
+            &transfer_y_to_initializer_ix,
+            &[
+                token_program.clone(),
+                taker_token_to_send_account.clone(),
+                initializer_token_to_recieve_account.clone(),
+                trade_taker_account.clone(),
+            ],
+        )?;
+
+        if fee > 0 {
+            let transfer_fee_ix = token::transfer(
+                token_program.key,
+                taker_token_to_send_account.key,
+                fee_collector_account.key,
+                trade_taker_account.key,
+                &[trade_taker_account.key],
+                fee,
+            )?;
+
+            msg!("Calling the token program to transfer the fee...");
+            invoke(
+                &transfer_fee_ix,
+                &[
+                    token_program.clone(),
+                    taker_token_to_send_account.clone(),
+                    fee_collector_account.clone(),
+                    trade_taker_account.clone(),
+                ],
+            )?;
+        }
+
+        // a small installment can pay for less than a whole token of the deposit
+        if release > 0 {
+            let transfer_x_to_trade_taker_ix = token::transfer(
+                token_program.key,
+                pdas_temp_token_account.key,
+                taker_token_to_recieve_account.key,
+                &pda,
+                &[&pda],
+                release,
+            )?;
+
+            msg!("Calling the token program to release the deposit to the taker...");
+            invoke_signed(
+                &transfer_x_to_trade_taker_ix,
+                &[
+                    token_program.clone(),
+                    pdas_temp_token_account.clone(),
+                    taker_token_to_recieve_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        }
+
+        escrow_info.stream_paid = paid;
+        escrow_info.stream_released = escrow_info
+            .stream_released
+            .checked_add(release)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.remaining_deposit =
+            math::checked_remainder(escrow_info.remaining_deposit, release)?;
+        escrow_info.remaining_expected =
+            math::checked_remainder(escrow_info.remaining_expected, payment)?;
+        escrow_info.advance_sequence();
+        events::emit_exchange(
+            escrow_account.key,
+            trade_taker_account.key,
+            release,
+            payment,
+            escrow_info.sequence,
+        );
+
+        if escrow_info.remaining_expected > 0 {
+            msg!(
+                "Installment paid, {} left to pay",
+                escrow_info.remaining_expected
+            );
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_init_crowd_fill(
+        accounts: &[AccountInfo],
+        amount: u64,
+        expiry_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if accounts.len() <= INIT_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (init_accounts, crowd_accounts) = accounts.split_at(INIT_ESCROW_ACCOUNTS);
+        Self::process_init_escrow(
+            init_accounts,
+            amount,
+            InitOptions {
+                expiry_slot,
+                ..InitOptions::default()
+            },
+            program_id,
+        )?;
+
+        let initializer = &init_accounts[0];
+        let escrow_account = &init_accounts[3];
+        let token_program = &init_accounts[4];
+        let crowd_vault = &crowd_accounts[0];
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        check_token_program(token_program, &[crowd_vault])?;
+        let crowd_vault_info = TokenAccount::unpack(&crowd_vault.try_borrow_data()?)?;
+        if crowd_vault_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        // the vault balance is what gets paid to the initializer on Settle
+        if crowd_vault_info.amount != 0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            crowd_vault.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            initializer.key,
+            &[initializer.key],
+        )?;
+
+        msg!("Calling the token program to transfer crowd vault ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                crowd_vault.clone(),
+                initializer.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        escrow_info.crowd_fill = true;
+        escrow_info.crowd_vault = *crowd_vault.key;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_contribute(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let contributor_account = next_account(accounts_info_iter, "contributor_account")?;
+
+        if !contributor_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let contributor_token_account =
+            next_account(accounts_info_iter, "contributor_token_account")?;
+        let contribution_account = next_account(accounts_info_iter, "contribution_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.crowd_fill {
+            return Err(EscrowError::NotCrowdFill.into());
+        }
+
+        if escrow_info.is_expired(&Clock::get()?) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if escrow_info.inactive {
+            return Err(EscrowError::EscrowInactive.into());
+        }
+
+        if !escrow_info.is_taker_allowed(contributor_account.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        if amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if escrow_info.remaining_expected == 0 {
+            return Err(EscrowError::CrowdFilled.into());
+        }
+
+        let crowd_vault = next_account(accounts_info_iter, "crowd_vault")?;
+        if escrow_info.crowd_vault != *crowd_vault.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(token_program, &[contributor_token_account, crowd_vault])?;
+
+        let system_program_account = next_account(accounts_info_iter, "system_program")?;
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let contributor_token_info =
+            TokenAccount::unpack(&contributor_token_account.try_borrow_data()?)?;
+        if contributor_token_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // the last contribution only pays what is left
+        let contribution = amount.min(escrow_info.remaining_expected);
+        if contributor_token_info.amount < contribution {
+            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        }
+
+        let (contribution_key, contribution_bump_seed) =
+            contribution_pda(program_id, escrow_account.key, contributor_account.key);
+        if *contribution_account.key != contribution_key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the contribution account is created on the first contribution and topped up afterwards
+        let mut contribution_info = if contribution_account.data_is_empty() {
+            let rent = Rent::get()?;
+            let create_contribution_ix = system_instruction::create_account(
+                contributor_account.key,
+                contribution_account.key,
+                rent.minimum_balance(Contribution::LEN),
+                Contribution::LEN as u64,
+                program_id,
+            );
+
+            msg!("Calling the system program to create the contribution account...");
+            invoke_signed(
+                &create_contribution_ix,
+                &[
+                    contributor_account.clone(),
+                    contribution_account.clone(),
+                    system_program_account.clone(),
+                ],
+                &[&[
+                    CONTRIBUTION_SEED,
+                    escrow_account.key.as_ref(),
+                    contributor_account.key.as_ref(),
+                    &[contribution_bump_seed],
+                ]],
+            )?;
+
+            Contribution {
+                is_initialized: true,
+                escrow_pubkey: *escrow_account.key,
+                contributor_pubkey: *contributor_account.key,
+                crowd_vault_pubkey: escrow_info.crowd_vault,
+                amount: 0,
+                bump_seed: escrow_info.bump_seed,
+                escrow_initializer_pubkey: escrow_info.pda_initializer_pubkey,
+                escrow_nonce: escrow_info.nonce,
+            }
+        } else {
+            if contribution_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            Contribution::unpack(&contribution_account.try_borrow_data()?)?
+        };
+
+        let transfer_contribution_ix = token::transfer(
+            token_program.key,
+            contributor_token_account.key,
+            crowd_vault.key,
+            contributor_account.key,
+            &[contributor_account.key],
+            contribution,
+        )?;
+
+        msg!("Calling the token program to transfer the contribution to the crowd vault...");
+        invoke(
+            &transfer_contribution_ix,
+            &[
+                token_program.clone(),
+                contributor_token_account.clone(),
+                crowd_vault.clone(),
+                contributor_account.clone(),
+            ],
+        )?;
+
+        contribution_info.amount = contribution_info
+            .amount
+            .checked_add(contribution)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.crowd_total = escrow_info
+            .crowd_total
+            .checked_add(contribution)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.remaining_expected =
+            math::checked_remainder(escrow_info.remaining_expected, contribution)?;
+
+        escrow_info.advance_sequence();
+        msg!(
+            "Contribution recorded, {} left to contribute",
+            escrow_info.remaining_expected
+        );
+        Contribution::pack(
+            contribution_info,
+            &mut contribution_account.try_borrow_mut_data()?,
+        )?;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_settle(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if !escrow_info.crowd_fill {
+            return Err(EscrowError::NotCrowdFill.into());
+        }
+
+        // a filled crowd fill can still be settled after it expired, it can no longer be refunded
+        if escrow_info.remaining_expected > 0 {
+            return Err(EscrowError::CrowdNotFilled.into());
+        }
+
+        let crowd_vault = next_account(accounts_info_iter, "crowd_vault")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let initializer_token_to_recieve_account =
+            next_account(accounts_info_iter, "initializer_token_to_recieve_account")?;
+        if escrow_info.crowd_vault != *crowd_vault.key
+            || escrow_info.initializer_pubkey != *initializer_account.key
+            || escrow_info.initializer_token_to_receive_account_pubkey
+                != *initializer_token_to_recieve_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if fee_collector_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                crowd_vault,
+                initializer_token_to_recieve_account,
+                fee_collector_account,
+            ],
+        )?;
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        // contributors already paid into the vault, so only the maker fee is left to take
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let fee = Self::load_config(config_account, program_id)?
+            .maker_fee(escrow_info.crowd_total, &escrow_info.initializer_pubkey)?;
+        let initializer_payment = math::checked_remainder(escrow_info.crowd_total, fee)?;
+
+        let transfer_to_initializer_ix = token::transfer(
+            token_program.key,
+            crowd_vault.key,
+            initializer_token_to_recieve_account.key,
+            &pda,
+            &[&pda],
+            initializer_payment,
+        )?;
+
+        msg!("Calling the token program to pay the contributions to the initializer...");
+        invoke_signed(
+            &transfer_to_initializer_ix,
+            &[
+                token_program.clone(),
+                crowd_vault.clone(),
+                initializer_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        if fee > 0 {
+            let transfer_fee_ix = token::transfer(
+                token_program.key,
+                crowd_vault.key,
+                fee_collector_account.key,
+                &pda,
+                &[&pda],
+                fee,
+            )?;
+
+            msg!("Calling the token program to transfer the fee...");
+            invoke_signed(
+                &transfer_fee_ix,
+                &[
+                    token_program.clone(),
+                    crowd_vault.clone(),
+                    fee_collector_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        }
+
+        Self::close_crowd_vault(
+            token_program,
+            crowd_vault,
+            initializer_account,
+            pda_account,
+            &seeds,
+        )?;
+
+        escrow_info.state = EscrowState::CrowdSettled;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_claim_share(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let contributor_account = next_account(accounts_info_iter, "contributor_account")?;
+
+        if !contributor_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let contributor_token_to_receive_account =
+            next_account(accounts_info_iter, "contributor_token_to_receive_account")?;
+        let contribution_account = next_account(accounts_info_iter, "contribution_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if contribution_account.owner != program_id || escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let contribution_info = Contribution::unpack(&contribution_account.try_borrow_data()?)?;
+        if contribution_info.contributor_pubkey != *contributor_account.key {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+        if contribution_info.escrow_pubkey != *escrow_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.state != EscrowState::CrowdSettled {
+            return Err(EscrowError::CrowdNotFilled.into());
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.initializer_pubkey != *initializer_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let contributor_token_to_receive_info =
+            TokenAccount::unpack(&contributor_token_to_receive_account.try_borrow_data()?)?;
+        if contributor_token_to_receive_info.mint != escrow_info.temp_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        if contributor_token_to_receive_info.owner != *contributor_account.key {
+            return Err(EscrowError::InvalidTakerReceiveAccount.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                contributor_token_to_receive_account,
+                pdas_temp_token_account,
+            ],
+        )?;
+
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let share = escrow_info.crowd_share_for(contribution_info.amount)?;
+
+        // a small contribution can be worth less than a whole token of the deposit
+        if share > 0 {
+            let transfer_share_ix = token::transfer(
+                token_program.key,
+                pdas_temp_token_account.key,
+                contributor_token_to_receive_account.key,
+                &pda,
+                &[&pda],
+                share,
+            )?;
+
+            msg!("Calling the token program to transfer the share to the contributor...");
+            invoke_signed(
+                &transfer_share_ix,
+                &[
+                    token_program.clone(),
+                    pdas_temp_token_account.clone(),
+                    contributor_token_to_receive_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+        }
+
+        escrow_info.crowd_claimed = escrow_info
+            .crowd_claimed
+            .checked_add(contribution_info.amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.remaining_deposit =
+            math::checked_remainder(escrow_info.remaining_deposit, share)?;
+        escrow_info.advance_sequence();
+        events::emit_exchange(
+            escrow_account.key,
+            contributor_account.key,
+            share,
+            contribution_info.amount,
+            escrow_info.sequence,
+        );
+
+        Self::close_program_account(contribution_account, contributor_account)?;
+
+        if escrow_info.crowd_claimed < escrow_info.crowd_total {
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &seeds,
+        )
+    }
+
+    fn process_refund_contribution(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let contributor_account = next_account(accounts_info_iter, "contributor_account")?;
+
+        if !contributor_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let contributor_token_to_refund_account =
+            next_account(accounts_info_iter, "contributor_token_to_refund_account")?;
+        let contribution_account = next_account(accounts_info_iter, "contribution_account")?;
+        if contribution_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let contribution_info = Contribution::unpack(&contribution_account.try_borrow_data()?)?;
+        if contribution_info.contributor_pubkey != *contributor_account.key {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        let crowd_vault = next_account(accounts_info_iter, "crowd_vault")?;
+        if contribution_info.escrow_pubkey != *escrow_account.key
+            || contribution_info.crowd_vault_pubkey != *crowd_vault.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the escrow account may have been closed and reused for another escrow since
+        let escrow_info = if escrow_account.owner == program_id {
+            Some(Escrow::unpack_unchecked(
+                &escrow_account.try_borrow_data()?,
+            )?)
+            .filter(|escrow_info| escrow_info.crowd_vault == contribution_info.crowd_vault_pubkey)
+        } else {
+            None
+        };
+        let escrow_closed = escrow_info.is_none();
+
+        // contributions are locked while the crowd fill can still fill up and once it is filled,
+        // they come back once it expired unfilled or was cancelled or refunded
+        if let Some(mut escrow_info) = escrow_info {
+            if escrow_info.state == EscrowState::CrowdSettled
+                || (escrow_info.state == EscrowState::Active && escrow_info.remaining_expected == 0)
+            {
+                return Err(EscrowError::CrowdFilled.into());
+            }
+            if escrow_info.state == EscrowState::Active {
+                if !escrow_info.is_expired(&Clock::get()?) {
+                    return Err(EscrowError::EscrowNotExpired.into());
+                }
+
+                escrow_info.crowd_total =
+                    math::checked_remainder(escrow_info.crowd_total, contribution_info.amount)?;
+                escrow_info.remaining_expected = escrow_info
+                    .remaining_expected
+                    .checked_add(contribution_info.amount)
+                    .ok_or(EscrowError::AmountOverFlow)?;
+                escrow_info.advance_sequence();
+                Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            }
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[contributor_token_to_refund_account, crowd_vault],
+        )?;
+
+        let seeds = PdaSeeds::of_contribution(&contribution_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        if contribution_info.escrow_initializer_pubkey != *initializer_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let transfer_refund_ix = token::transfer(
+            token_program.key,
+            crowd_vault.key,
+            contributor_token_to_refund_account.key,
+            &pda,
+            &[&pda],
+            contribution_info.amount,
+        )?;
+
+        msg!("Calling the token program to refund the contribution...");
+        invoke_signed(
+            &transfer_refund_ix,
+            &[
+                token_program.clone(),
+                crowd_vault.clone(),
+                contributor_token_to_refund_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::close_program_account(contribution_account, contributor_account)?;
+
+        // the escrow closed before every contribution was refunded, the last refund closes the vault
+        let crowd_vault_info = TokenAccount::unpack(&crowd_vault.try_borrow_data()?)?;
+        if escrow_closed && crowd_vault_info.amount == 0 {
+            Self::close_crowd_vault(
+                token_program,
+                crowd_vault,
+                initializer_account,
+                pda_account,
+                &seeds,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn process_update_amount(
+        accounts: &[AccountInfo],
+        new_amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if Self::escrow_owner(accounts_info_iter, &escrow_info)? != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if new_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // the price can only change while nothing was taken at the old one
+        if escrow_info.remaining_expected != escrow_info.expected_amount {
+            return Err(EscrowError::EscrowPartiallyFilled.into());
+        }
+
+        // the pending taker deposited for the old price
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        escrow_info.expected_amount = new_amount;
+        escrow_info.remaining_expected = new_amount;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_escrow_active(
+        accounts: &[AccountInfo],
+        active: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        escrow_info.inactive = !active;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_top_up(
+        accounts: &[AccountInfo],
+        additional_amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let source_token_account = next_account(accounts_info_iter, "source_token_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if additional_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let source_token_account_info =
+            TokenAccount::unpack(&source_token_account.try_borrow_data()?)?;
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+
+        if source_token_account_info.mint != pdas_temp_token_account_info.mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[source_token_account, pdas_temp_token_account],
+        )?;
+        let transfer_to_temp_ix = token::transfer(
+            token_program.key,
+            source_token_account.key,
+            pdas_temp_token_account.key,
+            initializer_account.key,
+            &[initializer_account.key],
+            additional_amount,
+        )?;
+
+        msg!("Calling the token program to top up the pda's temp account...");
+        invoke(
+            &transfer_to_temp_ix,
+            &[
+                source_token_account.clone(),
+                pdas_temp_token_account.clone(),
+                initializer_account.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        escrow_info.remaining_deposit = escrow_info
+            .remaining_deposit
+            .checked_add(additional_amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        // a pending counter-offer was made for the smaller deposit
+        escrow_info.clear_counter_offer();
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_mutual_cancel(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+        let taker_account = next_account(accounts_info_iter, "taker_account")?;
+
+        if !initializer_account.is_signer || !taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let initializer_token_to_refund_account =
+            next_account(accounts_info_iter, "initializer_token_to_refund_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        // without a designated taker anyone could co-sign, so this would just be an unrestricted cancel
+        if escrow_info.allowed_taker == Pubkey::default() {
+            return Err(EscrowError::NoDesignatedTaker.into());
+        }
+
+        if escrow_info.allowed_taker != *taker_account.key {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        if escrow_info.is_disputed() {
+            return Err(EscrowError::EscrowDisputed.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, initializer_token_to_refund_account],
+        )?;
+        let pda = PdaSeeds::of_escrow(&escrow_info).pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        Self::refund_and_close(
+            token_program,
+            pdas_temp_token_account,
+            initializer_token_to_refund_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            &PdaSeeds::of_escrow(&escrow_info),
+        )
+    }
+
+    fn process_extend_expiry(
+        accounts: &[AccountInfo],
+        new_expiry_slot: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        if Self::escrow_owner(accounts_info_iter, &escrow_info)? != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        // an escrow without an expiry slot never expires, so any slot would shorten it
+        let clock = Clock::get()?;
+        if escrow_info.expiry_slot == 0
+            || new_expiry_slot <= escrow_info.expiry_slot
+            || new_expiry_slot <= clock.slot
+        {
+            return Err(EscrowError::ExpiryNotExtended.into());
+        }
+
+        escrow_info.expiry_slot = new_expiry_slot;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_transfer_initializer(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+
+        // the rights of the initializer are with whoever holds the position token
+        if escrow_info.has_position() {
+            return Err(EscrowError::PositionOwned.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        let new_initializer_account = next_account(accounts_info_iter, "new_initializer_account")?;
+
+        // the PDA stays derived from pda_initializer_pubkey
+        escrow_info.initializer_pubkey = *new_initializer_account.key;
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_replace(
+        accounts: &[AccountInfo],
+        new_expected_amount: u64,
+        new_expiry: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if new_expected_amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the new terms are for what is left in the temp account, which has to match the books
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+        if pdas_temp_token_account_info.amount != escrow_info.remaining_deposit
+            || escrow_info.remaining_deposit == 0
+        {
+            return Err(EscrowError::InconsistentRemainingAmounts.into());
+        }
+
+        escrow_info.advance_sequence();
+        events::emit_cancel(
+            escrow_account.key,
+            initializer_account.key,
+            escrow_info.sequence,
+        );
+        escrow_info.expected_amount = new_expected_amount;
+        escrow_info.remaining_expected = new_expected_amount;
+        escrow_info.expiry_slot = new_expiry;
+        escrow_info.allowed_taker = Pubkey::default();
+        escrow_info.clear_counter_offer();
+        escrow_info.advance_sequence();
+        events::emit_init(
+            escrow_account.key,
+            initializer_account.key,
+            new_expected_amount,
+            escrow_info.sequence,
+        );
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_split_escrow(
+        accounts: &[AccountInfo],
+        split_deposit_amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        if escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        // the auction prices the whole deposit, a part of it has no price of its own
+        if escrow_info.is_dutch_auction() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        // the installments paid so far release the deposit as a whole, not a part of it
+        if escrow_info.streaming {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+
+        // splitting everything would just move the escrow and leave an empty one behind
+        if split_deposit_amount == 0
+            || split_deposit_amount >= escrow_info.remaining_deposit
+            || split_deposit_amount > pdas_temp_token_account_info.amount
+        {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+
+        // the new escrow is rounded down so the original one never asks for less than before
+        let split_expected_amount = (split_deposit_amount as u128
+            * escrow_info.remaining_expected as u128
+            / escrow_info.remaining_deposit as u128) as u64;
+        if split_expected_amount == 0 {
+            return Err(EscrowError::FillTooSmall.into());
+        }
+
+        let split_escrow_account = next_account(accounts_info_iter, "split_escrow_account")?;
+        if split_escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let split_temp_token_account =
+            next_account(accounts_info_iter, "split_temp_token_account")?;
+        let split_temp_token_account_info =
+            TokenAccount::unpack(&split_temp_token_account.try_borrow_data()?)?;
+
+        if split_temp_token_account_info.mint != escrow_info.temp_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        if split_temp_token_account_info.amount != 0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let rent = Rent::get()?;
+
+        if !rent.is_exempt(
+            split_escrow_account.lamports(),
+            split_escrow_account.data_len(),
+        ) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        let mut split_escrow_info =
+            Escrow::unpack_unchecked(&split_escrow_account.try_borrow_data()?)?;
+        if split_escrow_info.state != EscrowState::Uninitialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, split_temp_token_account],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        split_escrow_info.state = EscrowState::Active;
+        split_escrow_info.initializer_pubkey = escrow_info.initializer_pubkey;
+        split_escrow_info.pda_initializer_pubkey = escrow_info.pda_initializer_pubkey;
+        split_escrow_info.nonce = escrow_info.nonce;
+        split_escrow_info.temp_token_account_pubkey = *split_temp_token_account.key;
+        split_escrow_info.initializer_token_to_receive_account_pubkey =
+            escrow_info.initializer_token_to_receive_account_pubkey;
+        split_escrow_info.expected_amount = split_expected_amount;
+        split_escrow_info.expiry_slot = escrow_info.expiry_slot;
+        split_escrow_info.expiry_unix_timestamp = escrow_info.expiry_unix_timestamp;
+        split_escrow_info.allowed_taker = escrow_info.allowed_taker;
+        split_escrow_info.two_phase = escrow_info.two_phase;
+        split_escrow_info.arbiter = escrow_info.arbiter;
+        split_escrow_info.min_taker_amount = escrow_info.min_taker_amount;
+        split_escrow_info.price_numerator = escrow_info.price_numerator;
+        split_escrow_info.price_denominator = escrow_info.price_denominator;
+        split_escrow_info.bump_seed = escrow_info.bump_seed;
+        split_escrow_info.remaining_deposit = split_deposit_amount;
+        split_escrow_info.remaining_expected = split_expected_amount;
+        split_escrow_info.temp_token_mint = escrow_info.temp_token_mint;
+        split_escrow_info.receive_token_mint = escrow_info.receive_token_mint;
+        // the split escrow trades under the same terms, or it would be a way around them
+        split_escrow_info.oracle = escrow_info.oracle;
+        split_escrow_info.oracle_kind = escrow_info.oracle_kind;
+        split_escrow_info.oracle_max_deviation_bps = escrow_info.oracle_max_deviation_bps;
+        split_escrow_info.oracle_max_staleness = escrow_info.oracle_max_staleness;
+        split_escrow_info.deposit_decimals = escrow_info.deposit_decimals;
+        split_escrow_info.receive_decimals = escrow_info.receive_decimals;
+        split_escrow_info.taker_merkle_root = escrow_info.taker_merkle_root;
+        split_escrow_info.gate_mint = escrow_info.gate_mint;
+        split_escrow_info.gate_min_amount = escrow_info.gate_min_amount;
+        split_escrow_info.gatekeeper_network = escrow_info.gatekeeper_network;
+        split_escrow_info.memo_required = escrow_info.memo_required;
+        split_escrow_info.royalty_policy = escrow_info.royalty_policy;
+        split_escrow_info.nft = escrow_info.nft;
+        split_escrow_info.cancel_penalty_bps = escrow_info.cancel_penalty_bps;
+        split_escrow_info.cancel_penalty_lamports = escrow_info.cancel_penalty_lamports;
+        split_escrow_info.no_penalty_after_slot = escrow_info.no_penalty_after_slot;
+        split_escrow_info.same_mint_allowed = escrow_info.same_mint_allowed;
+        split_escrow_info.inactive = escrow_info.inactive;
+
+        Escrow::pack(
+            split_escrow_info,
+            &mut split_escrow_account.try_borrow_mut_data()?,
+        )?;
+
+        escrow_info.remaining_deposit -= split_deposit_amount;
+        escrow_info.remaining_expected = escrow_info
+            .remaining_expected
+            .checked_sub(split_expected_amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.expected_amount = escrow_info
+            .expected_amount
+            .checked_sub(split_expected_amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        // a pending counter-offer was made for the whole deposit
+        escrow_info.clear_counter_offer();
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            split_temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            initializer_account.key,
+            &[initializer_account.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                split_temp_token_account.clone(),
+                initializer_account.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        let transfer_to_split_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            split_temp_token_account.key,
+            &pda,
+            &[&pda],
+            split_deposit_amount,
+        )?;
+
+        msg!("Calling the token program to move tokens to the split escrow...");
+        invoke_signed(
+            &transfer_to_split_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                split_temp_token_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_merge_escrows(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let initializer_account = next_account(accounts_info_iter, "initializer_account")?;
+
+        if !initializer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account(accounts_info_iter, "escrow_account")?;
+        let pdas_temp_token_account = next_account(accounts_info_iter, "pdas_temp_token_account")?;
+        let merged_escrow_account = next_account(accounts_info_iter, "merged_escrow_account")?;
+        let merged_pdas_temp_token_account =
+            next_account(accounts_info_iter, "merged_pdas_temp_token_account")?;
+
+        if escrow_account.owner != program_id || merged_escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if escrow_account.key == merged_escrow_account.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        let merged_escrow_info = Self::unpack_active_escrow(merged_escrow_account)?;
+        if escrow_info.is_exchange_only() || merged_escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.bid_auction || merged_escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        // the price curves of two auctions can't be added up into one
+        if escrow_info.is_dutch_auction() || merged_escrow_info.is_dutch_auction() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer_account.key {
+            return Err(EscrowError::UnauthorizedInitializer.into());
+        }
+
+        if merged_escrow_info.initializer_pubkey != escrow_info.initializer_pubkey {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        if merged_escrow_info.temp_token_mint != escrow_info.temp_token_mint
+            || merged_escrow_info.receive_token_mint != escrow_info.receive_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        if merged_escrow_info.initializer_token_to_receive_account_pubkey
+            != escrow_info.initializer_token_to_receive_account_pubkey
+        {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        if escrow_info.has_pending_deposit() || merged_escrow_info.has_pending_deposit() {
+            return Err(EscrowError::DepositPending.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || merged_escrow_info.temp_token_account_pubkey != *merged_pdas_temp_token_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[pdas_temp_token_account, merged_pdas_temp_token_account],
+        )?;
+        let seeds = PdaSeeds::of_escrow(&escrow_info);
+        let pda = seeds.pda(program_id)?;
+
+        let pda_account = next_account(accounts_info_iter, "pda_account")?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPda.into());
+        }
+
+        let merged_seeds = PdaSeeds::of_escrow(&merged_escrow_info);
+        let merged_pda = merged_seeds.pda(program_id)?;
+
+        let merged_pda_account = next_account(accounts_info_iter, "merged_pda_account")?;
+        if *merged_pda_account.key != merged_pda {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let merged_pdas_temp_token_account_info =
+            TokenAccount::unpack(&merged_pdas_temp_token_account.try_borrow_data()?)?;
+
+        escrow_info.expected_amount = escrow_info
+            .expected_amount
+            .checked_add(merged_escrow_info.expected_amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.remaining_expected = escrow_info
+            .remaining_expected
+            .checked_add(merged_escrow_info.remaining_expected)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        escrow_info.remaining_deposit = escrow_info
+            .remaining_deposit
+            .checked_add(merged_pdas_temp_token_account_info.amount)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        // a pending counter-offer was made for the smaller deposit
+        escrow_info.clear_counter_offer();
+        escrow_info.advance_sequence();
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        let merge_deposit_ix = token::transfer(
+            token_program.key,
+            merged_pdas_temp_token_account.key,
+            pdas_temp_token_account.key,
+            &merged_pda,
+            &[&merged_pda],
+            merged_pdas_temp_token_account_info.amount,
+        )?;
+
+        msg!("Calling the token program to move the merged deposit...");
+        invoke_signed(
+            &merge_deposit_ix,
+            &[
+                token_program.clone(),
+                merged_pdas_temp_token_account.clone(),
+                pdas_temp_token_account.clone(),
+                merged_pda_account.clone(),
+            ],
+            &[&merged_seeds.seeds()],
+        )?;
+
+        Self::close_escrow(
+            token_program,
+            merged_pdas_temp_token_account,
+            initializer_account,
+            merged_escrow_account,
+            merged_pda_account,
+            &merged_seeds,
+        )
+    }
+
+    fn process_match_escrows(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let first_escrow_account = next_account(accounts_info_iter, "first_escrow_account")?;
+        let first_pdas_temp_token_account =
+            next_account(accounts_info_iter, "first_pdas_temp_token_account")?;
+        let first_initializer_account =
+            next_account(accounts_info_iter, "first_initializer_account")?;
+        let first_initializer_token_to_receive_account = next_account(
+            accounts_info_iter,
+            "first_initializer_token_to_receive_account",
+        )?;
+        let second_escrow_account = next_account(accounts_info_iter, "second_escrow_account")?;
+        let second_pdas_temp_token_account =
+            next_account(accounts_info_iter, "second_pdas_temp_token_account")?;
+        let second_initializer_account =
+            next_account(accounts_info_iter, "second_initializer_account")?;
+        let second_initializer_token_to_receive_account = next_account(
+            accounts_info_iter,
+            "second_initializer_token_to_receive_account",
+        )?;
+
+        if first_escrow_account.owner != program_id || second_escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if first_escrow_account.key == second_escrow_account.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut first_escrow_info = Self::unpack_active_escrow(first_escrow_account)?;
+        let mut second_escrow_info = Self::unpack_active_escrow(second_escrow_account)?;
+        if first_escrow_info.is_exchange_only() || second_escrow_info.is_exchange_only() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        if first_escrow_info.bid_auction || second_escrow_info.bid_auction {
+            return Err(EscrowError::BidAuctionOnly.into());
+        }
+
+        // an auction is only taken whole at its current price, not at the expected amount
+        if first_escrow_info.is_dutch_auction() || second_escrow_info.is_dutch_auction() {
+            return Err(EscrowError::ExchangeOnly.into());
+        }
+
+        for (escrow_info, pdas_temp_token_account, initializer_account, token_to_receive_account) in [
+            (
+                &first_escrow_info,
+                first_pdas_temp_token_account,
+                first_initializer_account,
+                first_initializer_token_to_receive_account,
+            ),
+            (
+                &second_escrow_info,
+                second_pdas_temp_token_account,
+                second_initializer_account,
+                second_initializer_token_to_receive_account,
+            ),
+        ] {
+            if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+                || escrow_info.initializer_pubkey != *initializer_account.key
+                || escrow_info.initializer_token_to_receive_account_pubkey
+                    != *token_to_receive_account.key
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+
+        if first_escrow_info.temp_token_mint != second_escrow_info.receive_token_mint
+            || second_escrow_info.temp_token_mint != first_escrow_info.receive_token_mint
+        {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let clock = Clock::get()?;
+        for (escrow_info, counterparty) in [
+            (&first_escrow_info, &second_escrow_info.initializer_pubkey),
+            (&second_escrow_info, &first_escrow_info.initializer_pubkey),
+        ] {
+            if escrow_info.is_expired(&clock) {
+                return Err(EscrowError::EscrowExpired.into());
+            }
+
+            if escrow_info.inactive {
+                return Err(EscrowError::EscrowInactive.into());
+            }
+
+            if escrow_info.two_phase {
+                return Err(EscrowError::TwoPhaseRequired.into());
+            }
+
+            if escrow_info.has_pending_deposit() {
+                return Err(EscrowError::DepositPending.into());
+            }
+
+            if !escrow_info.is_taker_allowed(counterparty) {
+                return Err(EscrowError::UnauthorizedTaker.into());
+            }
+        }
+
+        if first_escrow_info.remaining_deposit < second_escrow_info.remaining_expected
+            || second_escrow_info.remaining_deposit < first_escrow_info.remaining_expected
+        {
+            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        }
+
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(
+            token_program,
+            &[
+                first_pdas_temp_token_account,
+                first_initializer_token_to_receive_account,
+                second_pdas_temp_token_account,
+                second_initializer_token_to_receive_account,
+            ],
+        )?;
+        let first_seeds = PdaSeeds::of_escrow(&first_escrow_info);
+        let first_pda_account = next_account(accounts_info_iter, "first_pda_account")?;
+        let second_seeds = PdaSeeds::of_escrow(&second_escrow_info);
+        let second_pda_account = next_account(accounts_info_iter, "second_pda_account")?;
+        if *first_pda_account.key != first_seeds.pda(program_id)?
+            || *second_pda_account.key != second_seeds.pda(program_id)?
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let first_fee_collector_account =
+            next_account(accounts_info_iter, "first_fee_collector_account")?;
+        let second_fee_collector_account =
+            next_account(accounts_info_iter, "second_fee_collector_account")?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+        for (escrow_info, fee_collector_account) in [
+            (&first_escrow_info, first_fee_collector_account),
+            (&second_escrow_info, second_fee_collector_account),
+        ] {
+            let fee_collector_info =
+                TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+            if fee_collector_info.owner != fee_collector {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            if fee_collector_info.mint != escrow_info.receive_token_mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+        }
+
+        // each side is a trade of one escrow taken by the other initializer, paying the whole
+        // remaining deposit of their own. With nothing to pay the taker fee on top of it, both
+        // fees come out of that deposit
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let config_info = Self::load_config(config_account, program_id)?;
+        let first_fees = config_info.trade_fees(
+            second_escrow_info.remaining_deposit,
+            &first_escrow_info.initializer_pubkey,
+            &second_escrow_info.initializer_pubkey,
+        )?;
+        let second_fees = config_info.trade_fees(
+            first_escrow_info.remaining_deposit,
+            &second_escrow_info.initializer_pubkey,
+            &first_escrow_info.initializer_pubkey,
+        )?;
+
+        for (escrow_info, fees) in [
+            (&first_escrow_info, &first_fees),
+            (&second_escrow_info, &second_fees),
+        ] {
+            if escrow_info.has_oracle() {
+                let oracle_account = next_account(accounts_info_iter, "oracle_account")?;
+                Self::check_oracle_price(
+                    oracle_account,
+                    escrow_info,
+                    escrow_info.remaining_deposit,
+                    fees.payment,
+                    &clock,
+                )?;
+            }
+        }
+
+        for (
+            pdas_temp_token_account,
+            token_to_receive_account,
+            fee_collector_account,
+            fees,
+            pda_account,
+            seeds,
+        ) in [
+            (
+                first_pdas_temp_token_account,
+                second_initializer_token_to_receive_account,
+                second_fee_collector_account,
+                &second_fees,
+                first_pda_account,
+                &first_seeds,
+            ),
+            (
+                second_pdas_temp_token_account,
+                first_initializer_token_to_receive_account,
+                first_fee_collector_account,
+                &first_fees,
+                second_pda_account,
+                &second_seeds,
+            ),
+        ] {
+            let fee = fees.collected()?;
+            let transfer_ix = token::transfer(
+                token_program.key,
+                pdas_temp_token_account.key,
+                token_to_receive_account.key,
+                pda_account.key,
+                &[pda_account.key],
+                math::checked_remainder(fees.payment, fee)?,
+            )?;
+
+            msg!("Calling the token program to transfer tokens to the counterparty...");
+            invoke_signed(
+                &transfer_ix,
+                &[
+                    token_program.clone(),
+                    pdas_temp_token_account.clone(),
+                    token_to_receive_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+
+            if fee > 0 {
+                let transfer_fee_ix = token::transfer(
+                    token_program.key,
+                    pdas_temp_token_account.key,
+                    fee_collector_account.key,
+                    pda_account.key,
+                    &[pda_account.key],
+                    fee,
+                )?;
+
+                msg!("Calling the token program to transfer the fee...");
+                invoke_signed(
+                    &transfer_fee_ix,
+                    &[
+                        token_program.clone(),
+                        pdas_temp_token_account.clone(),
+                        fee_collector_account.clone(),
+                        pda_account.clone(),
+                    ],
+                    &[&seeds.seeds()],
+                )?;
+            }
+        }
+
+        first_escrow_info.advance_sequence();
+        second_escrow_info.advance_sequence();
+        events::emit_exchange(
+            first_escrow_account.key,
+            second_initializer_account.key,
+            first_escrow_info.remaining_deposit,
+            second_escrow_info.remaining_deposit,
+            first_escrow_info.sequence,
+        );
+        events::emit_exchange(
+            second_escrow_account.key,
+            first_initializer_account.key,
+            second_escrow_info.remaining_deposit,
+            first_escrow_info.remaining_deposit,
+            second_escrow_info.sequence,
+        );
+
+        Self::close_escrow(
+            token_program,
+            first_pdas_temp_token_account,
+            first_initializer_account,
+            first_escrow_account,
+            first_pda_account,
+            &first_seeds,
+        )?;
+        Self::close_escrow(
+            token_program,
+            second_pdas_temp_token_account,
+            second_initializer_account,
+            second_escrow_account,
+            second_pda_account,
+            &second_seeds,
+        )
+    }
+
+    fn process_initialize_config(
+        accounts: &[AccountInfo],
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_tiers: &[FeeTier],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let (config_key, bump_seed) = config_pda(program_id);
+        if *config_account.key != config_key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if !config_account.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let system_program_account = next_account(accounts_info_iter, "system_program")?;
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Config {
+            is_initialized: true,
+            admin: *admin_account.key,
+            bump_seed,
+            ..Config::default()
+        };
+        config_info.set_fee_schedule(maker_fee_bps, taker_fee_bps, fee_tiers)?;
+
+        let rent = Rent::get()?;
+        let create_config_ix = system_instruction::create_account(
+            admin_account.key,
+            config_account.key,
+            rent.minimum_balance(Config::LEN),
+            Config::LEN as u64,
+            program_id,
+        );
+
+        msg!("Calling the system program to create the config account...");
+        invoke_signed(
+            &create_config_ix,
+            &[
+                admin_account.clone(),
+                config_account.clone(),
+                system_program_account.clone(),
+            ],
+            &[&[CONFIG_SEED, &[bump_seed]]],
+        )?;
+
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_fee_schedule(
+        accounts: &[AccountInfo],
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_tiers: &[FeeTier],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        config_info.set_fee_schedule(maker_fee_bps, taker_fee_bps, fee_tiers)?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_add_fee_exemption(
+        accounts: &[AccountInfo],
+        as_maker: bool,
+        as_taker: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        let exempt_account = next_account(accounts_info_iter, "exempt_account")?;
+        config_info.add_fee_exemption(FeeExemption {
+            pubkey: *exempt_account.key,
+            as_maker,
+            as_taker,
+        })?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_remove_fee_exemption(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        let exempt_account = next_account(accounts_info_iter, "exempt_account")?;
+        config_info.remove_fee_exemption(exempt_account.key)?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_add_allowed_mint(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        let mint_account = next_account(accounts_info_iter, "mint_account")?;
+        config_info.add_allowed_mint(mint_account.key)?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_remove_allowed_mint(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        let mint_account = next_account(accounts_info_iter, "mint_account")?;
+        config_info.remove_allowed_mint(mint_account.key)?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_migrate_config(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let config_info = Config::migrate(&config_account.try_borrow_data()?)?;
+        if config_info.admin != *admin_account.key {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+
+        let system_program_account = next_account(accounts_info_iter, "system_program")?;
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let rent = Rent::get()?;
+        let rent_due = rent
+            .minimum_balance(Config::LEN)
+            .saturating_sub(config_account.lamports());
+        if rent_due > 0 {
+            let pay_rent_ix =
+                system_instruction::transfer(admin_account.key, config_account.key, rent_due);
+
+            msg!("Calling the system program to pay the rent of the migrated config...");
+            invoke(
+                &pay_rent_ix,
+                &[
+                    admin_account.clone(),
+                    config_account.clone(),
+                    system_program_account.clone(),
+                ],
+            )?;
+        }
+
+        config_account.realloc(Config::LEN, true)?;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_withdraw_fees(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        Self::unpack_config_of_admin(config_account, admin_account)?;
+
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        let destination_account = next_account(accounts_info_iter, "destination_account")?;
+        let token_program = next_account(accounts_info_iter, "token_program")?;
+        check_token_program(token_program, &[fee_collector_account, destination_account])?;
+
+        let fee_collector_pda_account =
+            next_account(accounts_info_iter, "fee_collector_pda_account")?;
+        let (fee_collector, bump_seed) =
+            Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
+        if *fee_collector_pda_account.key != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let destination_info = TokenAccount::unpack(&destination_account.try_borrow_data()?)?;
+        if destination_info.mint != fee_collector_info.mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        let amount = if amount == u64::MAX {
+            fee_collector_info.amount
+        } else {
+            amount
+        };
+        if amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+        if amount > fee_collector_info.amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        let transfer_fees_ix = token::transfer(
+            token_program.key,
+            fee_collector_account.key,
+            destination_account.key,
+            &fee_collector,
+            &[&fee_collector],
+            amount,
+        )?;
+
+        msg!("Calling the token program to withdraw the fees...");
+        invoke_signed(
+            &transfer_fees_ix,
+            &[
+                token_program.clone(),
+                fee_collector_account.clone(),
+                destination_account.clone(),
+                fee_collector_pda_account.clone(),
+            ],
+            &[&[FEE_COLLECTOR_SEED, &[bump_seed]]],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_set_creation_fee(
+        accounts: &[AccountInfo],
+        lamports: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        config_info.creation_fee_lamports = lamports;
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_config(
+        accounts: &[AccountInfo],
+        paused: bool,
+        new_admin: Option<Pubkey>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut config_info = Self::unpack_config_of_admin(config_account, admin_account)?;
+        config_info.paused = paused;
+        if let Some(new_admin) = new_admin {
+            config_info.admin = new_admin;
+        }
+        Config::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_withdraw_creation_fees(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin_account = next_account(accounts_info_iter, "admin_account")?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        Self::unpack_config_of_admin(config_account, admin_account)?;
+
+        let fee_collector_pda_account =
+            next_account(accounts_info_iter, "fee_collector_pda_account")?;
+        let (fee_collector, bump_seed) = fee_collector_pda(program_id);
+        if *fee_collector_pda_account.key != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let destination_account = next_account(accounts_info_iter, "destination_account")?;
+        let system_program_account = next_account(accounts_info_iter, "system_program")?;
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let amount = if amount == u64::MAX {
+            fee_collector_pda_account.lamports()
+        } else {
+            amount
+        };
+        if amount == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+        if amount > fee_collector_pda_account.lamports() {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        let transfer_fees_ix =
+            system_instruction::transfer(&fee_collector, destination_account.key, amount);
+
+        msg!("Calling the system program to withdraw the creation fees...");
+        invoke_signed(
+            &transfer_fees_ix,
+            &[
+                fee_collector_pda_account.clone(),
+                destination_account.clone(),
+                system_program_account.clone(),
+            ],
+            &[&[FEE_COLLECTOR_SEED, &[bump_seed]]],
+        )?;
+
+        Ok(())
+    }
+
+    /// Transfers the config's creation fee from the initializer to the fee collector PDA, which
+    /// the initializer can only afford while keeping their account rent exempt. A fee of 0 isn't
+    /// transferred
+    fn charge_creation_fee<'a>(
+        creation_fee: u64,
+        initializer: &AccountInfo<'a>,
+        fee_collector_pda_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        rent: &Rent,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (fee_collector, _) = fee_collector_pda(program_id);
+        if *fee_collector_pda_account.key != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if *system_program_account.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if creation_fee == 0 {
+            return Ok(());
+        }
+
+        let required_lamports = rent
+            .minimum_balance(initializer.data_len())
+            .checked_add(creation_fee)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        if initializer.lamports() < required_lamports {
+            msg!(
+                "The initializer needs {} lamports to pay the creation fee",
+                required_lamports
+            );
+            return Err(EscrowError::CreationFeeUnaffordable.into());
+        }
+
+        let transfer_fee_ix =
+            system_instruction::transfer(initializer.key, &fee_collector, creation_fee);
+
+        msg!("Calling the system program to pay the creation fee...");
+        invoke(
+            &transfer_fee_ix,
+            &[
+                initializer.clone(),
+                fee_collector_pda_account.clone(),
+                system_program_account.clone(),
+            ],
+        )
+    }
+
+    fn process_init_with_cancel_penalty(
+        accounts: &[AccountInfo],
+        amount: u64,
+        no_penalty_after_slot: u64,
+        penalty_lamports: u64,
+        penalty_bps: u16,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if no_penalty_after_slot == 0
+            || penalty_bps > 10_000
+            || (penalty_bps == 0 && penalty_lamports == 0)
+        {
+            return Err(EscrowError::InvalidCancelPenalty.into());
+        }
+
+        Self::process_init_escrow(accounts, amount, InitOptions::default(), program_id)?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Self::unpack_active_escrow(escrow_account)?;
+        escrow_info.cancel_penalty_bps = penalty_bps;
+        escrow_info.cancel_penalty_lamports = penalty_lamports;
+        escrow_info.no_penalty_after_slot = no_penalty_after_slot;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_same_mint(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        Self::process_init_escrow(
+            accounts,
+            amount,
+            InitOptions {
+                same_mint_allowed: true,
+                ..InitOptions::default()
+            },
+            program_id,
+        )
+    }
+
+    /// Pays the initializer and the `fee` from the pending deposit, gives the taker's temp token
+    /// account back to them and sends the remaining deposit to the taker, closing the escrow
+    #[allow(clippy::too_many_arguments)]
+    fn complete_pending_exchange<'a>(
+        escrow_info: &Escrow,
+        token_program: &AccountInfo<'a>,
+        initializer_account: &AccountInfo<'a>,
+        initializer_token_to_recieve_account: &AccountInfo<'a>,
+        pdas_temp_token_account: &AccountInfo<'a>,
+        taker_temp_token_account: &AccountInfo<'a>,
+        taker_token_to_recieve_account: &AccountInfo<'a>,
+        escrow_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+        fee_collector_account: &AccountInfo<'a>,
+        fee: u64,
+    ) -> ProgramResult {
+        let transfer_y_to_initializer_ix = token::transfer(
+            token_program.key,
+            taker_temp_token_account.key,
+            initializer_token_to_recieve_account.key,
+            pda_account.key,
+            &[pda_account.key],
+            math::checked_remainder(escrow_info.remaining_expected, fee)?,
+        )?;
+
+        msg!("Calling the token program to transfer the deposit to the initializer...");
+        invoke_signed(
+            &transfer_y_to_initializer_ix,
+            &[
+                token_program.clone(),
+                taker_temp_token_account.clone(),
+                initializer_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::collect_fee(
+            token_program,
+            taker_temp_token_account,
+            fee_collector_account,
+            pda_account,
+            seeds,
+            fee,
+        )?;
+
+        Self::return_taker_deposit(
+            token_program,
+            taker_temp_token_account,
+            &escrow_info.pending_taker,
+            pda_account,
+            seeds,
+        )?;
+
+        let transfer_x_to_trade_taker_ix = token::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            taker_token_to_recieve_account.key,
+            pda_account.key,
+            &[pda_account.key],
+            escrow_info.remaining_deposit,
+        )?;
+
+        msg!("Calling the token program to transfer tokens to the taker...");
+        invoke_signed(
+            &transfer_x_to_trade_taker_ix,
+            &[
+                token_program.clone(),
+                pdas_temp_token_account.clone(),
+                taker_token_to_recieve_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            seeds,
+        )
+    }
 
-use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
-pub struct Processor;
-impl Processor {
-    pub fn process(
-        program_id: &Pubkey,
-        accounts: &[AccountInfo],
-        instruction_data: &[u8],
+    /// Gives ownership of a taker's temp token account held by the PDA back to the taker
+    fn return_taker_deposit<'a>(
+        token_program: &AccountInfo<'a>,
+        taker_temp_token_account: &AccountInfo<'a>,
+        taker: &Pubkey,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
     ) -> ProgramResult {
-        let instruction = EscrowInstruction::unpack(instruction_data)?;
+        let owner_change_ix = token::set_authority(
+            token_program.key,
+            taker_temp_token_account.key,
+            Some(taker),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
 
-        match instruction {
-            EscrowInstruction::InitEscrow { amount } => {
-                msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(accounts, amount, program_id)
+        msg!("Calling the token program to give the taker's temp account back...");
+        invoke_signed(
+            &owner_change_ix,
+            &[
+                taker_temp_token_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&seeds.seeds()],
+        )
+    }
+
+    /// Sends the whole balance of every basket vault to the matching destination account, read as
+    /// (vault, destination) pairs from `accounts_info_iter`, and closes the vaults sending their
+    /// rent to the initializer. When `destination_owner` is set, the destinations must belong to it
+    fn empty_basket<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_info_iter: &mut I,
+        escrow_info: &Escrow,
+        destination_owner: Option<&Pubkey>,
+        token_program: &AccountInfo<'b>,
+        initializer_account: &AccountInfo<'b>,
+        pda_account: &AccountInfo<'b>,
+    ) -> ProgramResult {
+        let seeds = PdaSeeds::of_escrow(escrow_info);
+        for basket_vault_pubkey in escrow_info.basket_vaults() {
+            let basket_vault = next_account(accounts_info_iter, "basket_vault")?;
+            let destination_account = next_account(accounts_info_iter, "basket_destination")?;
+
+            if *basket_vault.key != *basket_vault_pubkey {
+                return Err(ProgramError::InvalidAccountData);
             }
 
-            EscrowInstruction::Exchange { amount } => {
-                msg!("Instruction: Exchange");
-                Self::process_trade(accounts, amount, program_id)
+            let basket_vault_info = TokenAccount::unpack(&basket_vault.try_borrow_data()?)?;
+            let destination_info = TokenAccount::unpack(&destination_account.try_borrow_data()?)?;
+            if destination_info.mint != basket_vault_info.mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+            if let Some(destination_owner) = destination_owner {
+                if destination_info.owner != *destination_owner {
+                    return Err(ProgramError::InvalidAccountData);
+                }
             }
+
+            let transfer_ix = token::transfer(
+                token_program.key,
+                basket_vault.key,
+                destination_account.key,
+                pda_account.key,
+                &[pda_account.key],
+                basket_vault_info.amount,
+            )?;
+
+            msg!("Calling the token program to transfer basket tokens...");
+            invoke_signed(
+                &transfer_ix,
+                &[
+                    token_program.clone(),
+                    basket_vault.clone(),
+                    destination_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
+
+            let close_ix = token::close_account(
+                token_program.key,
+                basket_vault.key,
+                initializer_account.key,
+                pda_account.key,
+                &[pda_account.key],
+            )?;
+
+            msg!("Calling the token program to close the basket account...");
+            invoke_signed(
+                &close_ix,
+                &[
+                    token_program.clone(),
+                    basket_vault.clone(),
+                    initializer_account.clone(),
+                    pda_account.clone(),
+                ],
+                &[&seeds.seeds()],
+            )?;
         }
+
+        Ok(())
     }
 
-    fn process_init_escrow(
-        accounts: &[AccountInfo],
-        amount: u64,
-        program_id: &Pubkey,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let initializer = next_account_info(account_info_iter)?;
+    /// Unpacks the escrow state, rejecting escrows that were never initialized or were already
+    /// settled or cancelled
+    fn unpack_active_escrow(escrow_account: &AccountInfo) -> Result<Escrow, ProgramError> {
+        let escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+        if !escrow_info.is_initialized() {
+            return Err(EscrowError::EscrowNotInitialized.into());
+        }
 
-        if !initializer.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
+        match escrow_info.state {
+            EscrowState::Active => Ok(escrow_info),
+            EscrowState::Vesting => Err(EscrowError::EscrowVesting.into()),
+            EscrowState::Locked => Err(EscrowError::EscrowLocked.into()),
+            _ => Err(EscrowError::EscrowClosed.into()),
+        }
+    }
+
+    /// Reads the config PDA, which holds the default fee rates until the admin creates it
+    fn load_config(
+        config_account: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<Config, ProgramError> {
+        let (config_key, _) = config_pda(program_id);
+        if *config_account.key != config_key {
+            return Err(ProgramError::InvalidAccountData);
         }
 
-        let temp_token_account = next_account_info(account_info_iter)?;
+        if config_account.data_is_empty() {
+            let mut config_info = Config::default();
+            config_info.set_fee_schedule(MAKER_FEE_BASIS_POINTS, TAKER_FEE_BASIS_POINTS, &[])?;
+            return Ok(config_info);
+        }
 
-        let token_to_receive_account = next_account_info(account_info_iter)?;
-        if *token_to_receive_account.owner != spl_token::id() {
+        if config_account.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        let escrow_account = next_account_info(account_info_iter)?;
-        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        // configs of an older layout are read as they are until the admin migrates them
+        Config::migrate(&config_account.try_borrow_data()?)
+    }
 
-        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
-            return Err(EscrowError::NotRentExempt.into());
-        }
+    /// Reads the fee collector's token account for the receive token mint and the config from
+    /// `accounts_info_iter`, returning the fee collector account and the maker fee of `payment`
+    fn next_maker_fee<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_info_iter: &mut I,
+        escrow_info: &Escrow,
+        token_program: &AccountInfo<'b>,
+        payment: u64,
+        program_id: &Pubkey,
+    ) -> Result<(&'a AccountInfo<'b>, u64), ProgramError> {
+        let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+        check_token_program(token_program, &[fee_collector_account])?;
+        let fee_collector_info = TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+        let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], program_id);
 
-        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
-        if escrow_info.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
+        if fee_collector_info.owner != fee_collector {
+            return Err(ProgramError::InvalidAccountData);
         }
 
-        escrow_info.is_initialized = true;
-        escrow_info.initializer_pubkey = *initializer.key;
-        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
-        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
-        escrow_info.expected_amount = amount;
+        if fee_collector_info.mint != escrow_info.receive_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
 
-        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+        let config_account = next_account(accounts_info_iter, "config_account")?;
+        let fee = Self::load_config(config_account, program_id)?
+            .maker_fee(payment, &escrow_info.initializer_pubkey)?;
+        Ok((fee_collector_account, fee))
+    }
 
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+    /// Sends `fee` to the fee collector from `source`, a token account the PDA can sign for
+    fn collect_fee<'a>(
+        token_program: &AccountInfo<'a>,
+        source: &AccountInfo<'a>,
+        fee_collector_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+        fee: u64,
+    ) -> ProgramResult {
+        // a small payment can round the fee down to nothing
+        if fee == 0 {
+            return Ok(());
+        }
 
-        let token_program = next_account_info(account_info_iter)?;
-        let owner_change_ix = spl_token::instruction::set_authority(
+        let transfer_fee_ix = token::transfer(
             token_program.key,
-            temp_token_account.key,
-            Some(&pda),
-            spl_token::instruction::AuthorityType::AccountOwner,
-            initializer.key,
-            &[&initializer.key],
+            source.key,
+            fee_collector_account.key,
+            pda_account.key,
+            &[pda_account.key],
+            fee,
         )?;
 
-        msg!("Calling the token program to transfer token account ownership...");
-        invoke(
-            &owner_change_ix,
+        msg!("Calling the token program to transfer the fee...");
+        invoke_signed(
+            &transfer_fee_ix,
             &[
-                temp_token_account.clone(),
-                initializer.clone(),
                 token_program.clone(),
+                source.clone(),
+                fee_collector_account.clone(),
+                pda_account.clone(),
             ],
+            &[&seeds.seeds()],
+        )
+    }
+
+    /// Unpacks the config, which has to be migrated to the current layout, rejecting signers
+    /// other than its admin
+    fn unpack_config_of_admin(
+        config_account: &AccountInfo,
+        admin_account: &AccountInfo,
+    ) -> Result<Config, ProgramError> {
+        let config_info = Config::unpack(&config_account.try_borrow_data()?)?;
+        if config_info.admin != *admin_account.key {
+            return Err(EscrowError::UnauthorizedSigner.into());
+        }
+        Ok(config_info)
+    }
+
+    /// Checks that trading `amount` of the deposit for `payment` stays within the price band of
+    /// the escrow's oracle, read from `oracle_account`
+    fn check_oracle_price(
+        oracle_account: &AccountInfo,
+        escrow_info: &Escrow,
+        amount: u64,
+        payment: u64,
+        clock: &Clock,
+    ) -> ProgramResult {
+        if *oracle_account.key != escrow_info.oracle {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // Pyth prices are timestamped, Switchboard results carry the slot of their round
+        let oracle_data = oracle_account.try_borrow_data()?;
+        let (oracle_price, now) = match escrow_info.oracle_kind {
+            OracleKind::Pyth => (oracle::load_price(&oracle_data)?, clock.unix_timestamp),
+            OracleKind::Switchboard => (
+                oracle::load_switchboard_price(&oracle_data)?,
+                clock.slot as i64,
+            ),
+        };
+        if now.saturating_sub(oracle_price.publish_time) > escrow_info.oracle_max_staleness {
+            return Err(EscrowError::StaleOraclePrice.into());
+        }
+
+        oracle::check_price_band(
+            amount,
+            payment,
+            escrow_info.deposit_decimals,
+            escrow_info.receive_decimals,
+            &oracle_price,
+            escrow_info.oracle_max_deviation_bps,
         )?;
+        Ok(())
+    }
 
+    /// Checks that the taker's token account holds the `required` amount they pay, logging the
+    /// shortfall otherwise
+    fn check_taker_balance(available: u64, required: u64) -> ProgramResult {
+        if available < required {
+            msg!(
+                "Taker's token account holds {} but the trade needs {}",
+                available,
+                required
+            );
+            return Err(EscrowError::InsufficientTakerBalance.into());
+        }
         Ok(())
     }
 
-    fn process_trade(
-        accounts: &[AccountInfo],
-        expected_amount: u64,
-        program_id: &Pubkey,
+    /// Checks that the taker's token account holds enough of the gate mint, the balance is only read
+    fn check_gate(
+        taker_gate_account: &AccountInfo,
+        taker: &Pubkey,
+        escrow_info: &Escrow,
     ) -> ProgramResult {
-        let accounts_info_iter = &mut accounts.iter();
-        let trade_taker_account = next_account_info(accounts_info_iter)?;
+        if !token::is_supported_token_program(taker_gate_account.owner) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        // checking if this account is the signer
-        if !trade_taker_account.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
+        let taker_gate_info = TokenAccount::unpack(&taker_gate_account.try_borrow_data()?)?;
+        if taker_gate_info.owner != *taker
+            || taker_gate_info.mint != escrow_info.gate_mint
+            || taker_gate_info.amount < escrow_info.gate_min_amount
+        {
+            return Err(EscrowError::GateRequirementNotMet.into());
         }
 
-        // getting the amount of Y tokens in takers account
-        let taker_token_to_send_account = next_account_info(accounts_info_iter)?;
+        Ok(())
+    }
 
-        let taker_token_to_recieve_account = next_account_info(accounts_info_iter)?;
+    /// Checks that `deposit_mint` is the deposit's and can only ever have one token
+    fn check_nft_mint(
+        deposit_mint: &AccountInfo,
+        token_program: &AccountInfo,
+        escrow_info: &Escrow,
+    ) -> ProgramResult {
+        if *deposit_mint.key != escrow_info.temp_token_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        if deposit_mint.owner != token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        let pdas_temp_token_account = next_account_info(accounts_info_iter)?;
-        let pdas_temp_token_account_info =
-            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+        let deposit_mint_info = Mint::unpack(&deposit_mint.try_borrow_data()?)?;
+        if deposit_mint_info.decimals != 0 || deposit_mint_info.supply != 1 {
+            return Err(EscrowError::NotAnNft.into());
+        }
 
-        if pdas_temp_token_account_info.amount != expected_amount {
-            return Err(EscrowError::ExpectedAmountMissmatch.into());
+        Ok(())
+    }
+
+    /// Royalties owed to each verified creator of the deposit mint out of `payment`, read from
+    /// its Metaplex metadata account
+    fn royalty_shares(
+        metadata_account: &AccountInfo,
+        escrow_info: &Escrow,
+        payment: u64,
+    ) -> Result<Vec<(Pubkey, u64)>, EscrowError> {
+        if *metadata_account.owner != metadata::token_metadata_program::id()
+            || *metadata_account.key != metadata::metadata_address(&escrow_info.temp_token_mint)
+        {
+            return Err(EscrowError::InvalidMetadata);
         }
 
-        let initializer_account = next_account_info(accounts_info_iter)?;
-        let initializer_token_to_recieve_account = next_account_info(accounts_info_iter)?;
-        let escrow_account = next_account_info(accounts_info_iter)?;
+        let metadata_data = metadata_account
+            .try_borrow_data()
+            .map_err(|_| EscrowError::InvalidMetadata)?;
+        let metadata = metadata::load_metadata(&metadata_data)?;
+        if metadata.mint != escrow_info.temp_token_mint {
+            return Err(EscrowError::InvalidMetadata);
+        }
 
-        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
-        let taker_token_to_send_info =
-            TokenAccount::unpack(&taker_token_to_send_account.try_borrow_data()?)?;
+        metadata::royalty_shares(&metadata, payment)
+    }
 
-        if taker_token_to_send_info.amount < escrow_info.expected_amount {
-            return Err(EscrowError::ExpectedAmountMissmatch.into());
+    /// Returns the owner of the token account holding the position token of the escrow
+    fn position_holder(
+        holder_position_account: &AccountInfo,
+        escrow_info: &Escrow,
+    ) -> Result<Pubkey, ProgramError> {
+        if !token::is_supported_token_program(holder_position_account.owner) {
+            return Err(ProgramError::IncorrectProgramId);
         }
 
-        if escrow_info.initializer_pubkey != *initializer_account.key {
-            return Err(ProgramError::InvalidAccountData);
+        let holder_position_info =
+            TokenAccount::unpack(&holder_position_account.try_borrow_data()?)?;
+        if holder_position_info.mint != escrow_info.position_mint
+            || holder_position_info.amount != 1
+        {
+            return Err(EscrowError::InvalidPositionAccount.into());
         }
 
-        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
-            return Err(ProgramError::InvalidAccountData);
+        Ok(holder_position_info.owner)
+    }
+
+    /// Returns who the escrow belongs to: the holder of the position token for escrows started
+    /// with one, reading their position token account from the iterator, the initializer otherwise
+    fn escrow_owner<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_info_iter: &mut I,
+        escrow_info: &Escrow,
+    ) -> Result<Pubkey, ProgramError> {
+        if !escrow_info.has_position() {
+            return Ok(escrow_info.initializer_pubkey);
         }
 
-        if escrow_info.initializer_token_to_receive_account_pubkey
-            != *initializer_token_to_recieve_account.key
-        {
-            return Err(ProgramError::InvalidAccountData);
+        let holder_position_account = next_account(accounts_info_iter, "holder_position_account")?;
+        Self::position_holder(holder_position_account, escrow_info)
+    }
+
+    /// Checks the mint can only ever hold one indivisible token, then moves its mint authority
+    /// from the initializer to the PDA
+    fn take_mint_authority<'a>(
+        token_program: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        initializer: &AccountInfo<'a>,
+        pda: &Pubkey,
+    ) -> ProgramResult {
+        if mint.owner != token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
         }
 
-        let token_program = next_account_info(accounts_info_iter)?;
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], &program_id);
+        // one token with no decimals, so it is an NFT
+        let mint_info = Mint::unpack(&mint.try_borrow_data()?)?;
+        if mint_info.decimals != 0 || mint_info.supply != 0 {
+            return Err(EscrowError::InvalidNftMint.into());
+        }
 
-        let transfer_y_to_initializer_ix = spl_token::instruction::transfer(
+        let mint_authority_change_ix = token::set_authority(
             token_program.key,
-            taker_token_to_send_account.key,
-            initializer_token_to_recieve_account.key,
-            trade_taker_account.key,
-            &[&trade_taker_account.key],
-            escrow_info.expected_amount,
+            mint.key,
+            Some(pda),
+            spl_token::instruction::AuthorityType::MintTokens,
+            initializer.key,
+            &[initializer.key],
         )?;
 
-        // transfers y from taker to initializer
+        msg!("Calling the token program to transfer the mint authority...");
         invoke(
-            &transfer_y_to_initializer_ix,
+            &mint_authority_change_ix,
+            &[mint.clone(), initializer.clone(), token_program.clone()],
+        )
+    }
+
+    /// Mints the one token of the mint to the destination, then drops the mint authority so no
+    /// other token of the mint can ever be minted
+    fn mint_nft<'a>(
+        token_program: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+    ) -> ProgramResult {
+        let mint_nft_ix = token::mint_to(
+            token_program.key,
+            mint.key,
+            destination.key,
+            pda_account.key,
+            &[pda_account.key],
+            1,
+        )?;
+
+        msg!("Calling the token program to mint the token...");
+        invoke_signed(
+            &mint_nft_ix,
             &[
                 token_program.clone(),
-                taker_token_to_send_account.clone(),
-                initializer_token_to_recieve_account.clone(),
-                trade_taker_account.clone(),
+                mint.clone(),
+                destination.clone(),
+                pda_account.clone(),
             ],
+            &[&seeds.seeds()],
         )?;
 
-        // invoke(&transfer_x_to_trade_taker_ix, &[token_program, pdas_temp_token_account, taker_token_to_recieve_account, ])
+        let drop_mint_authority_ix = token::set_authority(
+            token_program.key,
+            mint.key,
+            None,
+            spl_token::instruction::AuthorityType::MintTokens,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
+
+        msg!("Calling the token program to drop the mint authority...");
+        invoke_signed(
+            &drop_mint_authority_ix,
+            &[token_program.clone(), mint.clone(), pda_account.clone()],
+            &[&seeds.seeds()],
+        )
+    }
 
-        let pda_account = next_account_info(accounts_info_iter)?;
+    /// Sends the cancel penalty to the fee collector when the escrow is cancelled before its
+    /// no_penalty_after_slot, the share of the deposit signed for by the PDA and the lamports paid
+    /// by the initializer. The penalty accounts are read even when the cancel is free, so clients
+    /// don't depend on the slot the cancel lands in
+    fn charge_cancel_penalty<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_info_iter: &mut I,
+        escrow_info: &Escrow,
+        token_program: &AccountInfo<'b>,
+        pdas_temp_token_account: &AccountInfo<'b>,
+        initializer_account: &AccountInfo<'b>,
+        pda_account: &AccountInfo<'b>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (fee_collector, _) = fee_collector_pda(program_id);
+        let penalized = escrow_info.is_cancel_penalized(&Clock::get()?);
+
+        if escrow_info.cancel_penalty_bps != 0 {
+            let fee_collector_account = next_account(accounts_info_iter, "fee_collector_account")?;
+            check_token_program(token_program, &[fee_collector_account])?;
+
+            let fee_collector_info =
+                TokenAccount::unpack(&fee_collector_account.try_borrow_data()?)?;
+            if fee_collector_info.owner != fee_collector {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if fee_collector_info.mint != escrow_info.temp_token_mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            let pdas_temp_token_account_info =
+                TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+            let penalty = math::checked_fee(
+                pdas_temp_token_account_info.amount,
+                escrow_info.cancel_penalty_bps,
+            )?;
+
+            if penalized && penalty > 0 {
+                let transfer_penalty_ix = token::transfer(
+                    token_program.key,
+                    pdas_temp_token_account.key,
+                    fee_collector_account.key,
+                    pda_account.key,
+                    &[pda_account.key],
+                    penalty,
+                )?;
+
+                msg!("Calling the token program to transfer the cancel penalty...");
+                invoke_signed(
+                    &transfer_penalty_ix,
+                    &[
+                        token_program.clone(),
+                        pdas_temp_token_account.clone(),
+                        fee_collector_account.clone(),
+                        pda_account.clone(),
+                    ],
+                    &[&PdaSeeds::of_escrow(escrow_info).seeds()],
+                )?;
+            }
+        }
+
+        if escrow_info.cancel_penalty_lamports != 0 {
+            let fee_collector_pda_account =
+                next_account(accounts_info_iter, "fee_collector_pda_account")?;
+            if *fee_collector_pda_account.key != fee_collector {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let system_program_account = next_account(accounts_info_iter, "system_program")?;
+            if *system_program_account.key != system_program::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            if penalized {
+                let transfer_penalty_ix = system_instruction::transfer(
+                    initializer_account.key,
+                    &fee_collector,
+                    escrow_info.cancel_penalty_lamports,
+                );
 
-        let transfer_x_to_trade_taker_ix = spl_token::instruction::transfer(
+                msg!("Calling the system program to pay the cancel penalty...");
+                invoke(
+                    &transfer_penalty_ix,
+                    &[
+                        initializer_account.clone(),
+                        fee_collector_pda_account.clone(),
+                        system_program_account.clone(),
+                    ],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the whole temp token account balance to the initializer, then closes the temp
+    /// account and the escrow account sending their rent to the initializer
+    fn refund_and_close<'a>(
+        token_program: &AccountInfo<'a>,
+        pdas_temp_token_account: &AccountInfo<'a>,
+        initializer_token_to_refund_account: &AccountInfo<'a>,
+        initializer_account: &AccountInfo<'a>,
+        escrow_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+    ) -> ProgramResult {
+        let pdas_temp_token_account_info =
+            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
+
+        let refund_x_to_initializer_ix = token::transfer(
             token_program.key,
             pdas_temp_token_account.key,
-            taker_token_to_recieve_account.key,
-            &pda,
-            &[&pda],
-            expected_amount,
+            initializer_token_to_refund_account.key,
+            pda_account.key,
+            &[pda_account.key],
+            pdas_temp_token_account_info.amount,
         )?;
 
-        msg!("Calling the token program to transfer tokens to the taker...");
+        msg!("Calling the token program to return tokens to the initializer...");
         invoke_signed(
-            &transfer_x_to_trade_taker_ix,
+            &refund_x_to_initializer_ix,
             &[
                 token_program.clone(),
                 pdas_temp_token_account.clone(),
-                taker_token_to_recieve_account.clone(),
+                initializer_token_to_refund_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&seeds.seeds()],
+        )?;
+
+        Self::close_escrow(
+            token_program,
+            pdas_temp_token_account,
+            initializer_account,
+            escrow_account,
+            pda_account,
+            seeds,
+        )
+    }
+
+    /// Closes the crowd vault of a crowd fill being cancelled or refunded when no contribution is
+    /// left in it, otherwise the last RefundContribution closes it
+    fn close_empty_crowd_vault<'a>(
+        escrow_info: &Escrow,
+        token_program: &AccountInfo<'a>,
+        crowd_vault: &AccountInfo<'a>,
+        initializer_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if escrow_info.crowd_vault != *crowd_vault.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.crowd_total > 0 {
+            return Ok(());
+        }
+
+        Self::close_crowd_vault(
+            token_program,
+            crowd_vault,
+            initializer_account,
+            pda_account,
+            &PdaSeeds::of_escrow(escrow_info),
+        )
+    }
+
+    /// Closes the emptied crowd vault of a crowd fill, sending its rent to the initializer
+    fn close_crowd_vault<'a>(
+        token_program: &AccountInfo<'a>,
+        crowd_vault: &AccountInfo<'a>,
+        initializer_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+    ) -> ProgramResult {
+        let close_crowd_vault_ix = token::close_account(
+            token_program.key,
+            crowd_vault.key,
+            initializer_account.key,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
+
+        msg!("Calling the token program to close the crowd vault...");
+        invoke_signed(
+            &close_crowd_vault_ix,
+            &[
+                token_program.clone(),
+                crowd_vault.clone(),
+                initializer_account.clone(),
                 pda_account.clone(),
             ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
+            &[&seeds.seeds()],
+        )
+    }
+
+    /// Closes a bid or contribution account, sending its rent to `destination`
+    fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+        msg!("Closing the program account...");
+        **destination.lamports.borrow_mut() = destination
+            .lamports()
+            .checked_add(account.lamports())
+            .ok_or(EscrowError::AmountOverFlow)?;
+
+        **account.lamports.borrow_mut() = 0;
+        account.try_borrow_mut_data()?.fill(0);
+
+        Ok(())
+    }
+
+    /// Hands the close authority of the receiving account of an escrow started with `unwrap_sol`
+    /// back to the initializer when it ends without a trade, taking the account from `iter`
+    fn return_close_authority<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_info_iter: &mut I,
+        escrow_info: &Escrow,
+        token_program: &AccountInfo<'b>,
+        pda_account: &AccountInfo<'b>,
+    ) -> ProgramResult {
+        if !escrow_info.unwrap_sol {
+            return Ok(());
+        }
+
+        let initializer_token_to_receive_account =
+            next_account(accounts_info_iter, "initializer_token_to_receive_account")?;
+        if escrow_info.initializer_token_to_receive_account_pubkey
+            != *initializer_token_to_receive_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let close_authority_change_ix = token::set_authority(
+            token_program.key,
+            initializer_token_to_receive_account.key,
+            None,
+            spl_token::instruction::AuthorityType::CloseAccount,
+            pda_account.key,
+            &[pda_account.key],
         )?;
 
-        let close_pdas_temp_account_ix = spl_token::instruction::close_account(
+        msg!("Calling the token program to return the receiving account's close authority...");
+        invoke_signed(
+            &close_authority_change_ix,
+            &[
+                token_program.clone(),
+                initializer_token_to_receive_account.clone(),
+                pda_account.clone(),
+            ],
+            &[&PdaSeeds::of_escrow(escrow_info).seeds()],
+        )
+    }
+
+    /// Closes the PDA's temp token account and the escrow account, sending their rent to the
+    /// initializer
+    fn close_escrow<'a>(
+        token_program: &AccountInfo<'a>,
+        pdas_temp_token_account: &AccountInfo<'a>,
+        initializer_account: &AccountInfo<'a>,
+        escrow_account: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        seeds: &PdaSeeds,
+    ) -> ProgramResult {
+        let close_pdas_temp_account_ix = token::close_account(
             token_program.key,
             pdas_temp_token_account.key,
             initializer_account.key,
-            &pda,
-            &[&pda],
+            pda_account.key,
+            &[pda_account.key],
         )?;
 
         msg!("Calling the token program to close the pda's temp account...");
@@ -215,18 +7166,63 @@ impl Processor {
                 initializer_account.clone(),
                 pda_account.clone(),
             ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
+            &[&seeds.seeds()],
         )?;
 
         msg!("Closing the escrow account...");
+        // the data is wiped before the lamports go, so nothing of the escrow is left to read in
+        // the rest of the transaction, even if the account gets funded again
+        escrow_account.try_borrow_mut_data()?.fill(0);
+
         **initializer_account.lamports.borrow_mut() = initializer_account
             .lamports()
             .checked_add(escrow_account.lamports())
             .ok_or(EscrowError::AmountOverFlow)?;
 
         **escrow_account.lamports.borrow_mut() = 0;
-        *escrow_account.try_borrow_mut_data()? = &mut [];
 
         Ok(())
     }
 }
+
+/// Fails with `AccountNotWritable` when the client didn't mark an account the instruction writes
+/// to as writable, logging which one, instead of failing once the runtime sees the write
+fn check_writable(account: &AccountInfo, name: &str) -> ProgramResult {
+    if !account.is_writable {
+        msg!("{} account is not writable", name);
+        return Err(EscrowError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+/// Fails with `IncorrectProgramId` unless `token_program` is a supported token program owning
+/// every given token account. Any other program would be handed the PDA's signature
+fn check_token_program(
+    token_program: &AccountInfo,
+    token_accounts: &[&AccountInfo],
+) -> ProgramResult {
+    if !token::is_supported_token_program(token_program.key) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if token_accounts
+        .iter()
+        .any(|token_account| token_account.owner != token_program.key)
+    {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Same as `next_account_info`, but logs which account was expected when the accounts run out
+fn next_account<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    iter: &mut I,
+    name: &str,
+) -> Result<I::Item, ProgramError> {
+    match next_account_info(iter) {
+        Ok(account) => Ok(account),
+        Err(error) => {
+            msg!("missing {} account", name);
+            Err(error)
+        }
+    }
+}
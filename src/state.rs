@@ -1,4 +1,5 @@
 use solana_program::{
+    clock::{Clock, Slot, UnixTimestamp},
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
@@ -6,73 +7,2281 @@ use solana_program::{
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{error::EscrowError, math, merkle, metadata::RoyaltyPolicy, oracle::OracleKind};
+
+/// Most deposited assets an escrow can hold, temp_token_account included
+pub const MAX_BASKET_ASSETS: usize = 4;
+
+/// Most token accounts the initializer's payment can be split between, their receiving account included
+pub const MAX_PAYMENT_SPLITS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscrowState {
+    Uninitialized,
+    Active,
+    /// Set when the escrow is settled or cancelled so the account can't be used again
+    Closed,
+    /// Set when a vesting escrow is exchanged, the taker claims the deposit with ClaimVested
+    Vesting,
+    /// Set when a crowd fill is settled, the contributors claim the deposit with ClaimShare
+    CrowdSettled,
+    /// Set while a trade is moving tokens so a nested trade of the same escrow is refused
+    Locked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisputeStatus {
+    None,
+    /// Set by either party, only the arbiter can settle the escrow until it's resolved
+    Raised,
+}
+
+/// A token the taker of a basket payment escrow pays on top of the expected amount
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PaymentLeg {
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// The initializer's token account receiving the leg
+    pub destination: Pubkey,
+}
+
+/// A co-recipient of the initializer's payment
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PaymentSplit {
+    /// Token account for the receive token mint getting the share
+    pub destination: Pubkey,
+    /// Share of the payment, in basis points
+    pub basis_points: u16,
+}
+
+#[derive(Clone, Debug)]
 pub struct Escrow {
-    pub is_initialized: bool,
+    pub state: EscrowState,
     pub initializer_pubkey: Pubkey,
     pub temp_token_account_pubkey: Pubkey,
     pub initializer_token_to_receive_account_pubkey: Pubkey,
     pub expected_amount: u64,
+    /// Slot after which the escrow can no longer be exchanged, 0 means it never expires
+    pub expiry_slot: Slot,
+    pub bump_seed: u8,
+    /// Part of the deposited tokens that hasn't been taken yet
+    pub remaining_deposit: u64,
+    /// Part of the expected amount that hasn't been paid yet
+    pub remaining_expected: u64,
+    /// Unix timestamp after which the escrow can no longer be exchanged, 0 means it never expires
+    pub expiry_unix_timestamp: UnixTimestamp,
+    /// The only account allowed to take the trade, the default pubkey means anyone can take it
+    pub allowed_taker: Pubkey,
+    /// Taker who proposed the pending counter-offer, the default pubkey means there is none
+    pub counter_offer_taker: Pubkey,
+    /// Amount of the expected token the counter-offer proposes to pay for the remaining deposit
+    pub counter_offer_amount: u64,
+    /// Proposer's token account the counter-offer is paid from, the PDA is approved as its delegate
+    pub counter_offer_payment_account: Pubkey,
+    /// Proposer's token account that receives the deposit if the counter-offer is accepted
+    pub counter_offer_receive_account: Pubkey,
+    /// Whether a taker has to deposit first and wait for the initializer to confirm the exchange
+    pub two_phase: bool,
+    /// Taker whose deposit is waiting for confirmation, the default pubkey means there is none
+    pub pending_taker: Pubkey,
+    /// Taker's token account holding the deposit, owned by the PDA until confirmed or rejected
+    pub pending_taker_vault: Pubkey,
+    /// Taker's token account that receives the deposited tokens once confirmed
+    pub pending_taker_receive_account: Pubkey,
+    /// Mint of the deposited token
+    pub temp_token_mint: Pubkey,
+    /// Mint of the token the initializer expects to receive
+    pub receive_token_mint: Pubkey,
+    /// Smallest part of the deposit a taker can take unless they take everything that is left
+    pub min_taker_amount: u64,
+    /// Price of the whole deposit when the Dutch auction starts
+    pub auction_start_amount: u64,
+    /// Price of the whole deposit when the Dutch auction ends
+    pub auction_end_amount: u64,
+    /// Slot the Dutch auction price starts decaying at
+    pub auction_start_slot: Slot,
+    /// Slot the Dutch auction price stops decaying at, 0 means the escrow isn't a Dutch auction
+    pub auction_end_slot: Slot,
+    /// Whether the deposit is sold to the best bid placed with PlaceBid instead of being exchanged
+    pub bid_auction: bool,
+    /// Amount of the best bid placed so far, new bids have to beat it
+    pub best_bid_amount: u64,
+    /// Units of the expected token paid per price_denominator units of the deposit
+    pub price_numerator: u64,
+    /// Units of the deposit priced at price_numerator, 0 means the escrow is priced by expected_amount
+    pub price_denominator: u64,
+    /// Number of basket_vaults in use, 0 means only temp_token_account is deposited
+    pub basket_len: u8,
+    /// Temp token accounts owned by the PDA holding the rest of a basket deposit, all taken in one exchange
+    pub basket_vaults: [Pubkey; MAX_BASKET_ASSETS - 1],
+    /// Number of payment_legs in use, 0 means the taker only pays expected_amount
+    pub payment_legs_len: u8,
+    /// Tokens the taker pays besides expected_amount, each sent whole to its destination without a fee
+    pub payment_legs: [PaymentLeg; MAX_BASKET_ASSETS - 1],
+    /// SHA-256 hash of the secret a taker has to reveal to exchange, all zeroes means there is none
+    pub hash_lock: [u8; 32],
+    /// Pyth price account the price band is checked against, the default pubkey means there is none
+    pub oracle: Pubkey,
+    /// Largest deviation from the oracle price a trade can have, in basis points
+    pub oracle_max_deviation_bps: u16,
+    /// Oldest oracle price a trade can be checked against, in seconds
+    pub oracle_max_staleness: i64,
+    /// Decimals of the deposited mint, only stored for oracle checked escrows
+    pub deposit_decimals: u8,
+    /// Decimals of the received mint, only stored for oracle checked escrows
+    pub receive_decimals: u8,
+    /// Whether the initializer is paid expected_amount lamports with ExchangeForSol instead of a token
+    pub receive_native_sol: bool,
+    /// Distinguishes the escrows of one initializer, it is part of the PDA seeds
+    pub nonce: u64,
+    /// Program the oracle account belongs to, fixed at init
+    pub oracle_kind: OracleKind,
+    /// Neutral party resolving disputes, disputes can't be raised when it's not set
+    pub arbiter: Pubkey,
+    pub dispute_status: DisputeStatus,
+    /// Slot before which nothing vests, only set for vesting escrows
+    pub vesting_cliff_slot: u64,
+    /// Slot at which the whole deposit is vested, 0 means the escrow doesn't vest
+    pub vesting_end_slot: u64,
+    /// Slot of the exchange, the deposit vests linearly from it to the end slot
+    pub vesting_start_slot: u64,
+    /// Part of the deposit the taker bought, it stays in the temp token account until claimed
+    pub vesting_amount: u64,
+    /// Part of the vesting amount the taker has claimed so far
+    pub vesting_claimed: u64,
+    /// Taker who exchanged a vesting escrow and can claim the deposit
+    pub vesting_beneficiary: Pubkey,
+    /// Taker's token account the vested tokens are claimed to
+    pub vesting_receive_account: Pubkey,
+    /// Whether takers pay in installments with StreamPayment instead of taking the escrow whole
+    pub streaming: bool,
+    /// Part of the expected amount paid so far with StreamPayment
+    pub stream_paid: u64,
+    /// Part of the deposit released so far with StreamPayment
+    pub stream_released: u64,
+    /// Mint of the receipt the taker gets on exchange, the default pubkey means there is none
+    pub receipt_mint: Pubkey,
+    /// Mint of the position token, its holder acts as the initializer, the default pubkey means there is none
+    pub position_mint: Pubkey,
+    /// Whether the expected amount is paid by a crowd of contributors with Contribute
+    pub crowd_fill: bool,
+    /// Token account owned by the PDA holding the contributions of a crowd fill
+    pub crowd_vault: Pubkey,
+    /// Sum of the contributions to a crowd fill
+    pub crowd_total: u64,
+    /// Sum of the contributions whose share of the deposit was claimed with ClaimShare
+    pub crowd_claimed: u64,
+    /// Merkle root of the takers allowed to take the trade, all zeroes means anyone can take it
+    pub taker_merkle_root: [u8; 32],
+    /// Initializer the escrow's PDA is derived from, kept when TransferInitializer hands the escrow to someone else
+    pub pda_initializer_pubkey: Pubkey,
+    /// Mint takers have to hold gate_min_amount of to take the trade, the default pubkey means there is no gate
+    pub gate_mint: Pubkey,
+    /// Least balance of gate_mint a taker has to hold, it is only checked and never moved
+    pub gate_min_amount: u64,
+    /// Civic gatekeeper network takers need a valid gateway token of, the default pubkey means there is none
+    pub gatekeeper_network: Pubkey,
+    /// Whether a trade has to come with an spl-memo instruction in the same transaction
+    pub memo_required: bool,
+    /// Number of payment_splits in use, 0 means the initializer's receiving account gets the whole payment
+    pub payment_splits_len: u8,
+    /// Co-recipients of the initializer's payment, the initializer's receiving account gets what they don't
+    pub payment_splits: [PaymentSplit; MAX_PAYMENT_SPLITS - 1],
+    /// What trades do with the creator royalties of the deposit mint
+    pub royalty_policy: RoyaltyPolicy,
+    /// Whether the deposit is a single NFT, moved with transfer_checked
+    pub nft: bool,
+    /// Whether the final trade closes the initializer's wrapped SOL receive account, paying them lamports
+    pub unwrap_sol: bool,
+    /// Number of changes made to the escrow since init, which leaves it at 0. Indexers order the
+    /// events of the escrow by it and skip the ones they already processed
+    pub sequence: u64,
+    /// Share of the remaining deposit a cancel before no_penalty_after_slot sends to the fee collector, in basis points
+    pub cancel_penalty_bps: u16,
+    /// Lamports the initializer pays the fee collector when cancelling before no_penalty_after_slot
+    pub cancel_penalty_lamports: u64,
+    /// Slot from which cancelling is free, 0 means the escrow has no cancel penalty
+    pub no_penalty_after_slot: Slot,
+    /// Whether the payment may be of the deposit's own mint, as long as the amounts differ
+    pub same_mint_allowed: bool,
+    /// Whether the initializer took the escrow off the market, it can't be traded until they put
+    /// it back. Escrows start active, as do the ones migrated from older layouts
+    pub inactive: bool,
 }
 
+impl Escrow {
+    pub fn is_expired(&self, clock: &Clock) -> bool {
+        (self.expiry_slot != 0 && clock.slot > self.expiry_slot)
+            || (self.expiry_unix_timestamp != 0
+                && clock.unix_timestamp > self.expiry_unix_timestamp)
+    }
+
+    /// Whether the taker can take the trade without proving they are on the allowlist, showing
+    /// their gate mint balance or gateway token, adding a memo or paying creator royalties, which
+    /// only Exchange and PartialExchange check
+    pub fn is_taker_allowed(&self, taker: &Pubkey) -> bool {
+        self.is_designated_taker(taker)
+            && !self.has_taker_allowlist()
+            && !self.is_token_gated()
+            && !self.has_gatekeeper_network()
+            && !self.memo_required
+            && !self.has_royalties()
+    }
+
+    /// Whether the taker can take the trade, `proof` showing they are on the allowlist
+    pub fn is_taker_allowed_with_proof(
+        &self,
+        taker: &Pubkey,
+        proof: &[[u8; 32]],
+    ) -> Result<bool, EscrowError> {
+        if !self.has_taker_allowlist() {
+            return Ok(self.is_designated_taker(taker));
+        }
+        Ok(self.is_designated_taker(taker)
+            && merkle::verify(&self.taker_merkle_root, taker, proof)?)
+    }
+
+    fn is_designated_taker(&self, taker: &Pubkey) -> bool {
+        self.allowed_taker == Pubkey::default() || self.allowed_taker == *taker
+    }
+
+    pub fn has_gatekeeper_network(&self) -> bool {
+        self.gatekeeper_network != Pubkey::default()
+    }
+
+    pub fn has_royalties(&self) -> bool {
+        self.royalty_policy != RoyaltyPolicy::None
+    }
+
+    pub fn is_token_gated(&self) -> bool {
+        self.gate_mint != Pubkey::default()
+    }
+
+    pub fn has_taker_allowlist(&self) -> bool {
+        self.taker_merkle_root != [0; 32]
+    }
+
+    /// Checks that paying `payment` for `fill_amount` of the remaining deposit gives the
+    /// initializer at least the pro rata share of the remaining expected amount
+    pub fn check_offered_payment(&self, fill_amount: u64, payment: u64) -> Result<(), EscrowError> {
+        if fill_amount == 0 || fill_amount > self.remaining_deposit {
+            return Err(EscrowError::InvalidFillAmount);
+        }
+
+        if self.is_rate_priced() {
+            if payment < self.rate_payment(fill_amount)? {
+                return Err(EscrowError::ExpectedAmountMissmatch);
+            }
+            return Ok(());
+        }
+
+        if (payment as u128) * (self.remaining_deposit as u128)
+            < (fill_amount as u128) * (self.remaining_expected as u128)
+        {
+            return Err(EscrowError::ExpectedAmountMissmatch);
+        }
+
+        Ok(())
+    }
+
+    /// Counts a change made to the escrow, every instruction changing it calls this once
+    pub fn advance_sequence(&mut self) {
+        self.sequence += 1;
+    }
+
+    pub fn has_cancel_penalty(&self) -> bool {
+        self.no_penalty_after_slot != 0
+    }
+
+    /// Whether cancelling at `clock` costs the initializer the cancel penalty
+    pub fn is_cancel_penalized(&self, clock: &Clock) -> bool {
+        self.has_cancel_penalty() && clock.slot < self.no_penalty_after_slot
+    }
+
+    pub fn has_counter_offer(&self) -> bool {
+        self.counter_offer_taker != Pubkey::default()
+    }
+
+    pub fn clear_counter_offer(&mut self) {
+        self.counter_offer_taker = Pubkey::default();
+        self.counter_offer_amount = 0;
+        self.counter_offer_payment_account = Pubkey::default();
+        self.counter_offer_receive_account = Pubkey::default();
+    }
+
+    pub fn has_pending_deposit(&self) -> bool {
+        self.pending_taker != Pubkey::default()
+    }
+
+    pub fn clear_pending_deposit(&mut self) {
+        self.pending_taker = Pubkey::default();
+        self.pending_taker_vault = Pubkey::default();
+        self.pending_taker_receive_account = Pubkey::default();
+    }
+
+    /// Whether either side of the trade is a basket, these escrows can only be taken whole
+    pub fn has_basket(&self) -> bool {
+        self.basket_len != 0 || self.payment_legs_len != 0
+    }
+
+    /// Basket temp token accounts deposited besides temp_token_account
+    pub fn basket_vaults(&self) -> &[Pubkey] {
+        &self.basket_vaults[..self.basket_len as usize]
+    }
+
+    pub fn has_hash_lock(&self) -> bool {
+        self.hash_lock != [0; 32]
+    }
+
+    /// Basket, hash locked, native SOL, vesting, receipt, position, split payment and unwrapping
+    /// escrows are taken whole with Exchange or ExchangeForSol, crowd fills are settled with
+    /// Settle, or either is cancelled or refunded. Everything else that settles or changes the
+    /// deposit rejects them
+    pub fn is_exchange_only(&self) -> bool {
+        self.has_basket()
+            || self.has_hash_lock()
+            || self.receive_native_sol
+            || self.has_vesting()
+            || self.has_receipt()
+            || self.has_position()
+            || self.crowd_fill
+            || self.has_payment_split()
+            || self.unwrap_sol
+    }
+
+    pub fn has_receipt(&self) -> bool {
+        self.receipt_mint != Pubkey::default()
+    }
+
+    pub fn has_position(&self) -> bool {
+        self.position_mint != Pubkey::default()
+    }
+
+    pub fn has_vesting(&self) -> bool {
+        self.vesting_end_slot != 0
+    }
+
+    pub fn has_arbiter(&self) -> bool {
+        self.arbiter != Pubkey::default()
+    }
+
+    pub fn is_disputed(&self) -> bool {
+        self.dispute_status == DisputeStatus::Raised
+    }
+
+    pub fn has_oracle(&self) -> bool {
+        self.oracle != Pubkey::default()
+    }
+
+    pub fn payment_legs(&self) -> &[PaymentLeg] {
+        &self.payment_legs[..self.payment_legs_len as usize]
+    }
+
+    pub fn has_payment_split(&self) -> bool {
+        self.payment_splits_len != 0
+    }
+
+    pub fn payment_splits(&self) -> &[PaymentSplit] {
+        &self.payment_splits[..self.payment_splits_len as usize]
+    }
+
+    /// Shares of `payment` for the initializer's receiving account and each payment split. The
+    /// splits' shares are rounded down, the remainder goes to the initializer's receiving account
+    pub fn split_payment(&self, payment: u64) -> Result<(u64, Vec<u64>), EscrowError> {
+        let shares = self
+            .payment_splits()
+            .iter()
+            .map(|split| math::checked_fee(payment, split.basis_points))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut remainder = payment;
+        for share in &shares {
+            remainder = math::checked_remainder(remainder, *share)?;
+        }
+        Ok((remainder, shares))
+    }
+
+    pub fn is_rate_priced(&self) -> bool {
+        self.price_denominator != 0
+    }
+
+    /// Amount of the expected token a taker pays for `amount` of the deposit at the stored rate,
+    /// rounded up so rounding always favors the initializer
+    pub fn rate_payment(&self, amount: u64) -> Result<u64, EscrowError> {
+        if !self.is_rate_priced() {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        math::checked_rate_payment(amount, self.price_numerator, self.price_denominator)
+    }
+
+    pub fn is_dutch_auction(&self) -> bool {
+        self.auction_end_slot != 0
+    }
+
+    /// Price of the whole deposit at `slot`, decaying linearly from the start to the end amount
+    /// between the start and end slots. The decay is rounded down so the price is rounded up.
+    pub fn dutch_auction_price(&self, slot: Slot) -> Result<u64, EscrowError> {
+        if slot <= self.auction_start_slot {
+            return Ok(self.auction_start_amount);
+        }
+        if slot >= self.auction_end_slot {
+            return Ok(self.auction_end_amount);
+        }
+
+        let price_drop = self
+            .auction_start_amount
+            .checked_sub(self.auction_end_amount)
+            .ok_or(EscrowError::AmountOverFlow)? as u128;
+        let elapsed = (slot - self.auction_start_slot) as u128;
+        let duration = (self.auction_end_slot - self.auction_start_slot) as u128;
+
+        let decay = price_drop
+            .checked_mul(elapsed)
+            .and_then(|decay| decay.checked_div(duration))
+            .ok_or(EscrowError::AmountOverFlow)?;
+        Ok(self.auction_start_amount - decay as u64)
+    }
+
+    /// Part of the vesting amount unlocked at `slot`. Nothing is unlocked before the cliff slot,
+    /// after it the amount unlocks linearly from the exchange to the end slot, rounded down.
+    pub fn vested_amount(&self, slot: Slot) -> Result<u64, EscrowError> {
+        if slot < self.vesting_cliff_slot {
+            return Ok(0);
+        }
+        if slot >= self.vesting_end_slot {
+            return Ok(self.vesting_amount);
+        }
+
+        let elapsed = slot.saturating_sub(self.vesting_start_slot) as u128;
+        let duration = (self.vesting_end_slot - self.vesting_start_slot.min(slot)) as u128;
+
+        let vested = (self.vesting_amount as u128)
+            .checked_mul(elapsed)
+            .and_then(|vested| vested.checked_div(duration))
+            .ok_or(EscrowError::AmountOverFlow)?;
+        Ok(vested as u64)
+    }
+
+    /// Part of the deposit released once `paid` of the expected amount is paid with StreamPayment.
+    /// It's computed from the cumulative payment, rounded down, so rounding doesn't add up across
+    /// installments and paying the expected amount in full releases the whole deposit.
+    pub fn stream_release_for(&self, paid: u64) -> Result<u64, EscrowError> {
+        let deposit = self
+            .stream_released
+            .checked_add(self.remaining_deposit)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        if paid >= self.expected_amount {
+            return Ok(deposit);
+        }
+
+        let released = (deposit as u128)
+            .checked_mul(paid as u128)
+            .and_then(|released| released.checked_div(self.expected_amount as u128))
+            .ok_or(EscrowError::AmountOverFlow)?;
+        Ok(released as u64)
+    }
+
+    /// Part of the deposit left to claim that a contributor of `contribution` gets once the crowd
+    /// fill is settled. It is shared pro rata among the contributions not claimed yet and rounded
+    /// down, so the shares never add up to more than the deposit and the last claim takes the rest
+    pub fn crowd_share_for(&self, contribution: u64) -> Result<u64, EscrowError> {
+        let unclaimed = self
+            .crowd_total
+            .checked_sub(self.crowd_claimed)
+            .ok_or(EscrowError::AmountOverFlow)?;
+        if contribution == 0 || contribution > unclaimed {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let share = self.remaining_deposit as u128 * contribution as u128 / unclaimed as u128;
+        Ok(share as u64)
+    }
+
+    /// Amount of the expected token a taker has to pay to take `fill_amount` of the remaining
+    /// deposit. Partial fills are priced pro rata and rounded up so rounding always favors the
+    /// initializer, taking the whole remaining deposit pays exactly what is left.
+    pub fn payment_for_fill(&self, fill_amount: u64) -> Result<u64, EscrowError> {
+        if fill_amount == 0 || fill_amount > self.remaining_deposit {
+            return Err(EscrowError::InvalidFillAmount);
+        }
+
+        if self.is_rate_priced() {
+            return self.rate_payment(fill_amount);
+        }
+
+        if fill_amount == self.remaining_deposit {
+            return Ok(self.remaining_expected);
+        }
+
+        let numerator = fill_amount as u128 * self.remaining_expected as u128;
+        let denominator = self.remaining_deposit as u128;
+
+        // the exact share is worth less than one unit of the expected token
+        if numerator < denominator {
+            return Err(EscrowError::FillTooSmall);
+        }
+
+        let mut payment = numerator / denominator;
+        if payment * denominator < numerator {
+            payment += 1;
+        }
+        u64::try_from(payment).map_err(|_| EscrowError::AmountOverFlow)
+    }
+}
+
+/// Byte offsets of the packed `Escrow` fields, each field starts where the previous one ends and
+/// `Escrow::LEN` ends with the last one. The sizes given to `array_refs!` have to add up to it,
+/// so a field missing from either side fails to compile
+pub const VERSION_OFFSET: usize = 0;
+pub const STATE_OFFSET: usize = VERSION_OFFSET + 1;
+pub const INITIALIZER_OFFSET: usize = STATE_OFFSET + 1;
+pub const TEMP_TOKEN_ACCOUNT_OFFSET: usize = INITIALIZER_OFFSET + 32;
+pub const INITIALIZER_TOKEN_TO_RECEIVE_ACCOUNT_OFFSET: usize = TEMP_TOKEN_ACCOUNT_OFFSET + 32;
+pub const EXPECTED_AMOUNT_OFFSET: usize = INITIALIZER_TOKEN_TO_RECEIVE_ACCOUNT_OFFSET + 32;
+pub const EXPIRY_SLOT_OFFSET: usize = EXPECTED_AMOUNT_OFFSET + 8;
+pub const BUMP_SEED_OFFSET: usize = EXPIRY_SLOT_OFFSET + 8;
+pub const REMAINING_DEPOSIT_OFFSET: usize = BUMP_SEED_OFFSET + 1;
+pub const REMAINING_EXPECTED_OFFSET: usize = REMAINING_DEPOSIT_OFFSET + 8;
+pub const EXPIRY_UNIX_TIMESTAMP_OFFSET: usize = REMAINING_EXPECTED_OFFSET + 8;
+pub const ALLOWED_TAKER_OFFSET: usize = EXPIRY_UNIX_TIMESTAMP_OFFSET + 8;
+pub const COUNTER_OFFER_TAKER_OFFSET: usize = ALLOWED_TAKER_OFFSET + 32;
+pub const COUNTER_OFFER_AMOUNT_OFFSET: usize = COUNTER_OFFER_TAKER_OFFSET + 32;
+pub const COUNTER_OFFER_PAYMENT_ACCOUNT_OFFSET: usize = COUNTER_OFFER_AMOUNT_OFFSET + 8;
+pub const COUNTER_OFFER_RECEIVE_ACCOUNT_OFFSET: usize = COUNTER_OFFER_PAYMENT_ACCOUNT_OFFSET + 32;
+pub const TWO_PHASE_OFFSET: usize = COUNTER_OFFER_RECEIVE_ACCOUNT_OFFSET + 32;
+pub const PENDING_TAKER_OFFSET: usize = TWO_PHASE_OFFSET + 1;
+pub const PENDING_TAKER_VAULT_OFFSET: usize = PENDING_TAKER_OFFSET + 32;
+pub const PENDING_TAKER_RECEIVE_ACCOUNT_OFFSET: usize = PENDING_TAKER_VAULT_OFFSET + 32;
+pub const TEMP_TOKEN_MINT_OFFSET: usize = PENDING_TAKER_RECEIVE_ACCOUNT_OFFSET + 32;
+pub const RECEIVE_TOKEN_MINT_OFFSET: usize = TEMP_TOKEN_MINT_OFFSET + 32;
+pub const MIN_TAKER_AMOUNT_OFFSET: usize = RECEIVE_TOKEN_MINT_OFFSET + 32;
+pub const AUCTION_START_AMOUNT_OFFSET: usize = MIN_TAKER_AMOUNT_OFFSET + 8;
+pub const AUCTION_END_AMOUNT_OFFSET: usize = AUCTION_START_AMOUNT_OFFSET + 8;
+pub const AUCTION_START_SLOT_OFFSET: usize = AUCTION_END_AMOUNT_OFFSET + 8;
+pub const AUCTION_END_SLOT_OFFSET: usize = AUCTION_START_SLOT_OFFSET + 8;
+pub const BID_AUCTION_OFFSET: usize = AUCTION_END_SLOT_OFFSET + 8;
+pub const BEST_BID_AMOUNT_OFFSET: usize = BID_AUCTION_OFFSET + 1;
+pub const PRICE_NUMERATOR_OFFSET: usize = BEST_BID_AMOUNT_OFFSET + 8;
+pub const PRICE_DENOMINATOR_OFFSET: usize = PRICE_NUMERATOR_OFFSET + 8;
+pub const BASKET_LEN_OFFSET: usize = PRICE_DENOMINATOR_OFFSET + 8;
+pub const BASKET_VAULTS_OFFSET: usize = BASKET_LEN_OFFSET + 1;
+pub const PAYMENT_LEGS_LEN_OFFSET: usize = BASKET_VAULTS_OFFSET + 96;
+pub const PAYMENT_LEGS_OFFSET: usize = PAYMENT_LEGS_LEN_OFFSET + 1;
+pub const HASH_LOCK_OFFSET: usize = PAYMENT_LEGS_OFFSET + 216;
+pub const ORACLE_OFFSET: usize = HASH_LOCK_OFFSET + 32;
+pub const ORACLE_MAX_DEVIATION_BPS_OFFSET: usize = ORACLE_OFFSET + 32;
+pub const ORACLE_MAX_STALENESS_OFFSET: usize = ORACLE_MAX_DEVIATION_BPS_OFFSET + 2;
+pub const DEPOSIT_DECIMALS_OFFSET: usize = ORACLE_MAX_STALENESS_OFFSET + 8;
+pub const RECEIVE_DECIMALS_OFFSET: usize = DEPOSIT_DECIMALS_OFFSET + 1;
+pub const RECEIVE_NATIVE_SOL_OFFSET: usize = RECEIVE_DECIMALS_OFFSET + 1;
+pub const NONCE_OFFSET: usize = RECEIVE_NATIVE_SOL_OFFSET + 1;
+pub const ORACLE_KIND_OFFSET: usize = NONCE_OFFSET + 8;
+pub const ARBITER_OFFSET: usize = ORACLE_KIND_OFFSET + 1;
+pub const DISPUTE_STATUS_OFFSET: usize = ARBITER_OFFSET + 32;
+pub const VESTING_CLIFF_SLOT_OFFSET: usize = DISPUTE_STATUS_OFFSET + 1;
+pub const VESTING_END_SLOT_OFFSET: usize = VESTING_CLIFF_SLOT_OFFSET + 8;
+pub const VESTING_START_SLOT_OFFSET: usize = VESTING_END_SLOT_OFFSET + 8;
+pub const VESTING_AMOUNT_OFFSET: usize = VESTING_START_SLOT_OFFSET + 8;
+pub const VESTING_CLAIMED_OFFSET: usize = VESTING_AMOUNT_OFFSET + 8;
+pub const VESTING_BENEFICIARY_OFFSET: usize = VESTING_CLAIMED_OFFSET + 8;
+pub const VESTING_RECEIVE_ACCOUNT_OFFSET: usize = VESTING_BENEFICIARY_OFFSET + 32;
+pub const STREAMING_OFFSET: usize = VESTING_RECEIVE_ACCOUNT_OFFSET + 32;
+pub const STREAM_PAID_OFFSET: usize = STREAMING_OFFSET + 1;
+pub const STREAM_RELEASED_OFFSET: usize = STREAM_PAID_OFFSET + 8;
+pub const RECEIPT_MINT_OFFSET: usize = STREAM_RELEASED_OFFSET + 8;
+pub const POSITION_MINT_OFFSET: usize = RECEIPT_MINT_OFFSET + 32;
+pub const CROWD_FILL_OFFSET: usize = POSITION_MINT_OFFSET + 32;
+pub const CROWD_VAULT_OFFSET: usize = CROWD_FILL_OFFSET + 1;
+pub const CROWD_TOTAL_OFFSET: usize = CROWD_VAULT_OFFSET + 32;
+pub const CROWD_CLAIMED_OFFSET: usize = CROWD_TOTAL_OFFSET + 8;
+pub const TAKER_MERKLE_ROOT_OFFSET: usize = CROWD_CLAIMED_OFFSET + 8;
+pub const PDA_INITIALIZER_PUBKEY_OFFSET: usize = TAKER_MERKLE_ROOT_OFFSET + 32;
+pub const GATE_MINT_OFFSET: usize = PDA_INITIALIZER_PUBKEY_OFFSET + 32;
+pub const GATE_MIN_AMOUNT_OFFSET: usize = GATE_MINT_OFFSET + 32;
+pub const GATEKEEPER_NETWORK_OFFSET: usize = GATE_MIN_AMOUNT_OFFSET + 8;
+pub const MEMO_REQUIRED_OFFSET: usize = GATEKEEPER_NETWORK_OFFSET + 32;
+pub const PAYMENT_SPLITS_LEN_OFFSET: usize = MEMO_REQUIRED_OFFSET + 1;
+pub const PAYMENT_SPLITS_OFFSET: usize = PAYMENT_SPLITS_LEN_OFFSET + 1;
+pub const ROYALTY_POLICY_OFFSET: usize = PAYMENT_SPLITS_OFFSET + 102;
+pub const NFT_OFFSET: usize = ROYALTY_POLICY_OFFSET + 1;
+pub const UNWRAP_SOL_OFFSET: usize = NFT_OFFSET + 1;
+pub const SEQUENCE_OFFSET: usize = UNWRAP_SOL_OFFSET + 1;
+pub const CANCEL_PENALTY_BPS_OFFSET: usize = SEQUENCE_OFFSET + 8;
+pub const CANCEL_PENALTY_LAMPORTS_OFFSET: usize = CANCEL_PENALTY_BPS_OFFSET + 2;
+pub const NO_PENALTY_AFTER_SLOT_OFFSET: usize = CANCEL_PENALTY_LAMPORTS_OFFSET + 8;
+pub const SAME_MINT_ALLOWED_OFFSET: usize = NO_PENALTY_AFTER_SLOT_OFFSET + 8;
+pub const INACTIVE_OFFSET: usize = SAME_MINT_ALLOWED_OFFSET + 1;
+
+/// Layout version `pack` writes in the first byte of the escrow. Later versions only add fields
+/// after the ones of the previous version, see [Escrow::migrate](struct.Escrow.html#method.migrate)
+pub const ESCROW_VERSION: u8 = 5;
+
+/// Packed length of a version 1 escrow
+pub const ESCROW_V1_LEN: usize = UNWRAP_SOL_OFFSET + 1;
+
+/// Packed length of a version 2 escrow, which added the sequence
+pub const ESCROW_V2_LEN: usize = SEQUENCE_OFFSET + 8;
+
+/// Packed length of a version 3 escrow, which added the cancel penalty
+pub const ESCROW_V3_LEN: usize = NO_PENALTY_AFTER_SLOT_OFFSET + 8;
+
+/// Packed length of a version 4 escrow, which added same_mint_allowed
+pub const ESCROW_V4_LEN: usize = SAME_MINT_ALLOWED_OFFSET + 1;
+
+/// Packed length of a version 5 escrow, which added the inactive flag
+pub const ESCROW_V5_LEN: usize = INACTIVE_OFFSET + 1;
+
 impl Sealed for Escrow {}
 
 impl IsInitialized for Escrow {
     fn is_initialized(&self) -> bool {
-        self.is_initialized
+        self.state != EscrowState::Uninitialized
     }
 }
 
-impl Pack for Escrow {
-    const LEN: usize = 105;
-    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        let src = array_ref![src, 0, Escrow::LEN];
+impl Escrow {
+    /// Reads an escrow packed with an older version of the layout, the fields added since keep
+    /// their zero value. The account has to be reallocated to `Escrow::LEN` before the result is
+    /// packed back into it
+    pub fn migrate(old: &[u8]) -> Result<Escrow, ProgramError> {
+        match old.get(VERSION_OFFSET) {
+            Some(1) if old.len() >= ESCROW_V1_LEN => Escrow::unpack_v1(old),
+            Some(2) if old.len() >= ESCROW_V2_LEN => Escrow::unpack_v2(old),
+            Some(3) if old.len() >= ESCROW_V3_LEN => Escrow::unpack_v3(old),
+            Some(4) if old.len() >= ESCROW_V4_LEN => Escrow::unpack_v4(old),
+            Some(5) if old.len() >= ESCROW_V5_LEN => Escrow::unpack_v5(old),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    fn unpack_v5(src: &[u8]) -> Result<Escrow, ProgramError> {
+        let mut escrow = Escrow::unpack_v4(src)?;
+        escrow.inactive = unpack_bool(array_ref![src, INACTIVE_OFFSET, 1])?;
+        Ok(escrow)
+    }
+
+    fn unpack_v4(src: &[u8]) -> Result<Escrow, ProgramError> {
+        let mut escrow = Escrow::unpack_v3(src)?;
+        escrow.same_mint_allowed = unpack_bool(array_ref![src, SAME_MINT_ALLOWED_OFFSET, 1])?;
+        Ok(escrow)
+    }
+
+    fn unpack_v3(src: &[u8]) -> Result<Escrow, ProgramError> {
+        let mut escrow = Escrow::unpack_v2(src)?;
+        let src = array_ref![src, CANCEL_PENALTY_BPS_OFFSET, 18];
+        let (cancel_penalty_bps, cancel_penalty_lamports, no_penalty_after_slot) =
+            array_refs![src, 2, 8, 8];
+        escrow.cancel_penalty_bps = u16::from_le_bytes(*cancel_penalty_bps);
+        escrow.cancel_penalty_lamports = u64::from_le_bytes(*cancel_penalty_lamports);
+        escrow.no_penalty_after_slot = u64::from_le_bytes(*no_penalty_after_slot);
+        Ok(escrow)
+    }
+
+    fn unpack_v2(src: &[u8]) -> Result<Escrow, ProgramError> {
+        let mut escrow = Escrow::unpack_v1(src)?;
+        let sequence = array_ref![src, SEQUENCE_OFFSET, 8];
+        escrow.sequence = u64::from_le_bytes(*sequence);
+        Ok(escrow)
+    }
+
+    fn unpack_v1(src: &[u8]) -> Result<Escrow, ProgramError> {
+        let src = array_ref![src, 0, ESCROW_V1_LEN];
         let (
-            is_initialized,
+            _version,
+            state,
             initializer_pubkey,
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
-        ) = array_refs![src, 1, 32, 32, 32, 8];
-        let is_initialized = match is_initialized {
-            [0] => false,
-            [1] => true,
+            expiry_slot,
+            bump_seed,
+            remaining_deposit,
+            remaining_expected,
+            expiry_unix_timestamp,
+            allowed_taker,
+            counter_offer_taker,
+            counter_offer_amount,
+            counter_offer_payment_account,
+            counter_offer_receive_account,
+            two_phase,
+            pending_taker,
+            pending_taker_vault,
+            pending_taker_receive_account,
+            temp_token_mint,
+            receive_token_mint,
+            min_taker_amount,
+            auction_start_amount,
+            auction_end_amount,
+            auction_start_slot,
+            auction_end_slot,
+            bid_auction,
+            best_bid_amount,
+            price_numerator,
+            price_denominator,
+            basket_len,
+            basket_vaults,
+            payment_legs_len,
+            payment_legs,
+            hash_lock,
+            oracle,
+            oracle_max_deviation_bps,
+            oracle_max_staleness,
+            deposit_decimals,
+            receive_decimals,
+            receive_native_sol,
+            nonce,
+            oracle_kind,
+            arbiter,
+            dispute_status,
+            vesting_cliff_slot,
+            vesting_end_slot,
+            vesting_start_slot,
+            vesting_amount,
+            vesting_claimed,
+            vesting_beneficiary,
+            vesting_receive_account,
+            streaming,
+            stream_paid,
+            stream_released,
+            receipt_mint,
+            position_mint,
+            crowd_fill,
+            crowd_vault,
+            crowd_total,
+            crowd_claimed,
+            taker_merkle_root,
+            pda_initializer_pubkey,
+            gate_mint,
+            gate_min_amount,
+            gatekeeper_network,
+            memo_required,
+            payment_splits_len,
+            payment_splits,
+            royalty_policy,
+            nft,
+            unwrap_sol,
+        ) = array_refs![
+            src, 1, 1, 32, 32, 32, 8, 8, 1, 8, 8, 8, 32, 32, 8, 32, 32, 1, 32, 32, 32, 32, 32, 8,
+            8, 8, 8, 8, 1, 8, 8, 8, 1, 96, 1, 216, 32, 32, 2, 8, 1, 1, 1, 8, 1, 32, 1, 8, 8, 8, 8,
+            8, 32, 32, 1, 8, 8, 32, 32, 1, 32, 8, 8, 32, 32, 32, 8, 32, 1, 1, 102, 1, 1, 1
+        ];
+        if basket_len[0] as usize >= MAX_BASKET_ASSETS
+            || payment_legs_len[0] as usize >= MAX_BASKET_ASSETS
+            || payment_splits_len[0] as usize >= MAX_PAYMENT_SPLITS
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let state = match state {
+            [0] => EscrowState::Uninitialized,
+            [1] => EscrowState::Active,
+            [2] => EscrowState::Closed,
+            [3] => EscrowState::Vesting,
+            [4] => EscrowState::CrowdSettled,
+            [5] => EscrowState::Locked,
             _ => return Err(ProgramError::InvalidAccountData),
         };
 
         Ok(Escrow {
-            is_initialized,
+            state,
             initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
             temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
             initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(
                 *initializer_token_to_receive_account_pubkey,
             ),
             expected_amount: u64::from_le_bytes(*expected_amount),
+            expiry_slot: u64::from_le_bytes(*expiry_slot),
+            bump_seed: bump_seed[0],
+            remaining_deposit: u64::from_le_bytes(*remaining_deposit),
+            remaining_expected: u64::from_le_bytes(*remaining_expected),
+            expiry_unix_timestamp: i64::from_le_bytes(*expiry_unix_timestamp),
+            allowed_taker: Pubkey::new_from_array(*allowed_taker),
+            counter_offer_taker: Pubkey::new_from_array(*counter_offer_taker),
+            counter_offer_amount: u64::from_le_bytes(*counter_offer_amount),
+            counter_offer_payment_account: Pubkey::new_from_array(*counter_offer_payment_account),
+            counter_offer_receive_account: Pubkey::new_from_array(*counter_offer_receive_account),
+            two_phase: unpack_bool(two_phase)?,
+            pending_taker: Pubkey::new_from_array(*pending_taker),
+            pending_taker_vault: Pubkey::new_from_array(*pending_taker_vault),
+            pending_taker_receive_account: Pubkey::new_from_array(*pending_taker_receive_account),
+            temp_token_mint: Pubkey::new_from_array(*temp_token_mint),
+            receive_token_mint: Pubkey::new_from_array(*receive_token_mint),
+            min_taker_amount: u64::from_le_bytes(*min_taker_amount),
+            auction_start_amount: u64::from_le_bytes(*auction_start_amount),
+            auction_end_amount: u64::from_le_bytes(*auction_end_amount),
+            auction_start_slot: u64::from_le_bytes(*auction_start_slot),
+            auction_end_slot: u64::from_le_bytes(*auction_end_slot),
+            bid_auction: unpack_bool(bid_auction)?,
+            best_bid_amount: u64::from_le_bytes(*best_bid_amount),
+            price_numerator: u64::from_le_bytes(*price_numerator),
+            price_denominator: u64::from_le_bytes(*price_denominator),
+            basket_len: basket_len[0],
+            basket_vaults: unpack_pubkeys(basket_vaults),
+            payment_legs_len: payment_legs_len[0],
+            payment_legs: unpack_payment_legs(payment_legs),
+            hash_lock: *hash_lock,
+            oracle: Pubkey::new_from_array(*oracle),
+            oracle_max_deviation_bps: u16::from_le_bytes(*oracle_max_deviation_bps),
+            oracle_max_staleness: i64::from_le_bytes(*oracle_max_staleness),
+            deposit_decimals: deposit_decimals[0],
+            receive_decimals: receive_decimals[0],
+            receive_native_sol: unpack_bool(receive_native_sol)?,
+            nonce: u64::from_le_bytes(*nonce),
+            oracle_kind: unpack_oracle_kind(oracle_kind)?,
+            arbiter: Pubkey::new_from_array(*arbiter),
+            dispute_status: unpack_dispute_status(dispute_status)?,
+            vesting_cliff_slot: u64::from_le_bytes(*vesting_cliff_slot),
+            vesting_end_slot: u64::from_le_bytes(*vesting_end_slot),
+            vesting_start_slot: u64::from_le_bytes(*vesting_start_slot),
+            vesting_amount: u64::from_le_bytes(*vesting_amount),
+            vesting_claimed: u64::from_le_bytes(*vesting_claimed),
+            vesting_beneficiary: Pubkey::new_from_array(*vesting_beneficiary),
+            vesting_receive_account: Pubkey::new_from_array(*vesting_receive_account),
+            streaming: unpack_bool(streaming)?,
+            stream_paid: u64::from_le_bytes(*stream_paid),
+            stream_released: u64::from_le_bytes(*stream_released),
+            receipt_mint: Pubkey::new_from_array(*receipt_mint),
+            position_mint: Pubkey::new_from_array(*position_mint),
+            crowd_fill: unpack_bool(crowd_fill)?,
+            crowd_vault: Pubkey::new_from_array(*crowd_vault),
+            crowd_total: u64::from_le_bytes(*crowd_total),
+            crowd_claimed: u64::from_le_bytes(*crowd_claimed),
+            taker_merkle_root: *taker_merkle_root,
+            pda_initializer_pubkey: Pubkey::new_from_array(*pda_initializer_pubkey),
+            gate_mint: Pubkey::new_from_array(*gate_mint),
+            gate_min_amount: u64::from_le_bytes(*gate_min_amount),
+            gatekeeper_network: Pubkey::new_from_array(*gatekeeper_network),
+            memo_required: unpack_bool(memo_required)?,
+            payment_splits_len: payment_splits_len[0],
+            payment_splits: unpack_payment_splits(payment_splits),
+            royalty_policy: unpack_royalty_policy(royalty_policy)?,
+            nft: unpack_bool(nft)?,
+            unwrap_sol: unpack_bool(unwrap_sol)?,
+            sequence: 0,
+            cancel_penalty_bps: 0,
+            cancel_penalty_lamports: 0,
+            no_penalty_after_slot: 0,
+            same_mint_allowed: false,
+            inactive: false,
         })
     }
+}
+
+impl Pack for Escrow {
+    const LEN: usize = ESCROW_V5_LEN;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        match src[VERSION_OFFSET] {
+            // 0 is an account that was never packed or was zeroed when it was closed
+            0 | ESCROW_VERSION => Escrow::unpack_v5(src),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, Escrow::LEN];
         let (
-            is_initialized_dst,
+            version_dst,
+            state_dst,
             initializer_pubkey_dst,
             temp_token_account_pubkey_dst,
             initializer_token_to_receive_account_pubkey_dst,
             expected_amount_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
+            expiry_slot_dst,
+            bump_seed_dst,
+            remaining_deposit_dst,
+            remaining_expected_dst,
+            expiry_unix_timestamp_dst,
+            allowed_taker_dst,
+            counter_offer_taker_dst,
+            counter_offer_amount_dst,
+            counter_offer_payment_account_dst,
+            counter_offer_receive_account_dst,
+            two_phase_dst,
+            pending_taker_dst,
+            pending_taker_vault_dst,
+            pending_taker_receive_account_dst,
+            temp_token_mint_dst,
+            receive_token_mint_dst,
+            min_taker_amount_dst,
+            auction_start_amount_dst,
+            auction_end_amount_dst,
+            auction_start_slot_dst,
+            auction_end_slot_dst,
+            bid_auction_dst,
+            best_bid_amount_dst,
+            price_numerator_dst,
+            price_denominator_dst,
+            basket_len_dst,
+            basket_vaults_dst,
+            payment_legs_len_dst,
+            payment_legs_dst,
+            hash_lock_dst,
+            oracle_dst,
+            oracle_max_deviation_bps_dst,
+            oracle_max_staleness_dst,
+            deposit_decimals_dst,
+            receive_decimals_dst,
+            receive_native_sol_dst,
+            nonce_dst,
+            oracle_kind_dst,
+            arbiter_dst,
+            dispute_status_dst,
+            vesting_cliff_slot_dst,
+            vesting_end_slot_dst,
+            vesting_start_slot_dst,
+            vesting_amount_dst,
+            vesting_claimed_dst,
+            vesting_beneficiary_dst,
+            vesting_receive_account_dst,
+            streaming_dst,
+            stream_paid_dst,
+            stream_released_dst,
+            receipt_mint_dst,
+            position_mint_dst,
+            crowd_fill_dst,
+            crowd_vault_dst,
+            crowd_total_dst,
+            crowd_claimed_dst,
+            taker_merkle_root_dst,
+            pda_initializer_pubkey_dst,
+            gate_mint_dst,
+            gate_min_amount_dst,
+            gatekeeper_network_dst,
+            memo_required_dst,
+            payment_splits_len_dst,
+            payment_splits_dst,
+            royalty_policy_dst,
+            nft_dst,
+            unwrap_sol_dst,
+            sequence_dst,
+            cancel_penalty_bps_dst,
+            cancel_penalty_lamports_dst,
+            no_penalty_after_slot_dst,
+            same_mint_allowed_dst,
+            inactive_dst,
+        ) = mut_array_refs![
+            dst, 1, 1, 32, 32, 32, 8, 8, 1, 8, 8, 8, 32, 32, 8, 32, 32, 1, 32, 32, 32, 32, 32, 8,
+            8, 8, 8, 8, 1, 8, 8, 8, 1, 96, 1, 216, 32, 32, 2, 8, 1, 1, 1, 8, 1, 32, 1, 8, 8, 8, 8,
+            8, 32, 32, 1, 8, 8, 32, 32, 1, 32, 8, 8, 32, 32, 32, 8, 32, 1, 1, 102, 1, 1, 1, 8, 2,
+            8, 8, 1, 1
+        ];
 
         let Escrow {
-            is_initialized,
+            state,
             initializer_pubkey,
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
+            expiry_slot,
+            bump_seed,
+            remaining_deposit,
+            remaining_expected,
+            expiry_unix_timestamp,
+            allowed_taker,
+            counter_offer_taker,
+            counter_offer_amount,
+            counter_offer_payment_account,
+            counter_offer_receive_account,
+            two_phase,
+            pending_taker,
+            pending_taker_vault,
+            pending_taker_receive_account,
+            temp_token_mint,
+            receive_token_mint,
+            min_taker_amount,
+            auction_start_amount,
+            auction_end_amount,
+            auction_start_slot,
+            auction_end_slot,
+            bid_auction,
+            best_bid_amount,
+            price_numerator,
+            price_denominator,
+            basket_len,
+            basket_vaults,
+            payment_legs_len,
+            payment_legs,
+            hash_lock,
+            oracle,
+            oracle_max_deviation_bps,
+            oracle_max_staleness,
+            deposit_decimals,
+            receive_decimals,
+            receive_native_sol,
+            nonce,
+            oracle_kind,
+            arbiter,
+            dispute_status,
+            vesting_cliff_slot,
+            vesting_end_slot,
+            vesting_start_slot,
+            vesting_amount,
+            vesting_claimed,
+            vesting_beneficiary,
+            vesting_receive_account,
+            streaming,
+            stream_paid,
+            stream_released,
+            receipt_mint,
+            position_mint,
+            crowd_fill,
+            crowd_vault,
+            crowd_total,
+            crowd_claimed,
+            taker_merkle_root,
+            pda_initializer_pubkey,
+            gate_mint,
+            gate_min_amount,
+            gatekeeper_network,
+            memo_required,
+            payment_splits_len,
+            payment_splits,
+            royalty_policy,
+            nft,
+            unwrap_sol,
+            sequence,
+            cancel_penalty_bps,
+            cancel_penalty_lamports,
+            no_penalty_after_slot,
+            same_mint_allowed,
+            inactive,
         } = self;
 
-        is_initialized_dst[0] = *is_initialized as u8;
+        version_dst[0] = ESCROW_VERSION;
+        state_dst[0] = *state as u8;
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
         temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
         initializer_token_to_receive_account_pubkey_dst
             .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
         *expected_amount_dst = expected_amount.to_le_bytes();
+        *expiry_slot_dst = expiry_slot.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        *remaining_deposit_dst = remaining_deposit.to_le_bytes();
+        *remaining_expected_dst = remaining_expected.to_le_bytes();
+        *expiry_unix_timestamp_dst = expiry_unix_timestamp.to_le_bytes();
+        allowed_taker_dst.copy_from_slice(allowed_taker.as_ref());
+        counter_offer_taker_dst.copy_from_slice(counter_offer_taker.as_ref());
+        *counter_offer_amount_dst = counter_offer_amount.to_le_bytes();
+        counter_offer_payment_account_dst.copy_from_slice(counter_offer_payment_account.as_ref());
+        counter_offer_receive_account_dst.copy_from_slice(counter_offer_receive_account.as_ref());
+        two_phase_dst[0] = *two_phase as u8;
+        pending_taker_dst.copy_from_slice(pending_taker.as_ref());
+        pending_taker_vault_dst.copy_from_slice(pending_taker_vault.as_ref());
+        pending_taker_receive_account_dst.copy_from_slice(pending_taker_receive_account.as_ref());
+        temp_token_mint_dst.copy_from_slice(temp_token_mint.as_ref());
+        receive_token_mint_dst.copy_from_slice(receive_token_mint.as_ref());
+        *min_taker_amount_dst = min_taker_amount.to_le_bytes();
+        *auction_start_amount_dst = auction_start_amount.to_le_bytes();
+        *auction_end_amount_dst = auction_end_amount.to_le_bytes();
+        *auction_start_slot_dst = auction_start_slot.to_le_bytes();
+        *auction_end_slot_dst = auction_end_slot.to_le_bytes();
+        bid_auction_dst[0] = *bid_auction as u8;
+        *best_bid_amount_dst = best_bid_amount.to_le_bytes();
+        *price_numerator_dst = price_numerator.to_le_bytes();
+        *price_denominator_dst = price_denominator.to_le_bytes();
+        basket_len_dst[0] = *basket_len;
+        pack_pubkeys(basket_vaults, basket_vaults_dst);
+        payment_legs_len_dst[0] = *payment_legs_len;
+        pack_payment_legs(payment_legs, payment_legs_dst);
+        *hash_lock_dst = *hash_lock;
+        oracle_dst.copy_from_slice(oracle.as_ref());
+        *oracle_max_deviation_bps_dst = oracle_max_deviation_bps.to_le_bytes();
+        *oracle_max_staleness_dst = oracle_max_staleness.to_le_bytes();
+        deposit_decimals_dst[0] = *deposit_decimals;
+        receive_decimals_dst[0] = *receive_decimals;
+        receive_native_sol_dst[0] = *receive_native_sol as u8;
+        *nonce_dst = nonce.to_le_bytes();
+        oracle_kind_dst[0] = *oracle_kind as u8;
+        arbiter_dst.copy_from_slice(arbiter.as_ref());
+        dispute_status_dst[0] = *dispute_status as u8;
+        *vesting_cliff_slot_dst = vesting_cliff_slot.to_le_bytes();
+        *vesting_end_slot_dst = vesting_end_slot.to_le_bytes();
+        *vesting_start_slot_dst = vesting_start_slot.to_le_bytes();
+        *vesting_amount_dst = vesting_amount.to_le_bytes();
+        *vesting_claimed_dst = vesting_claimed.to_le_bytes();
+        vesting_beneficiary_dst.copy_from_slice(vesting_beneficiary.as_ref());
+        vesting_receive_account_dst.copy_from_slice(vesting_receive_account.as_ref());
+        streaming_dst[0] = *streaming as u8;
+        *stream_paid_dst = stream_paid.to_le_bytes();
+        *stream_released_dst = stream_released.to_le_bytes();
+        receipt_mint_dst.copy_from_slice(receipt_mint.as_ref());
+        position_mint_dst.copy_from_slice(position_mint.as_ref());
+        crowd_fill_dst[0] = *crowd_fill as u8;
+        crowd_vault_dst.copy_from_slice(crowd_vault.as_ref());
+        *crowd_total_dst = crowd_total.to_le_bytes();
+        *crowd_claimed_dst = crowd_claimed.to_le_bytes();
+        *taker_merkle_root_dst = *taker_merkle_root;
+        pda_initializer_pubkey_dst.copy_from_slice(pda_initializer_pubkey.as_ref());
+        gate_mint_dst.copy_from_slice(gate_mint.as_ref());
+        *gate_min_amount_dst = gate_min_amount.to_le_bytes();
+        gatekeeper_network_dst.copy_from_slice(gatekeeper_network.as_ref());
+        memo_required_dst[0] = *memo_required as u8;
+        payment_splits_len_dst[0] = *payment_splits_len;
+        pack_payment_splits(payment_splits, payment_splits_dst);
+        royalty_policy_dst[0] = *royalty_policy as u8;
+        nft_dst[0] = *nft as u8;
+        unwrap_sol_dst[0] = *unwrap_sol as u8;
+        *sequence_dst = sequence.to_le_bytes();
+        *cancel_penalty_bps_dst = cancel_penalty_bps.to_le_bytes();
+        *cancel_penalty_lamports_dst = cancel_penalty_lamports.to_le_bytes();
+        *no_penalty_after_slot_dst = no_penalty_after_slot.to_le_bytes();
+        same_mint_allowed_dst[0] = *same_mint_allowed as u8;
+        inactive_dst[0] = *inactive as u8;
+    }
+}
+
+/// A bid placed on a bid auction escrow, the bid amount is held in the bidder's temp token account
+/// owned by the PDA until the bid is accepted or withdrawn
+pub struct Bid {
+    pub is_initialized: bool,
+    pub escrow_pubkey: Pubkey,
+    pub bidder_pubkey: Pubkey,
+    pub vault_pubkey: Pubkey,
+    pub bidder_token_to_receive_account_pubkey: Pubkey,
+    pub amount: u64,
+    pub bump_seed: u8,
+    /// Initializer of the escrow, needed for the vault authority seeds
+    pub escrow_initializer_pubkey: Pubkey,
+    pub escrow_nonce: u64,
+    /// Deposit of the escrow when the bid was placed, the least the bidder receives
+    pub deposit_amount: u64,
+}
+
+impl Sealed for Bid {}
+
+impl IsInitialized for Bid {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Bid {
+    const LEN: usize = 186;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Bid::LEN];
+        let (
+            is_initialized,
+            escrow_pubkey,
+            bidder_pubkey,
+            vault_pubkey,
+            bidder_token_to_receive_account_pubkey,
+            amount,
+            bump_seed,
+            escrow_initializer_pubkey,
+            escrow_nonce,
+            deposit_amount,
+        ) = array_refs![src, 1, 32, 32, 32, 32, 8, 1, 32, 8, 8];
+
+        Ok(Bid {
+            is_initialized: unpack_bool(is_initialized)?,
+            escrow_pubkey: Pubkey::new_from_array(*escrow_pubkey),
+            bidder_pubkey: Pubkey::new_from_array(*bidder_pubkey),
+            vault_pubkey: Pubkey::new_from_array(*vault_pubkey),
+            bidder_token_to_receive_account_pubkey: Pubkey::new_from_array(
+                *bidder_token_to_receive_account_pubkey,
+            ),
+            amount: u64::from_le_bytes(*amount),
+            bump_seed: bump_seed[0],
+            escrow_initializer_pubkey: Pubkey::new_from_array(*escrow_initializer_pubkey),
+            escrow_nonce: u64::from_le_bytes(*escrow_nonce),
+            deposit_amount: u64::from_le_bytes(*deposit_amount),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Bid::LEN];
+        let (
+            is_initialized_dst,
+            escrow_pubkey_dst,
+            bidder_pubkey_dst,
+            vault_pubkey_dst,
+            bidder_token_to_receive_account_pubkey_dst,
+            amount_dst,
+            bump_seed_dst,
+            escrow_initializer_pubkey_dst,
+            escrow_nonce_dst,
+            deposit_amount_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 32, 32, 8, 1, 32, 8, 8];
+
+        let Bid {
+            is_initialized,
+            escrow_pubkey,
+            bidder_pubkey,
+            vault_pubkey,
+            bidder_token_to_receive_account_pubkey,
+            amount,
+            bump_seed,
+            escrow_initializer_pubkey,
+            escrow_nonce,
+            deposit_amount,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        escrow_pubkey_dst.copy_from_slice(escrow_pubkey.as_ref());
+        bidder_pubkey_dst.copy_from_slice(bidder_pubkey.as_ref());
+        vault_pubkey_dst.copy_from_slice(vault_pubkey.as_ref());
+        bidder_token_to_receive_account_pubkey_dst
+            .copy_from_slice(bidder_token_to_receive_account_pubkey.as_ref());
+        *amount_dst = amount.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        escrow_initializer_pubkey_dst.copy_from_slice(escrow_initializer_pubkey.as_ref());
+        *escrow_nonce_dst = escrow_nonce.to_le_bytes();
+        *deposit_amount_dst = deposit_amount.to_le_bytes();
+    }
+}
+
+/// Contributions of one contributor to a crowd fill, held in the crowd vault owned by the PDA until
+/// the crowd fill is settled or refunded. The account is a PDA of the escrow and the contributor
+pub struct Contribution {
+    pub is_initialized: bool,
+    pub escrow_pubkey: Pubkey,
+    pub contributor_pubkey: Pubkey,
+    pub crowd_vault_pubkey: Pubkey,
+    pub amount: u64,
+    pub bump_seed: u8,
+    /// Initializer of the escrow, needed for the vault authority seeds
+    pub escrow_initializer_pubkey: Pubkey,
+    pub escrow_nonce: u64,
+}
+
+impl Sealed for Contribution {}
+
+impl IsInitialized for Contribution {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Contribution {
+    const LEN: usize = 146;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Contribution::LEN];
+        let (
+            is_initialized,
+            escrow_pubkey,
+            contributor_pubkey,
+            crowd_vault_pubkey,
+            amount,
+            bump_seed,
+            escrow_initializer_pubkey,
+            escrow_nonce,
+        ) = array_refs![src, 1, 32, 32, 32, 8, 1, 32, 8];
+
+        Ok(Contribution {
+            is_initialized: unpack_bool(is_initialized)?,
+            escrow_pubkey: Pubkey::new_from_array(*escrow_pubkey),
+            contributor_pubkey: Pubkey::new_from_array(*contributor_pubkey),
+            crowd_vault_pubkey: Pubkey::new_from_array(*crowd_vault_pubkey),
+            amount: u64::from_le_bytes(*amount),
+            bump_seed: bump_seed[0],
+            escrow_initializer_pubkey: Pubkey::new_from_array(*escrow_initializer_pubkey),
+            escrow_nonce: u64::from_le_bytes(*escrow_nonce),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Contribution::LEN];
+        let (
+            is_initialized_dst,
+            escrow_pubkey_dst,
+            contributor_pubkey_dst,
+            crowd_vault_pubkey_dst,
+            amount_dst,
+            bump_seed_dst,
+            escrow_initializer_pubkey_dst,
+            escrow_nonce_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 32, 8, 1, 32, 8];
+
+        let Contribution {
+            is_initialized,
+            escrow_pubkey,
+            contributor_pubkey,
+            crowd_vault_pubkey,
+            amount,
+            bump_seed,
+            escrow_initializer_pubkey,
+            escrow_nonce,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        escrow_pubkey_dst.copy_from_slice(escrow_pubkey.as_ref());
+        contributor_pubkey_dst.copy_from_slice(contributor_pubkey.as_ref());
+        crowd_vault_pubkey_dst.copy_from_slice(crowd_vault_pubkey.as_ref());
+        *amount_dst = amount.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        escrow_initializer_pubkey_dst.copy_from_slice(escrow_initializer_pubkey.as_ref());
+        *escrow_nonce_dst = escrow_nonce.to_le_bytes();
+    }
+}
+
+/// Most fee tiers the config can hold
+pub const MAX_FEE_TIERS: usize = 4;
+
+/// A maker fee rate applying to payments of at least `threshold`
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeTier {
+    pub threshold: u64,
+    /// The maker fee, in basis points
+    pub basis_points: u16,
+}
+
+/// Most pubkeys the config can exempt from fees
+pub const MAX_FEE_EXEMPTIONS: usize = 8;
+
+/// A pubkey trading without fees, in the roles its flags allow
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeExemption {
+    pub pubkey: Pubkey,
+    /// Trades of escrows it initialized are fee free
+    pub as_maker: bool,
+    /// Trades it takes are fee free
+    pub as_taker: bool,
+}
+
+/// Most mints the config can allow escrows of
+pub const MAX_ALLOWED_MINTS: usize = 16;
+
+/// Layout version `pack` writes in the first byte of an initialized config, which held
+/// is_initialized before the allowed mints were added. Later versions only add fields after the
+/// ones of the previous version, see [Config::migrate](struct.Config.html#method.migrate)
+pub const CONFIG_VERSION: u8 = 2;
+
+/// Packed length of a version 1 config
+pub const CONFIG_V1_LEN: usize = 361;
+
+/// Packed length of a version 2 config, which added the allowed mints
+pub const CONFIG_V2_LEN: usize = CONFIG_V1_LEN + 1 + 32 * MAX_ALLOWED_MINTS;
+
+/// Program wide settings, held by the config PDA and changed by its admin. Until the admin
+/// creates it, trades pay the default fee rates
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    /// Share of the payment taken out of what the initializer receives below the first fee tier,
+    /// in basis points
+    pub maker_fee_bps: u16,
+    /// Share of the payment the taker pays on top of it, in basis points
+    pub taker_fee_bps: u16,
+    /// Number of fee_tiers in use
+    pub fee_tiers_len: u8,
+    /// Sorted by strictly ascending threshold, only the first fee_tiers_len are in use
+    pub fee_tiers: [FeeTier; MAX_FEE_TIERS],
+    pub bump_seed: u8,
+    /// Number of fee_exemptions in use
+    pub fee_exemptions_len: u8,
+    /// Only the first fee_exemptions_len are in use, a pubkey is listed at most once
+    pub fee_exemptions: [FeeExemption; MAX_FEE_EXEMPTIONS],
+    /// Lamports InitEscrow charges the initializer, 0 means escrows are created for free
+    pub creation_fee_lamports: u64,
+    /// Whether InitEscrow refuses new escrows, the existing ones can still be taken or cancelled
+    pub paused: bool,
+    /// Number of allowed_mints in use, 0 means escrows of any mint can be started
+    pub allowed_mints_len: u8,
+    /// Mints InitEscrow accepts for both the deposit and the payment, only the first
+    /// allowed_mints_len are in use and a mint is listed at most once
+    pub allowed_mints: [Pubkey; MAX_ALLOWED_MINTS],
+}
+
+impl Config {
+    /// Reads a config packed with any version of the layout, the fields added since keep their
+    /// zero value. The account has to be reallocated to `Config::LEN` before the result is packed
+    /// back into it
+    pub fn migrate(old: &[u8]) -> Result<Config, ProgramError> {
+        match old.first() {
+            Some(1) if old.len() >= CONFIG_V1_LEN => Config::unpack_v1(old),
+            Some(2) if old.len() >= CONFIG_V2_LEN => Config::unpack_v2(old),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    fn unpack_v2(src: &[u8]) -> Result<Config, ProgramError> {
+        let mut config = Config::unpack_v1(src)?;
+        let src = array_ref![src, CONFIG_V1_LEN, 1 + 32 * MAX_ALLOWED_MINTS];
+        let (allowed_mints_len, allowed_mints) = array_refs![src, 1, 512];
+        if allowed_mints_len[0] as usize > MAX_ALLOWED_MINTS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        config.allowed_mints_len = allowed_mints_len[0];
+        config.allowed_mints = unpack_allowed_mints(allowed_mints);
+        Ok(config)
+    }
+
+    fn unpack_v1(src: &[u8]) -> Result<Config, ProgramError> {
+        let src = array_ref![src, 0, CONFIG_V1_LEN];
+        let (
+            version,
+            admin,
+            maker_fee_bps,
+            taker_fee_bps,
+            fee_tiers_len,
+            fee_tiers,
+            bump_seed,
+            fee_exemptions_len,
+            fee_exemptions,
+            creation_fee_lamports,
+            paused,
+        ) = array_refs![src, 1, 32, 2, 2, 1, 40, 1, 1, 272, 8, 1];
+
+        if fee_tiers_len[0] as usize > MAX_FEE_TIERS
+            || fee_exemptions_len[0] as usize > MAX_FEE_EXEMPTIONS
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Config {
+            // version 1 wrote is_initialized in place of the version
+            is_initialized: version[0] != 0,
+            admin: Pubkey::new_from_array(*admin),
+            maker_fee_bps: u16::from_le_bytes(*maker_fee_bps),
+            taker_fee_bps: u16::from_le_bytes(*taker_fee_bps),
+            fee_tiers_len: fee_tiers_len[0],
+            fee_tiers: unpack_fee_tiers(fee_tiers),
+            bump_seed: bump_seed[0],
+            fee_exemptions_len: fee_exemptions_len[0],
+            fee_exemptions: unpack_fee_exemptions(fee_exemptions)?,
+            creation_fee_lamports: u64::from_le_bytes(*creation_fee_lamports),
+            paused: unpack_bool(paused)?,
+            allowed_mints_len: 0,
+            allowed_mints: [Pubkey::default(); MAX_ALLOWED_MINTS],
+        })
+    }
+
+    /// Replaces the fee rates, failing with `InvalidFeeSchedule` unless every rate is below
+    /// 10000 and the tiers are sorted by strictly ascending threshold
+    pub fn set_fee_schedule(
+        &mut self,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_tiers: &[FeeTier],
+    ) -> Result<(), EscrowError> {
+        if fee_tiers.len() > MAX_FEE_TIERS
+            || maker_fee_bps >= 10_000
+            || taker_fee_bps >= 10_000
+            || fee_tiers.iter().any(|tier| tier.basis_points >= 10_000)
+            || fee_tiers
+                .windows(2)
+                .any(|pair| pair[0].threshold >= pair[1].threshold)
+        {
+            return Err(EscrowError::InvalidFeeSchedule);
+        }
+
+        self.maker_fee_bps = maker_fee_bps;
+        self.taker_fee_bps = taker_fee_bps;
+        self.fee_tiers_len = fee_tiers.len() as u8;
+        self.fee_tiers = [FeeTier::default(); MAX_FEE_TIERS];
+        self.fee_tiers[..fee_tiers.len()].copy_from_slice(fee_tiers);
+        Ok(())
+    }
+
+    pub fn fee_tiers(&self) -> &[FeeTier] {
+        &self.fee_tiers[..self.fee_tiers_len as usize]
+    }
+
+    /// Maker fee rate of a trade paying `payment`, the rate of the tier with the largest
+    /// threshold not above it, or the flat rate when it's below every tier
+    pub fn maker_fee_bps_for(&self, payment: u64) -> u16 {
+        self.fee_tiers()
+            .iter()
+            .rev()
+            .find(|tier| tier.threshold <= payment)
+            .map_or(self.maker_fee_bps, |tier| tier.basis_points)
+    }
+
+    /// Fees of a trade of an escrow of `initializer` taken by `taker` paying `payment`, none
+    /// at all when either of them is exempt in their role
+    pub fn trade_fees(
+        &self,
+        payment: u64,
+        initializer: &Pubkey,
+        taker: &Pubkey,
+    ) -> Result<math::TradeFees, EscrowError> {
+        if self.is_maker_exempt(initializer) || self.is_taker_exempt(taker) {
+            return math::TradeFees::checked_new(payment, 0, 0);
+        }
+
+        math::TradeFees::checked_new(payment, self.maker_fee_bps_for(payment), self.taker_fee_bps)
+    }
+
+    /// Maker fee alone of a trade of an escrow of `initializer` paying `payment`, for payments
+    /// deposited before the trade settles, which leave no room for a taker fee on top
+    pub fn maker_fee(&self, payment: u64, initializer: &Pubkey) -> Result<u64, EscrowError> {
+        if self.is_maker_exempt(initializer) {
+            return Ok(0);
+        }
+
+        math::checked_fee(payment, self.maker_fee_bps_for(payment))
+    }
+
+    pub fn fee_exemptions(&self) -> &[FeeExemption] {
+        &self.fee_exemptions[..self.fee_exemptions_len as usize]
+    }
+
+    pub fn is_maker_exempt(&self, pubkey: &Pubkey) -> bool {
+        self.fee_exemptions()
+            .iter()
+            .any(|exemption| exemption.pubkey == *pubkey && exemption.as_maker)
+    }
+
+    pub fn is_taker_exempt(&self, pubkey: &Pubkey) -> bool {
+        self.fee_exemptions()
+            .iter()
+            .any(|exemption| exemption.pubkey == *pubkey && exemption.as_taker)
+    }
+
+    /// Lists `exemption`, replacing the flags of its pubkey when it's listed already. Fails with
+    /// `FeeExemptionsFull` when all MAX_FEE_EXEMPTIONS entries are in use
+    pub fn add_fee_exemption(&mut self, exemption: FeeExemption) -> Result<(), EscrowError> {
+        let len = self.fee_exemptions_len as usize;
+        if let Some(listed) = self.fee_exemptions[..len]
+            .iter_mut()
+            .find(|listed| listed.pubkey == exemption.pubkey)
+        {
+            *listed = exemption;
+            return Ok(());
+        }
+
+        if len == MAX_FEE_EXEMPTIONS {
+            return Err(EscrowError::FeeExemptionsFull);
+        }
+        self.fee_exemptions[len] = exemption;
+        self.fee_exemptions_len += 1;
+        Ok(())
+    }
+
+    /// Unlists `pubkey`, failing with `NotFeeExempt` when it isn't listed
+    pub fn remove_fee_exemption(&mut self, pubkey: &Pubkey) -> Result<(), EscrowError> {
+        let len = self.fee_exemptions_len as usize;
+        let index = self.fee_exemptions[..len]
+            .iter()
+            .position(|listed| listed.pubkey == *pubkey)
+            .ok_or(EscrowError::NotFeeExempt)?;
+
+        // the last entry takes its place, the order of the list doesn't matter
+        self.fee_exemptions[index] = self.fee_exemptions[len - 1];
+        self.fee_exemptions[len - 1] = FeeExemption::default();
+        self.fee_exemptions_len -= 1;
+        Ok(())
+    }
+
+    pub fn allowed_mints(&self) -> &[Pubkey] {
+        &self.allowed_mints[..self.allowed_mints_len as usize]
+    }
+
+    /// Whether escrows of `mint` can be started, any mint can while no mint is listed
+    pub fn is_mint_allowed(&self, mint: &Pubkey) -> bool {
+        self.allowed_mints_len == 0 || self.allowed_mints().contains(mint)
+    }
+
+    /// Lists `mint`, doing nothing when it's listed already. Fails with `AllowedMintsFull` when
+    /// all MAX_ALLOWED_MINTS entries are in use
+    pub fn add_allowed_mint(&mut self, mint: &Pubkey) -> Result<(), EscrowError> {
+        if self.allowed_mints().contains(mint) {
+            return Ok(());
+        }
+
+        let len = self.allowed_mints_len as usize;
+        if len == MAX_ALLOWED_MINTS {
+            return Err(EscrowError::AllowedMintsFull);
+        }
+        self.allowed_mints[len] = *mint;
+        self.allowed_mints_len += 1;
+        Ok(())
+    }
+
+    /// Unlists `mint`, failing with `MintNotListed` when it isn't listed. Removing the last one
+    /// allows every mint again
+    pub fn remove_allowed_mint(&mut self, mint: &Pubkey) -> Result<(), EscrowError> {
+        let len = self.allowed_mints_len as usize;
+        let index = self
+            .allowed_mints()
+            .iter()
+            .position(|listed| listed == mint)
+            .ok_or(EscrowError::MintNotListed)?;
+
+        // the last entry takes its place, the order of the list doesn't matter
+        self.allowed_mints[index] = self.allowed_mints[len - 1];
+        self.allowed_mints[len - 1] = Pubkey::default();
+        self.allowed_mints_len -= 1;
+        Ok(())
+    }
+}
+
+impl Sealed for Config {}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Config {
+    const LEN: usize = CONFIG_V2_LEN;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        match src[0] {
+            // 0 is a config that was never packed
+            0 | CONFIG_VERSION => Config::unpack_v2(src),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Config::LEN];
+        let (
+            version_dst,
+            admin_dst,
+            maker_fee_bps_dst,
+            taker_fee_bps_dst,
+            fee_tiers_len_dst,
+            fee_tiers_dst,
+            bump_seed_dst,
+            fee_exemptions_len_dst,
+            fee_exemptions_dst,
+            creation_fee_lamports_dst,
+            paused_dst,
+            allowed_mints_len_dst,
+            allowed_mints_dst,
+        ) = mut_array_refs![dst, 1, 32, 2, 2, 1, 40, 1, 1, 272, 8, 1, 1, 512];
+
+        let Config {
+            is_initialized,
+            admin,
+            maker_fee_bps,
+            taker_fee_bps,
+            fee_tiers_len,
+            fee_tiers,
+            bump_seed,
+            fee_exemptions_len,
+            fee_exemptions,
+            creation_fee_lamports,
+            paused,
+            allowed_mints_len,
+            allowed_mints,
+        } = self;
+
+        version_dst[0] = if *is_initialized { CONFIG_VERSION } else { 0 };
+        admin_dst.copy_from_slice(admin.as_ref());
+        *maker_fee_bps_dst = maker_fee_bps.to_le_bytes();
+        *taker_fee_bps_dst = taker_fee_bps.to_le_bytes();
+        fee_tiers_len_dst[0] = *fee_tiers_len;
+        pack_fee_tiers(fee_tiers, fee_tiers_dst);
+        bump_seed_dst[0] = *bump_seed;
+        fee_exemptions_len_dst[0] = *fee_exemptions_len;
+        pack_fee_exemptions(fee_exemptions, fee_exemptions_dst);
+        *creation_fee_lamports_dst = creation_fee_lamports.to_le_bytes();
+        paused_dst[0] = *paused as u8;
+        allowed_mints_len_dst[0] = *allowed_mints_len;
+        pack_allowed_mints(allowed_mints, allowed_mints_dst);
+    }
+}
+
+fn unpack_bool(src: &[u8; 1]) -> Result<bool, ProgramError> {
+    match src {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn unpack_dispute_status(src: &[u8; 1]) -> Result<DisputeStatus, ProgramError> {
+    match src {
+        [0] => Ok(DisputeStatus::None),
+        [1] => Ok(DisputeStatus::Raised),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn unpack_oracle_kind(src: &[u8; 1]) -> Result<OracleKind, ProgramError> {
+    match src {
+        [0] => Ok(OracleKind::Pyth),
+        [1] => Ok(OracleKind::Switchboard),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn unpack_royalty_policy(src: &[u8; 1]) -> Result<RoyaltyPolicy, ProgramError> {
+    match src {
+        [0] => Ok(RoyaltyPolicy::None),
+        [1] => Ok(RoyaltyPolicy::BestEffort),
+        [2] => Ok(RoyaltyPolicy::Enforced),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn unpack_pubkeys(src: &[u8; 32 * (MAX_BASKET_ASSETS - 1)]) -> [Pubkey; MAX_BASKET_ASSETS - 1] {
+    let mut pubkeys = [Pubkey::default(); MAX_BASKET_ASSETS - 1];
+    for (pubkey, src) in pubkeys.iter_mut().zip(src.chunks(32)) {
+        *pubkey = Pubkey::new(src);
+    }
+    pubkeys
+}
+
+fn pack_pubkeys(
+    pubkeys: &[Pubkey; MAX_BASKET_ASSETS - 1],
+    dst: &mut [u8; 32 * (MAX_BASKET_ASSETS - 1)],
+) {
+    for (pubkey, dst) in pubkeys.iter().zip(dst.chunks_mut(32)) {
+        dst.copy_from_slice(pubkey.as_ref());
+    }
+}
+
+fn unpack_allowed_mints(src: &[u8; 32 * MAX_ALLOWED_MINTS]) -> [Pubkey; MAX_ALLOWED_MINTS] {
+    let mut mints = [Pubkey::default(); MAX_ALLOWED_MINTS];
+    for (mint, src) in mints.iter_mut().zip(src.chunks(32)) {
+        *mint = Pubkey::new(src);
+    }
+    mints
+}
+
+fn pack_allowed_mints(mints: &[Pubkey; MAX_ALLOWED_MINTS], dst: &mut [u8; 32 * MAX_ALLOWED_MINTS]) {
+    for (mint, dst) in mints.iter().zip(dst.chunks_mut(32)) {
+        dst.copy_from_slice(mint.as_ref());
+    }
+}
+
+fn unpack_payment_legs(
+    src: &[u8; 72 * (MAX_BASKET_ASSETS - 1)],
+) -> [PaymentLeg; MAX_BASKET_ASSETS - 1] {
+    let mut payment_legs = [PaymentLeg::default(); MAX_BASKET_ASSETS - 1];
+    for (payment_leg, src) in payment_legs.iter_mut().zip(src.chunks(72)) {
+        let src = array_ref![src, 0, 72];
+        let (mint, amount, destination) = array_refs![src, 32, 8, 32];
+        *payment_leg = PaymentLeg {
+            mint: Pubkey::new_from_array(*mint),
+            amount: u64::from_le_bytes(*amount),
+            destination: Pubkey::new_from_array(*destination),
+        };
+    }
+    payment_legs
+}
+
+fn pack_payment_legs(
+    payment_legs: &[PaymentLeg; MAX_BASKET_ASSETS - 1],
+    dst: &mut [u8; 72 * (MAX_BASKET_ASSETS - 1)],
+) {
+    for (payment_leg, dst) in payment_legs.iter().zip(dst.chunks_mut(72)) {
+        let dst = array_mut_ref![dst, 0, 72];
+        let (mint_dst, amount_dst, destination_dst) = mut_array_refs![dst, 32, 8, 32];
+        mint_dst.copy_from_slice(payment_leg.mint.as_ref());
+        *amount_dst = payment_leg.amount.to_le_bytes();
+        destination_dst.copy_from_slice(payment_leg.destination.as_ref());
+    }
+}
+
+fn unpack_payment_splits(
+    src: &[u8; 34 * (MAX_PAYMENT_SPLITS - 1)],
+) -> [PaymentSplit; MAX_PAYMENT_SPLITS - 1] {
+    let mut payment_splits = [PaymentSplit::default(); MAX_PAYMENT_SPLITS - 1];
+    for (payment_split, src) in payment_splits.iter_mut().zip(src.chunks(34)) {
+        let src = array_ref![src, 0, 34];
+        let (destination, basis_points) = array_refs![src, 32, 2];
+        *payment_split = PaymentSplit {
+            destination: Pubkey::new_from_array(*destination),
+            basis_points: u16::from_le_bytes(*basis_points),
+        };
+    }
+    payment_splits
+}
+
+fn pack_payment_splits(
+    payment_splits: &[PaymentSplit; MAX_PAYMENT_SPLITS - 1],
+    dst: &mut [u8; 34 * (MAX_PAYMENT_SPLITS - 1)],
+) {
+    for (payment_split, dst) in payment_splits.iter().zip(dst.chunks_mut(34)) {
+        let dst = array_mut_ref![dst, 0, 34];
+        let (destination_dst, basis_points_dst) = mut_array_refs![dst, 32, 2];
+        destination_dst.copy_from_slice(payment_split.destination.as_ref());
+        *basis_points_dst = payment_split.basis_points.to_le_bytes();
+    }
+}
+
+fn unpack_fee_tiers(src: &[u8; 10 * MAX_FEE_TIERS]) -> [FeeTier; MAX_FEE_TIERS] {
+    let mut fee_tiers = [FeeTier::default(); MAX_FEE_TIERS];
+    for (fee_tier, src) in fee_tiers.iter_mut().zip(src.chunks(10)) {
+        let src = array_ref![src, 0, 10];
+        let (threshold, basis_points) = array_refs![src, 8, 2];
+        *fee_tier = FeeTier {
+            threshold: u64::from_le_bytes(*threshold),
+            basis_points: u16::from_le_bytes(*basis_points),
+        };
+    }
+    fee_tiers
+}
+
+fn pack_fee_tiers(fee_tiers: &[FeeTier; MAX_FEE_TIERS], dst: &mut [u8; 10 * MAX_FEE_TIERS]) {
+    for (fee_tier, dst) in fee_tiers.iter().zip(dst.chunks_mut(10)) {
+        let dst = array_mut_ref![dst, 0, 10];
+        let (threshold_dst, basis_points_dst) = mut_array_refs![dst, 8, 2];
+        *threshold_dst = fee_tier.threshold.to_le_bytes();
+        *basis_points_dst = fee_tier.basis_points.to_le_bytes();
+    }
+}
+
+fn unpack_fee_exemptions(
+    src: &[u8; 34 * MAX_FEE_EXEMPTIONS],
+) -> Result<[FeeExemption; MAX_FEE_EXEMPTIONS], ProgramError> {
+    let mut fee_exemptions = [FeeExemption::default(); MAX_FEE_EXEMPTIONS];
+    for (fee_exemption, src) in fee_exemptions.iter_mut().zip(src.chunks(34)) {
+        let src = array_ref![src, 0, 34];
+        let (pubkey, as_maker, as_taker) = array_refs![src, 32, 1, 1];
+        *fee_exemption = FeeExemption {
+            pubkey: Pubkey::new_from_array(*pubkey),
+            as_maker: unpack_bool(as_maker)?,
+            as_taker: unpack_bool(as_taker)?,
+        };
+    }
+    Ok(fee_exemptions)
+}
+
+fn pack_fee_exemptions(
+    fee_exemptions: &[FeeExemption; MAX_FEE_EXEMPTIONS],
+    dst: &mut [u8; 34 * MAX_FEE_EXEMPTIONS],
+) {
+    for (fee_exemption, dst) in fee_exemptions.iter().zip(dst.chunks_mut(34)) {
+        let dst = array_mut_ref![dst, 0, 34];
+        let (pubkey_dst, as_maker_dst, as_taker_dst) = mut_array_refs![dst, 32, 1, 1];
+        pubkey_dst.copy_from_slice(fee_exemption.pubkey.as_ref());
+        as_maker_dst[0] = fee_exemption.as_maker as u8;
+        as_taker_dst[0] = fee_exemption.as_taker as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An escrow with every field zeroed, for the tests to set the fields they look at
+    fn zeroed_escrow() -> Escrow {
+        Escrow::unpack_unchecked(&[0; Escrow::LEN]).unwrap()
+    }
+
+    #[test]
+    fn test_escrow_len_is_sum_of_fields() {
+        // every offset with the size of the field stored there, in pack order
+        let fields = [
+            (VERSION_OFFSET, 1),
+            (STATE_OFFSET, 1),
+            (INITIALIZER_OFFSET, 32),
+            (TEMP_TOKEN_ACCOUNT_OFFSET, 32),
+            (INITIALIZER_TOKEN_TO_RECEIVE_ACCOUNT_OFFSET, 32),
+            (EXPECTED_AMOUNT_OFFSET, 8),
+            (EXPIRY_SLOT_OFFSET, 8),
+            (BUMP_SEED_OFFSET, 1),
+            (REMAINING_DEPOSIT_OFFSET, 8),
+            (REMAINING_EXPECTED_OFFSET, 8),
+            (EXPIRY_UNIX_TIMESTAMP_OFFSET, 8),
+            (ALLOWED_TAKER_OFFSET, 32),
+            (COUNTER_OFFER_TAKER_OFFSET, 32),
+            (COUNTER_OFFER_AMOUNT_OFFSET, 8),
+            (COUNTER_OFFER_PAYMENT_ACCOUNT_OFFSET, 32),
+            (COUNTER_OFFER_RECEIVE_ACCOUNT_OFFSET, 32),
+            (TWO_PHASE_OFFSET, 1),
+            (PENDING_TAKER_OFFSET, 32),
+            (PENDING_TAKER_VAULT_OFFSET, 32),
+            (PENDING_TAKER_RECEIVE_ACCOUNT_OFFSET, 32),
+            (TEMP_TOKEN_MINT_OFFSET, 32),
+            (RECEIVE_TOKEN_MINT_OFFSET, 32),
+            (MIN_TAKER_AMOUNT_OFFSET, 8),
+            (AUCTION_START_AMOUNT_OFFSET, 8),
+            (AUCTION_END_AMOUNT_OFFSET, 8),
+            (AUCTION_START_SLOT_OFFSET, 8),
+            (AUCTION_END_SLOT_OFFSET, 8),
+            (BID_AUCTION_OFFSET, 1),
+            (BEST_BID_AMOUNT_OFFSET, 8),
+            (PRICE_NUMERATOR_OFFSET, 8),
+            (PRICE_DENOMINATOR_OFFSET, 8),
+            (BASKET_LEN_OFFSET, 1),
+            (BASKET_VAULTS_OFFSET, 96),
+            (PAYMENT_LEGS_LEN_OFFSET, 1),
+            (PAYMENT_LEGS_OFFSET, 216),
+            (HASH_LOCK_OFFSET, 32),
+            (ORACLE_OFFSET, 32),
+            (ORACLE_MAX_DEVIATION_BPS_OFFSET, 2),
+            (ORACLE_MAX_STALENESS_OFFSET, 8),
+            (DEPOSIT_DECIMALS_OFFSET, 1),
+            (RECEIVE_DECIMALS_OFFSET, 1),
+            (RECEIVE_NATIVE_SOL_OFFSET, 1),
+            (NONCE_OFFSET, 8),
+            (ORACLE_KIND_OFFSET, 1),
+            (ARBITER_OFFSET, 32),
+            (DISPUTE_STATUS_OFFSET, 1),
+            (VESTING_CLIFF_SLOT_OFFSET, 8),
+            (VESTING_END_SLOT_OFFSET, 8),
+            (VESTING_START_SLOT_OFFSET, 8),
+            (VESTING_AMOUNT_OFFSET, 8),
+            (VESTING_CLAIMED_OFFSET, 8),
+            (VESTING_BENEFICIARY_OFFSET, 32),
+            (VESTING_RECEIVE_ACCOUNT_OFFSET, 32),
+            (STREAMING_OFFSET, 1),
+            (STREAM_PAID_OFFSET, 8),
+            (STREAM_RELEASED_OFFSET, 8),
+            (RECEIPT_MINT_OFFSET, 32),
+            (POSITION_MINT_OFFSET, 32),
+            (CROWD_FILL_OFFSET, 1),
+            (CROWD_VAULT_OFFSET, 32),
+            (CROWD_TOTAL_OFFSET, 8),
+            (CROWD_CLAIMED_OFFSET, 8),
+            (TAKER_MERKLE_ROOT_OFFSET, 32),
+            (PDA_INITIALIZER_PUBKEY_OFFSET, 32),
+            (GATE_MINT_OFFSET, 32),
+            (GATE_MIN_AMOUNT_OFFSET, 8),
+            (GATEKEEPER_NETWORK_OFFSET, 32),
+            (MEMO_REQUIRED_OFFSET, 1),
+            (PAYMENT_SPLITS_LEN_OFFSET, 1),
+            (PAYMENT_SPLITS_OFFSET, 102),
+            (ROYALTY_POLICY_OFFSET, 1),
+            (NFT_OFFSET, 1),
+            (UNWRAP_SOL_OFFSET, 1),
+            (SEQUENCE_OFFSET, 8),
+            (CANCEL_PENALTY_BPS_OFFSET, 2),
+            (CANCEL_PENALTY_LAMPORTS_OFFSET, 8),
+            (NO_PENALTY_AFTER_SLOT_OFFSET, 8),
+            (SAME_MINT_ALLOWED_OFFSET, 1),
+            (INACTIVE_OFFSET, 1),
+        ];
+
+        let mut end = 0;
+        for (offset, size) in fields.iter() {
+            assert_eq!(*offset, end);
+            end += size;
+        }
+        assert_eq!(Escrow::LEN, end);
+        assert_eq!(Escrow::LEN, INACTIVE_OFFSET + 1);
+    }
+
+    fn auction(start_amount: u64, end_amount: u64, start_slot: u64, end_slot: u64) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        escrow.auction_start_amount = start_amount;
+        escrow.auction_end_amount = end_amount;
+        escrow.auction_start_slot = start_slot;
+        escrow.auction_end_slot = end_slot;
+        escrow
+    }
+
+    #[test]
+    fn test_price_at_boundaries() {
+        let escrow = auction(10_000, 4_000, 100, 200);
+        assert!(escrow.is_dutch_auction());
+        assert_eq!(escrow.dutch_auction_price(0).unwrap(), 10_000);
+        assert_eq!(escrow.dutch_auction_price(100).unwrap(), 10_000);
+        assert_eq!(escrow.dutch_auction_price(200).unwrap(), 4_000);
+        assert_eq!(escrow.dutch_auction_price(u64::MAX).unwrap(), 4_000);
+    }
+
+    #[test]
+    fn test_price_decays_linearly() {
+        let escrow = auction(10_000, 4_000, 100, 200);
+        assert_eq!(escrow.dutch_auction_price(150).unwrap(), 7_000);
+        assert_eq!(escrow.dutch_auction_price(125).unwrap(), 8_500);
+        assert_eq!(escrow.dutch_auction_price(199).unwrap(), 4_060);
+    }
+
+    #[test]
+    fn test_price_rounds_up() {
+        // a third of the drop is 333.33, so the price is 1000 - 333
+        let escrow = auction(1_000, 0, 0, 3);
+        assert_eq!(escrow.dutch_auction_price(1).unwrap(), 667);
+    }
+
+    #[test]
+    fn test_price_with_large_amounts() {
+        let escrow = auction(u64::MAX, 0, 0, u64::MAX);
+        assert_eq!(
+            escrow.dutch_auction_price(u64::MAX / 2).unwrap(),
+            u64::MAX / 2 + 1
+        );
+    }
+
+    fn rate_priced(price_numerator: u64, price_denominator: u64, deposit: u64) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        escrow.price_numerator = price_numerator;
+        escrow.price_denominator = price_denominator;
+        escrow.remaining_deposit = deposit;
+        escrow
+    }
+
+    #[test]
+    fn test_payment_rounds_up() {
+        // a third of a unit per deposit unit
+        let escrow = rate_priced(1, 3, 1_000);
+        assert_eq!(escrow.payment_for_fill(1).unwrap(), 1);
+        assert_eq!(escrow.payment_for_fill(3).unwrap(), 1);
+        assert_eq!(escrow.payment_for_fill(4).unwrap(), 2);
+        assert_eq!(escrow.payment_for_fill(999).unwrap(), 333);
+        assert_eq!(escrow.payment_for_fill(1_000).unwrap(), 334);
+
+        let escrow = rate_priced(2, 3, 1_000);
+        assert_eq!(escrow.payment_for_fill(1).unwrap(), 1);
+        assert_eq!(escrow.payment_for_fill(2).unwrap(), 2);
+        assert_eq!(escrow.payment_for_fill(3).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_payment_with_whole_rate() {
+        let escrow = rate_priced(7, 1, 1_000);
+        assert_eq!(escrow.payment_for_fill(10).unwrap(), 70);
+        assert_eq!(escrow.payment_for_fill(1_000).unwrap(), 7_000);
+    }
+
+    #[test]
+    fn test_payment_overflow() {
+        let escrow = rate_priced(2, 1, u64::MAX);
+        assert_eq!(
+            escrow.payment_for_fill(u64::MAX).unwrap_err(),
+            EscrowError::AmountOverFlow
+        );
+
+        // the intermediate product doesn't fit in a u64 but the payment does
+        let escrow = rate_priced(u64::MAX, u64::MAX, u64::MAX);
+        assert_eq!(escrow.payment_for_fill(u64::MAX).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn test_offered_payment_at_rate() {
+        let escrow = rate_priced(1, 3, 1_000);
+        assert!(escrow.check_offered_payment(4, 2).is_ok());
+        assert_eq!(
+            escrow.check_offered_payment(4, 1).unwrap_err(),
+            EscrowError::ExpectedAmountMissmatch
+        );
+    }
+
+    fn vesting(amount: u64, start_slot: u64, cliff_slot: u64, end_slot: u64) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        escrow.vesting_amount = amount;
+        escrow.vesting_start_slot = start_slot;
+        escrow.vesting_cliff_slot = cliff_slot;
+        escrow.vesting_end_slot = end_slot;
+        escrow
+    }
+
+    #[test]
+    fn test_nothing_vests_before_cliff() {
+        let escrow = vesting(1_000, 0, 100, 1_000);
+        assert!(escrow.has_vesting());
+        assert_eq!(escrow.vested_amount(0).unwrap(), 0);
+        assert_eq!(escrow.vested_amount(99).unwrap(), 0);
+        assert_eq!(escrow.vested_amount(100).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_vests_linearly_from_start() {
+        let escrow = vesting(1_000, 200, 0, 1_200);
+        assert_eq!(escrow.vested_amount(200).unwrap(), 0);
+        assert_eq!(escrow.vested_amount(450).unwrap(), 250);
+        assert_eq!(escrow.vested_amount(700).unwrap(), 500);
+        assert_eq!(escrow.vested_amount(1_200).unwrap(), 1_000);
+        assert_eq!(escrow.vested_amount(u64::MAX).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn test_vested_amount_rounds_down() {
+        let escrow = vesting(1_000, 0, 0, 3);
+        assert_eq!(escrow.vested_amount(1).unwrap(), 333);
+        assert_eq!(escrow.vested_amount(2).unwrap(), 666);
+    }
+
+    #[test]
+    fn test_exchange_after_end_vests_everything() {
+        let escrow = vesting(1_000, 2_000, 100, 1_000);
+        assert_eq!(escrow.vested_amount(2_000).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn test_vested_amount_with_large_amounts() {
+        let escrow = vesting(u64::MAX, 0, 0, u64::MAX);
+        assert_eq!(escrow.vested_amount(u64::MAX / 2).unwrap(), u64::MAX / 2);
+    }
+
+    #[test]
+    fn test_escrow_without_schedule_does_not_vest() {
+        let escrow = vesting(0, 0, 0, 0);
+        assert!(!escrow.has_vesting());
+    }
+
+    fn stream(deposit: u64, expected_amount: u64) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        escrow.streaming = true;
+        escrow.remaining_deposit = deposit;
+        escrow.expected_amount = expected_amount;
+        escrow.remaining_expected = expected_amount;
+        escrow
+    }
+
+    #[test]
+    fn test_release_is_proportional_to_cumulative_payment() {
+        let escrow = stream(1_000, 10_000);
+        assert_eq!(escrow.stream_release_for(0).unwrap(), 0);
+        assert_eq!(escrow.stream_release_for(2_500).unwrap(), 250);
+        assert_eq!(escrow.stream_release_for(9_999).unwrap(), 999);
+        assert_eq!(escrow.stream_release_for(10_000).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn test_release_does_not_lose_rounding_across_installments() {
+        // three installments of a third each would release 333 three times and strand 1 token
+        let mut escrow = stream(1_000, 3);
+        for paid in 1..=3 {
+            let release = escrow.stream_release_for(paid).unwrap() - escrow.stream_released;
+            escrow.stream_released += release;
+            escrow.remaining_deposit -= release;
+        }
+        assert_eq!(escrow.stream_released, 1_000);
+        assert_eq!(escrow.remaining_deposit, 0);
+    }
+
+    #[test]
+    fn test_release_with_large_amounts() {
+        let escrow = stream(u64::MAX, u64::MAX);
+        assert_eq!(
+            escrow.stream_release_for(u64::MAX / 2).unwrap(),
+            u64::MAX / 2
+        );
+    }
+
+    fn settled_crowd_fill(deposit: u64, contributions: &[u64]) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        escrow.crowd_fill = true;
+        escrow.remaining_deposit = deposit;
+        escrow.crowd_total = contributions.iter().sum();
+        escrow
+    }
+
+    /// Claims the shares of `contributions` in order, returning each share
+    fn claim_all(escrow: &mut Escrow, contributions: &[u64]) -> Vec<u64> {
+        contributions
+            .iter()
+            .map(|&contribution| {
+                let share = escrow.crowd_share_for(contribution).unwrap();
+                escrow.crowd_claimed += contribution;
+                escrow.remaining_deposit -= share;
+                share
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shares_are_pro_rata() {
+        let contributions = [2_500, 7_500];
+        let mut escrow = settled_crowd_fill(1_000, &contributions);
+        assert_eq!(claim_all(&mut escrow, &contributions), vec![250, 750]);
+        assert_eq!(escrow.remaining_deposit, 0);
+    }
+
+    #[test]
+    fn test_shares_never_exceed_the_deposit() {
+        // three equal contributions to a deposit that doesn't divide by three
+        let contributions = [1, 1, 1];
+        let mut escrow = settled_crowd_fill(1_000, &contributions);
+        let shares = claim_all(&mut escrow, &contributions);
+        assert_eq!(shares, vec![333, 333, 334]);
+        assert_eq!(shares.iter().sum::<u64>(), 1_000);
+    }
+
+    #[test]
+    fn test_last_claim_takes_the_rest_in_any_order() {
+        let contributions = [7, 1, 3];
+        let mut escrow = settled_crowd_fill(10, &contributions);
+        let shares = claim_all(&mut escrow, &contributions);
+        assert_eq!(shares.iter().sum::<u64>(), 10);
+        assert_eq!(escrow.remaining_deposit, 0);
+    }
+
+    #[test]
+    fn test_share_with_large_amounts() {
+        let contributions = [u64::MAX / 2, u64::MAX / 2];
+        let mut escrow = settled_crowd_fill(u64::MAX, &contributions);
+        assert_eq!(
+            claim_all(&mut escrow, &contributions),
+            vec![u64::MAX / 2, u64::MAX / 2 + 1]
+        );
+    }
+
+    #[test]
+    fn test_share_of_more_than_unclaimed_fails() {
+        let escrow = settled_crowd_fill(1_000, &[100]);
+        assert_eq!(escrow.crowd_share_for(101), Err(EscrowError::InvalidAmount));
+        assert_eq!(escrow.crowd_share_for(0), Err(EscrowError::InvalidAmount));
+    }
+
+    #[test]
+    fn test_zeroed_root_allows_anyone() {
+        let member = Pubkey::new_unique();
+        let other_member = merkle::leaf(&Pubkey::new_unique());
+        let root = merkle::node(&merkle::leaf(&member), &other_member);
+        let mut escrow = zeroed_escrow();
+        let outsider = Pubkey::new_unique();
+        assert!(escrow.is_taker_allowed(&outsider));
+        assert_eq!(escrow.is_taker_allowed_with_proof(&outsider, &[]), Ok(true));
+
+        // with an allowlist only Exchange, which carries a proof, lets takers in
+        escrow.taker_merkle_root = root;
+        assert!(!escrow.is_taker_allowed(&member));
+        assert_eq!(
+            escrow.is_taker_allowed_with_proof(&member, &[other_member]),
+            Ok(true)
+        );
+        assert_eq!(
+            escrow.is_taker_allowed_with_proof(&outsider, &[other_member]),
+            Ok(false)
+        );
+    }
+
+    fn split(basis_points: &[u16]) -> Escrow {
+        let mut escrow = zeroed_escrow();
+        for (index, &share) in basis_points.iter().enumerate() {
+            escrow.payment_splits[index] = PaymentSplit {
+                destination: Pubkey::new_unique(),
+                basis_points: share,
+            };
+        }
+        escrow.payment_splits_len = basis_points.len() as u8;
+        escrow
+    }
+
+    #[test]
+    fn test_payment_without_split_goes_to_initializer() {
+        let escrow = split(&[]);
+        assert!(!escrow.has_payment_split());
+        assert_eq!(escrow.split_payment(9_975).unwrap(), (9_975, vec![]));
+    }
+
+    #[test]
+    fn test_split_payment() {
+        let escrow = split(&[3_000]);
+        assert!(escrow.has_payment_split());
+        assert_eq!(escrow.split_payment(10_000).unwrap(), (7_000, vec![3_000]));
+    }
+
+    #[test]
+    fn test_split_remainder_goes_to_initializer() {
+        let escrow = split(&[3_000]);
+        assert_eq!(escrow.split_payment(9_975).unwrap(), (6_983, vec![2_992]));
+
+        // three shares of a third each round down, the initializer's share gets the rest
+        let escrow = split(&[2_500, 2_500, 2_500]);
+        assert_eq!(escrow.split_payment(3).unwrap(), (3, vec![0, 0, 0]));
+        assert_eq!(escrow.split_payment(7).unwrap(), (4, vec![1, 1, 1]));
+    }
+
+    #[test]
+    fn test_split_of_max_payment() {
+        let escrow = split(&[5_000]);
+        assert_eq!(
+            escrow.split_payment(u64::MAX).unwrap(),
+            (u64::MAX / 2 + 1, vec![u64::MAX / 2])
+        );
+    }
+
+    #[test]
+    fn test_split_over_whole_payment_fails() {
+        // InitSplitPayment rejects these, the shares can't add up to more than the payment
+        let escrow = split(&[6_000, 6_000]);
+        assert_eq!(escrow.split_payment(100), Err(EscrowError::AmountOverFlow));
     }
 }
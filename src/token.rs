@@ -0,0 +1,212 @@
+//! Token instruction builders working with both the classic token program and Token-2022.
+//!
+//! Token-2022 takes the same instructions as the classic token program for everything the escrow
+//! does, but the `spl_token` builders reject any other program id. The builders here build the
+//! instruction for the classic program and address it to the given one.
+//!
+//! Token-2022 accounts and mints with extensions are longer than the classic layout, so they fail
+//! to unpack and only accounts without extensions are supported.
+
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+use spl_token::instruction::AuthorityType;
+
+/// Id of the Token-2022 program. The `spl-token-2022` crate needs a newer `solana-program` than
+/// the one this program is built with, so only its id is declared here
+pub mod spl_token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+/// Whether the escrow can hold and move tokens of the given token program
+pub fn is_supported_token_program(key: &Pubkey) -> bool {
+    *key == spl_token::id() || *key == spl_token_2022::id()
+}
+
+fn addressed_to(
+    token_program_id: &Pubkey,
+    instruction: Result<Instruction, ProgramError>,
+) -> Result<Instruction, ProgramError> {
+    if !is_supported_token_program(token_program_id) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut instruction = instruction?;
+    instruction.program_id = *token_program_id;
+    Ok(instruction)
+}
+
+pub fn transfer(
+    token_program_id: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::transfer(
+            &spl_token::id(),
+            source_pubkey,
+            destination_pubkey,
+            authority_pubkey,
+            signer_pubkeys,
+            amount,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_checked(
+    token_program_id: &Pubkey,
+    source_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+    amount: u64,
+    decimals: u8,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            source_pubkey,
+            mint_pubkey,
+            destination_pubkey,
+            authority_pubkey,
+            signer_pubkeys,
+            amount,
+            decimals,
+        ),
+    )
+}
+
+pub fn set_authority(
+    token_program_id: &Pubkey,
+    owned_pubkey: &Pubkey,
+    new_authority_pubkey: Option<&Pubkey>,
+    authority_type: AuthorityType,
+    owner_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::set_authority(
+            &spl_token::id(),
+            owned_pubkey,
+            new_authority_pubkey,
+            authority_type,
+            owner_pubkey,
+            signer_pubkeys,
+        ),
+    )
+}
+
+pub fn close_account(
+    token_program_id: &Pubkey,
+    account_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::close_account(
+            &spl_token::id(),
+            account_pubkey,
+            destination_pubkey,
+            owner_pubkey,
+            signer_pubkeys,
+        ),
+    )
+}
+
+pub fn mint_to(
+    token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    account_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            mint_pubkey,
+            account_pubkey,
+            owner_pubkey,
+            signer_pubkeys,
+            amount,
+        ),
+    )
+}
+
+pub fn burn(
+    token_program_id: &Pubkey,
+    account_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::burn(
+            &spl_token::id(),
+            account_pubkey,
+            mint_pubkey,
+            authority_pubkey,
+            signer_pubkeys,
+            amount,
+        ),
+    )
+}
+
+pub fn approve(
+    token_program_id: &Pubkey,
+    source_pubkey: &Pubkey,
+    delegate_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            source_pubkey,
+            delegate_pubkey,
+            owner_pubkey,
+            signer_pubkeys,
+            amount,
+        ),
+    )
+}
+
+pub fn initialize_account(
+    token_program_id: &Pubkey,
+    account_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            account_pubkey,
+            mint_pubkey,
+            owner_pubkey,
+        ),
+    )
+}
+
+pub fn sync_native(
+    token_program_id: &Pubkey,
+    account_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    addressed_to(
+        token_program_id,
+        spl_token::instruction::sync_native(&spl_token::id(), account_pubkey),
+    )
+}
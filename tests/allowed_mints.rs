@@ -0,0 +1,77 @@
+use bpf_program_template::{
+    error::EscrowError,
+    state::{Config, MAX_ALLOWED_MINTS},
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+#[test]
+fn test_empty_list_allows_every_mint() {
+    let config = Config::default();
+    assert!(config.is_mint_allowed(&Pubkey::new_unique()));
+}
+
+#[test]
+fn test_listed_mints_are_the_only_ones_allowed() {
+    let (listed, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = Config::default();
+    config.add_allowed_mint(&listed).unwrap();
+    assert!(config.is_mint_allowed(&listed));
+    assert!(!config.is_mint_allowed(&other));
+
+    // adding it again keeps a single entry
+    config.add_allowed_mint(&listed).unwrap();
+    assert_eq!(config.allowed_mints(), &[listed]);
+}
+
+#[test]
+fn test_full_allowed_mint_list() {
+    let mut config = Config::default();
+    for _ in 0..MAX_ALLOWED_MINTS {
+        config.add_allowed_mint(&Pubkey::new_unique()).unwrap();
+    }
+    assert_eq!(
+        config.add_allowed_mint(&Pubkey::new_unique()),
+        Err(EscrowError::AllowedMintsFull)
+    );
+
+    let listed = config.allowed_mints()[0];
+    assert!(config.add_allowed_mint(&listed).is_ok());
+}
+
+#[test]
+fn test_remove_allowed_mint() {
+    let (first, second, third) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let mut config = Config::default();
+    for mint in [first, second, third] {
+        config.add_allowed_mint(&mint).unwrap();
+    }
+
+    config.remove_allowed_mint(&first).unwrap();
+    assert!(!config.is_mint_allowed(&first));
+    assert_eq!(config.allowed_mints(), &[third, second]);
+
+    assert_eq!(
+        config.remove_allowed_mint(&first),
+        Err(EscrowError::MintNotListed)
+    );
+
+    // unlisting every mint allows them all again
+    config.remove_allowed_mint(&second).unwrap();
+    config.remove_allowed_mint(&third).unwrap();
+    assert!(config.is_mint_allowed(&first));
+}
+
+#[test]
+fn test_config_with_allowed_mints_pack_round_trip() {
+    let mut config = Config::default();
+    config.is_initialized = true;
+    config.add_allowed_mint(&Pubkey::new_unique()).unwrap();
+
+    let mut data = [0; Config::LEN];
+    Config::pack(config.clone(), &mut data).unwrap();
+    assert_eq!(Config::unpack(&data).unwrap(), config);
+}
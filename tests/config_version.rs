@@ -0,0 +1,80 @@
+use bpf_program_template::state::{Config, CONFIG_V1_LEN, CONFIG_VERSION};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+/// A config as version 1 packed it, with is_initialized where the version now is and no allowed
+/// mints after the pause flag
+fn v1_config(admin: &Pubkey) -> Vec<u8> {
+    let config = Config {
+        is_initialized: true,
+        admin: *admin,
+        maker_fee_bps: 25,
+        creation_fee_lamports: 1_000,
+        paused: true,
+        ..Config::default()
+    };
+    let mut data = vec![0; Config::LEN];
+    Config::pack(config, &mut data).unwrap();
+    data.truncate(CONFIG_V1_LEN);
+    data[0] = 1;
+    data
+}
+
+#[test]
+fn test_migrate_v1_config() {
+    let admin = Pubkey::new_unique();
+    let config = Config::migrate(&v1_config(&admin)).unwrap();
+    assert!(config.is_initialized);
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.maker_fee_bps, 25);
+    assert_eq!(config.creation_fee_lamports, 1_000);
+    assert!(config.paused);
+    assert!(config.allowed_mints().is_empty());
+}
+
+#[test]
+fn test_migrated_config_packs_current_version() {
+    let admin = Pubkey::new_unique();
+    let mut config = Config::migrate(&v1_config(&admin)).unwrap();
+    let mint = Pubkey::new_unique();
+    config.add_allowed_mint(&mint).unwrap();
+
+    let mut data = [0; Config::LEN];
+    Config::pack(config, &mut data).unwrap();
+    assert_eq!(data[0], CONFIG_VERSION);
+    let config = Config::unpack(&data).unwrap();
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.allowed_mints(), &[mint]);
+    assert_eq!(Config::migrate(&data).unwrap(), config);
+}
+
+#[test]
+fn test_v1_config_only_unpacks_through_migrate() {
+    let data = v1_config(&Pubkey::new_unique());
+    assert_eq!(
+        Config::unpack(&data).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn test_unknown_config_version_fails() {
+    let mut data = [0; Config::LEN];
+    data[0] = CONFIG_VERSION + 1;
+    assert_eq!(
+        Config::unpack(&data).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+    assert_eq!(
+        Config::migrate(&data).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn test_migrate_truncated_config_fails() {
+    let data = v1_config(&Pubkey::new_unique());
+    assert_eq!(
+        Config::migrate(&data[..CONFIG_V1_LEN - 1]).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
@@ -0,0 +1,22 @@
+use bpf_program_template::cpi;
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+entrypoint!(process_instruction);
+/// Takes the escrow whose accounts follow the escrow program's, paying at most the u64 given
+fn process_instruction(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let (escrow_program, exchange_accounts) = accounts
+        .split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let max_payment = instruction_data
+        .try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    cpi::exchange_cpi(escrow_program.key, exchange_accounts, max_payment, &[])
+}
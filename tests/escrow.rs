@@ -0,0 +1,8210 @@
+#![cfg(feature = "test-bpf")]
+
+use bpf_program_template::{
+    constants::{ESCROW_SEED_PREFIX, FEE_COLLECTOR_SEED},
+    cpi,
+    error::EscrowError,
+    gateway::gateway_program,
+    instruction::{self, BatchEntry, EscrowInstruction},
+    memo::spl_memo,
+    merkle,
+    metadata::{metadata_address, token_metadata_program, RoyaltyPolicy},
+    oracle::OracleKind,
+    processor::{
+        config_pda, contribution_pda, escrow_pda, fee_collector_pda, Processor,
+        MAKER_FEE_BASIS_POINTS,
+    },
+    state::{Bid, Config, Escrow, EscrowState, FeeTier, CONFIG_V1_LEN},
+    token::spl_token_2022,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    hash::hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    system_instruction, system_program, sysvar,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use spl_token::{
+    native_mint,
+    state::{Account as TokenAccount, AccountState, Mint},
+};
+
+const DEPOSIT_AMOUNT: u64 = 1_000;
+const EXPECTED_AMOUNT: u64 = 10_000;
+
+/// A program settling escrows from its own instruction, as a DEX aggregator would
+mod caller_program {
+    solana_program::declare_id!("Cey35Dnh1LZXyGVoro6oyiiXToUShaZFzqLk8FGyJTJ5");
+}
+
+/// Takes the escrow whose accounts follow the escrow program's, paying at most the u64 given
+fn process_caller(_program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+    let (escrow_program, exchange_accounts) = accounts
+        .split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let max_payment = input
+        .try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    cpi::exchange_cpi(escrow_program.key, exchange_accounts, max_payment, &[])
+}
+
+struct TestEnv {
+    ctx: ProgramTestContext,
+    program_id: Pubkey,
+    token_program: Pubkey,
+    initializer: Keypair,
+    taker: Keypair,
+    mint_x: Pubkey,
+    mint_y: Pubkey,
+    escrow: Keypair,
+    initializer_temp_x: Pubkey,
+    initializer_receive_y: Pubkey,
+    taker_send_y: Pubkey,
+    taker_receive_x: Pubkey,
+    fee_collector_y: Pubkey,
+}
+
+async fn process(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await
+}
+
+async fn create_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+    create_mint_of(ctx, &spl_token::id()).await
+}
+
+async fn create_mint_of(ctx: &mut ProgramTestContext, token_program: &Pubkey) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let mut initialize_mint_ix = spl_token::instruction::initialize_mint(
+        &spl_token::id(),
+        &mint.pubkey(),
+        &ctx.payer.pubkey(),
+        None,
+        0,
+    )
+    .unwrap();
+    initialize_mint_ix.program_id = *token_program;
+    let instructions = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            token_program,
+        ),
+        initialize_mint_ix,
+    ];
+    process(ctx, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+async fn create_token_account(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    // the account belongs to the token program of its mint
+    let token_program = ctx
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .expect("mint not found")
+        .owner;
+    let account = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let mut instructions = vec![
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            &token_program,
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    if amount > 0 {
+        instructions.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &account.pubkey(),
+                &ctx.payer.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    for instruction in instructions.iter_mut().skip(1) {
+        instruction.program_id = token_program;
+    }
+    process(ctx, &instructions, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+/// Creates the fee collector's associated token account for `mint`, the one the `exchange`
+/// builder derives
+async fn create_fee_collector_account(
+    ctx: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    mint: &Pubkey,
+) -> Pubkey {
+    let fee_collector = fee_collector_pda(program_id).0;
+    let ix = create_associated_token_account(&ctx.payer.pubkey(), &fee_collector, mint);
+    process(ctx, &[ix], &[]).await.unwrap();
+    get_associated_token_address(&fee_collector, mint)
+}
+
+async fn token_balance(ctx: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    let account = ctx
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .expect("token account not found");
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+async fn setup() -> TestEnv {
+    setup_with_token_program(&spl_token::id()).await
+}
+
+/// Creates both mints with `token_program`, funds the initializer's temp account with the deposit
+/// and the taker's account with the expected amount, and allocates an empty escrow account
+async fn setup_with_token_program(token_program: &Pubkey) -> TestEnv {
+    setup_with_compute_budget(token_program, None).await
+}
+
+/// Like `setup_with_token_program`, capping the compute units of each instruction at
+/// `compute_max_units` when it's set. Only the BPF build of the program is metered
+async fn setup_with_compute_budget(
+    token_program: &Pubkey,
+    compute_max_units: Option<u64>,
+) -> TestEnv {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = ProgramTest::new(
+        "bpf_program_template",
+        program_id,
+        processor!(Processor::process),
+    );
+    if let Some(compute_max_units) = compute_max_units {
+        program_test.set_bpf_compute_max_units(compute_max_units);
+    }
+    // the classic token program stands in for Token-2022, both take the same instructions for
+    // accounts without extensions
+    program_test.add_program(
+        "spl_token_2022",
+        spl_token_2022::id(),
+        processor!(spl_token::processor::Processor::process),
+    );
+    program_test.add_program(
+        "caller_program",
+        caller_program::id(),
+        processor!(process_caller),
+    );
+    let mut ctx = program_test.start_with_context().await;
+
+    let payer = ctx.payer.pubkey();
+    let initializer = Keypair::new();
+    let taker = Keypair::new();
+    process(
+        &mut ctx,
+        &[system_instruction::transfer(
+            &payer,
+            &initializer.pubkey(),
+            1_000_000_000,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let mint_x = create_mint_of(&mut ctx, token_program).await;
+    let mint_y = create_mint_of(&mut ctx, token_program).await;
+
+    let initializer_temp_x =
+        create_token_account(&mut ctx, &mint_x, &initializer.pubkey(), DEPOSIT_AMOUNT).await;
+    let initializer_receive_y =
+        create_token_account(&mut ctx, &mint_y, &initializer.pubkey(), 0).await;
+    let taker_send_y =
+        create_token_account(&mut ctx, &mint_y, &taker.pubkey(), EXPECTED_AMOUNT).await;
+    let taker_receive_x = create_token_account(&mut ctx, &mint_x, &taker.pubkey(), 0).await;
+
+    // associated token accounts of Token-2022 are derived with its id, the builders only know
+    // those of the classic token program
+    let fee_collector_y = if *token_program == spl_token::id() {
+        create_fee_collector_account(&mut ctx, &program_id, &mint_y).await
+    } else {
+        let fee_collector = fee_collector_pda(&program_id).0;
+        create_token_account(&mut ctx, &mint_y, &fee_collector, 0).await
+    };
+
+    let escrow = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    process(
+        &mut ctx,
+        &[system_instruction::create_account(
+            &payer,
+            &escrow.pubkey(),
+            rent.minimum_balance(Escrow::LEN),
+            Escrow::LEN as u64,
+            &program_id,
+        )],
+        &[&escrow],
+    )
+    .await
+    .unwrap();
+
+    TestEnv {
+        ctx,
+        program_id,
+        token_program: *token_program,
+        initializer,
+        taker,
+        mint_x,
+        mint_y,
+        escrow,
+        initializer_temp_x,
+        initializer_receive_y,
+        taker_send_y,
+        taker_receive_x,
+        fee_collector_y,
+    }
+}
+
+async fn init_escrow(env: &mut TestEnv, amount: u64) -> Result<(), TransportError> {
+    let ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &env.token_program,
+        amount,
+    );
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+/// Starts the escrow like `init_escrow`, expiring after `expiry_slot` and
+/// `expiry_unix_timestamp`, 0 meaning no expiry
+async fn init_escrow_expiring(
+    env: &mut TestEnv,
+    expiry_slot: u64,
+    expiry_unix_timestamp: i64,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot,
+        expiry_unix_timestamp,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+async fn exchange(env: &mut TestEnv, taker_receive_account: &Pubkey) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        taker_receive_account,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    // tests swap these for Token-2022 and for other fee collector token accounts
+    ix.accounts[7] = AccountMeta::new_readonly(env.token_program, false);
+    ix.accounts[9] = AccountMeta::new(env.fee_collector_y, false);
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+async fn partial_exchange(
+    env: &mut TestEnv,
+    amount_to_take: u64,
+    amount_to_give: u64,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.data = EscrowInstruction::PartialExchange {
+        amount_to_take,
+        amount_to_give,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+/// Creates and initializes another escrow of the initializer with the same terms, funding the
+/// taker with the extra expected amount. Returns the temp token account and the escrow account
+async fn add_escrow(env: &mut TestEnv) -> (Pubkey, Pubkey) {
+    let payer = env.ctx.payer.pubkey();
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let temp_x = create_token_account(&mut env.ctx, &mint_x, &initializer, DEPOSIT_AMOUNT).await;
+
+    let escrow = Keypair::new();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &payer,
+            &escrow.pubkey(),
+            rent.minimum_balance(Escrow::LEN),
+            Escrow::LEN as u64,
+            &env.program_id,
+        ),
+        instruction::init_escrow(
+            &env.program_id,
+            &initializer,
+            &temp_x,
+            &env.initializer_receive_y,
+            &escrow.pubkey(),
+            &spl_token::id(),
+            EXPECTED_AMOUNT,
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &env.mint_y,
+            &env.taker_send_y,
+            &payer,
+            &[],
+            EXPECTED_AMOUNT,
+        )
+        .unwrap(),
+    ];
+    process(&mut env.ctx, &instructions, &[&escrow, &env.initializer])
+        .await
+        .unwrap();
+
+    (temp_x, escrow.pubkey())
+}
+
+fn assert_escrow_error(result: Result<(), TransportError>, error: EscrowError) {
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+    );
+}
+
+#[tokio::test]
+async fn test_init_and_exchange() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+
+    // both the temp token account and the escrow account are closed
+    let temp_account = env
+        .ctx
+        .banks_client
+        .get_account(env.initializer_temp_x)
+        .await
+        .unwrap();
+    assert!(temp_account.is_none());
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap();
+    assert!(escrow_account.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_zeroes_escrow_data() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // funding the closed escrow account in the same transaction keeps it around to be read
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        instruction::exchange(
+            &env.program_id,
+            &env.taker.pubkey(),
+            &env.taker_send_y,
+            &env.taker_receive_x,
+            &env.initializer_temp_x,
+            &env.initializer.pubkey(),
+            &env.initializer_receive_y,
+            &env.escrow.pubkey(),
+            0,
+            &env.mint_y,
+        ),
+        system_instruction::transfer(
+            &env.ctx.payer.pubkey(),
+            &env.escrow.pubkey(),
+            rent.minimum_balance(Escrow::LEN),
+        ),
+    ];
+    process(&mut env.ctx, &instructions, &[&env.taker])
+        .await
+        .unwrap();
+
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(escrow_account.data.len(), Escrow::LEN);
+    assert!(escrow_account.data.iter().all(|byte| *byte == 0));
+}
+
+#[tokio::test]
+async fn test_exchange_by_underfunded_taker_fails() {
+    let mut env = setup().await;
+    // the taker holds exactly EXPECTED_AMOUNT, one token short
+    init_escrow(&mut env, EXPECTED_AMOUNT + 1).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::InsufficientTakerBalance);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_into_wrong_mint_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mint_y = env.mint_y;
+    let taker = env.taker.pubkey();
+    let taker_receive_y = create_token_account(&mut env.ctx, &mint_y, &taker, 0).await;
+
+    let result = exchange(&mut env, &taker_receive_y).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_paying_with_wrong_mint_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the taker pays in the deposited token instead of the expected one
+    let mint_x = env.mint_x;
+    let taker = env.taker.pubkey();
+    env.taker_send_y = create_token_account(&mut env.ctx, &mint_x, &taker, EXPECTED_AMOUNT).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_fee_below_one_token_is_not_charged() {
+    let mut env = setup().await;
+    // 25 basis points of 399 round down to nothing
+    init_escrow(&mut env, 399).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        399
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_exchange_with_other_fee_collector_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // a token account for the right mint that the fee collector PDA doesn't own
+    let mint_y = env.mint_y;
+    let taker = env.taker.pubkey();
+    env.fee_collector_y = create_token_account(&mut env.ctx, &mint_y, &taker, 0).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_fee_collector_of_other_mint_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mint_x = env.mint_x;
+    let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], &env.program_id);
+    env.fee_collector_y = create_token_account(&mut env.ctx, &mint_x, &fee_collector, 0).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+const EXPIRY_SLOT: u64 = 500;
+
+#[tokio::test]
+async fn test_exchange_before_expiry_slot() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+    env.ctx.warp_to_slot(EXPIRY_SLOT - 1).unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_at_expiry_slot() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+    // the expiry slot is the last one the trade can be taken at
+    env.ctx.warp_to_slot(EXPIRY_SLOT).unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_after_expiry_slot_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+    env.ctx.warp_to_slot(EXPIRY_SLOT + 1).unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::EscrowExpired);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+async fn extend_expiry(
+    env: &mut TestEnv,
+    by_taker: bool,
+    new_expiry_slot: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+        ],
+        data: EscrowInstruction::ExtendExpiry { new_expiry_slot }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_extend_expiry() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+    extend_expiry(&mut env, false, EXPIRY_SLOT * 2)
+        .await
+        .unwrap();
+    env.ctx.warp_to_slot(EXPIRY_SLOT + 1).unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_shorten_expiry_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+
+    for new_expiry_slot in [EXPIRY_SLOT - 1, EXPIRY_SLOT] {
+        let result = extend_expiry(&mut env, false, new_expiry_slot).await;
+        assert_escrow_error(result, EscrowError::ExpiryNotExtended);
+    }
+}
+
+#[tokio::test]
+async fn test_extend_expiry_to_past_slot_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+    env.ctx.warp_to_slot(EXPIRY_SLOT + 10).unwrap();
+
+    // later than the expiry, but the slot has gone by already
+    let result = extend_expiry(&mut env, false, EXPIRY_SLOT + 5).await;
+    assert_escrow_error(result, EscrowError::ExpiryNotExtended);
+}
+
+#[tokio::test]
+async fn test_extend_expiry_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, EXPIRY_SLOT, 0)
+        .await
+        .unwrap();
+
+    let result = extend_expiry(&mut env, true, EXPIRY_SLOT * 2).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+}
+
+/// Moves the unix timestamp of the clock, leaving its slot as it is
+async fn set_unix_timestamp(env: &mut TestEnv, unix_timestamp: i64) {
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    env.ctx.set_sysvar(&clock);
+}
+
+const EXPIRY_UNIX_TIMESTAMP: i64 = 2_000_000_000;
+
+#[tokio::test]
+async fn test_exchange_before_expiry_unix_timestamp() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 0, EXPIRY_UNIX_TIMESTAMP)
+        .await
+        .unwrap();
+    set_unix_timestamp(&mut env, EXPIRY_UNIX_TIMESTAMP).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_after_expiry_unix_timestamp_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 0, EXPIRY_UNIX_TIMESTAMP)
+        .await
+        .unwrap();
+    set_unix_timestamp(&mut env, EXPIRY_UNIX_TIMESTAMP + 1).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::EscrowExpired);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+async fn update_expected_amount(
+    env: &mut TestEnv,
+    by_taker: bool,
+    new_amount: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+        ],
+        data: EscrowInstruction::UpdateExpectedAmount { new_amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_update_expected_amount() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    update_expected_amount(&mut env, false, EXPECTED_AMOUNT * 2)
+        .await
+        .unwrap();
+    let escrow = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow.data).unwrap();
+    assert_eq!(escrow_info.expected_amount, EXPECTED_AMOUNT * 2);
+    assert_eq!(escrow_info.remaining_expected, EXPECTED_AMOUNT * 2);
+}
+
+#[tokio::test]
+async fn test_exchange_after_update_pays_new_price() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    update_expected_amount(&mut env, false, EXPECTED_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    // half the deposit for half the old expected amount
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::ExpectedAmountMissmatch);
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT)
+        .await
+        .unwrap();
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT / 2
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+}
+
+#[tokio::test]
+async fn test_update_expected_amount_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = update_expected_amount(&mut env, true, EXPECTED_AMOUNT * 2).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+}
+
+#[tokio::test]
+async fn test_update_expected_amount_to_zero_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = update_expected_amount(&mut env, false, 0).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+}
+
+async fn replace(
+    env: &mut TestEnv,
+    by_taker: bool,
+    new_expected_amount: u64,
+    new_expiry: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new_readonly(env.initializer_temp_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+        ],
+        data: EscrowInstruction::Replace {
+            new_expected_amount,
+            new_expiry,
+        }
+        .pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_replace_keeps_unix_timestamp_expiry() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 0, 4_000_000_000)
+        .await
+        .unwrap();
+
+    replace(&mut env, false, EXPECTED_AMOUNT * 2, 500)
+        .await
+        .unwrap();
+    let escrow = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow.data).unwrap();
+    assert_eq!(escrow_info.expected_amount, EXPECTED_AMOUNT * 2);
+    assert_eq!(escrow_info.remaining_expected, EXPECTED_AMOUNT * 2);
+    assert_eq!(escrow_info.expiry_slot, 500);
+    assert_eq!(escrow_info.expiry_unix_timestamp, 4_000_000_000);
+}
+
+#[tokio::test]
+async fn test_replace_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = replace(&mut env, true, EXPECTED_AMOUNT * 2, 0).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+}
+
+/// Creates the config with the initializer as its admin
+async fn initialize_config(
+    env: &mut TestEnv,
+    maker_fee_bps: u16,
+    taker_fee_bps: u16,
+    fee_tiers: Vec<FeeTier>,
+) -> Result<(), TransportError> {
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::InitializeConfig {
+            maker_fee_bps,
+            taker_fee_bps,
+            fee_tiers,
+        }
+        .pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+async fn set_fee_schedule(
+    env: &mut TestEnv,
+    by_taker: bool,
+    maker_fee_bps: u16,
+    fee_tiers: Vec<FeeTier>,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::SetFeeSchedule {
+            maker_fee_bps,
+            taker_fee_bps: 0,
+            fee_tiers,
+        }
+        .pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_exchange_at_fee_tier_threshold() {
+    let mut env = setup().await;
+    let fee_tiers = vec![FeeTier {
+        threshold: EXPECTED_AMOUNT,
+        basis_points: 10,
+    }];
+    initialize_config(&mut env, 30, 20, fee_tiers)
+        .await
+        .unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the taker fee comes on top of the expected amount
+    let maker_fee = EXPECTED_AMOUNT * 10 / 10_000;
+    let taker_fee = EXPECTED_AMOUNT * 20 / 10_000;
+    let payer = env.ctx.payer.pubkey();
+    let ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &env.mint_y,
+        &env.taker_send_y,
+        &payer,
+        &[],
+        taker_fee,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - maker_fee
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.fee_collector_y).await,
+        maker_fee + taker_fee
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_below_fee_tier_threshold() {
+    let mut env = setup().await;
+    let fee_tiers = vec![FeeTier {
+        threshold: EXPECTED_AMOUNT + 1,
+        basis_points: 10,
+    }];
+    initialize_config(&mut env, 30, 0, fee_tiers).await.unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    // one token short of the tier, the flat rate applies
+    let fee = EXPECTED_AMOUNT * 30 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+}
+
+#[tokio::test]
+async fn test_set_fee_schedule() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 0, vec![]).await.unwrap();
+    set_fee_schedule(&mut env, false, 0, vec![]).await.unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_set_fee_schedule_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 0, vec![]).await.unwrap();
+
+    let result = set_fee_schedule(&mut env, true, 0, vec![]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+}
+
+#[tokio::test]
+async fn test_initialize_config_with_unsorted_tiers_fails() {
+    let mut env = setup().await;
+    let fee_tiers = vec![
+        FeeTier {
+            threshold: 1_000,
+            basis_points: 20,
+        },
+        FeeTier {
+            threshold: 100,
+            basis_points: 10,
+        },
+    ];
+    let result = initialize_config(&mut env, 30, 0, fee_tiers).await;
+    assert_escrow_error(result, EscrowError::InvalidFeeSchedule);
+}
+
+/// Exempts `pubkey` from fees as the initializer, who is the admin of the config
+async fn add_fee_exemption(
+    env: &mut TestEnv,
+    pubkey: Pubkey,
+    as_maker: bool,
+    as_taker: bool,
+) -> Result<(), TransportError> {
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.initializer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(pubkey, false),
+        ],
+        data: EscrowInstruction::AddFeeExemption { as_maker, as_taker }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+/// Runs a full exchange with a 30 basis points maker fee and 20 basis points taker fee, the
+/// taker being funded for both, and returns what the fee collector got
+async fn exchange_paying_fees(env: &mut TestEnv) -> u64 {
+    let payer = env.ctx.payer.pubkey();
+    let ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &env.mint_y,
+        &env.taker_send_y,
+        &payer,
+        &[],
+        EXPECTED_AMOUNT * 20 / 10_000,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+    init_escrow(env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(env, &taker_receive_x).await.unwrap();
+    token_balance(&mut env.ctx, &env.fee_collector_y).await
+}
+
+#[tokio::test]
+async fn test_exchange_with_exempt_maker() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 20, vec![]).await.unwrap();
+    let initializer = env.initializer.pubkey();
+    add_fee_exemption(&mut env, initializer, true, false)
+        .await
+        .unwrap();
+
+    assert_eq!(exchange_paying_fees(&mut env).await, 0);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_by_exempt_taker() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 20, vec![]).await.unwrap();
+    let taker = env.taker.pubkey();
+    add_fee_exemption(&mut env, taker, false, true)
+        .await
+        .unwrap();
+
+    assert_eq!(exchange_paying_fees(&mut env).await, 0);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT
+    );
+    // the taker keeps what they were funded with for the taker fee
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT * 20 / 10_000
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_exemptions_in_other_roles_pays_fees() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 20, vec![]).await.unwrap();
+    let (initializer, taker) = (env.initializer.pubkey(), env.taker.pubkey());
+    add_fee_exemption(&mut env, initializer, false, true)
+        .await
+        .unwrap();
+    add_fee_exemption(&mut env, taker, true, false)
+        .await
+        .unwrap();
+
+    let maker_fee = EXPECTED_AMOUNT * 30 / 10_000;
+    let taker_fee = EXPECTED_AMOUNT * 20 / 10_000;
+    assert_eq!(exchange_paying_fees(&mut env).await, maker_fee + taker_fee);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - maker_fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_remove_fee_exemption_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 30, 20, vec![]).await.unwrap();
+    let taker = env.taker.pubkey();
+    add_fee_exemption(&mut env, taker, false, true)
+        .await
+        .unwrap();
+
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(taker, true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(taker, false),
+        ],
+        data: EscrowInstruction::RemoveFeeExemption.pack(),
+    };
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+}
+
+/// Withdraws from the fee collector's account for the token Y into `destination`
+async fn withdraw_fees(
+    env: &mut TestEnv,
+    by_taker: bool,
+    destination: &Pubkey,
+    amount: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let (fee_collector, _) = Pubkey::find_program_address(&[FEE_COLLECTOR_SEED], &env.program_id);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(fee_collector, false),
+        ],
+        data: EscrowInstruction::WithdrawFees { amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+/// Creates the config with the initializer as admin and collects the fee of a full exchange,
+/// returning the fee and an empty token Y account of the admin
+async fn collect_fees(env: &mut TestEnv) -> (u64, Pubkey) {
+    initialize_config(env, 30, 0, vec![]).await.unwrap();
+    init_escrow(env, EXPECTED_AMOUNT).await.unwrap();
+    let taker_receive_x = env.taker_receive_x;
+    exchange(env, &taker_receive_x).await.unwrap();
+
+    let mint_y = env.mint_y;
+    let admin = env.initializer.pubkey();
+    let admin_y = create_token_account(&mut env.ctx, &mint_y, &admin, 0).await;
+    (EXPECTED_AMOUNT * 30 / 10_000, admin_y)
+}
+
+#[tokio::test]
+async fn test_withdraw_fees() {
+    let mut env = setup().await;
+    let (fee, admin_y) = collect_fees(&mut env).await;
+
+    withdraw_fees(&mut env, false, &admin_y, 10).await.unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &admin_y).await, 10);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.fee_collector_y).await,
+        fee - 10
+    );
+
+    withdraw_fees(&mut env, false, &admin_y, u64::MAX)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &admin_y).await, fee);
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_withdraw_more_than_collected_fails() {
+    let mut env = setup().await;
+    let (fee, admin_y) = collect_fees(&mut env).await;
+
+    let result = withdraw_fees(&mut env, false, &admin_y, fee + 1).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+}
+
+#[tokio::test]
+async fn test_withdraw_fees_by_other_signer_fails() {
+    let mut env = setup().await;
+    let (fee, _) = collect_fees(&mut env).await;
+
+    let taker_send_y = env.taker_send_y;
+    let result = withdraw_fees(&mut env, true, &taker_send_y, u64::MAX).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+}
+
+async fn set_creation_fee(
+    env: &mut TestEnv,
+    by_taker: bool,
+    lamports: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::SetCreationFee { lamports }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+/// Withdraws creation fees from the fee collector PDA into `destination`
+async fn withdraw_creation_fees(
+    env: &mut TestEnv,
+    by_taker: bool,
+    destination: &Pubkey,
+    amount: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+            AccountMeta::new(fee_collector_pda(&env.program_id).0, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::WithdrawCreationFees { amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+const CREATION_FEE: u64 = 1_000_000;
+
+#[tokio::test]
+async fn test_init_escrow_pays_creation_fee() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    set_creation_fee(&mut env, false, CREATION_FEE)
+        .await
+        .unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let initializer_before = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    assert_eq!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap(),
+        initializer_before - CREATION_FEE
+    );
+    assert_eq!(
+        env.ctx
+            .banks_client
+            .get_balance(fee_collector)
+            .await
+            .unwrap(),
+        CREATION_FEE
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_without_creation_fee_is_free() {
+    let mut env = setup().await;
+    let initializer = env.initializer.pubkey();
+    let initializer_before = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    assert_eq!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap(),
+        initializer_before
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_with_unaffordable_creation_fee_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    let initializer = env.initializer.pubkey();
+    let balance = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    // the fee would leave the initializer's account without rent
+    set_creation_fee(&mut env, false, balance).await.unwrap();
+
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::CreationFeeUnaffordable);
+    assert_eq!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap(),
+        balance
+    );
+}
+
+#[tokio::test]
+async fn test_set_creation_fee_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+
+    let result = set_creation_fee(&mut env, true, CREATION_FEE).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+}
+
+#[tokio::test]
+async fn test_withdraw_creation_fees() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    set_creation_fee(&mut env, false, CREATION_FEE)
+        .await
+        .unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let destination = Pubkey::new_unique();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let result = withdraw_creation_fees(&mut env, true, &destination, CREATION_FEE).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+
+    let result = withdraw_creation_fees(&mut env, false, &destination, CREATION_FEE + 1).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+    );
+
+    withdraw_creation_fees(&mut env, false, &destination, u64::MAX)
+        .await
+        .unwrap();
+    assert_eq!(
+        env.ctx.banks_client.get_balance(destination).await.unwrap(),
+        CREATION_FEE
+    );
+    assert_eq!(
+        env.ctx
+            .banks_client
+            .get_balance(fee_collector)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+async fn set_config(
+    env: &mut TestEnv,
+    by_taker: bool,
+    paused: bool,
+    new_admin: Option<Pubkey>,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::SetConfig { paused, new_admin }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_paused_config_refuses_new_escrows() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    set_config(&mut env, false, true, None).await.unwrap();
+
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::ProgramPaused);
+
+    set_config(&mut env, false, false, None).await.unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_config_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    let result = set_config(&mut env, true, true, Some(taker)).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_config_hands_over_admin() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    set_config(&mut env, false, false, Some(taker))
+        .await
+        .unwrap();
+    let result = set_config(&mut env, false, true, None).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+    set_config(&mut env, true, true, None).await.unwrap();
+}
+
+/// Points the payment side of `env` at token X accounts, so the escrow deposit and payment are of
+/// the same mint
+async fn use_same_mint(env: &mut TestEnv) {
+    let mint_x = env.mint_x;
+    let initializer = env.initializer.pubkey();
+    let taker = env.taker.pubkey();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    env.initializer_receive_y = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    env.taker_send_y = create_token_account(&mut env.ctx, &mint_x, &taker, EXPECTED_AMOUNT).await;
+    env.fee_collector_y = create_token_account(&mut env.ctx, &mint_x, &fee_collector, 0).await;
+}
+
+async fn init_same_mint(env: &mut TestEnv, amount: u64) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        amount,
+    );
+    ix.data = EscrowInstruction::InitSameMint { amount }.pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_exchange_same_mint_escrow() {
+    let mut env = setup().await;
+    use_same_mint(&mut env).await;
+    init_same_mint(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_init_escrow_with_same_mint_fails() {
+    let mut env = setup().await;
+    use_same_mint(&mut env).await;
+
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::SameMint);
+}
+
+#[tokio::test]
+async fn test_init_same_mint_with_equal_amounts_fails() {
+    let mut env = setup().await;
+    use_same_mint(&mut env).await;
+
+    let result = init_same_mint(&mut env, DEPOSIT_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+}
+
+async fn set_escrow_active(env: &mut TestEnv, active: bool) -> Result<(), TransportError> {
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.initializer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+        ],
+        data: EscrowInstruction::SetEscrowActive { active }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_inactive_escrow_refuses_trades_until_reactivated() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    set_escrow_active(&mut env, false).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::EscrowInactive);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+
+    set_escrow_active(&mut env, true).await.unwrap();
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_inactive_escrow_can_be_cancelled() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    set_escrow_active(&mut env, false).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_escrow() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let (temp_x, escrow) = (env.initializer_temp_x, env.escrow.pubkey());
+    assert!(!account_exists(&mut env.ctx, &temp_x).await);
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+}
+
+#[tokio::test]
+async fn test_cancel_escrow_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    let mint_x = env.mint_x;
+    let taker_refund_x = create_token_account(&mut env.ctx, &mint_x, &taker, 0).await;
+    let ix = cancel_ix(&env, &taker, &taker_refund_x);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_escrow_with_other_vault_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let (other_temp_x, _) = add_escrow(&mut env).await;
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    // the vault of another escrow with the same PDA
+    let mut ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    ix.accounts[1] = AccountMeta::new(other_temp_x, false);
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &other_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_escrow_of_other_program_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    // a token account in place of the escrow account
+    let mut ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    ix.accounts[3] = AccountMeta::new(env.initializer_receive_y, false);
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+}
+
+#[tokio::test]
+async fn test_refund_escrow_of_other_program_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 500, 0).await.unwrap();
+    env.ctx.warp_to_slot(501).unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(initializer, false),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RefundExpired.pack(),
+    };
+    let result = process(&mut env.ctx, &[ix], &[]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+}
+
+#[tokio::test]
+async fn test_refund_expired_with_fake_token_program_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 500, 0).await.unwrap();
+    env.ctx.warp_to_slot(501).unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    // anyone can crank the refund, a program of theirs must not get the PDA's signature
+    let fake_token_program = Pubkey::new_unique();
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(initializer, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(fake_token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RefundExpired.pack(),
+    };
+    let result = process(&mut env.ctx, &[ix], &[]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert!(account_exists(&mut env.ctx, &env.escrow.pubkey()).await);
+}
+
+#[tokio::test]
+async fn test_exchange_with_read_only_escrow_account_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts[6] = AccountMeta::new_readonly(env.escrow.pubkey(), false);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::AccountNotWritable);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_with_read_only_escrow_account_fails() {
+    let mut env = setup().await;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.accounts[3] = AccountMeta::new_readonly(env.escrow.pubkey(), false);
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::AccountNotWritable);
+}
+
+#[tokio::test]
+async fn test_init_sol_escrow_with_read_only_escrow_account_fails() {
+    let mut env = setup().await;
+    prepare_sol_escrow(&mut env, 0).await;
+    let mut ix = init_sol_escrow_ix(&env, EXPECTED_AMOUNT, 0, None);
+    ix.accounts[2] = AccountMeta::new_readonly(env.escrow.pubkey(), false);
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::AccountNotWritable);
+}
+
+async fn set_allowed_mint(
+    env: &mut TestEnv,
+    by_taker: bool,
+    mint: &Pubkey,
+    allowed: bool,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let data = if allowed {
+        EscrowInstruction::AddAllowedMint
+    } else {
+        EscrowInstruction::RemoveAllowedMint
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(*mint, false),
+        ],
+        data: data.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_init_escrow_of_unlisted_mint_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    let (mint_x, mint_y) = (env.mint_x, env.mint_y);
+    set_allowed_mint(&mut env, false, &mint_x, true)
+        .await
+        .unwrap();
+
+    // the payment mint isn't listed
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::MintNotAllowed);
+
+    set_allowed_mint(&mut env, false, &mint_y, true)
+        .await
+        .unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_emptied_allowed_mint_list_allows_every_mint() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    let other_mint = Pubkey::new_unique();
+    set_allowed_mint(&mut env, false, &other_mint, true)
+        .await
+        .unwrap();
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::MintNotAllowed);
+
+    set_allowed_mint(&mut env, false, &other_mint, false)
+        .await
+        .unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_add_allowed_mint_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+
+    let mint_x = env.mint_x;
+    let result = set_allowed_mint(&mut env, true, &mint_x, true).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+}
+
+/// Shrinks the config to the version 1 layout, as it was before the allowed mints were added
+async fn use_v1_config(env: &mut TestEnv) {
+    let config = config_pda(&env.program_id).0;
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(config)
+        .await
+        .unwrap()
+        .unwrap();
+    account.data.truncate(CONFIG_V1_LEN);
+    account.data[0] = 1;
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    account.lamports = rent.minimum_balance(CONFIG_V1_LEN);
+    env.ctx.set_account(&config, &account.into());
+}
+
+async fn migrate_config(env: &mut TestEnv) -> Result<(), TransportError> {
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::MigrateConfig.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_migrate_v1_config_to_list_allowed_mints() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    use_v1_config(&mut env).await;
+
+    // the admin instructions need the current layout
+    let mint_x = env.mint_x;
+    let result = set_allowed_mint(&mut env, false, &mint_x, true).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+
+    migrate_config(&mut env).await.unwrap();
+    let config = config_pda(&env.program_id).0;
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(config)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), Config::LEN);
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    assert!(rent.is_exempt(account.lamports, Config::LEN));
+
+    set_allowed_mint(&mut env, false, &mint_x, true)
+        .await
+        .unwrap();
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::MintNotAllowed);
+}
+
+#[tokio::test]
+async fn test_v1_config_allows_every_mint() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    use_v1_config(&mut env).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_config_by_other_signer_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    use_v1_config(&mut env).await;
+
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.taker.pubkey(), true),
+            AccountMeta::new(config_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::MigrateConfig.pack(),
+    };
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedSigner);
+}
+
+#[tokio::test]
+async fn test_exchange_into_send_account_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_send_y = env.taker_send_y;
+    let result = exchange(&mut env, &taker_send_y).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+/// Most escrows `ExchangeBatch` takes in one transaction, see its doc
+const MAX_EXCHANGE_BATCH: usize = 5;
+
+/// Compute units an instruction gets unless the transaction asks for more
+const DEFAULT_COMPUTE_BUDGET: u64 = 200_000;
+
+/// Initializes the escrow of `env` and `count - 1` more with the same terms, returns their temp
+/// token accounts and escrow accounts
+async fn init_escrows(env: &mut TestEnv, count: usize) -> Vec<(Pubkey, Pubkey)> {
+    init_escrow(env, EXPECTED_AMOUNT).await.unwrap();
+    let mut escrows = vec![(env.initializer_temp_x, env.escrow.pubkey())];
+    for _ in 1..count {
+        escrows.push(add_escrow(env).await);
+    }
+    escrows
+}
+
+fn exchange_batch_ix(env: &TestEnv, escrows: &[(Pubkey, Pubkey)]) -> Instruction {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(env.taker.pubkey(), true),
+        AccountMeta::new(env.taker_send_y, false),
+        AccountMeta::new(env.taker_receive_x, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(env.fee_collector_y, false),
+        AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+    ];
+    for (temp_x, escrow) in &escrows {
+        accounts.push(AccountMeta::new(*temp_x, false));
+        accounts.push(AccountMeta::new(*escrow, false));
+        accounts.push(AccountMeta::new(env.initializer.pubkey(), false));
+        accounts.push(AccountMeta::new(env.initializer_receive_y, false));
+        accounts.push(AccountMeta::new_readonly(pda, false));
+    }
+    Instruction {
+        program_id: env.program_id,
+        accounts,
+        data: EscrowInstruction::ExchangeBatch {
+            count: escrows.len() as u8,
+        }
+        .pack(),
+    }
+}
+
+/// Checks every escrow of `escrows` was taken in full and closed
+async fn assert_batch_taken(env: &mut TestEnv, escrows: &[(Pubkey, Pubkey)]) {
+    let count = escrows.len() as u64;
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT * count
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        (EXPECTED_AMOUNT - fee) * count
+    );
+    for (_, escrow) in escrows {
+        let escrow_account = env.ctx.banks_client.get_account(*escrow).await.unwrap();
+        assert!(escrow_account.is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_exchange_batch() {
+    let mut env = setup().await;
+    let escrows = init_escrows(&mut env, 3).await;
+
+    let ix = exchange_batch_ix(&env, &escrows);
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+    assert_batch_taken(&mut env, &escrows).await;
+}
+
+#[tokio::test]
+async fn test_exchange_batch_of_max_size_fits_compute_budget() {
+    let mut env = setup_with_compute_budget(&spl_token::id(), Some(DEFAULT_COMPUTE_BUDGET)).await;
+    let escrows = init_escrows(&mut env, MAX_EXCHANGE_BATCH).await;
+
+    let ix = exchange_batch_ix(&env, &escrows);
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+    assert_batch_taken(&mut env, &escrows).await;
+}
+
+#[tokio::test]
+async fn test_escrows_of_one_initializer_with_different_nonces() {
+    let mut env = setup().await;
+    let payer = env.ctx.payer.pubkey();
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+
+    let mut escrows = vec![];
+    for nonce in [1, 2] {
+        let temp_x =
+            create_token_account(&mut env.ctx, &mint_x, &initializer, DEPOSIT_AMOUNT).await;
+        let escrow = Keypair::new();
+        let mut init_ix = instruction::init_escrow(
+            &env.program_id,
+            &initializer,
+            &temp_x,
+            &env.initializer_receive_y,
+            &escrow.pubkey(),
+            &spl_token::id(),
+            EXPECTED_AMOUNT,
+        );
+        init_ix.data = EscrowInstruction::InitEscrow {
+            amount: EXPECTED_AMOUNT,
+            expiry_slot: 0,
+            expiry_unix_timestamp: 0,
+            allowed_taker: None,
+            two_phase: false,
+            min_taker_amount: 0,
+            nonce,
+            arbiter: None,
+            receipt: false,
+            position: false,
+            taker_merkle_root: None,
+            gate_mint: None,
+            gate_min_amount: 0,
+            gatekeeper_network: None,
+            memo_required: false,
+            royalty_policy: RoyaltyPolicy::None,
+            nft: false,
+            unwrap_sol: false,
+        }
+        .pack();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &escrow.pubkey(),
+                rent.minimum_balance(Escrow::LEN),
+                Escrow::LEN as u64,
+                &env.program_id,
+            ),
+            init_ix,
+        ];
+        process(&mut env.ctx, &instructions, &[&escrow, &env.initializer])
+            .await
+            .unwrap();
+
+        let (pda, _) = escrow_pda(&env.program_id, &initializer, nonce);
+        let temp_account = env.ctx.banks_client.get_account(temp_x).await.unwrap();
+        assert_eq!(
+            TokenAccount::unpack(&temp_account.unwrap().data)
+                .unwrap()
+                .owner,
+            pda
+        );
+        escrows.push((temp_x, escrow.pubkey(), pda));
+    }
+    assert_ne!(escrows[0].2, escrows[1].2);
+
+    // the first escrow is taken with its own PDA, the second one stays open
+    let (temp_x, escrow, pda) = escrows[0];
+    let ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &temp_x,
+        &initializer,
+        &env.initializer_receive_y,
+        &escrow,
+        1,
+        &env.mint_y,
+    );
+    assert_eq!(ix.accounts[8].pubkey, pda);
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &escrows[1].0).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_missing_accounts_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    // drops the config account, the log naming it is checked in tests/logs.rs
+    ix.accounts.pop();
+
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::NotEnoughAccountKeys)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_below_minimum_fails() {
+    let mut env = setup().await;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: DEPOSIT_AMOUNT / 2,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2 - 1, EXPECTED_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::BelowMinimum);
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT / 2
+    );
+}
+
+#[tokio::test]
+async fn test_init_with_zero_amount_fails() {
+    let mut env = setup().await;
+    let result = init_escrow(&mut env, 0).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+
+    // the escrow account is left uninitialized
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!Escrow::unpack_unchecked(&escrow_account.data)
+        .unwrap()
+        .is_initialized());
+}
+
+#[tokio::test]
+async fn test_init_with_empty_deposit_fails() {
+    let mut env = setup().await;
+    let mint_x = env.mint_x;
+    let initializer = env.initializer.pubkey();
+    env.initializer_temp_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+}
+
+#[tokio::test]
+async fn test_init_receiving_into_temp_account_fails() {
+    let mut env = setup().await;
+    env.initializer_receive_y = env.initializer_temp_x;
+
+    let result = init_escrow(&mut env, EXPECTED_AMOUNT).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+}
+
+#[tokio::test]
+async fn test_init_with_fake_token_program_fails() {
+    let mut env = setup().await;
+    let ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &Pubkey::new_unique(),
+        EXPECTED_AMOUNT,
+    );
+
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TokenProgramCase {
+    Cancel,
+    MutualCancel,
+    TopUp,
+    Split,
+    Merge,
+    ProposeCounterOffer,
+    AcceptCounterOffer,
+}
+
+/// Every handler that moves tokens must refuse a token program it doesn't support before handing
+/// it the PDA's signature
+#[tokio::test]
+async fn test_handlers_with_fake_token_program_fail() {
+    use TokenProgramCase::*;
+    for case in [
+        Cancel,
+        MutualCancel,
+        TopUp,
+        Split,
+        Merge,
+        ProposeCounterOffer,
+        AcceptCounterOffer,
+    ] {
+        let mut env = setup().await;
+        if let MutualCancel = case {
+            let taker = env.taker.pubkey();
+            init_escrow_for_taker(&mut env, taker).await;
+        } else {
+            init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+        }
+        if let AcceptCounterOffer = case {
+            propose_counter_offer(&mut env, EXPECTED_AMOUNT / 2)
+                .await
+                .unwrap();
+        }
+        let merged = match case {
+            Merge => Some(add_escrow(&mut env).await),
+            _ => None,
+        };
+        let initializer = env.initializer.pubkey();
+        let mint_x = env.mint_x;
+        let source_x =
+            create_token_account(&mut env.ctx, &mint_x, &initializer, DEPOSIT_AMOUNT).await;
+
+        env.token_program = Pubkey::new_unique();
+        let result = match case {
+            Cancel => {
+                let ix = cancel_ix(&env, &initializer, &source_x);
+                process(&mut env.ctx, &[ix], &[&env.initializer]).await
+            }
+            MutualCancel => mutual_cancel(&mut env, true, true).await.1,
+            TopUp => top_up(&mut env, false, &source_x, DEPOSIT_AMOUNT).await,
+            Split => split_escrow(&mut env, DEPOSIT_AMOUNT / 2).await.map(|_| ()),
+            Merge => merge_escrows(&mut env, merged.unwrap()).await,
+            ProposeCounterOffer => propose_counter_offer(&mut env, EXPECTED_AMOUNT / 2).await,
+            AcceptCounterOffer => accept_counter_offer(&mut env).await,
+        };
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId),
+            "{:?}",
+            case
+        );
+        let temp_x = env.initializer_temp_x;
+        assert_eq!(token_balance(&mut env.ctx, &temp_x).await, DEPOSIT_AMOUNT);
+    }
+}
+
+#[tokio::test]
+async fn test_init_without_rent_sysvar_account() {
+    let mut env = setup().await;
+    let ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    assert_eq!(ix.accounts.len(), 8);
+    assert!(ix
+        .accounts
+        .iter()
+        .all(|meta| meta.pubkey != solana_program::sysvar::rent::id()));
+
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.expected_amount, EXPECTED_AMOUNT);
+}
+
+async fn init_escrow_for_taker(env: &mut TestEnv, allowed_taker: Pubkey) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: Some(allowed_taker),
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+/// Sends a `MutualCancel` of the escrow of `env`, signed by the initializer and the taker as
+/// asked. Returns the initializer's token account the refund goes to along with the result
+async fn mutual_cancel(
+    env: &mut TestEnv,
+    initializer_signs: bool,
+    taker_signs: bool,
+) -> (Pubkey, Result<(), TransportError>) {
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(initializer, initializer_signs),
+            AccountMeta::new_readonly(env.taker.pubkey(), taker_signs),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::MutualCancel.pack(),
+    };
+    let mut signers = vec![];
+    if initializer_signs {
+        signers.push(&env.initializer);
+    }
+    if taker_signs {
+        signers.push(&env.taker);
+    }
+    let result = process(&mut env.ctx, &[ix], &signers).await;
+    (initializer_refund_x, result)
+}
+
+#[tokio::test]
+async fn test_mutual_cancel() {
+    let mut env = setup().await;
+    let taker = env.taker.pubkey();
+    init_escrow_for_taker(&mut env, taker).await;
+
+    let (initializer_refund_x, result) = mutual_cancel(&mut env, true, true).await;
+    result.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let escrow = env.escrow.pubkey();
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+}
+
+#[tokio::test]
+async fn test_mutual_cancel_without_initializer_signature_fails() {
+    let mut env = setup().await;
+    let taker = env.taker.pubkey();
+    init_escrow_for_taker(&mut env, taker).await;
+
+    let (_, result) = mutual_cancel(&mut env, false, true).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_mutual_cancel_without_taker_signature_fails() {
+    let mut env = setup().await;
+    let taker = env.taker.pubkey();
+    init_escrow_for_taker(&mut env, taker).await;
+
+    let (_, result) = mutual_cancel(&mut env, true, false).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_mutual_cancel_without_designated_taker_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let (_, result) = mutual_cancel(&mut env, true, true).await;
+    assert_escrow_error(result, EscrowError::NoDesignatedTaker);
+}
+
+#[tokio::test]
+async fn test_exchange_by_allowed_taker() {
+    let mut env = setup().await;
+    let taker = env.taker.pubkey();
+    init_escrow_for_taker(&mut env, taker).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_by_other_taker_fails() {
+    let mut env = setup().await;
+    init_escrow_for_taker(&mut env, Pubkey::new_unique()).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedTaker);
+}
+
+/// Funds `count` more temp accounts of the initializer, each with a new mint, for a basket
+/// deposit. Returns each temp account with a new empty taker account for the same mint
+async fn create_basket(env: &mut TestEnv, count: usize) -> Vec<(Pubkey, Pubkey)> {
+    let initializer = env.initializer.pubkey();
+    let taker = env.taker.pubkey();
+    let mut basket = Vec::new();
+    for _ in 0..count {
+        let mint = create_mint(&mut env.ctx).await;
+        let temp = create_token_account(&mut env.ctx, &mint, &initializer, DEPOSIT_AMOUNT).await;
+        let taker_receive = create_token_account(&mut env.ctx, &mint, &taker, 0).await;
+        basket.push((temp, taker_receive));
+    }
+    basket
+}
+
+async fn init_basket_escrow(
+    env: &mut TestEnv,
+    basket: &[(Pubkey, Pubkey)],
+) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    for (temp, _) in basket {
+        ix.accounts.push(AccountMeta::new(*temp, false));
+    }
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+async fn exchange_basket(
+    env: &mut TestEnv,
+    basket: &[(Pubkey, Pubkey)],
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    for (temp, taker_receive) in basket {
+        ix.accounts.push(AccountMeta::new(*temp, false));
+        ix.accounts.push(AccountMeta::new(*taker_receive, false));
+    }
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+/// Exchanges a basket of `size` deposited assets, temp_token_account included
+async fn assert_basket_exchange(size: usize) {
+    let mut env = setup().await;
+    let basket = create_basket(&mut env, size - 1).await;
+    init_basket_escrow(&mut env, &basket).await.unwrap();
+
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.basket_vaults().len(), size - 1);
+
+    exchange_basket(&mut env, &basket).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    for (temp, taker_receive) in &basket {
+        assert_eq!(
+            token_balance(&mut env.ctx, taker_receive).await,
+            DEPOSIT_AMOUNT
+        );
+        let temp_account = env.ctx.banks_client.get_account(*temp).await.unwrap();
+        assert!(temp_account.is_none());
+    }
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_basket_of_one() {
+    assert_basket_exchange(1).await;
+}
+
+#[tokio::test]
+async fn test_exchange_basket_of_two() {
+    assert_basket_exchange(2).await;
+}
+
+#[tokio::test]
+async fn test_exchange_basket_of_four() {
+    assert_basket_exchange(4).await;
+}
+
+#[tokio::test]
+async fn test_init_basket_of_five_fails() {
+    let mut env = setup().await;
+    let basket = create_basket(&mut env, 4).await;
+    let result = init_basket_escrow(&mut env, &basket).await;
+    assert_escrow_error(result, EscrowError::BasketTooLarge);
+}
+
+#[tokio::test]
+async fn test_exchange_basket_into_wrong_mint_fails() {
+    let mut env = setup().await;
+    let mut basket = create_basket(&mut env, 2).await;
+    init_basket_escrow(&mut env, &basket).await.unwrap();
+
+    // the second asset would be sent to the taker's account for the first one
+    basket[1].1 = basket[0].1;
+    let result = exchange_basket(&mut env, &basket).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+
+    let first_temp = basket[0].0;
+    assert_eq!(
+        token_balance(&mut env.ctx, &first_temp).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+/// Starts a basket payment asking for the expected amount of Y plus `leg_amount` of a new mint,
+/// funding the taker with `taker_leg_balance` of it. Returns the taker's and the initializer's
+/// accounts for the new mint
+async fn init_basket_payment(
+    env: &mut TestEnv,
+    leg_amount: u64,
+    taker_leg_balance: u64,
+) -> (Pubkey, Pubkey) {
+    let initializer = env.initializer.pubkey();
+    let taker = env.taker.pubkey();
+    let mint_z = create_mint(&mut env.ctx).await;
+    let taker_send_z = create_token_account(&mut env.ctx, &mint_z, &taker, taker_leg_balance).await;
+    let initializer_receive_z = create_token_account(&mut env.ctx, &mint_z, &initializer, 0).await;
+
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &initializer,
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitBasketPayment {
+        amounts: vec![EXPECTED_AMOUNT, leg_amount],
+    }
+    .pack();
+    ix.accounts
+        .push(AccountMeta::new_readonly(initializer_receive_z, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    (taker_send_z, initializer_receive_z)
+}
+
+async fn exchange_basket_payment(
+    env: &mut TestEnv,
+    taker_send_z: &Pubkey,
+    initializer_receive_z: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts.push(AccountMeta::new(*taker_send_z, false));
+    ix.accounts.push(AccountMeta::new(*initializer_receive_z, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_basket_payment() {
+    let mut env = setup().await;
+    let (taker_send_z, initializer_receive_z) = init_basket_payment(&mut env, 5, 5).await;
+
+    exchange_basket_payment(&mut env, &taker_send_z, &initializer_receive_z)
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &initializer_receive_z).await, 5);
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_z).await, 0);
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_basket_payment_with_short_leg_fails() {
+    let mut env = setup().await;
+    let (taker_send_z, initializer_receive_z) = init_basket_payment(&mut env, 5, 4).await;
+
+    let result = exchange_basket_payment(&mut env, &taker_send_z, &initializer_receive_z).await;
+    assert_escrow_error(result, EscrowError::InsufficientTakerBalance);
+
+    // nothing moved, the Y leg included
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_takes_what_is_left_in_the_vault() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 4, EXPECTED_AMOUNT / 4)
+        .await
+        .unwrap();
+
+    // the instruction only carries the tag and the max payment, the amount comes from the vault
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_exchange_returns_tokens_sent_to_the_vault_to_the_initializer() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // anyone can send dust to the vault, it's no part of the deposit
+    let ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &env.mint_x,
+        &env.initializer_temp_x,
+        &env.ctx.payer.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let initializer_temp_x = env.initializer_temp_x;
+    assert_eq!(token_balance(&mut env.ctx, &initializer_temp_x).await, 1);
+    assert_eq!(
+        token_owner(&mut env.ctx, &initializer_temp_x).await,
+        env.initializer.pubkey()
+    );
+    let escrow = env.escrow.pubkey();
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+}
+
+const SECRET: [u8; 32] = [7; 32];
+const TIMEOUT_SLOT: u64 = 1_000;
+
+async fn init_hash_lock(env: &mut TestEnv) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitHashLock {
+        amount: EXPECTED_AMOUNT,
+        hash: hash(&SECRET).to_bytes(),
+        timeout_slot: TIMEOUT_SLOT,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+async fn exchange_with_data(env: &mut TestEnv, data: Vec<u8>) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.data = data;
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_hash_lock_with_preimage() {
+    let mut env = setup().await;
+    init_hash_lock(&mut env).await;
+
+    let data = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: Some(SECRET),
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+    exchange_with_data(&mut env, data).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_hash_lock_with_wrong_preimage_fails() {
+    let mut env = setup().await;
+    init_hash_lock(&mut env).await;
+
+    for preimage in [None, Some([8; 32])] {
+        let data = EscrowInstruction::Exchange {
+            max_payment: 0,
+            preimage,
+            merkle_proof: Vec::new(),
+        }
+        .pack();
+        let result = exchange_with_data(&mut env, data).await;
+        assert_escrow_error(result, EscrowError::PreimageMismatch);
+    }
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_hash_lock_with_preimage() {
+    let mut env = setup().await;
+    init_hash_lock(&mut env).await;
+
+    for (preimage, error) in [
+        (None, Some(EscrowError::PreimageMismatch)),
+        (Some(SECRET), None),
+    ] {
+        let data = EscrowInstruction::SimulateExchange {
+            amount: 0,
+            preimage,
+            merkle_proof: Vec::new(),
+        }
+        .pack();
+        let result = exchange_with_data(&mut env, data).await;
+        match error {
+            Some(error) => assert_escrow_error(result, error),
+            None => result.unwrap(),
+        }
+    }
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_hash_lock_with_short_preimage_fails() {
+    let mut env = setup().await;
+    init_hash_lock(&mut env).await;
+
+    let mut data = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: Some(SECRET),
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+    data.truncate(data.len() - 12);
+    let result = exchange_with_data(&mut env, data).await;
+    assert_escrow_error(result, EscrowError::InvalidInstruction);
+}
+
+#[tokio::test]
+async fn test_hash_lock_refund_after_timeout() {
+    let mut env = setup().await;
+    init_hash_lock(&mut env).await;
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let cancel_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(initializer, true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::CancelEscrow.pack(),
+    };
+    let result = process(&mut env.ctx, &[cancel_ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::EscrowNotExpired);
+
+    env.ctx.warp_to_slot(TIMEOUT_SLOT + 1).unwrap();
+    let refund_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(initializer, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RefundExpired.pack(),
+    };
+    // anyone can crank the refund once the timeout has passed
+    process(&mut env.ctx, &[refund_ix], &[]).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+
+    let mut env = setup().await;
+    let initializer = env.initializer.pubkey();
+    init_sol_escrow(&mut env, SOL_AMOUNT, None, SOL_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    let lamports = |account: Option<solana_sdk::account::Account>| account.unwrap().lamports;
+    let initializer_before = lamports(env.ctx.banks_client.get_account(initializer).await.unwrap());
+    let escrow_rent = lamports(
+        env.ctx
+            .banks_client
+            .get_account(env.escrow.pubkey())
+            .await
+            .unwrap(),
+    );
+    let temp_rent = lamports(
+        env.ctx
+            .banks_client
+            .get_account(env.initializer_temp_x)
+            .await
+            .unwrap(),
+    );
+
+    exchange_for_sol(&mut env, None).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    // the fee is paid in wrapped SOL, the initializer gets the rest
+    let fee = SOL_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let initializer_after = lamports(env.ctx.banks_client.get_account(initializer).await.unwrap());
+    assert_eq!(
+        initializer_after,
+        initializer_before + SOL_AMOUNT - fee + escrow_rent + temp_rent
+    );
+    let fee_collector_sol = fee_collector_sol_account(&env);
+    assert_eq!(token_balance(&mut env.ctx, &fee_collector_sol).await, fee);
+}
+
+/// The fee collector's wrapped SOL account, which escrows paid in SOL send the fee to
+fn fee_collector_sol_account(env: &TestEnv) -> Pubkey {
+    get_associated_token_address(&fee_collector_pda(&env.program_id).0, &native_mint::id())
+}
+
+/// Starts an escrow paid `sol_amount` lamports into `destination`, the initializer's main account
+/// when it's not given, and funds the taker with `taker_lamports`
+async fn init_sol_escrow(
+    env: &mut TestEnv,
+    sol_amount: u64,
+    destination: Option<Pubkey>,
+    taker_lamports: u64,
+) -> Result<(), TransportError> {
+    prepare_sol_escrow(env, taker_lamports).await;
+    let init_ix = init_sol_escrow_ix(env, sol_amount, 0, destination);
+    process(&mut env.ctx, &[init_ix], &[&env.initializer]).await
+}
+
+async fn prepare_sol_escrow(env: &mut TestEnv, taker_lamports: u64) {
+    let taker = env.taker.pubkey();
+    let payer = env.ctx.payer.pubkey();
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(&payer, &taker, taker_lamports)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let fee_collector_sol = fee_collector_sol_account(env);
+    if !account_exists(&mut env.ctx, &fee_collector_sol).await {
+        add_native_mint(env);
+        create_fee_collector_account(&mut env.ctx, &env.program_id, &native_mint::id()).await;
+    }
+}
+
+fn init_sol_escrow_ix(
+    env: &TestEnv,
+    sol_amount: u64,
+    nonce: u64,
+    destination: Option<Pubkey>,
+) -> Instruction {
+    let mut init_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+            AccountMeta::new(fee_collector_pda(&env.program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::InitEscrowForSol { sol_amount, nonce }.pack(),
+    };
+    if let Some(destination) = destination {
+        init_ix
+            .accounts
+            .push(AccountMeta::new_readonly(destination, false));
+    }
+    init_ix
+}
+
+async fn exchange_for_sol(
+    env: &mut TestEnv,
+    destination: Option<Pubkey>,
+) -> Result<(), TransportError> {
+    let exchange_ix = exchange_for_sol_ix(env, destination).await;
+    process(&mut env.ctx, &[exchange_ix], &[&env.taker]).await
+}
+
+async fn exchange_for_sol_ix(env: &mut TestEnv, destination: Option<Pubkey>) -> Instruction {
+    let escrow = env.escrow.pubkey();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+    let nonce = Escrow::unpack(&account.unwrap().data).unwrap().nonce;
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), nonce);
+    let mut exchange_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(fee_collector_sol_account(env), false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::ExchangeForSol.pack(),
+    };
+    if let Some(destination) = destination {
+        exchange_ix
+            .accounts
+            .push(AccountMeta::new(destination, false));
+    }
+    exchange_ix
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_with_nonce() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+    const NONCE: u64 = 3;
+
+    let mut env = setup().await;
+    prepare_sol_escrow(&mut env, SOL_AMOUNT * 2).await;
+    let init_ix = init_sol_escrow_ix(&env, SOL_AMOUNT, NONCE, None);
+    process(&mut env.ctx, &[init_ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), NONCE);
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.initializer_temp_x).await,
+        pda
+    );
+
+    exchange_for_sol(&mut env, None).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_into_account_of_someone_else_fails() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+
+    let mut env = setup().await;
+    init_sol_escrow(&mut env, SOL_AMOUNT, None, SOL_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    let mint_x = env.mint_x;
+    let stranger = Pubkey::new_unique();
+    let stranger_receive_x = create_token_account(&mut env.ctx, &mint_x, &stranger, 0).await;
+
+    let mut ix = exchange_for_sol_ix(&mut env, None).await;
+    ix.accounts[1] = AccountMeta::new(stranger_receive_x, false);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::InvalidTakerReceiveAccount);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_with_read_only_escrow_account_fails() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+
+    let mut env = setup().await;
+    init_sol_escrow(&mut env, SOL_AMOUNT, None, SOL_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    let mut ix = exchange_for_sol_ix(&mut env, None).await;
+    ix.accounts[4] = AccountMeta::new_readonly(env.escrow.pubkey(), false);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::AccountNotWritable);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_by_underfunded_taker_fails() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+
+    let mut env = setup().await;
+    init_sol_escrow(&mut env, SOL_AMOUNT, None, SOL_AMOUNT / 2)
+        .await
+        .unwrap();
+
+    let result = exchange_for_sol(&mut env, None).await;
+    assert_escrow_error(result, EscrowError::InsufficientLamports);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_pays_destination() {
+    const SOL_AMOUNT: u64 = 500_000_000;
+
+    let mut env = setup().await;
+    let destination = Pubkey::new_unique();
+    init_sol_escrow(&mut env, SOL_AMOUNT, Some(destination), SOL_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    // the SOL has to go to the destination picked at init
+    let result = exchange_for_sol(&mut env, Some(Pubkey::new_unique())).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+
+    exchange_for_sol(&mut env, Some(destination)).await.unwrap();
+    assert_eq!(
+        env.ctx.banks_client.get_balance(destination).await.unwrap(),
+        SOL_AMOUNT - SOL_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_for_sol_leaving_destination_below_rent_fails() {
+    let mut env = setup().await;
+    let destination = Pubkey::new_unique();
+    init_sol_escrow(&mut env, 1_000, Some(destination), 1_000_000_000)
+        .await
+        .unwrap();
+
+    let result = exchange_for_sol(&mut env, Some(destination)).await;
+    assert_escrow_error(result, EscrowError::NotRentExempt);
+}
+
+#[tokio::test]
+async fn test_init_sol_escrow_pays_creation_fee() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    set_creation_fee(&mut env, false, CREATION_FEE)
+        .await
+        .unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let initializer_before = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    init_sol_escrow(&mut env, 1_000_000_000, None, 1_000_000_000)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap(),
+        initializer_before - CREATION_FEE
+    );
+    assert_eq!(
+        env.ctx
+            .banks_client
+            .get_balance(fee_collector)
+            .await
+            .unwrap(),
+        CREATION_FEE
+    );
+}
+
+#[tokio::test]
+async fn test_init_sol_escrow_of_unlisted_mint_fails() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    let mint_x = env.mint_x;
+    set_allowed_mint(&mut env, false, &mint_x, true)
+        .await
+        .unwrap();
+
+    // SOL isn't listed as the native mint
+    let result = init_sol_escrow(&mut env, 1_000_000_000, None, 1_000_000_000).await;
+    assert_escrow_error(result, EscrowError::MintNotAllowed);
+
+    set_allowed_mint(&mut env, false, &native_mint::id(), true)
+        .await
+        .unwrap();
+    init_sol_escrow(&mut env, 1_000_000_000, None, 1_000_000_000)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_paused_config_refuses_new_sol_escrows() {
+    let mut env = setup().await;
+    initialize_config(&mut env, 0, 0, vec![]).await.unwrap();
+    set_config(&mut env, false, true, None).await.unwrap();
+
+    let result = init_sol_escrow(&mut env, 1_000_000_000, None, 1_000_000_000).await;
+    assert_escrow_error(result, EscrowError::ProgramPaused);
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.initializer_temp_x).await,
+        env.initializer.pubkey()
+    );
+}
+
+/// Starts a two-phase escrow, settled by `arbiter` in a dispute when there is one
+async fn init_two_phase_escrow(env: &mut TestEnv, arbiter: Option<Pubkey>) {
+    let mut init_ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    init_ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: true,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[init_ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+async fn deposit_for_exchange(env: &mut TestEnv) -> Result<(), TransportError> {
+    let deposit_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new_readonly(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: EscrowInstruction::DepositForExchange.pack(),
+    };
+    process(&mut env.ctx, &[deposit_ix], &[&env.taker]).await
+}
+
+/// Starts a two-phase escrow settled by `arbiter` in a dispute and deposits the taker's payment.
+/// Returns an empty token account of the initializer for the deposited mint to refund to
+async fn init_arbitrated_escrow(env: &mut TestEnv, arbiter: Pubkey) -> Pubkey {
+    init_two_phase_escrow(env, Some(arbiter)).await;
+    deposit_for_exchange(env).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await
+}
+
+async fn raise_dispute(env: &mut TestEnv, by_taker: bool) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+        ],
+        data: EscrowInstruction::RaiseDispute.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+async fn resolve_dispute(
+    env: &mut TestEnv,
+    signer: &Keypair,
+    initializer_refund_x: &Pubkey,
+    release_to_taker: bool,
+) -> Result<(), TransportError> {
+    resolve_dispute_with_token_program(
+        env,
+        signer,
+        initializer_refund_x,
+        release_to_taker,
+        &spl_token::id(),
+    )
+    .await
+}
+
+async fn resolve_dispute_with_token_program(
+    env: &mut TestEnv,
+    signer: &Keypair,
+    initializer_refund_x: &Pubkey,
+    release_to_taker: bool,
+    token_program: &Pubkey,
+) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(*initializer_refund_x, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::ResolveDispute { release_to_taker }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+async fn token_owner(ctx: &mut ProgramTestContext, account: &Pubkey) -> Pubkey {
+    let account = ctx.banks_client.get_account(*account).await.unwrap();
+    TokenAccount::unpack(&account.unwrap().data).unwrap().owner
+}
+
+#[tokio::test]
+async fn test_resolve_dispute_releasing_to_taker() {
+    let mut env = setup().await;
+    let arbiter = Keypair::new();
+    let initializer_refund_x = init_arbitrated_escrow(&mut env, arbiter.pubkey()).await;
+    raise_dispute(&mut env, true).await.unwrap();
+
+    resolve_dispute(&mut env, &arbiter, &initializer_refund_x, true)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    assert_eq!(token_balance(&mut env.ctx, &initializer_refund_x).await, 0);
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.taker_send_y).await,
+        env.taker.pubkey()
+    );
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap();
+    assert!(escrow_account.is_none());
+}
+
+#[tokio::test]
+async fn test_resolve_dispute_refunding_initializer() {
+    let mut env = setup().await;
+    let arbiter = Keypair::new();
+    let initializer_refund_x = init_arbitrated_escrow(&mut env, arbiter.pubkey()).await;
+    raise_dispute(&mut env, false).await.unwrap();
+
+    // the escrow is frozen until the arbiter resolves it
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let reject_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.initializer.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RejectExchange.pack(),
+    };
+    let result = process(&mut env.ctx, &[reject_ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::EscrowDisputed);
+
+    resolve_dispute(&mut env, &arbiter, &initializer_refund_x, false)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.taker_send_y).await,
+        env.taker.pubkey()
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_resolve_dispute_by_non_arbiter_fails() {
+    let mut env = setup().await;
+    let initializer_refund_x = init_arbitrated_escrow(&mut env, Pubkey::new_unique()).await;
+    raise_dispute(&mut env, true).await.unwrap();
+
+    let stranger = Keypair::new();
+    let result = resolve_dispute(&mut env, &stranger, &initializer_refund_x, true).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedArbiter);
+}
+
+#[tokio::test]
+async fn test_resolve_dispute_with_fake_token_program_fails() {
+    let mut env = setup().await;
+    let arbiter = Keypair::new();
+    let initializer_refund_x = init_arbitrated_escrow(&mut env, arbiter.pubkey()).await;
+    raise_dispute(&mut env, true).await.unwrap();
+
+    // the arbiter picks a side, a program of theirs must not get both vaults
+    for release_to_taker in [true, false] {
+        let result = resolve_dispute_with_token_program(
+            &mut env,
+            &arbiter,
+            &initializer_refund_x,
+            release_to_taker,
+            &Pubkey::new_unique(),
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_raise_dispute_without_arbiter_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = raise_dispute(&mut env, false).await;
+    assert_escrow_error(result, EscrowError::NoArbiter);
+}
+
+const VESTING_CLIFF_SLOT: u64 = 100;
+const VESTING_END_SLOT: u64 = 1_000;
+
+/// Initializes a vesting escrow and exchanges it, returns the slot the deposit started vesting at
+async fn exchange_vesting_escrow(env: &mut TestEnv) -> u64 {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitVesting {
+        amount: EXPECTED_AMOUNT,
+        cliff_slot: VESTING_CLIFF_SLOT,
+        end_slot: VESTING_END_SLOT,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(env, &taker_receive_x).await.unwrap();
+
+    let escrow = env.escrow.pubkey();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+    Escrow::unpack(&account.unwrap().data)
+        .unwrap()
+        .vesting_start_slot
+}
+
+async fn claim_vested(env: &mut TestEnv) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::ClaimVested.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_vesting_escrow_pays_initializer_up_front() {
+    let mut env = setup().await;
+    exchange_vesting_escrow(&mut env).await;
+
+    let taker_send_y = env.taker_send_y;
+    let taker_receive_x = env.taker_receive_x;
+    let initializer_temp_x = env.initializer_temp_x;
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_y).await, 0);
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, 0);
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_claim_vested_before_cliff_fails() {
+    let mut env = setup().await;
+    exchange_vesting_escrow(&mut env).await;
+
+    let result = claim_vested(&mut env).await;
+    assert_escrow_error(result, EscrowError::NothingToClaim);
+}
+
+#[tokio::test]
+async fn test_claim_vested_partially_then_fully() {
+    let mut env = setup().await;
+    let start_slot = exchange_vesting_escrow(&mut env).await;
+
+    let claim_slot = 500;
+    env.ctx.warp_to_slot(claim_slot).unwrap();
+    claim_vested(&mut env).await.unwrap();
+
+    let vested = DEPOSIT_AMOUNT * (claim_slot - start_slot) / (VESTING_END_SLOT - start_slot);
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, vested);
+
+    let escrow = env.escrow.pubkey();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+    let escrow_info = Escrow::unpack(&account.unwrap().data).unwrap();
+    assert_eq!(escrow_info.vesting_claimed, vested);
+
+    env.ctx.warp_to_slot(VESTING_END_SLOT).unwrap();
+    claim_vested(&mut env).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let initializer_temp_x = env.initializer_temp_x;
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(initializer_temp_x)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_claim_vested_by_other_taker_fails() {
+    let mut env = setup().await;
+    exchange_vesting_escrow(&mut env).await;
+    env.ctx.warp_to_slot(VESTING_END_SLOT).unwrap();
+
+    env.taker = Keypair::new();
+    let result = claim_vested(&mut env).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedTaker);
+}
+
+async fn init_stream(env: &mut TestEnv) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitStream {
+        amount: EXPECTED_AMOUNT,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+async fn stream_payment(env: &mut TestEnv, amount: u64) -> Result<(), TransportError> {
+    let data = EscrowInstruction::StreamPayment { amount }.pack();
+    exchange_with_data(env, data).await
+}
+
+#[tokio::test]
+async fn test_stream_payments_release_deposit_proportionally() {
+    let mut env = setup().await;
+    init_stream(&mut env).await;
+    let taker_receive_x = env.taker_receive_x;
+
+    stream_payment(&mut env, 2_500).await.unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, 250);
+
+    // 5_833 paid so far unlocks 583.3 of the deposit, rounded down
+    stream_payment(&mut env, 3_333).await.unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, 583);
+
+    let escrow = env.escrow.pubkey();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+    let escrow_info = Escrow::unpack(&account.unwrap().data).unwrap();
+    assert_eq!(escrow_info.stream_paid, 5_833);
+    assert_eq!(escrow_info.stream_released, 583);
+    assert_eq!(escrow_info.remaining_deposit, DEPOSIT_AMOUNT - 583);
+}
+
+#[tokio::test]
+async fn test_last_stream_payment_is_clamped_and_closes_escrow() {
+    let mut env = setup().await;
+    init_stream(&mut env).await;
+
+    stream_payment(&mut env, 6_000).await.unwrap();
+    // only 4_000 is left to pay, the taker couldn't afford 6_000 more
+    stream_payment(&mut env, 6_000).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let taker_send_y = env.taker_send_y;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_y).await, 0);
+
+    let escrow = env.escrow.pubkey();
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_streamed_escrow_fails() {
+    let mut env = setup().await;
+    init_stream(&mut env).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::StreamOnly);
+}
+
+#[tokio::test]
+async fn test_cancel_stream_refunds_unreleased_deposit() {
+    let mut env = setup().await;
+    init_stream(&mut env).await;
+    stream_payment(&mut env, 2_500).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let cancel_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(initializer, true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::CancelEscrow.pack(),
+    };
+    process(&mut env.ctx, &[cancel_ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT - 250
+    );
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, 250);
+}
+
+const NO_PENALTY_AFTER_SLOT: u64 = 1_000;
+const CANCEL_PENALTY_LAMPORTS: u64 = 1_000_000;
+
+async fn init_with_cancel_penalty(
+    env: &mut TestEnv,
+    penalty_lamports: u64,
+    penalty_bps: u16,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitWithCancelPenalty {
+        amount: EXPECTED_AMOUNT,
+        no_penalty_after_slot: NO_PENALTY_AFTER_SLOT,
+        penalty_lamports,
+        penalty_bps,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+/// Cancels an escrow started with both a 10% and a lamport cancel penalty, returning the
+/// initializer's refund account and the fee collector's token X account
+async fn cancel_with_penalty(env: &mut TestEnv) -> (Pubkey, Pubkey) {
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let fee_collector_x = create_token_account(&mut env.ctx, &mint_x, &fee_collector, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let cancel_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(initializer, true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(fee_collector_x, false),
+            AccountMeta::new(fee_collector, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::CancelEscrow.pack(),
+    };
+    process(&mut env.ctx, &[cancel_ix], &[&env.initializer])
+        .await
+        .unwrap();
+    (initializer_refund_x, fee_collector_x)
+}
+
+#[tokio::test]
+async fn test_cancel_before_no_penalty_slot_pays_penalty() {
+    let mut env = setup().await;
+    init_with_cancel_penalty(&mut env, CANCEL_PENALTY_LAMPORTS, 1_000)
+        .await
+        .unwrap();
+
+    env.ctx.warp_to_slot(NO_PENALTY_AFTER_SLOT - 1).unwrap();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let (initializer_refund_x, fee_collector_x) = cancel_with_penalty(&mut env).await;
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT - DEPOSIT_AMOUNT / 10
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &fee_collector_x).await,
+        DEPOSIT_AMOUNT / 10
+    );
+    assert_eq!(
+        env.ctx
+            .banks_client
+            .get_balance(fee_collector)
+            .await
+            .unwrap(),
+        CANCEL_PENALTY_LAMPORTS
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_from_no_penalty_slot_is_free() {
+    let mut env = setup().await;
+    init_with_cancel_penalty(&mut env, CANCEL_PENALTY_LAMPORTS, 1_000)
+        .await
+        .unwrap();
+
+    env.ctx.warp_to_slot(NO_PENALTY_AFTER_SLOT).unwrap();
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let (initializer_refund_x, fee_collector_x) = cancel_with_penalty(&mut env).await;
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &fee_collector_x).await, 0);
+    assert_eq!(
+        env.ctx
+            .banks_client
+            .get_balance(fee_collector)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_init_with_invalid_cancel_penalty_fails() {
+    let mut env = setup().await;
+    let result = init_with_cancel_penalty(&mut env, 0, 0).await;
+    assert_escrow_error(result, EscrowError::InvalidCancelPenalty);
+
+    let result = init_with_cancel_penalty(&mut env, 0, 10_001).await;
+    assert_escrow_error(result, EscrowError::InvalidCancelPenalty);
+}
+
+async fn create_receipt_mint(ctx: &mut ProgramTestContext, authority: &Keypair) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &authority.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(ctx, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Initializes an escrow minting a receipt on exchange, returns the receipt mint
+async fn init_receipt_escrow(env: &mut TestEnv) -> Pubkey {
+    let receipt_mint = create_receipt_mint(&mut env.ctx, &env.initializer).await;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: true,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    ix.accounts.push(AccountMeta::new(receipt_mint, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    receipt_mint
+}
+
+async fn exchange_with_receipt(
+    env: &mut TestEnv,
+    receipt_mint: &Pubkey,
+    taker_receipt_account: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts.push(AccountMeta::new(*receipt_mint, false));
+    ix.accounts
+        .push(AccountMeta::new(*taker_receipt_account, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_mints_receipt_to_taker() {
+    let mut env = setup().await;
+    let receipt_mint = init_receipt_escrow(&mut env).await;
+
+    let taker = env.taker.pubkey();
+    let taker_receipt_account = create_token_account(&mut env.ctx, &receipt_mint, &taker, 0).await;
+    exchange_with_receipt(&mut env, &receipt_mint, &taker_receipt_account)
+        .await
+        .unwrap();
+
+    assert_eq!(token_balance(&mut env.ctx, &taker_receipt_account).await, 1);
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(receipt_mint)
+        .await
+        .unwrap();
+    let receipt_mint_info = Mint::unpack(&account.unwrap().data).unwrap();
+    assert_eq!(receipt_mint_info.supply, 1);
+    assert!(receipt_mint_info.mint_authority.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_with_forged_receipt_mint_fails() {
+    let mut env = setup().await;
+    init_receipt_escrow(&mut env).await;
+
+    let forged_mint = create_receipt_mint(&mut env.ctx, &env.taker).await;
+    let taker = env.taker.pubkey();
+    let taker_receipt_account = create_token_account(&mut env.ctx, &forged_mint, &taker, 0).await;
+    let result = exchange_with_receipt(&mut env, &forged_mint, &taker_receipt_account).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_without_receipt_accounts_fails() {
+    let mut env = setup().await;
+    init_receipt_escrow(&mut env).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::NotEnoughAccountKeys)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_into_account_of_someone_else_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mint_x = env.mint_x;
+    let stranger = Pubkey::new_unique();
+    let stranger_receive_x = create_token_account(&mut env.ctx, &mint_x, &stranger, 0).await;
+
+    let result = exchange(&mut env, &stranger_receive_x).await;
+    assert_escrow_error(result, EscrowError::InvalidTakerReceiveAccount);
+    let initializer_temp_x = env.initializer_temp_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_anyone_can_refund_expired_escrow() {
+    let mut env = setup().await;
+    let expiry_slot = 500;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let refund_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(initializer, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RefundExpired.pack(),
+    };
+    let result = process(&mut env.ctx, std::slice::from_ref(&refund_ix), &[]).await;
+    assert_escrow_error(result, EscrowError::EscrowNotExpired);
+
+    env.ctx.warp_to_slot(expiry_slot + 1).unwrap();
+    let escrow = env.escrow.pubkey();
+    let escrow_rent = env.ctx.banks_client.get_balance(escrow).await.unwrap();
+    let initializer_lamports = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    process(&mut env.ctx, &[refund_ix], &[]).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let initializer_temp_x = env.initializer_temp_x;
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(initializer_temp_x)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .is_none());
+    // the rent of the temp token account comes back on top of the escrow's
+    assert!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap()
+            > initializer_lamports + escrow_rent
+    );
+}
+
+/// Initializes an escrow minting its position token to the initializer, returns the position
+/// mint and the initializer's position token account
+async fn init_position_escrow(env: &mut TestEnv) -> (Pubkey, Pubkey) {
+    let position_mint = create_receipt_mint(&mut env.ctx, &env.initializer).await;
+    let initializer = env.initializer.pubkey();
+    let initializer_position =
+        create_token_account(&mut env.ctx, &position_mint, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &initializer,
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: true,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    ix.accounts.push(AccountMeta::new(position_mint, false));
+    ix.accounts
+        .push(AccountMeta::new(initializer_position, false));
+    ix.accounts.push(AccountMeta::new_readonly(pda, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    (position_mint, initializer_position)
+}
+
+/// Moves the position token to a new holder, returns the holder and their position token account
+async fn transfer_position(
+    env: &mut TestEnv,
+    position_mint: &Pubkey,
+    initializer_position: &Pubkey,
+) -> (Keypair, Pubkey) {
+    let holder = Keypair::new();
+    let holder_position =
+        create_token_account(&mut env.ctx, position_mint, &holder.pubkey(), 0).await;
+    let ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        initializer_position,
+        &holder_position,
+        &env.initializer.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    (holder, holder_position)
+}
+
+async fn exchange_position(
+    env: &mut TestEnv,
+    holder: &Pubkey,
+    holder_receive_y: &Pubkey,
+    holder_position: &Pubkey,
+) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        holder,
+        holder_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    // the PDA stays the one of the original initializer
+    ix.accounts[8] = AccountMeta::new_readonly(pda, false);
+    ix.accounts
+        .push(AccountMeta::new_readonly(*holder_position, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+fn cancel_position_ix(
+    env: &TestEnv,
+    signer: &Pubkey,
+    refund_x: &Pubkey,
+    holder_position: &Pubkey,
+    position_mint: &Pubkey,
+) -> Instruction {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(*signer, true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(*refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(*holder_position, false),
+            AccountMeta::new(*position_mint, false),
+        ],
+        data: EscrowInstruction::CancelEscrow.pack(),
+    }
+}
+
+async fn mint_supply(ctx: &mut ProgramTestContext, mint: &Pubkey) -> u64 {
+    let account = ctx.banks_client.get_account(*mint).await.unwrap();
+    Mint::unpack(&account.unwrap().data).unwrap().supply
+}
+
+#[tokio::test]
+async fn test_init_mints_position_token_to_initializer() {
+    let mut env = setup().await;
+    let (position_mint, initializer_position) = init_position_escrow(&mut env).await;
+
+    assert_eq!(token_balance(&mut env.ctx, &initializer_position).await, 1);
+    assert_eq!(mint_supply(&mut env.ctx, &position_mint).await, 1);
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(position_mint)
+        .await
+        .unwrap();
+    assert!(Mint::unpack(&account.unwrap().data)
+        .unwrap()
+        .mint_authority
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_pays_position_holder() {
+    let mut env = setup().await;
+    let (position_mint, initializer_position) = init_position_escrow(&mut env).await;
+    let (holder, holder_position) =
+        transfer_position(&mut env, &position_mint, &initializer_position).await;
+
+    let mint_y = env.mint_y;
+    let holder_receive_y = create_token_account(&mut env.ctx, &mint_y, &holder.pubkey(), 0).await;
+
+    // the initializer no longer holds the position, so they aren't paid anymore
+    let initializer = env.initializer.pubkey();
+    let initializer_receive_y = env.initializer_receive_y;
+    let result = exchange_position(
+        &mut env,
+        &initializer,
+        &initializer_receive_y,
+        &holder_position,
+    )
+    .await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+
+    exchange_position(
+        &mut env,
+        &holder.pubkey(),
+        &holder_receive_y,
+        &holder_position,
+    )
+    .await
+    .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &holder_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &initializer_receive_y).await, 0);
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    // the escrow's rent goes to the holder as well
+    assert!(
+        env.ctx
+            .banks_client
+            .get_balance(holder.pubkey())
+            .await
+            .unwrap()
+            > 0
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_forged_position_account_fails() {
+    let mut env = setup().await;
+    init_position_escrow(&mut env).await;
+
+    // a token of another mint doesn't prove holding the position
+    let forger = Pubkey::new_unique();
+    let forged_mint = create_mint(&mut env.ctx).await;
+    let forged_position = create_token_account(&mut env.ctx, &forged_mint, &forger, 1).await;
+    let mint_y = env.mint_y;
+    let forger_receive_y = create_token_account(&mut env.ctx, &mint_y, &forger, 0).await;
+
+    let result = exchange_position(&mut env, &forger, &forger_receive_y, &forged_position).await;
+    assert_escrow_error(result, EscrowError::InvalidPositionAccount);
+}
+
+#[tokio::test]
+async fn test_former_position_holder_cannot_manage_escrow() {
+    let mut env = setup().await;
+    let (position_mint, initializer_position) = init_position_escrow(&mut env).await;
+    let (_holder, holder_position) =
+        transfer_position(&mut env, &position_mint, &initializer_position).await;
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let cancel_ix = cancel_position_ix(
+        &env,
+        &initializer,
+        &initializer_refund_x,
+        &holder_position,
+        &position_mint,
+    );
+    let result = process(&mut env.ctx, &[cancel_ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+
+    // their own emptied position token account proves nothing either
+    let cancel_ix = cancel_position_ix(
+        &env,
+        &initializer,
+        &initializer_refund_x,
+        &initializer_position,
+        &position_mint,
+    );
+    let result = process(&mut env.ctx, &[cancel_ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::InvalidPositionAccount);
+
+    let update_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(initializer, true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(holder_position, false),
+        ],
+        data: EscrowInstruction::UpdateExpectedAmount { new_amount: 1 }.pack(),
+    };
+    let result = process(&mut env.ctx, &[update_ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+}
+
+#[tokio::test]
+async fn test_position_holder_updates_amount_and_cancels() {
+    let mut env = setup().await;
+    let (position_mint, initializer_position) = init_position_escrow(&mut env).await;
+    let (holder, holder_position) =
+        transfer_position(&mut env, &position_mint, &initializer_position).await;
+
+    let update_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(holder.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(holder_position, false),
+        ],
+        data: EscrowInstruction::UpdateExpectedAmount {
+            new_amount: EXPECTED_AMOUNT / 2,
+        }
+        .pack(),
+    };
+    process(&mut env.ctx, &[update_ix], &[&holder])
+        .await
+        .unwrap();
+    let escrow = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Escrow::unpack(&escrow.data).unwrap().expected_amount,
+        EXPECTED_AMOUNT / 2
+    );
+
+    let mint_x = env.mint_x;
+    let holder_refund_x = create_token_account(&mut env.ctx, &mint_x, &holder.pubkey(), 0).await;
+    let cancel_ix = cancel_position_ix(
+        &env,
+        &holder.pubkey(),
+        &holder_refund_x,
+        &holder_position,
+        &position_mint,
+    );
+    process(&mut env.ctx, &[cancel_ix], &[&holder])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &holder_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &holder_position).await, 0);
+    assert_eq!(mint_supply(&mut env.ctx, &position_mint).await, 0);
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_init_and_exchange_with_token_2022() {
+    let mut env = setup_with_token_program(&spl_token_2022::id()).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let initializer_receive_y = env.initializer_receive_y;
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    let initializer_temp_x = env.initializer_temp_x;
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(initializer_temp_x)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_with_other_token_program_than_the_deposit_fails() {
+    let mut env = setup_with_token_program(&spl_token_2022::id()).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the deposit is held by Token-2022, the classic token program can't move it
+    env.token_program = spl_token::id();
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+}
+
+/// Initializes a crowd fill of the deposit, returns the crowd vault
+async fn init_crowd_fill(env: &mut TestEnv, expiry_slot: u64) -> Pubkey {
+    let initializer = env.initializer.pubkey();
+    let mint_y = env.mint_y;
+    let crowd_vault = create_token_account(&mut env.ctx, &mint_y, &initializer, 0).await;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &initializer,
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitCrowdFill {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot,
+    }
+    .pack();
+    ix.accounts.push(AccountMeta::new(crowd_vault, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    crowd_vault
+}
+
+/// Creates a funded contributor holding `amount` of the expected token, returns the contributor
+/// and their token account
+async fn add_contributor(env: &mut TestEnv, amount: u64) -> (Keypair, Pubkey) {
+    let contributor = Keypair::new();
+    let payer = env.ctx.payer.pubkey();
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(
+            &payer,
+            &contributor.pubkey(),
+            100_000_000,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let mint_y = env.mint_y;
+    let contributor_y =
+        create_token_account(&mut env.ctx, &mint_y, &contributor.pubkey(), amount).await;
+    (contributor, contributor_y)
+}
+
+async fn contribute(
+    env: &mut TestEnv,
+    crowd_vault: &Pubkey,
+    contributor: &Keypair,
+    contributor_y: &Pubkey,
+    amount: u64,
+) -> Result<(), TransportError> {
+    let (contribution, _) =
+        contribution_pda(&env.program_id, &env.escrow.pubkey(), &contributor.pubkey());
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(contributor.pubkey(), true),
+            AccountMeta::new(*contributor_y, false),
+            AccountMeta::new(contribution, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(*crowd_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: EscrowInstruction::Contribute { amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[contributor]).await
+}
+
+async fn settle(env: &mut TestEnv, crowd_vault: &Pubkey) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(*crowd_vault, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::Settle.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[]).await
+}
+
+async fn claim_share(env: &mut TestEnv, contributor: &Keypair) -> Result<Pubkey, TransportError> {
+    let mint_x = env.mint_x;
+    let contributor_x = create_token_account(&mut env.ctx, &mint_x, &contributor.pubkey(), 0).await;
+    let (contribution, _) =
+        contribution_pda(&env.program_id, &env.escrow.pubkey(), &contributor.pubkey());
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(contributor.pubkey(), true),
+            AccountMeta::new(contributor_x, false),
+            AccountMeta::new(contribution, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::ClaimShare.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[contributor]).await?;
+    Ok(contributor_x)
+}
+
+async fn refund_contribution(
+    env: &mut TestEnv,
+    crowd_vault: &Pubkey,
+    contributor: &Keypair,
+    contributor_y: &Pubkey,
+) -> Result<(), TransportError> {
+    let (contribution, _) =
+        contribution_pda(&env.program_id, &env.escrow.pubkey(), &contributor.pubkey());
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(contributor.pubkey(), true),
+            AccountMeta::new(*contributor_y, false),
+            AccountMeta::new(contribution, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(*crowd_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+        ],
+        data: EscrowInstruction::RefundContribution.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[contributor]).await
+}
+
+async fn account_exists(ctx: &mut ProgramTestContext, account: &Pubkey) -> bool {
+    ctx.banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+#[tokio::test]
+async fn test_crowd_fill_settles_and_shares_the_deposit() {
+    let mut env = setup().await;
+    let crowd_vault = init_crowd_fill(&mut env, 0).await;
+    let (first, first_y) = add_contributor(&mut env, 2_500).await;
+    let (second, second_y) = add_contributor(&mut env, EXPECTED_AMOUNT).await;
+
+    contribute(&mut env, &crowd_vault, &first, &first_y, 1_000)
+        .await
+        .unwrap();
+    contribute(&mut env, &crowd_vault, &first, &first_y, 1_500)
+        .await
+        .unwrap();
+    assert_escrow_error(
+        settle(&mut env, &crowd_vault).await,
+        EscrowError::CrowdNotFilled,
+    );
+
+    // the second contribution only pays what is left
+    contribute(&mut env, &crowd_vault, &second, &second_y, EXPECTED_AMOUNT)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &second_y).await, 2_500);
+    let result = contribute(&mut env, &crowd_vault, &first, &first_y, 1).await;
+    assert_escrow_error(result, EscrowError::CrowdFilled);
+
+    settle(&mut env, &crowd_vault).await.unwrap();
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let initializer_receive_y = env.initializer_receive_y;
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert!(!account_exists(&mut env.ctx, &crowd_vault).await);
+
+    let first_x = claim_share(&mut env, &first).await.unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &first_x).await, 250);
+    let escrow = env.escrow.pubkey();
+    assert!(account_exists(&mut env.ctx, &escrow).await);
+
+    let second_x = claim_share(&mut env, &second).await.unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &second_x).await, 750);
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+    let initializer_temp_x = env.initializer_temp_x;
+    assert!(!account_exists(&mut env.ctx, &initializer_temp_x).await);
+}
+
+#[tokio::test]
+async fn test_claim_share_before_settle_fails() {
+    let mut env = setup().await;
+    let crowd_vault = init_crowd_fill(&mut env, 0).await;
+    let (contributor, contributor_y) = add_contributor(&mut env, EXPECTED_AMOUNT).await;
+    contribute(
+        &mut env,
+        &crowd_vault,
+        &contributor,
+        &contributor_y,
+        EXPECTED_AMOUNT,
+    )
+    .await
+    .unwrap();
+
+    let result = claim_share(&mut env, &contributor).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::CrowdNotFilled);
+}
+
+#[tokio::test]
+async fn test_exchange_crowd_fill_fails() {
+    let mut env = setup().await;
+    init_crowd_fill(&mut env, 0).await;
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::CrowdFillOnly);
+}
+
+#[tokio::test]
+async fn test_contributions_to_expired_crowd_fill_are_refunded() {
+    let mut env = setup().await;
+    let expiry_slot = 500;
+    let crowd_vault = init_crowd_fill(&mut env, expiry_slot).await;
+    let (first, first_y) = add_contributor(&mut env, 2_500).await;
+    let (second, second_y) = add_contributor(&mut env, 2_500).await;
+    contribute(&mut env, &crowd_vault, &first, &first_y, 2_500)
+        .await
+        .unwrap();
+    contribute(&mut env, &crowd_vault, &second, &second_y, 2_500)
+        .await
+        .unwrap();
+
+    // the crowd fill can still fill up
+    let result = refund_contribution(&mut env, &crowd_vault, &first, &first_y).await;
+    assert_escrow_error(result, EscrowError::EscrowNotExpired);
+
+    env.ctx.warp_to_slot(expiry_slot + 1).unwrap();
+    refund_contribution(&mut env, &crowd_vault, &first, &first_y)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &first_y).await, 2_500);
+
+    // the deposit goes back to the initializer while a contribution is still in the vault
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let (pda, _) = escrow_pda(&env.program_id, &initializer, 0);
+    let refund_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(initializer_refund_x, false),
+            AccountMeta::new(initializer, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(crowd_vault, false),
+        ],
+        data: EscrowInstruction::RefundExpired.pack(),
+    };
+    process(&mut env.ctx, &[refund_ix], &[]).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert!(account_exists(&mut env.ctx, &crowd_vault).await);
+
+    // the last refund closes the vault
+    refund_contribution(&mut env, &crowd_vault, &second, &second_y)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &second_y).await, 2_500);
+    assert!(!account_exists(&mut env.ctx, &crowd_vault).await);
+}
+
+#[tokio::test]
+async fn test_refund_contribution_of_filled_crowd_fill_fails() {
+    let mut env = setup().await;
+    let expiry_slot = 500;
+    let crowd_vault = init_crowd_fill(&mut env, expiry_slot).await;
+    let (contributor, contributor_y) = add_contributor(&mut env, EXPECTED_AMOUNT).await;
+    contribute(
+        &mut env,
+        &crowd_vault,
+        &contributor,
+        &contributor_y,
+        EXPECTED_AMOUNT,
+    )
+    .await
+    .unwrap();
+
+    // once filled it is settled, even after it expired
+    env.ctx.warp_to_slot(expiry_slot + 1).unwrap();
+    let result = refund_contribution(&mut env, &crowd_vault, &contributor, &contributor_y).await;
+    assert_escrow_error(result, EscrowError::CrowdFilled);
+    settle(&mut env, &crowd_vault).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_partial_exchange_unlocks_escrow() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.state, EscrowState::Active);
+}
+
+#[tokio::test]
+async fn test_sequential_partial_exchanges() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let fee = |payment: u64| payment * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let fills = [
+        (DEPOSIT_AMOUNT / 4, EXPECTED_AMOUNT / 4),
+        (DEPOSIT_AMOUNT / 4, EXPECTED_AMOUNT / 4),
+        (DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2),
+    ];
+    let (mut taken, mut paid, mut fees) = (0, 0, 0);
+    for (index, (amount_to_take, amount_to_give)) in fills.iter().enumerate() {
+        partial_exchange(&mut env, *amount_to_take, *amount_to_give)
+            .await
+            .unwrap();
+        taken += amount_to_take;
+        paid += amount_to_give;
+        fees += fee(*amount_to_give);
+
+        assert_eq!(
+            token_balance(&mut env.ctx, &env.taker_receive_x).await,
+            taken
+        );
+        assert_eq!(
+            token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+            paid - fees
+        );
+        let escrow_account = env
+            .ctx
+            .banks_client
+            .get_account(env.escrow.pubkey())
+            .await
+            .unwrap();
+        // the last fill takes what is left and closes the escrow
+        if index + 1 == fills.len() {
+            assert!(escrow_account.is_none());
+        } else {
+            let escrow_info = Escrow::unpack(&escrow_account.unwrap().data).unwrap();
+            assert_eq!(escrow_info.remaining_deposit, DEPOSIT_AMOUNT - taken);
+            assert_eq!(escrow_info.remaining_expected, EXPECTED_AMOUNT - paid);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_partial_exchange_over_remaining_deposit_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+
+    // the vault only holds what is left of the deposit
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT, EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::ExpectedAmountMissmatch);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT / 2
+    );
+}
+
+#[tokio::test]
+async fn test_partial_exchange_below_pro_rata_share_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2 - 1).await;
+    assert_escrow_error(result, EscrowError::ExpectedAmountMissmatch);
+
+    // exactly the share is enough
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT / 2
+    );
+}
+
+#[tokio::test]
+async fn test_partial_exchange_above_pro_rata_share() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 4, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT / 2 * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT / 2 - fee
+    );
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.remaining_deposit, DEPOSIT_AMOUNT * 3 / 4);
+    assert_eq!(escrow_info.remaining_expected, EXPECTED_AMOUNT / 2);
+}
+
+#[tokio::test]
+async fn test_partial_exchange_paying_over_remaining_expected() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    update_expected_amount(&mut env, false, EXPECTED_AMOUNT / 10)
+        .await
+        .unwrap();
+
+    // pays more than the whole expected amount for half the deposit
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.remaining_deposit, DEPOSIT_AMOUNT / 2);
+    assert_eq!(escrow_info.remaining_expected, 0);
+
+    // nothing is left to expect, the rest goes for any payment
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert!(!account_exists(&mut env.ctx, &env.escrow.pubkey()).await);
+}
+
+async fn escrow_sequence(env: &mut TestEnv) -> u64 {
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    Escrow::unpack(&escrow_account.data).unwrap().sequence
+}
+
+#[tokio::test]
+async fn test_partial_exchanges_advance_sequence() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    assert_eq!(escrow_sequence(&mut env).await, 0);
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 4, EXPECTED_AMOUNT / 4)
+        .await
+        .unwrap();
+    assert_eq!(escrow_sequence(&mut env).await, 1);
+
+    partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    assert_eq!(escrow_sequence(&mut env).await, 2);
+}
+
+#[tokio::test]
+async fn test_exchange_of_locked_escrow_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // an exchange nested in a CPI of another exchange finds the escrow in this state
+    let mut escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    escrow_info.state = EscrowState::Locked;
+    Escrow::pack(escrow_info, &mut escrow_account.data).unwrap();
+    env.ctx
+        .set_account(&env.escrow.pubkey(), &escrow_account.into());
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::EscrowLocked);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+/// Initializes an escrow whose allowlist holds the taker and three other keys, returns the
+/// taker's proof
+async fn init_escrow_for_allowlist(env: &mut TestEnv) -> Vec<[u8; 32]> {
+    let others: Vec<[u8; 32]> = (0..3)
+        .map(|_| merkle::leaf(&Pubkey::new_unique()))
+        .collect();
+    let taker_leaf = merkle::leaf(&env.taker.pubkey());
+    let sibling_node = merkle::node(&others[1], &others[2]);
+    let root = merkle::node(&merkle::node(&taker_leaf, &others[0]), &sibling_node);
+
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: Some(root),
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    vec![others[0], sibling_node]
+}
+
+#[tokio::test]
+async fn test_exchange_by_taker_on_allowlist() {
+    let mut env = setup().await;
+    let merkle_proof = init_escrow_for_allowlist(&mut env).await;
+
+    let data = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: None,
+        merkle_proof,
+    }
+    .pack();
+    exchange_with_data(&mut env, data).await.unwrap();
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_by_taker_on_allowlist() {
+    let mut env = setup().await;
+    let merkle_proof = init_escrow_for_allowlist(&mut env).await;
+
+    let data = EscrowInstruction::SimulateExchange {
+        amount: 0,
+        preimage: None,
+        merkle_proof,
+    }
+    .pack();
+    exchange_with_data(&mut env, data).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_by_taker_off_allowlist_fails() {
+    let mut env = setup().await;
+    let mut merkle_proof = init_escrow_for_allowlist(&mut env).await;
+
+    // a proof for another leaf doesn't lead to the root from the taker's
+    merkle_proof[0] = merkle::leaf(&Pubkey::new_unique());
+    for merkle_proof in [Vec::new(), merkle_proof] {
+        let data = EscrowInstruction::Exchange {
+            max_payment: 0,
+            preimage: None,
+            merkle_proof,
+        }
+        .pack();
+        let result = exchange_with_data(&mut env, data).await;
+        assert_escrow_error(result, EscrowError::UnauthorizedTaker);
+    }
+
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedTaker);
+}
+
+async fn transfer_initializer(
+    env: &mut TestEnv,
+    by_taker: bool,
+    new_initializer: &Pubkey,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(*new_initializer, false),
+        ],
+        data: EscrowInstruction::TransferInitializer.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+fn cancel_ix(env: &TestEnv, initializer: &Pubkey, initializer_refund_x: &Pubkey) -> Instruction {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(*initializer_refund_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::CancelEscrow.pack(),
+    }
+}
+
+#[tokio::test]
+async fn test_transferred_escrow_is_cancelled_by_new_initializer() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let new_initializer = Keypair::new();
+    transfer_initializer(&mut env, false, &new_initializer.pubkey())
+        .await
+        .unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let initializer_refund_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+
+    // the PDA derived from the first initializer still owns the deposit
+    let new_initializer_refund_x =
+        create_token_account(&mut env.ctx, &mint_x, &new_initializer.pubkey(), 0).await;
+    let ix = cancel_ix(&env, &new_initializer.pubkey(), &new_initializer_refund_x);
+    process(&mut env.ctx, &[ix], &[&new_initializer])
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &new_initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+    let escrow = env.escrow.pubkey();
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+}
+
+#[tokio::test]
+async fn test_transfer_initializer_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    let result = transfer_initializer(&mut env, true, &taker).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.initializer_pubkey, env.initializer.pubkey());
+}
+
+const GATE_MIN_AMOUNT: u64 = 100;
+
+/// Initializes an escrow gated by a new mint, returns the gate mint
+async fn init_gated_escrow(env: &mut TestEnv) -> Pubkey {
+    let gate_mint = create_mint(&mut env.ctx).await;
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: Some(gate_mint),
+        gate_min_amount: GATE_MIN_AMOUNT,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    gate_mint
+}
+
+async fn exchange_gated(
+    env: &mut TestEnv,
+    taker_gate_account: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts
+        .push(AccountMeta::new_readonly(*taker_gate_account, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_by_gate_mint_holder() {
+    let mut env = setup().await;
+    let gate_mint = init_gated_escrow(&mut env).await;
+    let taker = env.taker.pubkey();
+    let taker_gate_account =
+        create_token_account(&mut env.ctx, &gate_mint, &taker, GATE_MIN_AMOUNT).await;
+
+    exchange_gated(&mut env, &taker_gate_account).await.unwrap();
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    // the gate balance is only checked
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_gate_account).await,
+        GATE_MIN_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_by_insufficient_gate_mint_holder_fails() {
+    let mut env = setup().await;
+    let gate_mint = init_gated_escrow(&mut env).await;
+    let taker = env.taker.pubkey();
+    let taker_gate_account =
+        create_token_account(&mut env.ctx, &gate_mint, &taker, GATE_MIN_AMOUNT - 1).await;
+
+    let result = exchange_gated(&mut env, &taker_gate_account).await;
+    assert_escrow_error(result, EscrowError::GateRequirementNotMet);
+
+    // nor can someone else's balance be borrowed
+    let initializer = env.initializer.pubkey();
+    let initializer_gate_account =
+        create_token_account(&mut env.ctx, &gate_mint, &initializer, GATE_MIN_AMOUNT).await;
+    let result = exchange_gated(&mut env, &initializer_gate_account).await;
+    assert_escrow_error(result, EscrowError::GateRequirementNotMet);
+}
+
+#[tokio::test]
+async fn test_exchange_with_account_of_other_mint_than_gate_fails() {
+    let mut env = setup().await;
+    init_gated_escrow(&mut env).await;
+
+    // holds far more than the minimum, but of the expected mint
+    let taker_send_y = env.taker_send_y;
+    let result = exchange_gated(&mut env, &taker_send_y).await;
+    assert_escrow_error(result, EscrowError::GateRequirementNotMet);
+}
+
+/// Initializes an escrow for takers of a new gatekeeper network, returns the network
+async fn init_gatekeeper_escrow(env: &mut TestEnv) -> Pubkey {
+    let gatekeeper_network = Pubkey::new_unique();
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: Some(gatekeeper_network),
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+    gatekeeper_network
+}
+
+/// Adds an active gateway token account of `owner_wallet` owned by `program`
+fn add_gateway_token(
+    env: &mut TestEnv,
+    program: &Pubkey,
+    owner_wallet: &Pubkey,
+    gatekeeper_network: &Pubkey,
+    expire_time: i64,
+) -> Pubkey {
+    let mut data = vec![0, 0];
+    data.extend_from_slice(owner_wallet.as_ref());
+    data.push(0);
+    data.extend_from_slice(gatekeeper_network.as_ref());
+    data.extend_from_slice(&[5; 32]);
+    data.push(0);
+    data.push(1);
+    data.extend_from_slice(&expire_time.to_le_bytes());
+
+    let gateway_token = Pubkey::new_unique();
+    let account = Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: *program,
+        ..Account::default()
+    };
+    env.ctx.set_account(&gateway_token, &account.into());
+    gateway_token
+}
+
+async fn exchange_with_gateway_token(
+    env: &mut TestEnv,
+    gateway_token: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts
+        .push(AccountMeta::new_readonly(*gateway_token, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_with_gateway_token() {
+    let mut env = setup().await;
+    let gatekeeper_network = init_gatekeeper_escrow(&mut env).await;
+    let taker = env.taker.pubkey();
+    let gateway_token = add_gateway_token(
+        &mut env,
+        &gateway_program::id(),
+        &taker,
+        &gatekeeper_network,
+        i64::MAX,
+    );
+
+    exchange_with_gateway_token(&mut env, &gateway_token)
+        .await
+        .unwrap();
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_invalid_gateway_token_fails() {
+    let mut env = setup().await;
+    let gatekeeper_network = init_gatekeeper_escrow(&mut env).await;
+    let taker = env.taker.pubkey();
+    let initializer = env.initializer.pubkey();
+
+    let cases = [
+        (
+            Pubkey::new_unique(),
+            taker,
+            gatekeeper_network,
+            i64::MAX,
+            EscrowError::GatewayTokenMissing,
+        ),
+        (
+            gateway_program::id(),
+            taker,
+            gatekeeper_network,
+            1,
+            EscrowError::GatewayTokenExpired,
+        ),
+        (
+            gateway_program::id(),
+            initializer,
+            gatekeeper_network,
+            i64::MAX,
+            EscrowError::GatewayTokenMismatch,
+        ),
+        (
+            gateway_program::id(),
+            taker,
+            Pubkey::new_unique(),
+            i64::MAX,
+            EscrowError::GatewayTokenMismatch,
+        ),
+    ];
+    for (program, owner_wallet, network, expire_time, error) in cases {
+        let gateway_token =
+            add_gateway_token(&mut env, &program, &owner_wallet, &network, expire_time);
+        let result = exchange_with_gateway_token(&mut env, &gateway_token).await;
+        assert_escrow_error(result, error);
+    }
+}
+
+#[tokio::test]
+async fn test_exchange_of_uninitialized_escrow_fails() {
+    let mut env = setup().await;
+
+    // a zeroed account of the program, as left by creating it without InitEscrow
+    let escrow_account = Account {
+        lamports: 1_000_000_000,
+        data: vec![0; Escrow::LEN],
+        owner: env.program_id,
+        ..Account::default()
+    };
+    env.ctx
+        .set_account(&env.escrow.pubkey(), &escrow_account.into());
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::EscrowNotInitialized);
+}
+
+async fn init_memo_escrow(env: &mut TestEnv) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: true,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+/// Exchanges in a transaction starting with the given instructions
+async fn exchange_after(
+    env: &mut TestEnv,
+    instructions: &[Instruction],
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts
+        .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    let mut instructions = instructions.to_vec();
+    instructions.push(ix);
+    process(&mut env.ctx, &instructions, &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_with_memo() {
+    let mut env = setup().await;
+    init_memo_escrow(&mut env).await;
+
+    let memo_ix = Instruction {
+        program_id: spl_memo::id(),
+        accounts: vec![],
+        data: b"INV-2026-0042".to_vec(),
+    };
+    exchange_after(&mut env, &[memo_ix]).await.unwrap();
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_without_memo_fails() {
+    let mut env = setup().await;
+    init_memo_escrow(&mut env).await;
+
+    let result = exchange_after(&mut env, &[]).await;
+    assert_escrow_error(result, EscrowError::MemoRequired);
+
+    // the memo can't be left out by taking part of the deposit either
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2).await;
+    assert!(result.is_err());
+}
+
+/// Starts a split payment giving the initializer's receiving account the first share and a
+/// new account of the co-owner each further one. Returns the co-owners' receiving accounts
+async fn init_split_payment(
+    env: &mut TestEnv,
+    basis_points: Vec<u16>,
+) -> Result<Vec<Pubkey>, TransportError> {
+    let mut co_owner_accounts = Vec::new();
+    for _ in 1..basis_points.len() {
+        let co_owner = Pubkey::new_unique();
+        let mint_y = env.mint_y;
+        co_owner_accounts.push(create_token_account(&mut env.ctx, &mint_y, &co_owner, 0).await);
+    }
+
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitSplitPayment {
+        amount: EXPECTED_AMOUNT,
+        basis_points,
+    }
+    .pack();
+    for account in &co_owner_accounts {
+        ix.accounts.push(AccountMeta::new_readonly(*account, false));
+    }
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await?;
+    Ok(co_owner_accounts)
+}
+
+async fn exchange_split_payment(
+    env: &mut TestEnv,
+    co_owner_accounts: &[Pubkey],
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    for account in co_owner_accounts {
+        ix.accounts.push(AccountMeta::new(*account, false));
+    }
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_split_payment() {
+    let mut env = setup().await;
+    let co_owner_accounts = init_split_payment(&mut env, vec![7_000, 3_000])
+        .await
+        .unwrap();
+
+    exchange_split_payment(&mut env, &co_owner_accounts)
+        .await
+        .unwrap();
+
+    // 30% of what is left after the fee rounds down, the initializer gets the remainder
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let co_owner_share = (EXPECTED_AMOUNT - fee) * 3_000 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &co_owner_accounts[0]).await,
+        co_owner_share
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee - co_owner_share
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_init_split_payment_not_adding_up_fails() {
+    let mut env = setup().await;
+
+    let result = init_split_payment(&mut env, vec![7_000, 2_000]).await;
+    assert_escrow_error(result.map(|_| ()), EscrowError::InvalidPaymentSplit);
+
+    let result = init_split_payment(&mut env, vec![2_500, 2_500, 2_500, 2_000, 500]).await;
+    assert_escrow_error(result.map(|_| ()), EscrowError::InvalidPaymentSplit);
+}
+
+#[tokio::test]
+async fn test_exchange_split_payment_with_wrong_recipient_fails() {
+    let mut env = setup().await;
+    init_split_payment(&mut env, vec![7_000, 3_000])
+        .await
+        .unwrap();
+
+    // the taker can't send the co-owner's share to an account of their choosing
+    let taker = env.taker.pubkey();
+    let mint_y = env.mint_y;
+    let taker_receive_y = create_token_account(&mut env.ctx, &mint_y, &taker, 0).await;
+    let result = exchange_split_payment(&mut env, &[taker_receive_y]).await;
+    assert!(result.is_err());
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_y).await, 0);
+
+    // and can't take only part of the deposit
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::InvalidFillAmount);
+}
+
+async fn exchange_with_ata_creation(
+    env: &mut TestEnv,
+    taker_receive_x: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.data = EscrowInstruction::ExchangeWithAtaCreation {
+        max_payment: 0,
+        preimage: None,
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+    // the taker pays the rent of their new account
+    ix.accounts[0].is_writable = true;
+    let mut accounts = vec![
+        AccountMeta::new_readonly(env.mint_x, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    accounts.append(&mut ix.accounts);
+    ix.accounts = accounts;
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_creates_taker_ata() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the taker has no account for the deposit mint yet
+    let taker_ata = get_associated_token_address(&env.taker.pubkey(), &env.mint_x);
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(taker_ata)
+        .await
+        .unwrap()
+        .is_none());
+
+    exchange_with_ata_creation(&mut env, &taker_ata)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_ata).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_exchange_with_ata_creation_to_other_account_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the taker's existing receive account isn't their associated token account
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange_with_ata_creation(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::InvalidTakerReceiveAccount);
+}
+
+async fn init_royalty_escrow(env: &mut TestEnv, royalty_policy: RoyaltyPolicy) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy,
+        nft: false,
+        unwrap_sol: false,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+/// Adds the metadata account of `mint` with a 10% seller fee, listing `creators` as
+/// (address, verified, share) entries
+fn add_metadata(env: &mut TestEnv, mint: &Pubkey, creators: &[(Pubkey, bool, u8)]) -> Pubkey {
+    let mut data = vec![4];
+    data.extend_from_slice(&[7; 32]);
+    data.extend_from_slice(mint.as_ref());
+    for field in [&b"Cat"[..], b"CAT", b"https://example.com/cat.json"] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field);
+    }
+    data.extend_from_slice(&1_000u16.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(&(creators.len() as u32).to_le_bytes());
+    for (address, verified, share) in creators {
+        data.extend_from_slice(address.as_ref());
+        data.push(*verified as u8);
+        data.push(*share);
+    }
+
+    let metadata = metadata_address(mint);
+    let account = Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: token_metadata_program::id(),
+        ..Account::default()
+    };
+    env.ctx.set_account(&metadata, &account.into());
+    metadata
+}
+
+async fn exchange_with_royalties(
+    env: &mut TestEnv,
+    metadata: &Pubkey,
+    creator_accounts: &[Pubkey],
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts
+        .push(AccountMeta::new_readonly(*metadata, false));
+    for account in creator_accounts {
+        ix.accounts.push(AccountMeta::new(*account, false));
+    }
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_pays_creator_royalties() {
+    let mut env = setup().await;
+    init_royalty_escrow(&mut env, RoyaltyPolicy::Enforced).await;
+
+    let creators = [
+        (Pubkey::new_unique(), true, 70),
+        (Pubkey::new_unique(), false, 10),
+        (Pubkey::new_unique(), true, 20),
+    ];
+    let mint_x = env.mint_x;
+    let metadata = add_metadata(&mut env, &mint_x, &creators);
+    let mint_y = env.mint_y;
+    let first_creator_y = create_token_account(&mut env.ctx, &mint_y, &creators[0].0, 0).await;
+    let third_creator_y = create_token_account(&mut env.ctx, &mint_y, &creators[2].0, 0).await;
+
+    // only verified creators are paid, the unverified one's share stays with the initializer
+    exchange_with_royalties(&mut env, &metadata, &[first_creator_y, third_creator_y])
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let royalties = EXPECTED_AMOUNT / 10;
+    assert_eq!(
+        token_balance(&mut env.ctx, &first_creator_y).await,
+        royalties * 70 / 100
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &third_creator_y).await,
+        royalties * 20 / 100
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee - royalties * 90 / 100
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_other_mint_metadata() {
+    // enforced royalties fail the trade
+    let mut env = setup().await;
+    init_royalty_escrow(&mut env, RoyaltyPolicy::Enforced).await;
+    let mint_y = env.mint_y;
+    let metadata = add_metadata(&mut env, &mint_y, &[(Pubkey::new_unique(), true, 100)]);
+
+    let result = exchange_with_royalties(&mut env, &metadata, &[]).await;
+    assert_escrow_error(result, EscrowError::InvalidMetadata);
+
+    // best effort ones are skipped
+    let mut env = setup().await;
+    init_royalty_escrow(&mut env, RoyaltyPolicy::BestEffort).await;
+    let mint_y = env.mint_y;
+    let metadata = add_metadata(&mut env, &mint_y, &[(Pubkey::new_unique(), true, 100)]);
+
+    exchange_with_royalties(&mut env, &metadata, &[])
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+}
+
+async fn exchange_through_caller(
+    env: &mut TestEnv,
+    max_payment: u64,
+) -> Result<(), TransportError> {
+    let exchange_ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    let mut accounts = vec![AccountMeta::new_readonly(env.program_id, false)];
+    accounts.extend(exchange_ix.accounts);
+    let ix = Instruction {
+        program_id: caller_program::id(),
+        accounts,
+        data: max_payment.to_le_bytes().to_vec(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_exchange_through_cpi() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    exchange_through_caller(&mut env, EXPECTED_AMOUNT)
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert!(!account_exists(&mut env.ctx, &env.escrow.pubkey()).await);
+}
+
+#[tokio::test]
+async fn test_exchange_through_cpi_above_max_payment_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = exchange_through_caller(&mut env, EXPECTED_AMOUNT - 1).await;
+    assert_escrow_error(result, EscrowError::SlippageExceeded);
+}
+
+/// Adds a mint with `supply` and `decimals` and a token account of the initializer holding
+/// `amount` of it, as they would be after minting an NFT. Returns both
+fn add_nft(env: &mut TestEnv, supply: u64, decimals: u8, amount: u64) -> (Pubkey, Pubkey) {
+    let mint = Pubkey::new_unique();
+    let mut mint_data = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::None,
+            supply,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut mint_data,
+    )
+    .unwrap();
+
+    let initializer_temp = Pubkey::new_unique();
+    let mut account_data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint,
+            owner: env.initializer.pubkey(),
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+        &mut account_data,
+    )
+    .unwrap();
+
+    for (key, data) in [(mint, mint_data), (initializer_temp, account_data)] {
+        let account = Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        };
+        env.ctx.set_account(&key, &account.into());
+    }
+    (mint, initializer_temp)
+}
+
+async fn init_nft_escrow(
+    env: &mut TestEnv,
+    mint: &Pubkey,
+    initializer_temp: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        initializer_temp,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: true,
+        unwrap_sol: false,
+    }
+    .pack();
+    ix.accounts.push(AccountMeta::new_readonly(*mint, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_exchange_nft() {
+    let mut env = setup().await;
+    let (mint, initializer_temp) = add_nft(&mut env, 1, 0, 1);
+    init_nft_escrow(&mut env, &mint, &initializer_temp)
+        .await
+        .unwrap();
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert!(escrow_info.nft);
+    assert_eq!(escrow_info.temp_token_mint, mint);
+
+    let taker = env.taker.pubkey();
+    let taker_receive_nft = create_token_account(&mut env.ctx, &mint, &taker, 0).await;
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &taker,
+        &env.taker_send_y,
+        &taker_receive_nft,
+        &initializer_temp,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.accounts.push(AccountMeta::new_readonly(mint, false));
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_nft).await, 1);
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+}
+
+#[tokio::test]
+async fn test_init_nft_escrow_of_fungible_mint_fails() {
+    // more than one token was minted
+    let mut env = setup().await;
+    let (mint, initializer_temp) = add_nft(&mut env, 2, 0, 1);
+    let result = init_nft_escrow(&mut env, &mint, &initializer_temp).await;
+    assert_escrow_error(result, EscrowError::NotAnNft);
+
+    // the one token can be split
+    let (mint, initializer_temp) = add_nft(&mut env, 1, 6, 1);
+    let result = init_nft_escrow(&mut env, &mint, &initializer_temp).await;
+    assert_escrow_error(result, EscrowError::NotAnNft);
+
+    // the mint isn't the deposit's
+    let (_, initializer_temp) = add_nft(&mut env, 1, 0, 1);
+    let mint_x = env.mint_x;
+    let result = init_nft_escrow(&mut env, &mint_x, &initializer_temp).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+#[tokio::test]
+async fn test_init_nft_escrow_with_other_amount_fails() {
+    let mut env = setup().await;
+    let (mint, initializer_temp) = add_nft(&mut env, 1, 0, 2);
+    let result = init_nft_escrow(&mut env, &mint, &initializer_temp).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+}
+
+#[tokio::test]
+async fn test_exchange_with_non_canonical_bump_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // another bump giving a valid address for the same seeds, which isn't the escrow's PDA
+    let initializer = env.initializer.pubkey();
+    let (_, canonical_bump) = escrow_pda(&env.program_id, &initializer, 0);
+    let other_bump = (0..canonical_bump)
+        .rev()
+        .find(|&bump| {
+            Pubkey::create_program_address(
+                &[
+                    ESCROW_SEED_PREFIX,
+                    initializer.as_ref(),
+                    &0u64.to_le_bytes(),
+                    &[bump],
+                ],
+                &env.program_id,
+            )
+            .is_ok()
+        })
+        .unwrap();
+
+    let mut escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    escrow_info.bump_seed = other_bump;
+    Escrow::pack(escrow_info, &mut escrow_account.data).unwrap();
+    env.ctx
+        .set_account(&env.escrow.pubkey(), &escrow_account.into());
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::InvalidPda);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+/// Builds an `Exchange` of the escrow of `env` taking the deposit from `temp_account`
+fn exchange_from_ix(env: &TestEnv, temp_account: &Pubkey) -> Instruction {
+    instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        temp_account,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    )
+}
+
+#[tokio::test]
+async fn test_exchange_from_spoofed_temp_account_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // the deposit mint and amount, but the taker owns it
+    let taker = env.taker.pubkey();
+    let mint_x = env.mint_x;
+    let spoofed_temp_x = create_token_account(&mut env.ctx, &mint_x, &taker, DEPOSIT_AMOUNT).await;
+    let ix = exchange_from_ix(&env, &spoofed_temp_x);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::InvalidTempAccountOwner);
+
+    // owned by the PDA, but of another mint
+    let other_mint = create_mint(&mut env.ctx).await;
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let spoofed_temp = create_token_account(&mut env.ctx, &other_mint, &pda, DEPOSIT_AMOUNT).await;
+    let ix = exchange_from_ix(&env, &spoofed_temp);
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_other_pda_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // nonce 1 picks the PDA of another escrow of the same initializer
+    let ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        1,
+        &env.mint_y,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::InvalidPda);
+}
+
+/// Adds the native mint, which the test validator doesn't start with
+fn add_native_mint(env: &mut TestEnv) {
+    let mut mint_data = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals: native_mint::DECIMALS,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut mint_data,
+    )
+    .unwrap();
+    let account = Account {
+        lamports: 1_000_000_000,
+        data: mint_data,
+        owner: spl_token::id(),
+        ..Account::default()
+    };
+    env.ctx.set_account(&native_mint::id(), &account.into());
+}
+
+/// Makes wrapped SOL the token the initializer expects, giving the initializer, the taker and the
+/// fee collector empty wrapped SOL accounts, and funds the taker with `taker_lamports`
+async fn use_wrapped_sol(env: &mut TestEnv, taker_lamports: u64) {
+    add_native_mint(env);
+    let initializer = env.initializer.pubkey();
+    let taker = env.taker.pubkey();
+    env.mint_y = native_mint::id();
+    env.initializer_receive_y =
+        create_token_account(&mut env.ctx, &native_mint::id(), &initializer, 0).await;
+    env.taker_send_y = create_token_account(&mut env.ctx, &native_mint::id(), &taker, 0).await;
+    env.fee_collector_y =
+        create_fee_collector_account(&mut env.ctx, &env.program_id, &native_mint::id()).await;
+
+    let payer = env.ctx.payer.pubkey();
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(&payer, &taker, taker_lamports)],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+async fn init_unwrap_escrow(env: &mut TestEnv) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitEscrow {
+        amount: EXPECTED_AMOUNT,
+        expiry_slot: 0,
+        expiry_unix_timestamp: 0,
+        allowed_taker: None,
+        two_phase: false,
+        min_taker_amount: 0,
+        nonce: 0,
+        arbiter: None,
+        receipt: false,
+        position: false,
+        taker_merkle_root: None,
+        gate_mint: None,
+        gate_min_amount: 0,
+        gatekeeper_network: None,
+        memo_required: false,
+        royalty_policy: RoyaltyPolicy::None,
+        nft: false,
+        unwrap_sol: true,
+    }
+    .pack();
+    // its close authority moves to the PDA
+    ix.accounts[2].is_writable = true;
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+async fn exchange_with_sol(
+    env: &mut TestEnv,
+    taker_wrapped_sol: &Keypair,
+    max_payment: u64,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &taker_wrapped_sol.pubkey(),
+        &env.taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.data = EscrowInstruction::ExchangeWithSol {
+        max_payment,
+        preimage: None,
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+    // the taker funds the new wrapped SOL account, which signs its creation
+    ix.accounts[0].is_writable = true;
+    ix.accounts[1].is_signer = true;
+    let mut accounts = vec![
+        AccountMeta::new_readonly(native_mint::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    accounts.append(&mut ix.accounts);
+    ix.accounts = accounts;
+    process(&mut env.ctx, &[ix], &[&env.taker, taker_wrapped_sol]).await
+}
+
+#[tokio::test]
+async fn test_exchange_with_sol() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    let taker_before = env.ctx.banks_client.get_balance(taker).await.unwrap();
+
+    // the taker offers more than the price, what isn't spent comes back unwrapped
+    let taker_wrapped_sol = Keypair::new();
+    exchange_with_sol(&mut env, &taker_wrapped_sol, EXPECTED_AMOUNT * 2)
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    assert_eq!(
+        env.ctx.banks_client.get_balance(taker).await.unwrap(),
+        taker_before - EXPECTED_AMOUNT
+    );
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(taker_wrapped_sol.pubkey())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_with_sol_without_enough_lamports_fails() {
+    let mut env = setup().await;
+    // enough for the payment but not for the rent of the wrapped SOL account on top of it
+    use_wrapped_sol(&mut env, 1_000_000).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = exchange_with_sol(&mut env, &Keypair::new(), EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::InsufficientLamports);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_with_sol_below_price_fails() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = exchange_with_sol(&mut env, &Keypair::new(), EXPECTED_AMOUNT - 1).await;
+    assert_escrow_error(result, EscrowError::SlippageExceeded);
+}
+
+#[tokio::test]
+async fn test_exchange_with_sol_for_token_escrow_fails() {
+    let mut env = setup().await;
+    let taker = env.taker.pubkey();
+    let payer = env.ctx.payer.pubkey();
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(&payer, &taker, 1_000_000_000)],
+        &[],
+    )
+    .await
+    .unwrap();
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = exchange_with_sol(&mut env, &Keypair::new(), EXPECTED_AMOUNT).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+#[tokio::test]
+async fn test_exchange_syncs_wrapped_sol() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // lamports sent to the wrapped SOL account without syncing it
+    let taker = env.taker.pubkey();
+    let taker_send_y = env.taker_send_y;
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(
+            &taker,
+            &taker_send_y,
+            EXPECTED_AMOUNT,
+        )],
+        &[&env.taker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_y).await, 0);
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_exchange_with_too_little_wrapped_sol_fails() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let taker = env.taker.pubkey();
+    let taker_send_y = env.taker_send_y;
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(
+            &taker,
+            &taker_send_y,
+            EXPECTED_AMOUNT - 1,
+        )],
+        &[&env.taker],
+    )
+    .await
+    .unwrap();
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::InsufficientTakerBalance);
+}
+
+#[tokio::test]
+async fn test_exchange_unwraps_initializer_sol() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_unwrap_escrow(&mut env).await.unwrap();
+
+    let initializer = env.initializer.pubkey();
+    let initializer_before = env.ctx.banks_client.get_balance(initializer).await.unwrap();
+    let mut rents = 0;
+    for account in [
+        env.escrow.pubkey(),
+        env.initializer_temp_x,
+        env.initializer_receive_y,
+    ] {
+        rents += env.ctx.banks_client.get_balance(account).await.unwrap();
+    }
+
+    let taker_wrapped_sol = Keypair::new();
+    exchange_with_sol(&mut env, &taker_wrapped_sol, EXPECTED_AMOUNT)
+        .await
+        .unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(env.initializer_receive_y)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        env.ctx.banks_client.get_balance(initializer).await.unwrap(),
+        initializer_before + rents + EXPECTED_AMOUNT - fee
+    );
+}
+
+#[tokio::test]
+async fn test_unwrap_escrow_is_taken_whole() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_unwrap_escrow(&mut env).await.unwrap();
+
+    let result = partial_exchange(&mut env, DEPOSIT_AMOUNT / 2, EXPECTED_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::InvalidFillAmount);
+}
+
+#[tokio::test]
+async fn test_init_unwrap_escrow_for_other_mint_fails() {
+    let mut env = setup().await;
+    let result = init_unwrap_escrow(&mut env).await;
+    assert_escrow_error(result, EscrowError::NotWrappedSol);
+}
+
+#[tokio::test]
+async fn test_cancel_unwrap_escrow_returns_close_authority() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 1_000_000_000).await;
+    init_unwrap_escrow(&mut env).await.unwrap();
+
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let receive_account = env
+        .ctx
+        .banks_client
+        .get_account(env.initializer_receive_y)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TokenAccount::unpack(&receive_account.data)
+            .unwrap()
+            .close_authority,
+        COption::Some(pda)
+    );
+
+    let initializer = env.initializer.pubkey();
+    let initializer_refund_x =
+        create_token_account(&mut env.ctx, &env.mint_x, &initializer, 0).await;
+    let mut ix = cancel_ix(&env, &initializer, &initializer_refund_x);
+    ix.accounts
+        .push(AccountMeta::new(env.initializer_receive_y, false));
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    let receive_account = env
+        .ctx
+        .banks_client
+        .get_account(env.initializer_receive_y)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TokenAccount::unpack(&receive_account.data)
+            .unwrap()
+            .close_authority,
+        COption::None
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_refund_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_client_derived_pda_owns_the_deposit() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // derived the way a client without the processor would
+    let initializer = env.initializer.pubkey();
+    let (pda, bump_seed) = Pubkey::find_program_address(
+        &[
+            ESCROW_SEED_PREFIX,
+            initializer.as_ref(),
+            &0u64.to_le_bytes(),
+        ],
+        &env.program_id,
+    );
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.initializer_temp_x).await,
+        pda
+    );
+
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Escrow::unpack(&escrow_account.data).unwrap().bump_seed,
+        bump_seed
+    );
+}
+
+async fn simulate_exchange(
+    env: &mut TestEnv,
+    taker_send_y: &Pubkey,
+    taker_receive_x: &Pubkey,
+    amount: u64,
+) -> Result<(), TransportError> {
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        taker_send_y,
+        taker_receive_x,
+        &env.initializer_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        0,
+        &env.mint_y,
+    );
+    ix.data = EscrowInstruction::SimulateExchange {
+        amount,
+        preimage: None,
+        merkle_proof: Vec::new(),
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_moves_nothing() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let (taker_send_y, taker_receive_x) = (env.taker_send_y, env.taker_receive_x);
+    simulate_exchange(&mut env, &taker_send_y, &taker_receive_x, 0)
+        .await
+        .unwrap();
+    simulate_exchange(
+        &mut env,
+        &taker_send_y,
+        &taker_receive_x,
+        DEPOSIT_AMOUNT / 2,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &taker_receive_x).await, 0);
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Escrow::unpack(&escrow_account.data).unwrap().state,
+        EscrowState::Active
+    );
+
+    // the escrow can still be taken for real
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_with_insufficient_balance_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mint_y = env.mint_y;
+    let taker = env.taker.pubkey();
+    let taker_send_y =
+        create_token_account(&mut env.ctx, &mint_y, &taker, EXPECTED_AMOUNT - 1).await;
+    let taker_receive_x = env.taker_receive_x;
+    let result = simulate_exchange(&mut env, &taker_send_y, &taker_receive_x, 0).await;
+    assert_escrow_error(result, EscrowError::InsufficientTakerBalance);
+
+    // taking half of the deposit costs half as much
+    simulate_exchange(
+        &mut env,
+        &taker_send_y,
+        &taker_receive_x,
+        DEPOSIT_AMOUNT / 2,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_counts_unsynced_wrapped_sol() {
+    let mut env = setup().await;
+    use_wrapped_sol(&mut env, 0).await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let payer = env.ctx.payer.pubkey();
+    let (taker_send_y, taker_receive_x) = (env.taker_send_y, env.taker_receive_x);
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(
+            &payer,
+            &taker_send_y,
+            EXPECTED_AMOUNT - 1,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = simulate_exchange(&mut env, &taker_send_y, &taker_receive_x, 0).await;
+    assert_escrow_error(result, EscrowError::InsufficientTakerBalance);
+
+    process(
+        &mut env.ctx,
+        &[system_instruction::transfer(&payer, &taker_send_y, 1)],
+        &[],
+    )
+    .await
+    .unwrap();
+    // the whole deposit asked for by amount, the transaction would repeat the failed one otherwise
+    simulate_exchange(&mut env, &taker_send_y, &taker_receive_x, DEPOSIT_AMOUNT)
+        .await
+        .unwrap();
+
+    // the simulation didn't sync the account
+    assert_eq!(token_balance(&mut env.ctx, &taker_send_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_simulate_exchange_reports_the_trade_error() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let mint_y = env.mint_y;
+    let taker = env.taker.pubkey();
+    let taker_send_y = env.taker_send_y;
+    let taker_receive_y = create_token_account(&mut env.ctx, &mint_y, &taker, 0).await;
+    let result = simulate_exchange(&mut env, &taker_send_y, &taker_receive_y, 0).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+
+    let taker_receive_x = env.taker_receive_x;
+    let result = simulate_exchange(
+        &mut env,
+        &taker_send_y,
+        &taker_receive_x,
+        DEPOSIT_AMOUNT + 1,
+    )
+    .await;
+    assert_escrow_error(result, EscrowError::ExpectedAmountMissmatch);
+}
+
+const RESERVE_AMOUNT: u64 = 5_000;
+const AUCTION_END_SLOT: u64 = 1_000;
+
+async fn init_bid_auction(env: &mut TestEnv, end_slot: u64) -> Result<(), TransportError> {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        RESERVE_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitBidAuction {
+        reserve_amount: RESERVE_AMOUNT,
+        end_slot,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+/// A bidder holding the amount bid in a temp token account, with the bid account it creates
+struct Bidder {
+    keypair: Keypair,
+    temp_y: Pubkey,
+    receive_x: Pubkey,
+    bid: Keypair,
+}
+
+async fn add_bidder(env: &mut TestEnv, amount: u64) -> Bidder {
+    let keypair = Keypair::new();
+    let (mint_x, mint_y) = (env.mint_x, env.mint_y);
+    let temp_y = create_token_account(&mut env.ctx, &mint_y, &keypair.pubkey(), amount).await;
+    let receive_x = create_token_account(&mut env.ctx, &mint_x, &keypair.pubkey(), 0).await;
+    Bidder {
+        keypair,
+        temp_y,
+        receive_x,
+        bid: Keypair::new(),
+    }
+}
+
+async fn place_bid(env: &mut TestEnv, bidder: &Bidder, amount: u64) -> Result<(), TransportError> {
+    place_bid_with_token_program(env, bidder, amount, &spl_token::id()).await
+}
+
+async fn place_bid_with_token_program(
+    env: &mut TestEnv,
+    bidder: &Bidder,
+    amount: u64,
+    token_program: &Pubkey,
+) -> Result<(), TransportError> {
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &env.ctx.payer.pubkey(),
+            &bidder.bid.pubkey(),
+            rent.minimum_balance(Bid::LEN),
+            Bid::LEN as u64,
+            &env.program_id,
+        ),
+        Instruction::new_with_bytes(
+            env.program_id,
+            &EscrowInstruction::PlaceBid { amount }.pack(),
+            vec![
+                AccountMeta::new_readonly(bidder.keypair.pubkey(), true),
+                AccountMeta::new(bidder.temp_y, false),
+                AccountMeta::new_readonly(bidder.receive_x, false),
+                AccountMeta::new(bidder.bid.pubkey(), false),
+                AccountMeta::new(env.escrow.pubkey(), false),
+                AccountMeta::new_readonly(*token_program, false),
+            ],
+        ),
+    ];
+    process(&mut env.ctx, &instructions, &[&bidder.bid, &bidder.keypair]).await
+}
+
+async fn accept_bid(env: &mut TestEnv, bidder: &Bidder) -> Result<(), TransportError> {
+    accept_bid_with_token_program(env, bidder, &spl_token::id()).await
+}
+
+async fn accept_bid_with_token_program(
+    env: &mut TestEnv,
+    bidder: &Bidder,
+    token_program: &Pubkey,
+) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction::new_with_bytes(
+        env.program_id,
+        &EscrowInstruction::AcceptBid.pack(),
+        vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(bidder.bid.pubkey(), false),
+            AccountMeta::new(bidder.temp_y, false),
+            AccountMeta::new(bidder.receive_x, false),
+            AccountMeta::new(bidder.keypair.pubkey(), false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+    );
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+async fn withdraw_bid(env: &mut TestEnv, bidder: &Bidder) -> Result<(), TransportError> {
+    withdraw_bid_with_token_program(env, bidder, &spl_token::id()).await
+}
+
+async fn withdraw_bid_with_token_program(
+    env: &mut TestEnv,
+    bidder: &Bidder,
+    token_program: &Pubkey,
+) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction::new_with_bytes(
+        env.program_id,
+        &EscrowInstruction::WithdrawBid.pack(),
+        vec![
+            AccountMeta::new(bidder.keypair.pubkey(), true),
+            AccountMeta::new(bidder.bid.pubkey(), false),
+            AccountMeta::new(bidder.temp_y, false),
+            AccountMeta::new_readonly(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+    );
+    process(&mut env.ctx, &[ix], &[&bidder.keypair]).await
+}
+
+#[tokio::test]
+async fn test_accept_best_bid_and_withdraw_outbid_one() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+
+    let first = add_bidder(&mut env, 6_000).await;
+    place_bid(&mut env, &first, 6_000).await.unwrap();
+    let second = add_bidder(&mut env, 7_000).await;
+    place_bid(&mut env, &second, 7_000).await.unwrap();
+
+    // the outbid bidder gets their temp token account back
+    withdraw_bid(&mut env, &first).await.unwrap();
+    assert_eq!(
+        token_owner(&mut env.ctx, &first.temp_y).await,
+        first.keypair.pubkey()
+    );
+    assert_eq!(token_balance(&mut env.ctx, &first.temp_y).await, 6_000);
+    assert!(!account_exists(&mut env.ctx, &first.bid.pubkey()).await);
+
+    accept_bid(&mut env, &second).await.unwrap();
+    let fee = 7_000 * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let initializer_receive_y = env.initializer_receive_y;
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_receive_y).await,
+        7_000 - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    assert_eq!(
+        token_balance(&mut env.ctx, &second.receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_owner(&mut env.ctx, &second.temp_y).await,
+        second.keypair.pubkey()
+    );
+    assert!(!account_exists(&mut env.ctx, &second.bid.pubkey()).await);
+    let escrow = env.escrow.pubkey();
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+}
+
+#[tokio::test]
+async fn test_bid_not_beating_the_best_bid_fails() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+
+    let below_reserve = add_bidder(&mut env, RESERVE_AMOUNT - 1).await;
+    let result = place_bid(&mut env, &below_reserve, RESERVE_AMOUNT - 1).await;
+    assert_escrow_error(result, EscrowError::BidTooLow);
+
+    let first = add_bidder(&mut env, 6_000).await;
+    place_bid(&mut env, &first, 6_000).await.unwrap();
+    let second = add_bidder(&mut env, 6_000).await;
+    let result = place_bid(&mut env, &second, 6_000).await;
+    assert_escrow_error(result, EscrowError::BidTooLow);
+}
+
+#[tokio::test]
+async fn test_best_bid_is_locked_until_the_auction_ends() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+    let bidder = add_bidder(&mut env, 6_000).await;
+    place_bid(&mut env, &bidder, 6_000).await.unwrap();
+
+    let result = withdraw_bid(&mut env, &bidder).await;
+    assert_escrow_error(result, EscrowError::BestBidLocked);
+
+    env.ctx.warp_to_slot(AUCTION_END_SLOT + 1).unwrap();
+    let result = accept_bid(&mut env, &bidder).await;
+    assert_escrow_error(result, EscrowError::EscrowExpired);
+
+    withdraw_bid(&mut env, &bidder).await.unwrap();
+    assert_eq!(
+        token_owner(&mut env.ctx, &bidder.temp_y).await,
+        bidder.keypair.pubkey()
+    );
+    assert_eq!(token_balance(&mut env.ctx, &bidder.temp_y).await, 6_000);
+}
+
+#[tokio::test]
+async fn test_bids_with_fake_token_program_fail() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+    let fake_token_program = Pubkey::new_unique();
+
+    let first = add_bidder(&mut env, 6_000).await;
+    let result = place_bid_with_token_program(&mut env, &first, 6_000, &fake_token_program).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::IncorrectProgramId)
+    );
+
+    place_bid(&mut env, &first, 6_000).await.unwrap();
+    let second = add_bidder(&mut env, 7_000).await;
+    place_bid(&mut env, &second, 7_000).await.unwrap();
+
+    // an outbid bidder must not get the PDA's signature for a program of theirs
+    let result = withdraw_bid_with_token_program(&mut env, &first, &fake_token_program).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+    let result = accept_bid_with_token_program(&mut env, &second, &fake_token_program).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_bid_from_fake_vault_fails() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+
+    // a token account layout with a forged balance, owned by another program
+    let mut bidder = add_bidder(&mut env, 0).await;
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint: env.mint_y,
+            owner: bidder.keypair.pubkey(),
+            amount: u64::MAX,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+        &mut data,
+    )
+    .unwrap();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let fake_vault = Pubkey::new_unique();
+    let account = Account {
+        lamports: rent.minimum_balance(TokenAccount::LEN),
+        data,
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    env.ctx.set_account(&fake_vault, &account.into());
+    bidder.temp_y = fake_vault;
+
+    let result = place_bid(&mut env, &bidder, u64::MAX).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::IncorrectProgramId)
+    );
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Escrow::unpack(&escrow_account.data)
+            .unwrap()
+            .best_bid_amount,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_init_bid_auction_without_end_fails() {
+    let mut env = setup().await;
+    let result = init_bid_auction(&mut env, 0).await;
+    assert_escrow_error(result, EscrowError::InvalidAuctionParameters);
+}
+
+/// Creates an escrow account owned by the program, to be initialized by a later instruction
+async fn create_escrow_account(env: &mut TestEnv) -> Keypair {
+    let escrow = Keypair::new();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let ix = system_instruction::create_account(
+        &env.ctx.payer.pubkey(),
+        &escrow.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &env.program_id,
+    );
+    process(&mut env.ctx, &[ix], &[&escrow]).await.unwrap();
+    escrow
+}
+
+/// Starts a batch of escrows from new temp accounts holding the deposit, returning the temp and
+/// escrow accounts of each entry along with the result
+async fn init_escrow_batch(
+    env: &mut TestEnv,
+    entries: Vec<BatchEntry>,
+) -> (Vec<(Pubkey, Pubkey)>, Result<(), TransportError>) {
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let mut accounts = vec![
+        AccountMeta::new(initializer, true),
+        AccountMeta::new_readonly(env.initializer_receive_y, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        AccountMeta::new(fee_collector_pda(&env.program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    let mut escrows = vec![];
+    for _ in &entries {
+        let temp_x =
+            create_token_account(&mut env.ctx, &mint_x, &initializer, DEPOSIT_AMOUNT).await;
+        let escrow = create_escrow_account(env).await.pubkey();
+        accounts.push(AccountMeta::new(temp_x, false));
+        accounts.push(AccountMeta::new(escrow, false));
+        escrows.push((temp_x, escrow));
+    }
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts,
+        data: EscrowInstruction::InitEscrowBatch { entries }.pack(),
+    };
+    let result = process(&mut env.ctx, &[ix], &[&env.initializer]).await;
+    (escrows, result)
+}
+
+#[tokio::test]
+async fn test_init_escrow_batch_with_nonces() {
+    let mut env = setup().await;
+    let initializer = env.initializer.pubkey();
+    let entries = vec![
+        BatchEntry {
+            amount: EXPECTED_AMOUNT,
+            nonce: 1,
+        },
+        BatchEntry {
+            amount: EXPECTED_AMOUNT * 2,
+            nonce: 2,
+        },
+    ];
+    let (escrows, result) = init_escrow_batch(&mut env, entries.clone()).await;
+    result.unwrap();
+
+    for ((temp_x, escrow), entry) in escrows.into_iter().zip(entries) {
+        let (pda, _) = escrow_pda(&env.program_id, &initializer, entry.nonce);
+        assert_eq!(token_owner(&mut env.ctx, &temp_x).await, pda);
+        let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+        let escrow_info = Escrow::unpack(&account.unwrap().data).unwrap();
+        assert_eq!(escrow_info.nonce, entry.nonce);
+        assert_eq!(escrow_info.expected_amount, entry.amount);
+    }
+}
+
+#[tokio::test]
+async fn test_init_escrow_batch_with_failing_entry_fails() {
+    let mut env = setup().await;
+    let initializer = env.initializer.pubkey();
+    let entries = vec![
+        BatchEntry {
+            amount: EXPECTED_AMOUNT,
+            nonce: 1,
+        },
+        BatchEntry {
+            amount: 0,
+            nonce: 2,
+        },
+    ];
+    let (escrows, result) = init_escrow_batch(&mut env, entries).await;
+    assert_escrow_error(result, EscrowError::InvalidAmount);
+
+    // the entry before the failing one is rolled back with it
+    let (temp_x, escrow) = escrows[0];
+    assert_eq!(token_owner(&mut env.ctx, &temp_x).await, initializer);
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap();
+    assert!(!Escrow::unpack_unchecked(&account.unwrap().data)
+        .unwrap()
+        .is_initialized());
+}
+
+/// Moves `split_deposit_amount` of the escrow into a new one, returning the new escrow account
+/// and its temp token account
+async fn split_escrow(
+    env: &mut TestEnv,
+    split_deposit_amount: u64,
+) -> Result<(Pubkey, Pubkey), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let new_temp_x = create_token_account(&mut env.ctx, &mint_x, &initializer, 0).await;
+    let new_escrow = create_escrow_account(env).await.pubkey();
+    let ix = Instruction::new_with_bytes(
+        env.program_id,
+        &EscrowInstruction::SplitEscrow {
+            split_deposit_amount,
+        }
+        .pack(),
+        vec![
+            AccountMeta::new_readonly(initializer, true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(new_escrow, false),
+            AccountMeta::new(new_temp_x, false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+    );
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await?;
+    Ok((new_escrow, new_temp_x))
+}
+
+#[tokio::test]
+async fn test_split_bid_auction_fails() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+    let result = split_escrow(&mut env, DEPOSIT_AMOUNT / 2).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::BidAuctionOnly);
+}
+
+async fn init_dutch_auction(env: &mut TestEnv) {
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT * 2,
+    );
+    ix.data = EscrowInstruction::InitDutchAuction {
+        start_amount: EXPECTED_AMOUNT * 2,
+        end_amount: EXPECTED_AMOUNT,
+        start_slot: 0,
+        end_slot: AUCTION_END_SLOT,
+    }
+    .pack();
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+}
+
+/// Merges the escrow of `merged` into the initializer's first one, given as its escrow account
+/// and temp token account as returned by `add_escrow`
+async fn merge_escrows(env: &mut TestEnv, merged: (Pubkey, Pubkey)) -> Result<(), TransportError> {
+    let (merged_temp_x, merged_escrow) = merged;
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction::new_with_bytes(
+        env.program_id,
+        &EscrowInstruction::MergeEscrows.pack(),
+        vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(merged_escrow, false),
+            AccountMeta::new(merged_temp_x, false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+    );
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+/// Starts an escrow of the taker depositing `deposit` of `mint` for `expected` of token X, the
+/// opposite side of the initializer's when `mint` is token Y. Returns its temp token account and
+/// escrow account
+async fn add_opposing_escrow(
+    env: &mut TestEnv,
+    mint: &Pubkey,
+    deposit: u64,
+    expected: u64,
+) -> (Pubkey, Pubkey) {
+    let taker = env.taker.pubkey();
+    let temp = create_token_account(&mut env.ctx, mint, &taker, deposit).await;
+    let escrow = create_escrow_account(env).await.pubkey();
+    let ix = instruction::init_escrow(
+        &env.program_id,
+        &taker,
+        &temp,
+        &env.taker_receive_x,
+        &escrow,
+        &spl_token::id(),
+        expected,
+    );
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+    (temp, escrow)
+}
+
+/// Matches the initializer's escrow against the taker's one returned by `add_opposing_escrow`,
+/// followed by `extra_accounts`. Returns the fee collector's token account for token X
+async fn match_escrows(
+    env: &mut TestEnv,
+    opposing: (Pubkey, Pubkey),
+    extra_accounts: &[AccountMeta],
+) -> Result<Pubkey, TransportError> {
+    let (ix, fee_collector_x) = match_escrows_ix(env, opposing, extra_accounts).await;
+    process(&mut env.ctx, &[ix], &[]).await?;
+    Ok(fee_collector_x)
+}
+
+/// Builds the instruction of `match_escrows` without processing it
+async fn match_escrows_ix(
+    env: &mut TestEnv,
+    opposing: (Pubkey, Pubkey),
+    extra_accounts: &[AccountMeta],
+) -> (Instruction, Pubkey) {
+    let (opposing_temp, opposing_escrow) = opposing;
+    let (first_pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let (second_pda, _) = escrow_pda(&env.program_id, &env.taker.pubkey(), 0);
+    let mint_x = env.mint_x;
+    let fee_collector = fee_collector_pda(&env.program_id).0;
+    let fee_collector_x = create_token_account(&mut env.ctx, &mint_x, &fee_collector, 0).await;
+    let mut ix = Instruction::new_with_bytes(
+        env.program_id,
+        &EscrowInstruction::MatchEscrows.pack(),
+        vec![
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(opposing_escrow, false),
+            AccountMeta::new(opposing_temp, false),
+            AccountMeta::new(env.taker.pubkey(), false),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(first_pda, false),
+            AccountMeta::new_readonly(second_pda, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new(fee_collector_x, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+    );
+    ix.accounts.extend_from_slice(extra_accounts);
+    (ix, fee_collector_x)
+}
+
+#[tokio::test]
+async fn test_split_dutch_auction_fails() {
+    let mut env = setup().await;
+    init_dutch_auction(&mut env).await;
+    let result = split_escrow(&mut env, DEPOSIT_AMOUNT / 2).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::ExchangeOnly);
+}
+
+#[tokio::test]
+async fn test_merge_dutch_auction_fails() {
+    let mut env = setup().await;
+    init_dutch_auction(&mut env).await;
+    let merged = add_escrow(&mut env).await;
+    let result = merge_escrows(&mut env, merged).await;
+    assert_escrow_error(result, EscrowError::ExchangeOnly);
+}
+
+#[tokio::test]
+async fn test_match_dutch_auction_fails() {
+    let mut env = setup().await;
+    init_dutch_auction(&mut env).await;
+    let mint_y = env.mint_y;
+    let opposing =
+        add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT * 2, DEPOSIT_AMOUNT).await;
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::ExchangeOnly);
+}
+
+/// Adds a Switchboard aggregator pricing one token X at `price` tokens Y, as of slot 0
+fn add_aggregator(env: &mut TestEnv, price: i128) -> Pubkey {
+    let aggregator = Pubkey::new_unique();
+    set_aggregator_price(env, &aggregator, price);
+    aggregator
+}
+
+fn set_aggregator_price(env: &mut TestEnv, aggregator: &Pubkey, price: i128) {
+    let mut data = vec![0; 400];
+    data[0..8].copy_from_slice(&[217, 230, 65, 101, 201, 162, 27, 125]);
+    data[341..345].copy_from_slice(&1u32.to_le_bytes());
+    data[366..382].copy_from_slice(&price.to_le_bytes());
+    let account = Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    env.ctx.set_account(aggregator, &account.into());
+}
+
+#[tokio::test]
+async fn test_match_crossed_escrows_pays_fees() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+    let fee_collector_x = match_escrows(&mut env, opposing, &[]).await.unwrap();
+
+    // both initializers pay the default maker fee out of what they receive
+    let fee_y = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let fee_x = DEPOSIT_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    let (initializer_receive_y, fee_collector_y) = (env.initializer_receive_y, env.fee_collector_y);
+    assert_eq!(
+        token_balance(&mut env.ctx, &initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee_y
+    );
+    assert_eq!(token_balance(&mut env.ctx, &fee_collector_y).await, fee_y);
+    let taker_receive_x = env.taker_receive_x;
+    assert_eq!(
+        token_balance(&mut env.ctx, &taker_receive_x).await,
+        DEPOSIT_AMOUNT - fee_x
+    );
+    assert_eq!(token_balance(&mut env.ctx, &fee_collector_x).await, fee_x);
+
+    let escrow = env.escrow.pubkey();
+    assert!(!account_exists(&mut env.ctx, &escrow).await);
+    assert!(!account_exists(&mut env.ctx, &opposing.1).await);
+}
+
+#[tokio::test]
+async fn test_match_escrows_with_fake_token_program_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+
+    // matching is permissionless, a program of the keeper must not get both PDAs' signatures
+    let (mut ix, _) = match_escrows_ix(&mut env, opposing, &[]).await;
+    ix.accounts[8] = AccountMeta::new_readonly(Pubkey::new_unique(), false);
+    let result = process(&mut env.ctx, &[ix], &[]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &opposing.0).await,
+        EXPECTED_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_match_uncrossed_escrows_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing =
+        add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT - 1, DEPOSIT_AMOUNT).await;
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::ExpectedAmountMissmatch);
+}
+
+#[tokio::test]
+async fn test_match_escrows_of_other_mints_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let mint_z = create_mint(&mut env.ctx).await;
+    let opposing = add_opposing_escrow(&mut env, &mint_z, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+#[tokio::test]
+async fn test_match_expired_escrow_fails() {
+    let mut env = setup().await;
+    init_escrow_expiring(&mut env, 500, 0).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+
+    env.ctx.warp_to_slot(501).unwrap();
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::EscrowExpired);
+}
+
+#[tokio::test]
+async fn test_match_inactive_escrow_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    set_escrow_active(&mut env, false).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::EscrowInactive);
+}
+
+#[tokio::test]
+async fn test_match_bid_auction_fails() {
+    let mut env = setup().await;
+    init_bid_auction(&mut env, AUCTION_END_SLOT).await.unwrap();
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+    let result = match_escrows(&mut env, opposing, &[]).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::BidAuctionOnly);
+}
+
+#[tokio::test]
+async fn test_match_outside_oracle_band_fails() {
+    let mut env = setup().await;
+    let aggregator = add_aggregator(&mut env, 20);
+    let mut ix = instruction::init_escrow(
+        &env.program_id,
+        &env.initializer.pubkey(),
+        &env.initializer_temp_x,
+        &env.initializer_receive_y,
+        &env.escrow.pubkey(),
+        &spl_token::id(),
+        EXPECTED_AMOUNT,
+    );
+    ix.data = EscrowInstruction::InitOracleBand {
+        amount: EXPECTED_AMOUNT,
+        max_deviation_bps: 100,
+        max_staleness: 1_000,
+        oracle_kind: OracleKind::Switchboard,
+    }
+    .pack();
+    ix.accounts.extend([
+        AccountMeta::new_readonly(aggregator, false),
+        AccountMeta::new_readonly(env.mint_x, false),
+        AccountMeta::new_readonly(env.mint_y, false),
+    ]);
+    process(&mut env.ctx, &[ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    // the oracle prices X at 20 Y, twice what the opposing escrow pays
+    let mint_y = env.mint_y;
+    let opposing = add_opposing_escrow(&mut env, &mint_y, EXPECTED_AMOUNT, DEPOSIT_AMOUNT).await;
+    let oracle = [AccountMeta::new_readonly(aggregator, false)];
+    let result = match_escrows(&mut env, opposing, &oracle).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::PriceOutOfBand);
+
+    set_aggregator_price(&mut env, &aggregator, 10);
+    match_escrows(&mut env, opposing, &oracle).await.unwrap();
+}
+
+async fn propose_counter_offer(env: &mut TestEnv, amount: u64) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new_readonly(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::ProposeCounterOffer { amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.taker]).await
+}
+
+async fn accept_counter_offer(env: &mut TestEnv) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(env.token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::AcceptCounterOffer.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_accept_counter_offer() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    // a newer counter-offer replaces the pending one
+    propose_counter_offer(&mut env, EXPECTED_AMOUNT / 4)
+        .await
+        .unwrap();
+    propose_counter_offer(&mut env, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+    accept_counter_offer(&mut env).await.unwrap();
+
+    // the maker fee comes out of the counter-offer
+    let fee = EXPECTED_AMOUNT / 2 * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT / 2 - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT / 2
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert!(!account_exists(&mut env.ctx, &env.escrow.pubkey()).await);
+}
+
+#[tokio::test]
+async fn test_accept_without_counter_offer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = accept_counter_offer(&mut env).await;
+    assert_escrow_error(result, EscrowError::NoCounterOffer);
+
+    // the listed price is still there for anyone ignoring counter-offers
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_accept_unfunded_counter_offer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    propose_counter_offer(&mut env, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+
+    // the proposer takes the approval back after proposing
+    let revoke_ix = spl_token::instruction::revoke(
+        &spl_token::id(),
+        &env.taker_send_y,
+        &env.taker.pubkey(),
+        &[],
+    )
+    .unwrap();
+    process(&mut env.ctx, &[revoke_ix], &[&env.taker])
+        .await
+        .unwrap();
+
+    let result = accept_counter_offer(&mut env).await;
+    assert_escrow_error(result, EscrowError::CounterOfferUnfunded);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+async fn confirm_exchange(env: &mut TestEnv) -> Result<(), TransportError> {
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.initializer.pubkey(), true),
+            AccountMeta::new(env.initializer_receive_y, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(env.fee_collector_y, false),
+            AccountMeta::new_readonly(config_pda(&env.program_id).0, false),
+        ],
+        data: EscrowInstruction::ConfirmExchange.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[&env.initializer]).await
+}
+
+#[tokio::test]
+async fn test_confirm_two_phase_exchange() {
+    let mut env = setup().await;
+    init_two_phase_escrow(&mut env, None).await;
+
+    // takers have to deposit and wait for the initializer
+    let taker_receive_x = env.taker_receive_x;
+    let result = exchange(&mut env, &taker_receive_x).await;
+    assert_escrow_error(result, EscrowError::TwoPhaseRequired);
+
+    deposit_for_exchange(&mut env).await.unwrap();
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.taker_send_y).await,
+        escrow_pda(&env.program_id, &env.initializer.pubkey(), 0).0
+    );
+    confirm_exchange(&mut env).await.unwrap();
+
+    let fee = EXPECTED_AMOUNT * MAKER_FEE_BASIS_POINTS as u64 / 10_000;
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_receive_y).await,
+        EXPECTED_AMOUNT - fee
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.fee_collector_y).await, fee);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.taker_send_y).await,
+        env.taker.pubkey()
+    );
+    assert!(!account_exists(&mut env.ctx, &env.escrow.pubkey()).await);
+}
+
+#[tokio::test]
+async fn test_accept_counter_offer_with_other_fee_collector_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    propose_counter_offer(&mut env, EXPECTED_AMOUNT / 2)
+        .await
+        .unwrap();
+
+    // the initializer can't route the fee to an account of their own
+    let initializer = env.initializer.pubkey();
+    let mint_y = env.mint_y;
+    env.fee_collector_y = create_token_account(&mut env.ctx, &mint_y, &initializer, 0).await;
+    let result = accept_counter_offer(&mut env).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+    );
+}
+
+#[tokio::test]
+async fn test_reject_two_phase_exchange() {
+    let mut env = setup().await;
+    init_two_phase_escrow(&mut env, None).await;
+    deposit_for_exchange(&mut env).await.unwrap();
+
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let reject_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.initializer.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RejectExchange.pack(),
+    };
+    process(&mut env.ctx, &[reject_ix], &[&env.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_send_y).await,
+        EXPECTED_AMOUNT
+    );
+    assert_eq!(
+        token_owner(&mut env.ctx, &env.taker_send_y).await,
+        env.taker.pubkey()
+    );
+    let result = confirm_exchange(&mut env).await;
+    assert_escrow_error(result, EscrowError::NoPendingDeposit);
+}
+
+#[tokio::test]
+async fn test_deposit_for_single_shot_escrow_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    let result = deposit_for_exchange(&mut env).await;
+    assert_escrow_error(result, EscrowError::NotTwoPhase);
+}
+
+#[tokio::test]
+async fn test_deposit_for_exchange_of_other_mint_fails() {
+    let mut env = setup().await;
+    init_two_phase_escrow(&mut env, None).await;
+    let taker = env.taker.pubkey();
+    let mint_x = env.mint_x;
+    env.taker_send_y = create_token_account(&mut env.ctx, &mint_x, &taker, EXPECTED_AMOUNT).await;
+
+    let result = deposit_for_exchange(&mut env).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+#[tokio::test]
+async fn test_two_phase_with_fake_token_program_fails() {
+    let mut env = setup().await;
+    init_two_phase_escrow(&mut env, None).await;
+    let fake_token_program = Pubkey::new_unique();
+
+    let mut deposit_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new_readonly(env.taker_receive_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(fake_token_program, false),
+        ],
+        data: EscrowInstruction::DepositForExchange.pack(),
+    };
+    let result = process(&mut env.ctx, &[deposit_ix.clone()], &[&env.taker]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+
+    deposit_ix.accounts[4] = AccountMeta::new_readonly(spl_token::id(), false);
+    process(&mut env.ctx, &[deposit_ix], &[&env.taker])
+        .await
+        .unwrap();
+
+    // the pending taker can reject, a program of theirs must not get the PDA's signature
+    let (pda, _) = escrow_pda(&env.program_id, &env.initializer.pubkey(), 0);
+    let reject_ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_send_y, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(fake_token_program, false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data: EscrowInstruction::RejectExchange.pack(),
+    };
+    let result = process(&mut env.ctx, &[reject_ix], &[&env.taker]).await;
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+async fn top_up(
+    env: &mut TestEnv,
+    by_taker: bool,
+    source: &Pubkey,
+    additional_amount: u64,
+) -> Result<(), TransportError> {
+    let signer = if by_taker {
+        &env.taker
+    } else {
+        &env.initializer
+    };
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(*source, false),
+            AccountMeta::new(env.initializer_temp_x, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(env.token_program, false),
+        ],
+        data: EscrowInstruction::TopUp { additional_amount }.pack(),
+    };
+    process(&mut env.ctx, &[ix], &[signer]).await
+}
+
+#[tokio::test]
+async fn test_top_up_then_exchange() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let initializer = env.initializer.pubkey();
+    let mint_x = env.mint_x;
+    let source_x = create_token_account(&mut env.ctx, &mint_x, &initializer, DEPOSIT_AMOUNT).await;
+
+    top_up(&mut env, false, &source_x, DEPOSIT_AMOUNT / 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT * 3 / 2
+    );
+
+    // the taker gets the enlarged deposit for the same expected amount
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT * 3 / 2
+    );
+}
+
+#[tokio::test]
+async fn test_top_up_with_other_mint_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let initializer = env.initializer.pubkey();
+    let mint_y = env.mint_y;
+    let source_y = create_token_account(&mut env.ctx, &mint_y, &initializer, DEPOSIT_AMOUNT).await;
+
+    let result = top_up(&mut env, false, &source_y, DEPOSIT_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::MintMismatch);
+}
+
+#[tokio::test]
+async fn test_top_up_by_other_signer_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    let taker = env.taker.pubkey();
+    let mint_x = env.mint_x;
+    let source_x = create_token_account(&mut env.ctx, &mint_x, &taker, DEPOSIT_AMOUNT).await;
+
+    let result = top_up(&mut env, true, &source_x, DEPOSIT_AMOUNT / 2).await;
+    assert_escrow_error(result, EscrowError::UnauthorizedInitializer);
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_split_escrow_then_exchange_both() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+    // a price that doesn't divide evenly, the new escrow is rounded down
+    update_expected_amount(&mut env, false, EXPECTED_AMOUNT - 1)
+        .await
+        .unwrap();
+
+    let (new_escrow, new_temp_x) = split_escrow(&mut env, 333).await.unwrap();
+    let new_escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(new_escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let new_escrow_info = Escrow::unpack(&new_escrow_account.data).unwrap();
+    assert_eq!(new_escrow_info.remaining_deposit, 333);
+    assert_eq!(new_escrow_info.remaining_expected, 3_329);
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(env.escrow.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.remaining_deposit, DEPOSIT_AMOUNT - 333);
+    assert_eq!(escrow_info.remaining_expected, EXPECTED_AMOUNT - 1 - 3_329);
+
+    let taker_receive_x = env.taker_receive_x;
+    exchange(&mut env, &taker_receive_x).await.unwrap();
+    let ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &new_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &new_escrow,
+        0,
+        &env.mint_y,
+    );
+    process(&mut env.ctx, &[ix], &[&env.taker]).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.taker_receive_x).await,
+        DEPOSIT_AMOUNT
+    );
+    assert_eq!(token_balance(&mut env.ctx, &env.taker_send_y).await, 1);
+    assert!(!account_exists(&mut env.ctx, &new_escrow).await);
+}
+
+#[tokio::test]
+async fn test_split_escrow_of_invalid_amount_fails() {
+    let mut env = setup().await;
+    init_escrow(&mut env, EXPECTED_AMOUNT).await.unwrap();
+
+    for split_deposit_amount in [0, DEPOSIT_AMOUNT, DEPOSIT_AMOUNT + 1] {
+        let result = split_escrow(&mut env, split_deposit_amount)
+            .await
+            .map(|_| ());
+        assert_escrow_error(result, EscrowError::InvalidAmount);
+    }
+    assert_eq!(
+        token_balance(&mut env.ctx, &env.initializer_temp_x).await,
+        DEPOSIT_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_split_stream_fails() {
+    let mut env = setup().await;
+    init_stream(&mut env).await;
+    let result = split_escrow(&mut env, DEPOSIT_AMOUNT / 2).await.map(|_| ());
+    assert_escrow_error(result, EscrowError::ExchangeOnly);
+}
+
+#[tokio::test]
+async fn test_split_escrow_keeps_the_terms() {
+    let mut env = setup().await;
+    init_memo_escrow(&mut env).await;
+
+    let (new_escrow, new_temp_x) = split_escrow(&mut env, DEPOSIT_AMOUNT / 2).await.unwrap();
+    let escrow = env.escrow.pubkey();
+    let escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    let new_escrow_account = env
+        .ctx
+        .banks_client
+        .get_account(new_escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let new_escrow_info = Escrow::unpack(&new_escrow_account.data).unwrap();
+    assert!(new_escrow_info.memo_required);
+    assert_eq!(new_escrow_info.oracle, escrow_info.oracle);
+    assert_eq!(
+        new_escrow_info.taker_merkle_root,
+        escrow_info.taker_merkle_root
+    );
+    assert_eq!(new_escrow_info.gate_mint, escrow_info.gate_mint);
+    assert_eq!(
+        new_escrow_info.gatekeeper_network,
+        escrow_info.gatekeeper_network
+    );
+    assert_eq!(new_escrow_info.royalty_policy, escrow_info.royalty_policy);
+    assert_eq!(
+        new_escrow_info.deposit_decimals,
+        escrow_info.deposit_decimals
+    );
+    assert_eq!(
+        new_escrow_info.receive_decimals,
+        escrow_info.receive_decimals
+    );
+
+    // the memo can't be left out by taking the split part of the deposit
+    let mut ix = instruction::exchange(
+        &env.program_id,
+        &env.taker.pubkey(),
+        &env.taker_send_y,
+        &env.taker_receive_x,
+        &new_temp_x,
+        &env.initializer.pubkey(),
+        &env.initializer_receive_y,
+        &new_escrow,
+        0,
+        &env.mint_y,
+    );
+    ix.accounts
+        .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    let result = process(&mut env.ctx, &[ix], &[&env.taker]).await;
+    assert_escrow_error(result, EscrowError::MemoRequired);
+}
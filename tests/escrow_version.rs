@@ -0,0 +1,105 @@
+use bpf_program_template::state::{
+    Escrow, EscrowState, ESCROW_V1_LEN, ESCROW_VERSION, EXPECTED_AMOUNT_OFFSET, INITIALIZER_OFFSET,
+    NONCE_OFFSET, REMAINING_DEPOSIT_OFFSET, STATE_OFFSET, UNWRAP_SOL_OFFSET, VERSION_OFFSET,
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+fn v1_escrow(initializer: &Pubkey) -> Vec<u8> {
+    let mut data = vec![0; ESCROW_V1_LEN];
+    data[VERSION_OFFSET] = 1;
+    data[STATE_OFFSET] = 1;
+    data[INITIALIZER_OFFSET..INITIALIZER_OFFSET + 32].copy_from_slice(initializer.as_ref());
+    data[EXPECTED_AMOUNT_OFFSET..EXPECTED_AMOUNT_OFFSET + 8]
+        .copy_from_slice(&1_000u64.to_le_bytes());
+    data[REMAINING_DEPOSIT_OFFSET..REMAINING_DEPOSIT_OFFSET + 8]
+        .copy_from_slice(&500u64.to_le_bytes());
+    data[NONCE_OFFSET..NONCE_OFFSET + 8].copy_from_slice(&7u64.to_le_bytes());
+    data[UNWRAP_SOL_OFFSET] = 1;
+    data
+}
+
+#[test]
+fn test_migrate_v1_escrow() {
+    let initializer = Pubkey::new_unique();
+    let escrow = Escrow::migrate(&v1_escrow(&initializer)).unwrap();
+    assert_eq!(escrow.state, EscrowState::Active);
+    assert_eq!(escrow.initializer_pubkey, initializer);
+    assert_eq!(escrow.expected_amount, 1_000);
+    assert_eq!(escrow.remaining_deposit, 500);
+    assert_eq!(escrow.nonce, 7);
+    assert!(escrow.unwrap_sol);
+    assert!(!escrow.two_phase);
+    assert_eq!(escrow.sequence, 0);
+    assert!(!escrow.inactive);
+}
+
+#[test]
+fn test_migrate_current_escrow_keeps_sequence() {
+    let initializer = Pubkey::new_unique();
+    let mut escrow = Escrow::migrate(&v1_escrow(&initializer)).unwrap();
+    escrow.advance_sequence();
+    escrow.advance_sequence();
+
+    let mut data = [0; Escrow::LEN];
+    Escrow::pack(escrow, &mut data).unwrap();
+    let escrow = Escrow::migrate(&data).unwrap();
+    assert_eq!(escrow.sequence, 2);
+    assert_eq!(escrow.initializer_pubkey, initializer);
+}
+
+#[test]
+fn test_migrated_escrow_packs_current_version() {
+    let initializer = Pubkey::new_unique();
+    let escrow = Escrow::migrate(&v1_escrow(&initializer)).unwrap();
+
+    let mut data = [0; Escrow::LEN];
+    Escrow::pack(escrow, &mut data).unwrap();
+    assert_eq!(data[VERSION_OFFSET], ESCROW_VERSION);
+    assert_eq!(
+        Escrow::unpack(&data).unwrap().initializer_pubkey,
+        initializer
+    );
+}
+
+#[test]
+fn test_unknown_version_fails() {
+    let mut data = v1_escrow(&Pubkey::new_unique());
+    data[VERSION_OFFSET] = ESCROW_VERSION + 1;
+    assert_eq!(
+        Escrow::unpack(&data).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+    assert_eq!(
+        Escrow::migrate(&data).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn test_migrate_truncated_escrow_fails() {
+    let data = v1_escrow(&Pubkey::new_unique());
+    assert_eq!(
+        Escrow::migrate(&data[..ESCROW_V1_LEN - 1]).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn test_blank_escrow_unpacks_uninitialized() {
+    let escrow = Escrow::unpack_unchecked(&[0; Escrow::LEN]).unwrap();
+    assert_eq!(escrow.state, EscrowState::Uninitialized);
+}
+
+#[test]
+fn test_stored_mints_round_trip() {
+    let mut escrow = Escrow::migrate(&v1_escrow(&Pubkey::new_unique())).unwrap();
+    let (temp_token_mint, receive_token_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    escrow.temp_token_mint = temp_token_mint;
+    escrow.receive_token_mint = receive_token_mint;
+
+    let mut data = [0; Escrow::LEN];
+    Escrow::pack(escrow, &mut data).unwrap();
+    let escrow = Escrow::unpack(&data).unwrap();
+    assert_eq!(escrow.temp_token_mint, temp_token_mint);
+    assert_eq!(escrow.receive_token_mint, receive_token_mint);
+}
@@ -0,0 +1,140 @@
+use bpf_program_template::{
+    error::EscrowError,
+    state::{Config, FeeExemption, MAX_FEE_EXEMPTIONS},
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.set_fee_schedule(25, 30, &[]).unwrap();
+    config
+}
+
+fn exemption(pubkey: Pubkey, as_maker: bool, as_taker: bool) -> FeeExemption {
+    FeeExemption {
+        pubkey,
+        as_maker,
+        as_taker,
+    }
+}
+
+fn collected(config: &Config, initializer: &Pubkey, taker: &Pubkey) -> u64 {
+    let fees = config.trade_fees(10_000, initializer, taker).unwrap();
+    fees.collected().unwrap()
+}
+
+#[test]
+fn test_exempt_maker_pays_no_fee() {
+    let (initializer, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = config();
+    assert_eq!(collected(&config, &initializer, &taker), 55);
+
+    config
+        .add_fee_exemption(exemption(initializer, true, false))
+        .unwrap();
+    let fees = config.trade_fees(10_000, &initializer, &taker).unwrap();
+    assert_eq!(fees.initializer_amount().unwrap(), 10_000);
+    assert_eq!(fees.taker_amount().unwrap(), 10_000);
+    assert_eq!(fees.collected().unwrap(), 0);
+}
+
+#[test]
+fn test_exempt_taker_pays_no_fee() {
+    let (initializer, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = config();
+    config
+        .add_fee_exemption(exemption(taker, false, true))
+        .unwrap();
+    assert_eq!(collected(&config, &initializer, &taker), 0);
+}
+
+#[test]
+fn test_exemption_only_applies_in_its_role() {
+    let (initializer, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = config();
+    config
+        .add_fee_exemption(exemption(initializer, false, true))
+        .unwrap();
+    config
+        .add_fee_exemption(exemption(taker, true, false))
+        .unwrap();
+    assert_eq!(collected(&config, &initializer, &taker), 55);
+
+    // the same pubkeys trading the other way round are exempt
+    assert_eq!(collected(&config, &taker, &initializer), 0);
+}
+
+#[test]
+fn test_adding_listed_pubkey_replaces_its_flags() {
+    let pubkey = Pubkey::new_unique();
+    let mut config = config();
+    config
+        .add_fee_exemption(exemption(pubkey, true, false))
+        .unwrap();
+    config
+        .add_fee_exemption(exemption(pubkey, false, true))
+        .unwrap();
+    assert_eq!(config.fee_exemptions(), &[exemption(pubkey, false, true)]);
+    assert!(!config.is_maker_exempt(&pubkey));
+    assert!(config.is_taker_exempt(&pubkey));
+}
+
+#[test]
+fn test_full_exemption_list() {
+    let mut config = config();
+    for _ in 0..MAX_FEE_EXEMPTIONS {
+        config
+            .add_fee_exemption(exemption(Pubkey::new_unique(), true, true))
+            .unwrap();
+    }
+    assert_eq!(
+        config.add_fee_exemption(exemption(Pubkey::new_unique(), true, true)),
+        Err(EscrowError::FeeExemptionsFull)
+    );
+
+    // a listed pubkey can still change its flags
+    let listed = config.fee_exemptions()[0].pubkey;
+    assert!(config
+        .add_fee_exemption(exemption(listed, false, false))
+        .is_ok());
+}
+
+#[test]
+fn test_remove_fee_exemption() {
+    let (first, second, third) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let mut config = config();
+    for pubkey in [first, second, third] {
+        config
+            .add_fee_exemption(exemption(pubkey, true, true))
+            .unwrap();
+    }
+
+    config.remove_fee_exemption(&first).unwrap();
+    assert!(!config.is_maker_exempt(&first));
+    assert_eq!(
+        config.fee_exemptions(),
+        &[exemption(third, true, true), exemption(second, true, true)]
+    );
+
+    assert_eq!(
+        config.remove_fee_exemption(&first),
+        Err(EscrowError::NotFeeExempt)
+    );
+}
+
+#[test]
+fn test_config_with_exemptions_pack_round_trip() {
+    let mut config = config();
+    config.is_initialized = true;
+    config
+        .add_fee_exemption(exemption(Pubkey::new_unique(), true, false))
+        .unwrap();
+
+    let mut data = [0; Config::LEN];
+    Config::pack(config.clone(), &mut data).unwrap();
+    assert_eq!(Config::unpack(&data).unwrap(), config);
+}
@@ -0,0 +1,124 @@
+use bpf_program_template::{
+    error::EscrowError,
+    state::{Config, FeeTier, MAX_FEE_TIERS},
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+fn tier(threshold: u64, basis_points: u16) -> FeeTier {
+    FeeTier {
+        threshold,
+        basis_points,
+    }
+}
+
+fn config(maker_fee_bps: u16, taker_fee_bps: u16, fee_tiers: &[FeeTier]) -> Config {
+    let mut config = Config::default();
+    config
+        .set_fee_schedule(maker_fee_bps, taker_fee_bps, fee_tiers)
+        .unwrap();
+    config
+}
+
+#[test]
+fn test_flat_rate_without_tiers() {
+    let config = config(25, 0, &[]);
+    assert_eq!(config.maker_fee_bps_for(0), 25);
+    assert_eq!(config.maker_fee_bps_for(u64::MAX), 25);
+}
+
+#[test]
+fn test_tier_applies_from_its_threshold() {
+    let config = config(30, 0, &[tier(10_000, 20), tier(1_000_000, 10)]);
+
+    // below the first tier the flat rate applies
+    assert_eq!(config.maker_fee_bps_for(9_999), 30);
+    // a payment right at a threshold pays that tier's rate
+    assert_eq!(config.maker_fee_bps_for(10_000), 20);
+    assert_eq!(config.maker_fee_bps_for(999_999), 20);
+    assert_eq!(config.maker_fee_bps_for(1_000_000), 10);
+    assert_eq!(config.maker_fee_bps_for(u64::MAX), 10);
+}
+
+#[test]
+fn test_tier_at_zero_replaces_flat_rate() {
+    let config = config(30, 0, &[tier(0, 5), tier(500, 0)]);
+    assert_eq!(config.maker_fee_bps_for(0), 5);
+    assert_eq!(config.maker_fee_bps_for(499), 5);
+    assert_eq!(config.maker_fee_bps_for(500), 0);
+}
+
+#[test]
+fn test_trade_fees_use_tier_rate() {
+    let config = config(30, 10, &[tier(10_000, 20)]);
+
+    let fees = config
+        .trade_fees(9_999, &Pubkey::new_unique(), &Pubkey::new_unique())
+        .unwrap();
+    assert_eq!((fees.maker_fee, fees.taker_fee), (29, 9));
+
+    // only the maker rate is tiered, the taker rate stays flat
+    let fees = config
+        .trade_fees(10_000, &Pubkey::new_unique(), &Pubkey::new_unique())
+        .unwrap();
+    assert_eq!((fees.maker_fee, fees.taker_fee), (20, 10));
+    assert_eq!(fees.initializer_amount().unwrap(), 9_980);
+    assert_eq!(fees.taker_amount().unwrap(), 10_010);
+}
+
+#[test]
+fn test_unsorted_tiers_are_rejected() {
+    let mut config = Config::default();
+    assert_eq!(
+        config.set_fee_schedule(25, 0, &[tier(1_000, 20), tier(100, 10)]),
+        Err(EscrowError::InvalidFeeSchedule)
+    );
+    // two tiers can't share a threshold
+    assert_eq!(
+        config.set_fee_schedule(25, 0, &[tier(1_000, 20), tier(1_000, 10)]),
+        Err(EscrowError::InvalidFeeSchedule)
+    );
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn test_rates_of_whole_payment_are_rejected() {
+    let mut config = Config::default();
+    for (maker_fee_bps, taker_fee_bps, fee_tiers) in [
+        (10_000, 0, vec![]),
+        (0, 10_000, vec![]),
+        (0, 0, vec![tier(100, 10_000)]),
+    ] {
+        assert_eq!(
+            config.set_fee_schedule(maker_fee_bps, taker_fee_bps, &fee_tiers),
+            Err(EscrowError::InvalidFeeSchedule)
+        );
+    }
+    assert!(config
+        .set_fee_schedule(9_999, 9_999, &[tier(0, 9_999)])
+        .is_ok());
+}
+
+#[test]
+fn test_too_many_tiers_are_rejected() {
+    let fee_tiers: Vec<_> = (0..=MAX_FEE_TIERS as u64)
+        .map(|index| tier(index * 100, 10))
+        .collect();
+    assert_eq!(
+        Config::default().set_fee_schedule(25, 0, &fee_tiers),
+        Err(EscrowError::InvalidFeeSchedule)
+    );
+    assert!(Config::default()
+        .set_fee_schedule(25, 0, &fee_tiers[..MAX_FEE_TIERS])
+        .is_ok());
+}
+
+#[test]
+fn test_config_pack_round_trip() {
+    let mut config = config(25, 5, &[tier(10_000, 20), tier(1_000_000, 10)]);
+    config.is_initialized = true;
+    config.bump_seed = 254;
+
+    let mut data = [0; Config::LEN];
+    Config::pack(config.clone(), &mut data).unwrap();
+    assert_eq!(Config::unpack(&data).unwrap(), config);
+}
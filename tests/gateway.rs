@@ -0,0 +1,158 @@
+use bpf_program_template::{
+    error::EscrowError,
+    gateway::{check_gateway_token, load_gateway_token, GatewayToken, GatewayTokenState},
+};
+use solana_program::pubkey::Pubkey;
+
+/// A gateway token of `owner_wallet` issued by `gatekeeper_network`, with a parent token and an
+/// owner identity when `session` is set
+fn gateway_token_account(
+    owner_wallet: &Pubkey,
+    gatekeeper_network: &Pubkey,
+    state: u8,
+    expire_time: Option<i64>,
+    session: bool,
+) -> Vec<u8> {
+    let mut data = vec![0];
+    if session {
+        data.push(1);
+        data.extend_from_slice(&[3; 32]);
+    } else {
+        data.push(0);
+    }
+    data.extend_from_slice(owner_wallet.as_ref());
+    if session {
+        data.push(1);
+        data.extend_from_slice(&[4; 32]);
+    } else {
+        data.push(0);
+    }
+    data.extend_from_slice(gatekeeper_network.as_ref());
+    data.extend_from_slice(&[5; 32]);
+    data.push(state);
+    match expire_time {
+        Some(expire_time) => {
+            data.push(1);
+            data.extend_from_slice(&expire_time.to_le_bytes());
+        }
+        None => data.push(0),
+    }
+    data
+}
+
+#[test]
+fn test_load_gateway_token() {
+    let owner_wallet = Pubkey::new_unique();
+    let gatekeeper_network = Pubkey::new_unique();
+    for session in [false, true] {
+        let data =
+            gateway_token_account(&owner_wallet, &gatekeeper_network, 1, Some(1_000), session);
+        assert_eq!(
+            load_gateway_token(&data),
+            Ok(GatewayToken {
+                owner_wallet,
+                gatekeeper_network,
+                state: GatewayTokenState::Frozen,
+                expire_time: Some(1_000),
+            })
+        );
+    }
+
+    let data = gateway_token_account(&owner_wallet, &gatekeeper_network, 0, None, false);
+    assert_eq!(load_gateway_token(&data).unwrap().expire_time, None);
+}
+
+#[test]
+fn test_load_malformed_gateway_token_fails() {
+    let owner_wallet = Pubkey::new_unique();
+    let gatekeeper_network = Pubkey::new_unique();
+    let data = gateway_token_account(&owner_wallet, &gatekeeper_network, 0, Some(1_000), false);
+
+    assert_eq!(
+        load_gateway_token(&data[..data.len() - 1]),
+        Err(EscrowError::GatewayTokenMissing)
+    );
+    assert_eq!(
+        load_gateway_token(&[]),
+        Err(EscrowError::GatewayTokenMissing)
+    );
+
+    let mut unknown_state = data.clone();
+    unknown_state[1 + 1 + 32 + 1 + 32 + 32] = 3;
+    assert_eq!(
+        load_gateway_token(&unknown_state),
+        Err(EscrowError::GatewayTokenMissing)
+    );
+
+    let mut bad_option = data;
+    bad_option[1] = 2;
+    assert_eq!(
+        load_gateway_token(&bad_option),
+        Err(EscrowError::GatewayTokenMissing)
+    );
+}
+
+fn token(owner_wallet: Pubkey, gatekeeper_network: Pubkey) -> GatewayToken {
+    GatewayToken {
+        owner_wallet,
+        gatekeeper_network,
+        state: GatewayTokenState::Active,
+        expire_time: Some(1_000),
+    }
+}
+
+#[test]
+fn test_check_valid_gateway_token() {
+    let taker = Pubkey::new_unique();
+    let network = Pubkey::new_unique();
+    assert_eq!(
+        check_gateway_token(&token(taker, network), &taker, &network, 1_000),
+        Ok(())
+    );
+
+    let mut never_expires = token(taker, network);
+    never_expires.expire_time = None;
+    assert_eq!(
+        check_gateway_token(&never_expires, &taker, &network, i64::MAX),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_check_expired_gateway_token_fails() {
+    let taker = Pubkey::new_unique();
+    let network = Pubkey::new_unique();
+    assert_eq!(
+        check_gateway_token(&token(taker, network), &taker, &network, 1_001),
+        Err(EscrowError::GatewayTokenExpired)
+    );
+}
+
+#[test]
+fn test_check_inactive_gateway_token_fails() {
+    let taker = Pubkey::new_unique();
+    let network = Pubkey::new_unique();
+    for state in [GatewayTokenState::Frozen, GatewayTokenState::Revoked] {
+        let mut gateway_token = token(taker, network);
+        gateway_token.state = state;
+        assert_eq!(
+            check_gateway_token(&gateway_token, &taker, &network, 0),
+            Err(EscrowError::GatewayTokenInactive)
+        );
+    }
+}
+
+#[test]
+fn test_check_mismatched_gateway_token_fails() {
+    let taker = Pubkey::new_unique();
+    let network = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    assert_eq!(
+        check_gateway_token(&token(other, network), &taker, &network, 0),
+        Err(EscrowError::GatewayTokenMismatch)
+    );
+    assert_eq!(
+        check_gateway_token(&token(taker, other), &taker, &network, 0),
+        Err(EscrowError::GatewayTokenMismatch)
+    );
+}
@@ -0,0 +1,220 @@
+use bpf_program_template::{
+    error::EscrowError,
+    instruction::{self, BatchEntry, EscrowInstruction},
+    metadata::RoyaltyPolicy,
+    processor::{config_pda, escrow_pda, fee_collector_pda},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::{packet::PACKET_DATA_SIZE, transaction::Transaction};
+use spl_associated_token_account::get_associated_token_address;
+
+#[test]
+fn test_init_escrow_accounts() {
+    let program_id = Pubkey::new_unique();
+    let keys: Vec<_> = (0..5).map(|_| Pubkey::new_unique()).collect();
+    let ix = instruction::init_escrow(
+        &program_id,
+        &keys[0],
+        &keys[1],
+        &keys[2],
+        &keys[3],
+        &keys[4],
+        10_000,
+    );
+
+    assert_eq!(ix.program_id, program_id);
+    assert_eq!(
+        ix.accounts,
+        vec![
+            // the initializer signs and pays the creation fee
+            AccountMeta::new(keys[0], true),
+            AccountMeta::new(keys[1], false),
+            AccountMeta::new_readonly(keys[2], false),
+            AccountMeta::new(keys[3], false),
+            AccountMeta::new_readonly(keys[4], false),
+            AccountMeta::new_readonly(config_pda(&program_id).0, false),
+            AccountMeta::new(fee_collector_pda(&program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ]
+    );
+    assert_eq!(
+        EscrowInstruction::unpack(&ix.data).unwrap(),
+        EscrowInstruction::InitEscrow {
+            amount: 10_000,
+            expiry_slot: 0,
+            expiry_unix_timestamp: 0,
+            allowed_taker: None,
+            two_phase: false,
+            min_taker_amount: 0,
+            nonce: 0,
+            arbiter: None,
+            receipt: false,
+            position: false,
+            taker_merkle_root: None,
+            gate_mint: None,
+            gate_min_amount: 0,
+            gatekeeper_network: None,
+            memo_required: false,
+            royalty_policy: RoyaltyPolicy::None,
+            nft: false,
+            unwrap_sol: false,
+        }
+    );
+}
+
+#[test]
+fn test_exchange_accounts() {
+    let program_id = Pubkey::new_unique();
+    let keys: Vec<_> = (0..7).map(|_| Pubkey::new_unique()).collect();
+    let receive_mint = Pubkey::new_unique();
+    let ix = instruction::exchange(
+        &program_id,
+        &keys[0],
+        &keys[1],
+        &keys[2],
+        &keys[3],
+        &keys[4],
+        &keys[5],
+        &keys[6],
+        2,
+        &receive_mint,
+    );
+
+    assert_eq!(ix.program_id, program_id);
+    assert_eq!(
+        ix.accounts,
+        vec![
+            // only the taker signs
+            AccountMeta::new_readonly(keys[0], true),
+            AccountMeta::new(keys[1], false),
+            AccountMeta::new(keys[2], false),
+            AccountMeta::new(keys[3], false),
+            // the initializer gets the rent of the closed accounts
+            AccountMeta::new(keys[4], false),
+            AccountMeta::new(keys[5], false),
+            AccountMeta::new(keys[6], false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            // the PDA of the initializer's escrow with that nonce
+            AccountMeta::new_readonly(escrow_pda(&program_id, &keys[4], 2).0, false),
+            AccountMeta::new(
+                get_associated_token_address(&fee_collector_pda(&program_id).0, &receive_mint),
+                false
+            ),
+            AccountMeta::new_readonly(config_pda(&program_id).0, false),
+        ]
+    );
+    assert_eq!(
+        EscrowInstruction::unpack(&ix.data).unwrap(),
+        EscrowInstruction::Exchange {
+            max_payment: 0,
+            preimage: None,
+            merkle_proof: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn test_exchange_packs_the_proof() {
+    let instruction = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: None,
+        merkle_proof: vec![[1; 32], [2; 32]],
+    };
+    let mut data = instruction.pack();
+    assert_eq!(EscrowInstruction::unpack(&data), Ok(instruction));
+
+    data.pop();
+    assert_eq!(
+        EscrowInstruction::unpack(&data),
+        Err(ProgramError::Custom(EscrowError::InvalidInstruction as u32))
+    );
+
+    // older clients leave the proof out
+    data.truncate(1 + 8 + 1);
+    assert_eq!(
+        EscrowInstruction::unpack(&data),
+        Ok(EscrowInstruction::Exchange {
+            max_payment: 0,
+            preimage: None,
+            merkle_proof: Vec::new(),
+        })
+    );
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn test_borsh_round_trip() {
+    use borsh::BorshSerialize;
+
+    let init = EscrowInstruction::InitEscrow {
+        amount: 10_000,
+        expiry_slot: 500,
+        expiry_unix_timestamp: 1_700_000_000,
+        allowed_taker: Some(Pubkey::new_unique()),
+        two_phase: false,
+        min_taker_amount: 10,
+        nonce: 3,
+        arbiter: None,
+        receipt: true,
+        position: false,
+        taker_merkle_root: Some([7; 32]),
+        gate_mint: Some(Pubkey::new_unique()),
+        gate_min_amount: 1,
+        gatekeeper_network: None,
+        memo_required: true,
+        royalty_policy: RoyaltyPolicy::BestEffort,
+        nft: false,
+        unwrap_sol: false,
+    };
+    let data = init.try_to_vec().unwrap();
+    assert_eq!(EscrowInstruction::unpack_borsh(&data).unwrap(), init);
+    // without Vec fields both formats write the same bytes
+    assert_eq!(data, init.pack());
+    assert_eq!(EscrowInstruction::unpack(&data).unwrap(), init);
+
+    let batch = EscrowInstruction::InitEscrowBatch {
+        entries: (1..=3)
+            .map(|amount| BatchEntry { amount, nonce: 0 })
+            .collect(),
+    };
+    let data = batch.try_to_vec().unwrap();
+    assert_eq!(EscrowInstruction::unpack_borsh(&data).unwrap(), batch);
+    // Borsh writes the length of a Vec as a u32, the tag byte format as a u8
+    assert_eq!(data[1..5], 3u32.to_le_bytes());
+    let packed = batch.pack();
+    assert_eq!(packed[1], 3);
+    assert_eq!(data.len(), packed.len() + 3);
+    assert_eq!(EscrowInstruction::unpack(&packed).unwrap(), batch);
+}
+
+/// Size of `transaction` on the wire, the compact length of the signatures, the signatures and
+/// the message
+fn transaction_size(transaction: &Transaction) -> usize {
+    1 + transaction.signatures.len() * 64 + transaction.message_data().len()
+}
+
+#[test]
+fn test_exchange_batch_transaction_size() {
+    let program_id = Pubkey::new_unique();
+    let taker = Pubkey::new_unique();
+    // every escrow of its own initializer, the largest transaction for the batch
+    let batch_transaction = |count: usize| {
+        let mut accounts = vec![AccountMeta::new_readonly(taker, true)];
+        accounts.extend((0..5).map(|_| AccountMeta::new(Pubkey::new_unique(), false)));
+        accounts.extend((0..count * 5).map(|_| AccountMeta::new(Pubkey::new_unique(), false)));
+        let ix = Instruction {
+            program_id,
+            accounts,
+            data: EscrowInstruction::ExchangeBatch { count: count as u8 }.pack(),
+        };
+        Transaction::new_with_payer(&[ix], Some(&taker))
+    };
+
+    assert!(transaction_size(&batch_transaction(5)) <= PACKET_DATA_SIZE);
+    assert!(transaction_size(&batch_transaction(6)) > PACKET_DATA_SIZE);
+}
@@ -0,0 +1,162 @@
+use bpf_program_template::{
+    error::EscrowError,
+    events::{self, ESCROW_CANCEL, ESCROW_EXCHANGE, ESCROW_INIT, ESCROW_MEMO, ESCROW_RECEIPT},
+    instruction::{BatchEntry, EscrowInstruction},
+    processor::Processor,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+};
+use std::{cell::RefCell, sync::Once};
+
+thread_local! {
+    static LOGS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+/// Collects what `msg!` logs instead of printing it, per thread so parallel tests don't mix
+struct CaptureLogs;
+
+impl SyscallStubs for CaptureLogs {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+    }
+}
+
+fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(CaptureLogs));
+    });
+    LOGS.with(|logs| logs.borrow_mut().clear());
+    f();
+    LOGS.with(|logs| logs.take())
+}
+
+#[test]
+fn test_event_lines() {
+    let escrow = Pubkey::new_unique();
+    let initializer = Pubkey::new_unique();
+    let taker = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+
+    let logs = capture_logs(|| {
+        events::emit_init(&escrow, &initializer, 1_000, 1);
+        events::emit_exchange(&escrow, &taker, 400, 4_000, 2);
+        events::emit_cancel(&escrow, &initializer, 3);
+        events::emit_receipt(&escrow, &taker, &mint, 400, 4_000);
+        events::emit_memo(&escrow, b"order 42");
+    });
+
+    assert_eq!(
+        logs,
+        vec![
+            format!(
+                "{} escrow={} initializer={} amount=1000 sequence=1",
+                ESCROW_INIT, escrow, initializer
+            ),
+            format!(
+                "{} escrow={} taker={} amount=400 payment=4000 sequence=2",
+                ESCROW_EXCHANGE, escrow, taker
+            ),
+            format!(
+                "{} escrow={} initializer={} sequence=3",
+                ESCROW_CANCEL, escrow, initializer
+            ),
+            format!(
+                "{} escrow={} taker={} mint={} amount=400 payment=4000",
+                ESCROW_RECEIPT, escrow, taker, mint
+            ),
+            format!("{} escrow={} memo=order 42", ESCROW_MEMO, escrow),
+        ]
+    );
+}
+
+#[test]
+fn test_event_prefixes() {
+    let escrow = Pubkey::new_unique();
+    let logs = capture_logs(|| {
+        events::emit_init(&escrow, &Pubkey::new_unique(), 1, 1);
+        events::emit_exchange(&escrow, &Pubkey::new_unique(), 1, 1, 2);
+    });
+
+    assert_eq!(logs.len(), 2);
+    assert!(logs[0].starts_with("ESCROW_INIT "));
+    assert!(logs[1].starts_with("ESCROW_EXCHANGE "));
+}
+
+#[test]
+fn test_missing_account_is_logged() {
+    let program_id = Pubkey::new_unique();
+    let taker = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [];
+    let owner = Pubkey::default();
+    // only the taker, the program runs out at the account the taker pays from
+    let accounts = [AccountInfo::new(
+        &taker,
+        true,
+        false,
+        &mut lamports,
+        &mut data,
+        &owner,
+        false,
+        0,
+    )];
+    let data = EscrowInstruction::Exchange {
+        max_payment: 0,
+        preimage: None,
+        merkle_proof: vec![],
+    }
+    .pack();
+
+    let mut result = Ok(());
+    let logs = capture_logs(|| result = Processor::process(&program_id, &accounts, &data));
+
+    assert_eq!(result, Err(ProgramError::NotEnoughAccountKeys));
+    assert_eq!(
+        logs.last().unwrap(),
+        "missing taker_token_to_send_account account"
+    );
+}
+
+#[test]
+fn test_failing_batch_entry_is_logged() {
+    let program_id = Pubkey::new_unique();
+    let keys: Vec<_> = (0..8).map(|_| Pubkey::new_unique()).collect();
+    let token_program = spl_token::id();
+    let system_owner = Pubkey::default();
+    let mut lamports = [0; 8];
+    let mut data = [[0u8; 0]; 8];
+    // the initializer, the receiving token account, the token program, the config, the fee
+    // collector, the system program, then the temp and escrow accounts of the one entry
+    let accounts: Vec<_> = lamports
+        .iter_mut()
+        .zip(data.iter_mut())
+        .enumerate()
+        .map(|(index, (lamports, data))| {
+            let (key, owner) = match index {
+                1 => (&keys[1], &token_program),
+                2 => (&token_program, &system_owner),
+                _ => (&keys[index], &system_owner),
+            };
+            AccountInfo::new(key, true, true, lamports, data, owner, false, 0)
+        })
+        .collect();
+    // an amount of 0 fails the entry before any account is read
+    let data = EscrowInstruction::InitEscrowBatch {
+        entries: vec![BatchEntry {
+            amount: 0,
+            nonce: 0,
+        }],
+    }
+    .pack();
+
+    let mut result = Ok(());
+    let logs = capture_logs(|| result = Processor::process(&program_id, &accounts, &data));
+
+    assert_eq!(result, Err(EscrowError::InvalidAmount.into()));
+    assert_eq!(logs.last().unwrap(), "InitEscrowBatch entry 0 failed");
+}
@@ -0,0 +1,100 @@
+use bpf_program_template::{
+    error::EscrowError,
+    math::{checked_fee, checked_rate_payment, checked_remainder, TradeFees},
+};
+
+#[test]
+fn test_fee_rounds_down() {
+    assert_eq!(checked_fee(10_000, 25).unwrap(), 25);
+    assert_eq!(checked_fee(399, 25).unwrap(), 0);
+    assert_eq!(checked_fee(401, 25).unwrap(), 1);
+    assert_eq!(checked_fee(0, 25).unwrap(), 0);
+}
+
+#[test]
+fn test_fee_of_max_amount() {
+    // the intermediate product doesn't fit in a u64 but the fee does
+    assert_eq!(checked_fee(u64::MAX, 25).unwrap(), u64::MAX / 400);
+    assert_eq!(checked_fee(u64::MAX, 10_000).unwrap(), u64::MAX);
+    assert_eq!(
+        checked_fee(u64::MAX, u16::MAX),
+        Err(EscrowError::AmountOverFlow)
+    );
+}
+
+#[test]
+fn test_remainder() {
+    assert_eq!(checked_remainder(10_000, 25).unwrap(), 9_975);
+    assert_eq!(checked_remainder(u64::MAX, u64::MAX).unwrap(), 0);
+    assert_eq!(checked_remainder(u64::MAX, 0).unwrap(), u64::MAX);
+    assert_eq!(checked_remainder(0, 1), Err(EscrowError::AmountOverFlow));
+    assert_eq!(
+        checked_remainder(u64::MAX - 1, u64::MAX),
+        Err(EscrowError::AmountOverFlow)
+    );
+}
+
+#[test]
+fn test_rate_payment_rounds_up() {
+    assert_eq!(checked_rate_payment(10, 3, 2).unwrap(), 15);
+    assert_eq!(checked_rate_payment(3, 1, 2).unwrap(), 2);
+    assert_eq!(checked_rate_payment(0, 3, 2).unwrap(), 0);
+    assert_eq!(
+        checked_rate_payment(10, 3, 0),
+        Err(EscrowError::InvalidAmount)
+    );
+    assert_eq!(
+        checked_rate_payment(u64::MAX, 2, 1),
+        Err(EscrowError::AmountOverFlow)
+    );
+}
+
+fn settle(payment: u64, maker_fee_bps: u16, taker_fee_bps: u16) -> (u64, u64, u64) {
+    let fees = TradeFees::checked_new(payment, maker_fee_bps, taker_fee_bps).unwrap();
+    let taker = fees.taker_amount().unwrap();
+    let initializer = fees.initializer_amount().unwrap();
+    let collected = fees.collected().unwrap();
+    // whatever leaves the taker is either the initializer's or the fee collector's
+    assert_eq!(taker, initializer + collected);
+    (taker, initializer, collected)
+}
+
+#[test]
+fn test_trade_fees_without_rates() {
+    assert_eq!(settle(10_000, 0, 0), (10_000, 10_000, 0));
+}
+
+#[test]
+fn test_trade_fees_maker_only() {
+    assert_eq!(settle(10_000, 25, 0), (10_000, 9_975, 25));
+}
+
+#[test]
+fn test_trade_fees_taker_only() {
+    assert_eq!(settle(10_000, 0, 30), (10_030, 10_000, 30));
+}
+
+#[test]
+fn test_trade_fees_both_legs() {
+    assert_eq!(settle(10_000, 25, 30), (10_030, 9_975, 55));
+    assert_eq!(settle(1_000_000, 100, 50), (1_005_000, 990_000, 15_000));
+}
+
+#[test]
+fn test_trade_fees_round_down_separately() {
+    // 0.25% of 399 and 0.3% of 333 both round to nothing
+    assert_eq!(settle(399, 25, 0), (399, 399, 0));
+    assert_eq!(settle(333, 0, 30), (333, 333, 0));
+    // each leg rounds down on its own rather than on their sum
+    assert_eq!(settle(500, 25, 30), (501, 499, 2));
+}
+
+#[test]
+fn test_trade_fees_overflow() {
+    let fees = TradeFees::checked_new(u64::MAX, 0, 1).unwrap();
+    assert_eq!(fees.taker_amount(), Err(EscrowError::AmountOverFlow));
+    assert_eq!(
+        TradeFees::checked_new(u64::MAX, u16::MAX, 0),
+        Err(EscrowError::AmountOverFlow)
+    );
+}
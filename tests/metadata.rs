@@ -0,0 +1,163 @@
+use bpf_program_template::{
+    error::EscrowError,
+    metadata::{load_metadata, royalty_shares, Creator, Metadata},
+};
+use solana_program::pubkey::Pubkey;
+
+/// A metadata account of `mint` with `seller_fee_basis_points`, listing `creators` when given
+fn metadata_account(
+    mint: &Pubkey,
+    seller_fee_basis_points: u16,
+    creators: Option<&[Creator]>,
+) -> Vec<u8> {
+    let mut data = vec![4];
+    data.extend_from_slice(&[7; 32]);
+    data.extend_from_slice(mint.as_ref());
+    for field in [&b"Cat #12"[..], b"CAT", b"https://example.com/12.json"] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field);
+    }
+    data.extend_from_slice(&seller_fee_basis_points.to_le_bytes());
+    match creators {
+        Some(creators) => {
+            data.push(1);
+            data.extend_from_slice(&(creators.len() as u32).to_le_bytes());
+            for creator in creators {
+                data.extend_from_slice(creator.address.as_ref());
+                data.push(creator.verified as u8);
+                data.push(creator.share);
+            }
+        }
+        None => data.push(0),
+    }
+    // the primary sale and mutability flags follow
+    data.extend_from_slice(&[0, 1]);
+    data
+}
+
+fn creator(verified: bool, share: u8) -> Creator {
+    Creator {
+        address: Pubkey::new_unique(),
+        verified,
+        share,
+    }
+}
+
+#[test]
+fn test_load_metadata() {
+    let mint = Pubkey::new_unique();
+    let creators = [creator(true, 60), creator(false, 40)];
+    let data = metadata_account(&mint, 500, Some(&creators));
+
+    assert_eq!(
+        load_metadata(&data).unwrap(),
+        Metadata {
+            mint,
+            seller_fee_basis_points: 500,
+            creators: creators.to_vec(),
+        }
+    );
+}
+
+#[test]
+fn test_load_metadata_without_creators() {
+    let mint = Pubkey::new_unique();
+    let metadata = load_metadata(&metadata_account(&mint, 500, None)).unwrap();
+    assert!(metadata.creators.is_empty());
+    assert_eq!(royalty_shares(&metadata, 10_000).unwrap(), vec![]);
+}
+
+#[test]
+fn test_load_other_account_fails() {
+    let mint = Pubkey::new_unique();
+    let mut data = metadata_account(&mint, 500, None);
+    data[0] = 6;
+    assert_eq!(load_metadata(&data), Err(EscrowError::InvalidMetadata));
+
+    let data = metadata_account(&mint, 500, Some(&[creator(true, 100)]));
+    assert_eq!(
+        load_metadata(&data[..data.len() - 4]),
+        Err(EscrowError::InvalidMetadata)
+    );
+    assert_eq!(load_metadata(&[]), Err(EscrowError::InvalidMetadata));
+}
+
+#[test]
+fn test_royalties_split_between_verified_creators() {
+    let creators = vec![creator(true, 50), creator(false, 20), creator(true, 30)];
+    let metadata = Metadata {
+        mint: Pubkey::new_unique(),
+        seller_fee_basis_points: 1_000,
+        creators: creators.clone(),
+    };
+
+    assert_eq!(
+        royalty_shares(&metadata, 10_000).unwrap(),
+        vec![(creators[0].address, 500), (creators[2].address, 300)]
+    );
+}
+
+#[test]
+fn test_royalties_round_down() {
+    let creators = vec![creator(true, 34), creator(true, 33), creator(true, 33)];
+    let metadata = Metadata {
+        mint: Pubkey::new_unique(),
+        seller_fee_basis_points: 1_000,
+        creators: creators.clone(),
+    };
+
+    // 10% of 1_005 is 100, the creators' shares of it round down
+    assert_eq!(
+        royalty_shares(&metadata, 1_005).unwrap(),
+        vec![
+            (creators[0].address, 34),
+            (creators[1].address, 33),
+            (creators[2].address, 33)
+        ]
+    );
+    assert_eq!(
+        royalty_shares(&metadata, 29).unwrap(),
+        vec![
+            (creators[0].address, 0),
+            (creators[1].address, 0),
+            (creators[2].address, 0)
+        ]
+    );
+}
+
+#[test]
+fn test_royalties_of_max_payment() {
+    let creators = vec![creator(true, 100)];
+    let metadata = Metadata {
+        mint: Pubkey::new_unique(),
+        seller_fee_basis_points: 10_000,
+        creators: creators.clone(),
+    };
+    assert_eq!(
+        royalty_shares(&metadata, u64::MAX).unwrap(),
+        vec![(creators[0].address, u64::MAX)]
+    );
+}
+
+#[test]
+fn test_royalties_over_whole_payment_fail() {
+    let metadata = Metadata {
+        mint: Pubkey::new_unique(),
+        seller_fee_basis_points: 10_001,
+        creators: vec![creator(true, 100)],
+    };
+    assert_eq!(
+        royalty_shares(&metadata, 100),
+        Err(EscrowError::InvalidMetadata)
+    );
+
+    let metadata = Metadata {
+        mint: Pubkey::new_unique(),
+        seller_fee_basis_points: 1_000,
+        creators: vec![creator(true, 60), creator(true, 60)],
+    };
+    assert_eq!(
+        royalty_shares(&metadata, 100),
+        Err(EscrowError::InvalidMetadata)
+    );
+}
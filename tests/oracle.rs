@@ -0,0 +1,147 @@
+use bpf_program_template::{
+    error::EscrowError,
+    oracle::{check_price_band, load_price, load_switchboard_price, OraclePrice},
+};
+
+/// A trading Pyth price account pricing one whole token at `price * 10^expo`
+fn price_account(price: i64, expo: i32, publish_time: i64) -> Vec<u8> {
+    let mut data = vec![0; 240];
+    data[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    data[4..8].copy_from_slice(&2u32.to_le_bytes());
+    data[8..12].copy_from_slice(&3u32.to_le_bytes());
+    data[20..24].copy_from_slice(&expo.to_le_bytes());
+    data[96..104].copy_from_slice(&publish_time.to_le_bytes());
+    data[208..216].copy_from_slice(&price.to_le_bytes());
+    data[224..228].copy_from_slice(&1u32.to_le_bytes());
+    data
+}
+
+/// A Switchboard aggregator whose latest confirmed round resulted in `mantissa * 10^-scale`
+fn aggregator_account(mantissa: i128, scale: u32, open_slot: u64) -> Vec<u8> {
+    let mut data = vec![0; 400];
+    data[0..8].copy_from_slice(&[217, 230, 65, 101, 201, 162, 27, 125]);
+    data[341..345].copy_from_slice(&1u32.to_le_bytes());
+    data[350..358].copy_from_slice(&open_slot.to_le_bytes());
+    data[366..382].copy_from_slice(&mantissa.to_le_bytes());
+    data[382..386].copy_from_slice(&scale.to_le_bytes());
+    data
+}
+
+// 100 USDC per SOL
+const SOL_PRICE: OraclePrice = OraclePrice {
+    price: 10_000_000_000,
+    expo: -8,
+    publish_time: 0,
+};
+
+#[test]
+fn test_load_price() {
+    let data = price_account(10_000_000_000, -8, 42);
+    assert_eq!(
+        load_price(&data).unwrap(),
+        OraclePrice {
+            publish_time: 42,
+            ..SOL_PRICE
+        }
+    );
+}
+
+#[test]
+fn test_load_price_rejects_other_accounts() {
+    let mut data = price_account(10_000_000_000, -8, 0);
+    data[0] = 0;
+    assert_eq!(load_price(&data), Err(EscrowError::InvalidOracleAccount));
+    assert_eq!(
+        load_price(&[0; 100]),
+        Err(EscrowError::InvalidOracleAccount)
+    );
+
+    // a halted feed
+    let mut data = price_account(10_000_000_000, -8, 0);
+    data[224] = 2;
+    assert_eq!(load_price(&data), Err(EscrowError::OraclePriceUnavailable));
+}
+
+#[test]
+fn test_price_band() {
+    // 1 SOL with 9 decimals for USDC with 6 decimals, within 1% of 100 USDC either way
+    for payment in [99_000_000, 100_000_000, 101_000_000] {
+        check_price_band(1_000_000_000, payment, 9, 6, &SOL_PRICE, 100).unwrap();
+    }
+    for payment in [98_999_999, 101_000_001, 1_000_000] {
+        assert_eq!(
+            check_price_band(1_000_000_000, payment, 9, 6, &SOL_PRICE, 100),
+            Err(EscrowError::PriceOutOfBand)
+        );
+    }
+}
+
+#[test]
+fn test_price_band_with_positive_exponent() {
+    // 2000 of the received token per deposited token, both without decimals
+    let price = OraclePrice {
+        price: 2,
+        expo: 3,
+        publish_time: 0,
+    };
+    check_price_band(5, 10_000, 0, 0, &price, 1).unwrap();
+    assert_eq!(
+        check_price_band(5, 10_002, 0, 0, &price, 1),
+        Err(EscrowError::PriceOutOfBand)
+    );
+}
+
+#[test]
+fn test_load_switchboard_price() {
+    let data = aggregator_account(100_000_000, 6, 42);
+    assert_eq!(
+        load_switchboard_price(&data).unwrap(),
+        OraclePrice {
+            price: 100_000_000,
+            expo: -6,
+            publish_time: 42,
+        }
+    );
+}
+
+#[test]
+fn test_load_switchboard_price_rejects_other_accounts() {
+    let mut data = aggregator_account(100_000_000, 6, 0);
+    data[0] = 0;
+    assert_eq!(
+        load_switchboard_price(&data),
+        Err(EscrowError::InvalidOracleAccount)
+    );
+    assert_eq!(
+        load_switchboard_price(&[0; 100]),
+        Err(EscrowError::InvalidOracleAccount)
+    );
+
+    // each reader rejects the other oracle's accounts
+    assert_eq!(
+        load_switchboard_price(&price_account(10_000_000_000, -8, 0)),
+        Err(EscrowError::InvalidOracleAccount)
+    );
+    assert_eq!(
+        load_price(&aggregator_account(100_000_000, 6, 0)),
+        Err(EscrowError::InvalidOracleAccount)
+    );
+
+    // no round has succeeded yet
+    let mut data = aggregator_account(100_000_000, 6, 0);
+    data[341] = 0;
+    assert_eq!(
+        load_switchboard_price(&data),
+        Err(EscrowError::OraclePriceUnavailable)
+    );
+}
+
+#[test]
+fn test_price_band_with_switchboard_price() {
+    let price = load_switchboard_price(&aggregator_account(100_000_000, 6, 0)).unwrap();
+    check_price_band(1_000_000_000, 100_500_000, 9, 6, &price, 100).unwrap();
+    assert_eq!(
+        check_price_band(1_000_000_000, 98_000_000, 9, 6, &price, 100),
+        Err(EscrowError::PriceOutOfBand)
+    );
+}